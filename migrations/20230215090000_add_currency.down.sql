@@ -0,0 +1,3 @@
+ALTER TABLE refunds DROP COLUMN currency;
+
+ALTER TABLE payments DROP COLUMN currency;
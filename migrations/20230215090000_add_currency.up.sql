@@ -0,0 +1,3 @@
+ALTER TABLE payments ADD COLUMN currency character varying(3) NOT NULL DEFAULT 'USD';
+
+ALTER TABLE refunds ADD COLUMN currency character varying(3) NOT NULL DEFAULT 'USD';
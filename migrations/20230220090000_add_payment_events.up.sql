@@ -0,0 +1,9 @@
+CREATE TABLE payment_events (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    payment_id uuid REFERENCES payments(id) NOT NULL,
+    from_status Status NOT NULL,
+    to_status Status NOT NULL,
+    inserted_at timestamp not null default clock_timestamp()
+);
+
+CREATE INDEX payment_events_payment_id_index ON payment_events(payment_id, inserted_at);
@@ -0,0 +1,3 @@
+DROP INDEX payments_card_number_inserted_at_index;
+
+ALTER TABLE payments ADD CONSTRAINT payments_card_number_key UNIQUE (card_number);
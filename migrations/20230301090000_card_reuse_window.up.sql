@@ -0,0 +1,5 @@
+-- Card numbers may be reused once the reuse window configured on the service
+-- has elapsed, which `payments::insert` enforces under an advisory lock.
+ALTER TABLE payments DROP CONSTRAINT payments_card_number_key;
+
+CREATE INDEX payments_card_number_inserted_at_index ON payments(card_number, inserted_at);
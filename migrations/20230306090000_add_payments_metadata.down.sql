@@ -0,0 +1,3 @@
+DROP INDEX payments_metadata_index;
+
+ALTER TABLE payments DROP COLUMN metadata;
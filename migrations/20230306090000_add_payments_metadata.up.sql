@@ -0,0 +1,3 @@
+ALTER TABLE payments ADD COLUMN metadata jsonb;
+
+CREATE INDEX payments_metadata_index ON payments USING gin (metadata jsonb_path_ops);
@@ -0,0 +1,21 @@
+CREATE TABLE webhooks (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    url text NOT NULL,
+    secret text NOT NULL,
+    inserted_at timestamp not null default current_timestamp,
+    updated_at timestamp not null default current_timestamp
+);
+
+CREATE TABLE webhook_deliveries (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    webhook_id uuid REFERENCES webhooks(id) NOT NULL,
+    payment_id uuid REFERENCES payments(id) NOT NULL,
+    event character varying(255) NOT NULL,
+    attempts integer NOT NULL default 0,
+    last_status_code integer,
+    delivered_at timestamp,
+    inserted_at timestamp not null default current_timestamp,
+    updated_at timestamp not null default current_timestamp
+);
+
+CREATE INDEX webhook_deliveries_webhook_id_index ON webhook_deliveries(webhook_id, inserted_at);
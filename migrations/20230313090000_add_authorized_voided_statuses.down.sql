@@ -0,0 +1,12 @@
+-- enum values can't be dropped, so the type is recreated without them
+ALTER TYPE Status RENAME TO status_with_authorized;
+
+CREATE TYPE Status AS ENUM ('Processing', 'Approved', 'Declined', 'Failed');
+
+ALTER TABLE payments
+    ALTER COLUMN status TYPE Status USING status::text::Status;
+ALTER TABLE payment_events
+    ALTER COLUMN from_status TYPE Status USING from_status::text::Status,
+    ALTER COLUMN to_status TYPE Status USING to_status::text::Status;
+
+DROP TYPE status_with_authorized;
@@ -0,0 +1,2 @@
+ALTER TYPE Status ADD VALUE 'Authorized';
+ALTER TYPE Status ADD VALUE 'Voided';
@@ -0,0 +1,11 @@
+CREATE TABLE worker_claims (
+    kind character varying(255) NOT NULL,
+    payment_id uuid REFERENCES payments(id) NOT NULL,
+    claimed_by uuid NOT NULL,
+    attempts integer NOT NULL default 1,
+    expires_at timestamp NOT NULL,
+    completed_at timestamp,
+    inserted_at timestamp not null default current_timestamp,
+    updated_at timestamp not null default current_timestamp,
+    PRIMARY KEY (kind, payment_id)
+);
@@ -0,0 +1,3 @@
+CREATE TYPE RefundReason AS ENUM ('Duplicate', 'Fraudulent', 'RequestedByCustomer');
+
+ALTER TABLE refunds ADD COLUMN reason RefundReason;
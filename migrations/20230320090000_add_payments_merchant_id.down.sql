@@ -0,0 +1,3 @@
+DROP INDEX payments_merchant_id_index;
+
+ALTER TABLE payments DROP COLUMN merchant_id;
@@ -0,0 +1,5 @@
+-- payments made before merchants were told apart belong to the nil merchant
+ALTER TABLE payments ADD COLUMN merchant_id uuid NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+ALTER TABLE payments ALTER COLUMN merchant_id DROP DEFAULT;
+
+CREATE INDEX payments_merchant_id_index ON payments (merchant_id, inserted_at DESC, id DESC);
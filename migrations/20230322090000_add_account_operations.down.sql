@@ -0,0 +1,4 @@
+DROP TABLE account_operations;
+
+DROP TYPE AccountOperationOutcome;
+DROP TYPE AccountOperationKind;
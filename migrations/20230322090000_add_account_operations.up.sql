@@ -0,0 +1,16 @@
+CREATE TYPE AccountOperationKind AS ENUM ('Hold', 'Release', 'Withdraw');
+CREATE TYPE AccountOperationOutcome AS ENUM ('Succeeded', 'Failed');
+
+CREATE TABLE account_operations (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    kind AccountOperationKind NOT NULL,
+    payment_id uuid REFERENCES payments(id),
+    hold_ref uuid,
+    started_at timestamp not null default clock_timestamp(),
+    finished_at timestamp,
+    outcome AccountOperationOutcome,
+    error text
+);
+
+CREATE INDEX account_operations_payment_id_index ON account_operations(payment_id, started_at);
+CREATE INDEX account_operations_hold_ref_index ON account_operations(hold_ref);
@@ -0,0 +1,3 @@
+ALTER TABLE payments DROP COLUMN processing_mode;
+
+DROP TYPE ProcessingMode;
@@ -0,0 +1,3 @@
+CREATE TYPE ProcessingMode AS ENUM ('Sync', 'Async');
+
+ALTER TABLE payments ADD COLUMN processing_mode ProcessingMode NOT NULL DEFAULT 'Sync';
@@ -0,0 +1 @@
+DROP INDEX payments_card_suffix_index;
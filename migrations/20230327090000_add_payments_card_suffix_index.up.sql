@@ -0,0 +1 @@
+CREATE INDEX payments_card_suffix_index ON payments(right(card_number, 4), inserted_at DESC);
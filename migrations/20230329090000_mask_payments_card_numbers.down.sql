@@ -0,0 +1,5 @@
+-- Masked card numbers can't be restored
+DROP INDEX payments_card_fingerprint_inserted_at_index;
+CREATE INDEX payments_card_number_inserted_at_index ON payments(card_number, inserted_at);
+
+ALTER TABLE payments DROP COLUMN card_fingerprint;
@@ -0,0 +1,8 @@
+-- Card numbers are only kept masked, the fingerprint identifying reused cards.
+-- Existing rows are fingerprinted and masked by the `card_fingerprint` and
+-- `account_prefix` backfills, see `bank::backfill`, rather than here so the
+-- table isn't locked while rewritten.
+ALTER TABLE payments ADD COLUMN card_fingerprint character(64);
+
+DROP INDEX payments_card_number_inserted_at_index;
+CREATE INDEX payments_card_fingerprint_inserted_at_index ON payments(card_fingerprint, inserted_at);
@@ -0,0 +1,2 @@
+-- Archived payments are hidden from listings, but never deleted
+ALTER TABLE payments ADD COLUMN archived_at timestamp;
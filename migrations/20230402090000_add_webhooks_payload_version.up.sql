@@ -0,0 +1,3 @@
+-- Webhooks registered before payloads were versioned keep receiving the
+-- first version, new registrations choose theirs
+ALTER TABLE webhooks ADD COLUMN payload_version integer NOT NULL DEFAULT 1;
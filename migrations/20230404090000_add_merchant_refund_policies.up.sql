@@ -0,0 +1,8 @@
+-- merchants without a policy row have no refund restrictions
+CREATE TABLE merchant_refund_policies (
+    merchant_id uuid PRIMARY KEY,
+    -- days after a payment during which it can be refunded, unlimited when NULL
+    refund_window_days integer CHECK (refund_window_days >= 0),
+    inserted_at timestamp not null default current_timestamp,
+    updated_at timestamp not null default current_timestamp
+);
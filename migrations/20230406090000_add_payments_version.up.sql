@@ -0,0 +1,2 @@
+-- bumped by every change of a payment visible to merchants, for If-Match
+ALTER TABLE payments ADD COLUMN version integer NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+ALTER TABLE payments DROP COLUMN decline_reason;
+
+DROP TYPE DeclineReason;
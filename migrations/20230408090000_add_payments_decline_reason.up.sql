@@ -0,0 +1,3 @@
+CREATE TYPE DeclineReason AS ENUM ('InsufficientFunds', 'InvalidAccountNumber', 'InvalidAmount', 'ServiceFailure');
+
+ALTER TABLE payments ADD COLUMN decline_reason DeclineReason;
@@ -0,0 +1,3 @@
+DROP INDEX payments_customer_reference_index;
+
+ALTER TABLE payments DROP COLUMN customer_reference;
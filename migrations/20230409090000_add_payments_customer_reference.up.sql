@@ -0,0 +1,3 @@
+ALTER TABLE payments ADD COLUMN customer_reference text;
+
+CREATE INDEX payments_customer_reference_index ON payments (merchant_id, customer_reference, inserted_at DESC, id DESC);
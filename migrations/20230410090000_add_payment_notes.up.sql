@@ -0,0 +1,9 @@
+CREATE TABLE payment_notes (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    payment_id uuid REFERENCES payments(id) NOT NULL,
+    author text NOT NULL,
+    body text NOT NULL,
+    inserted_at timestamp not null default clock_timestamp()
+);
+
+CREATE INDEX payment_notes_payment_id_index ON payment_notes(payment_id, inserted_at, id);
@@ -0,0 +1,10 @@
+CREATE TABLE outbox_events (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    event_type text NOT NULL,
+    payload jsonb NOT NULL,
+    inserted_at timestamp not null default clock_timestamp(),
+    dispatched_at timestamp
+);
+
+-- only the events left to publish are looked up
+CREATE INDEX outbox_events_pending_index ON outbox_events(inserted_at, id) WHERE dispatched_at IS NULL;
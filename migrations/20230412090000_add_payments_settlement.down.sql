@@ -0,0 +1,4 @@
+ALTER TABLE payments DROP COLUMN settlement_outcome;
+ALTER TABLE payments DROP COLUMN hold_ref;
+
+DROP TYPE SettlementOutcome;
@@ -0,0 +1,4 @@
+CREATE TYPE SettlementOutcome AS ENUM ('Withdrawn', 'Released');
+
+ALTER TABLE payments ADD COLUMN hold_ref uuid;
+ALTER TABLE payments ADD COLUMN settlement_outcome SettlementOutcome;
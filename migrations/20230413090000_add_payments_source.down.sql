@@ -0,0 +1,3 @@
+ALTER TABLE payments DROP COLUMN source;
+
+DROP TYPE PaymentSource;
@@ -0,0 +1,3 @@
+CREATE TYPE PaymentSource AS ENUM ('Web', 'Mobile', 'Backoffice', 'Api');
+
+ALTER TABLE payments ADD COLUMN source PaymentSource NOT NULL DEFAULT 'Api';
@@ -0,0 +1,12 @@
+ALTER TABLE refunds DROP COLUMN reason_detail;
+
+-- enum values can't be dropped, so the type is recreated without it
+ALTER TYPE RefundReason RENAME TO refund_reason_with_other;
+
+CREATE TYPE RefundReason AS ENUM ('Duplicate', 'Fraudulent', 'RequestedByCustomer');
+
+ALTER TABLE refunds
+    ALTER COLUMN reason TYPE RefundReason
+    USING NULLIF(reason::text, 'Other')::RefundReason;
+
+DROP TYPE refund_reason_with_other;
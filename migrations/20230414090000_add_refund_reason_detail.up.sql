@@ -0,0 +1,3 @@
+ALTER TYPE RefundReason ADD VALUE 'Other';
+
+ALTER TABLE refunds ADD COLUMN reason_detail text;
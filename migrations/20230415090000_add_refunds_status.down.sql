@@ -0,0 +1,15 @@
+-- enum values can't be dropped, so the type is recreated without it
+DELETE FROM account_operations WHERE kind = 'Deposit';
+
+ALTER TYPE AccountOperationKind RENAME TO account_operation_kind_with_deposit;
+
+CREATE TYPE AccountOperationKind AS ENUM ('Hold', 'Release', 'Withdraw');
+
+ALTER TABLE account_operations
+    ALTER COLUMN kind TYPE AccountOperationKind USING kind::text::AccountOperationKind;
+
+DROP TYPE account_operation_kind_with_deposit;
+
+ALTER TABLE refunds DROP COLUMN status;
+
+DROP TYPE RefundStatus;
@@ -0,0 +1,7 @@
+CREATE TYPE RefundStatus AS ENUM ('Pending', 'Completed', 'Failed');
+
+-- refunds recorded until now were considered effective
+ALTER TABLE refunds ADD COLUMN status RefundStatus NOT NULL DEFAULT 'Completed';
+ALTER TABLE refunds ALTER COLUMN status SET DEFAULT 'Pending';
+
+ALTER TYPE AccountOperationKind ADD VALUE 'Deposit';
@@ -0,0 +1,4 @@
+ALTER TABLE refunds ADD COLUMN idempotency_key text;
+
+-- refunds without a key are never duplicates, as NULLs are distinct
+CREATE UNIQUE INDEX refunds_payment_id_idempotency_key_index ON refunds(payment_id, idempotency_key);
@@ -0,0 +1 @@
+ALTER TABLE refunds DROP CONSTRAINT refunds_amount_positive;
@@ -0,0 +1,2 @@
+-- refunds of no or negative amounts would add to the refundable amount
+ALTER TABLE refunds ADD CONSTRAINT refunds_amount_positive CHECK (amount > 0);
@@ -0,0 +1 @@
+ALTER TYPE RefundStatus RENAME VALUE 'Settled' TO 'Completed';
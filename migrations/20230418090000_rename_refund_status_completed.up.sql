@@ -0,0 +1 @@
+ALTER TYPE RefundStatus RENAME VALUE 'Completed' TO 'Settled';
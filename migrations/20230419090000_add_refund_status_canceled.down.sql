@@ -0,0 +1,14 @@
+-- enum values can't be dropped, so the type is recreated without it, and
+-- canceled refunds recorded as failed, which don't count against payments
+UPDATE refunds SET status = 'Failed' WHERE status = 'Canceled';
+
+ALTER TYPE RefundStatus RENAME TO refund_status_with_canceled;
+
+CREATE TYPE RefundStatus AS ENUM ('Pending', 'Settled', 'Failed');
+
+ALTER TABLE refunds ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE refunds
+    ALTER COLUMN status TYPE RefundStatus USING status::text::RefundStatus;
+ALTER TABLE refunds ALTER COLUMN status SET DEFAULT 'Pending';
+
+DROP TYPE refund_status_with_canceled;
@@ -0,0 +1 @@
+ALTER TYPE RefundStatus ADD VALUE 'Canceled';
@@ -0,0 +1 @@
+DROP INDEX refunds_inserted_at_index;
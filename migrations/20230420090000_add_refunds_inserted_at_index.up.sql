@@ -0,0 +1,2 @@
+-- refunds of every payment are listed newest first
+CREATE INDEX refunds_inserted_at_index ON refunds (inserted_at DESC, id DESC);
@@ -0,0 +1,5 @@
+DROP INDEX refunds_merchant_id_external_reference_index;
+
+ALTER TABLE refunds DROP COLUMN merchant_id;
+ALTER TABLE refunds DROP COLUMN external_reference;
+ALTER TABLE refunds DROP COLUMN metadata;
@@ -0,0 +1,10 @@
+ALTER TABLE refunds ADD COLUMN metadata jsonb;
+ALTER TABLE refunds ADD COLUMN external_reference text;
+
+-- external references are unique per merchant, whose id is copied from the
+-- payment so the unique index can hold it
+ALTER TABLE refunds ADD COLUMN merchant_id uuid;
+UPDATE refunds SET merchant_id = payments.merchant_id FROM payments WHERE payments.id = refunds.payment_id;
+ALTER TABLE refunds ALTER COLUMN merchant_id SET NOT NULL;
+
+CREATE UNIQUE INDEX refunds_merchant_id_external_reference_index ON refunds (merchant_id, external_reference);
@@ -0,0 +1,2 @@
+DROP TABLE disputes;
+DROP TYPE DisputeStatus;
@@ -0,0 +1,14 @@
+CREATE TYPE DisputeStatus AS ENUM ('Open', 'Closed');
+
+CREATE TABLE disputes (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    payment_id uuid REFERENCES payments(id) NOT NULL,
+    status DisputeStatus NOT NULL default 'Open',
+    inserted_at timestamp not null default clock_timestamp(),
+    closed_at timestamp
+);
+
+CREATE INDEX disputes_payment_id_index ON disputes(payment_id, inserted_at, id);
+
+-- a payment is disputed at most once at a time
+CREATE UNIQUE INDEX disputes_open_payment_id_index ON disputes(payment_id) WHERE status = 'Open';
@@ -0,0 +1,3 @@
+ALTER TABLE refunds DROP CONSTRAINT refunds_payment_id_fkey;
+ALTER TABLE refunds ADD CONSTRAINT refunds_payment_id_fkey
+    FOREIGN KEY (payment_id) REFERENCES payments(id);
@@ -0,0 +1,4 @@
+-- payments with refunds can't be deleted, see `payments::delete`
+ALTER TABLE refunds DROP CONSTRAINT refunds_payment_id_fkey;
+ALTER TABLE refunds ADD CONSTRAINT refunds_payment_id_fkey
+    FOREIGN KEY (payment_id) REFERENCES payments(id) ON DELETE RESTRICT;
@@ -0,0 +1,17 @@
+DROP INDEX refunds_failed_updated_at_idx;
+ALTER TABLE refunds DROP COLUMN deposit_attempts;
+
+-- enum values can't be dropped, so the type is recreated without it, and
+-- abandoned refunds recorded as failed, which don't count against payments
+UPDATE refunds SET status = 'Failed' WHERE status = 'Abandoned';
+
+ALTER TYPE RefundStatus RENAME TO refund_status_with_abandoned;
+
+CREATE TYPE RefundStatus AS ENUM ('Pending', 'Settled', 'Failed', 'Canceled');
+
+ALTER TABLE refunds ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE refunds
+    ALTER COLUMN status TYPE RefundStatus USING status::text::RefundStatus;
+ALTER TABLE refunds ALTER COLUMN status SET DEFAULT 'Pending';
+
+DROP TYPE refund_status_with_abandoned;
@@ -0,0 +1,8 @@
+ALTER TYPE RefundStatus ADD VALUE 'Abandoned';
+
+-- refunds settled or failed so far had their deposit attempted once
+ALTER TABLE refunds ADD COLUMN deposit_attempts integer NOT NULL DEFAULT 0;
+UPDATE refunds SET deposit_attempts = 1 WHERE status IN ('Settled', 'Failed');
+
+-- failed refunds are looked up by the retrier, see `refunds::retries`
+CREATE INDEX refunds_failed_updated_at_idx ON refunds (updated_at) WHERE status = 'Failed';
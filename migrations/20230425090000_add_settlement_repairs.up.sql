@@ -0,0 +1,11 @@
+-- payments whose funds moved but whose final status couldn't be recorded,
+-- which the expiry reaper moves to `status`
+CREATE TABLE settlement_repairs (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    payment_id uuid REFERENCES payments(id) NOT NULL,
+    status Status NOT NULL,
+    inserted_at timestamp not null default clock_timestamp(),
+    repaired_at timestamp
+);
+
+CREATE INDEX settlement_repairs_pending_index ON settlement_repairs(inserted_at) WHERE repaired_at IS NULL;
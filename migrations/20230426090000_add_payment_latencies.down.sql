@@ -0,0 +1,2 @@
+ALTER TABLE payments DROP COLUMN withdraw_latency_ms;
+ALTER TABLE payments DROP COLUMN hold_latency_ms;
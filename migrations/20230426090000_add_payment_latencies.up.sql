@@ -0,0 +1,4 @@
+-- time the account service took to place the hold and withdraw the funds,
+-- only known for the payments that got that far
+ALTER TABLE payments ADD COLUMN hold_latency_ms integer;
+ALTER TABLE payments ADD COLUMN withdraw_latency_ms integer;
@@ -0,0 +1,2 @@
+ALTER TABLE payments DROP CONSTRAINT payments_card_number_length;
+ALTER TABLE payments DROP CONSTRAINT payments_amount_positive;
@@ -0,0 +1,5 @@
+-- mirror the validation of the handlers, like `refunds_amount_positive`, so
+-- writes bypassing them can't break the invariants the API relies on
+ALTER TABLE payments ADD CONSTRAINT payments_amount_positive CHECK (amount > 0);
+-- card numbers are stored masked, see `Card::masked`
+ALTER TABLE payments ADD CONSTRAINT payments_card_number_length CHECK (char_length(card_number) = 15);
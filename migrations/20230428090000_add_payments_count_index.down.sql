@@ -0,0 +1 @@
+DROP INDEX payments_merchant_id_status_source_index;
@@ -0,0 +1,2 @@
+-- covers the filters of merchants' counts, which are always exact
+CREATE INDEX payments_merchant_id_status_source_index ON payments(merchant_id, status, source) WHERE archived_at IS NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE refunds ALTER COLUMN amount TYPE integer;
+ALTER TABLE payments ALTER COLUMN amount TYPE integer;
@@ -0,0 +1,4 @@
+-- amounts are in minor units, which overflow integers above about 21M in
+-- major units
+ALTER TABLE payments ALTER COLUMN amount TYPE bigint;
+ALTER TABLE refunds ALTER COLUMN amount TYPE bigint;
@@ -0,0 +1,15 @@
+-- enum values can't be dropped, so the type is recreated without it, and
+-- repaired payments recorded as failed by the service
+ALTER TYPE DeclineReason RENAME TO decline_reason_with_operator_repair;
+
+CREATE TYPE DeclineReason AS ENUM ('InsufficientFunds', 'InvalidAccountNumber', 'InvalidAmount', 'ServiceFailure');
+
+ALTER TABLE payments
+    ALTER COLUMN decline_reason TYPE DeclineReason USING (
+        CASE decline_reason::text
+            WHEN 'OperatorRepair' THEN 'ServiceFailure'
+            ELSE decline_reason::text
+        END
+    )::DeclineReason;
+
+DROP TYPE decline_reason_with_operator_repair;
@@ -0,0 +1 @@
+ALTER TYPE DeclineReason ADD VALUE 'OperatorRepair';
@@ -0,0 +1,3 @@
+DROP INDEX webhooks_merchant_id_index;
+
+ALTER TABLE webhooks DROP COLUMN merchant_id;
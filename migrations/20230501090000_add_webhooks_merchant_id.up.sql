@@ -0,0 +1,6 @@
+-- Webhooks registered before they belonged to a merchant received the events
+-- of every merchant. Their owner is unknown, so they are left without one and
+-- receive no events until registered again.
+ALTER TABLE webhooks ADD COLUMN merchant_id uuid;
+
+CREATE INDEX webhooks_merchant_id_index ON webhooks (merchant_id);
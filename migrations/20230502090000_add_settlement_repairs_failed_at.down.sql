@@ -0,0 +1,5 @@
+DROP INDEX settlement_repairs_pending_index;
+CREATE INDEX settlement_repairs_pending_index ON settlement_repairs(inserted_at) WHERE repaired_at IS NULL;
+
+ALTER TABLE settlement_repairs DROP COLUMN failure;
+ALTER TABLE settlement_repairs DROP COLUMN failed_at;
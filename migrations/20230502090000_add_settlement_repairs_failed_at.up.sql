@@ -0,0 +1,7 @@
+-- repairs whose payment can't be moved to their status are left unrepaired and
+-- marked failed, for the reconciliation report
+ALTER TABLE settlement_repairs ADD COLUMN failed_at timestamp;
+ALTER TABLE settlement_repairs ADD COLUMN failure text;
+
+DROP INDEX settlement_repairs_pending_index;
+CREATE INDEX settlement_repairs_pending_index ON settlement_repairs(payment_id) WHERE repaired_at IS NULL AND failed_at IS NULL;
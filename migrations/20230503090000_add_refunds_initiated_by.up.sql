@@ -0,0 +1,3 @@
+-- principal which requested the refund, refunds made before principals were
+-- recorded being anonymous
+ALTER TABLE refunds ADD COLUMN initiated_by text NOT NULL DEFAULT 'anonymous';
@@ -0,0 +1,3 @@
+DROP TABLE merchant_digests;
+DROP TABLE merchant_contacts;
+ALTER TABLE webhook_deliveries DROP COLUMN abandoned_at;
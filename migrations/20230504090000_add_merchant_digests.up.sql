@@ -0,0 +1,28 @@
+-- deliveries which ran out of attempts, reported in the digests of their
+-- merchant
+ALTER TABLE webhook_deliveries ADD COLUMN abandoned_at timestamp;
+
+CREATE INDEX webhook_deliveries_abandoned_at_index ON webhook_deliveries(abandoned_at) WHERE abandoned_at IS NOT NULL;
+
+-- address the digests of a merchant are emailed to
+CREATE TABLE merchant_contacts (
+    merchant_id uuid PRIMARY KEY,
+    email text NOT NULL,
+    inserted_at timestamp not null default current_timestamp,
+    updated_at timestamp not null default current_timestamp
+);
+
+-- daily summaries of the problems of a merchant, at most one per day
+CREATE TABLE merchant_digests (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    merchant_id uuid NOT NULL,
+    day date NOT NULL,
+    dead_deliveries bigint NOT NULL,
+    stuck_payments bigint NOT NULL,
+    failed_refunds bigint NOT NULL,
+    inserted_at timestamp not null default current_timestamp,
+    notified_at timestamp,
+    UNIQUE (merchant_id, day)
+);
+
+CREATE INDEX merchant_digests_pending_index ON merchant_digests(inserted_at) WHERE notified_at IS NULL;
@@ -0,0 +1,2 @@
+DROP TABLE exports;
+DROP TYPE ExportStatus;
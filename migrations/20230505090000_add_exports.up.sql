@@ -0,0 +1,18 @@
+CREATE TYPE ExportStatus AS ENUM ('Pending', 'Running', 'Completed', 'Failed');
+
+-- full exports of the data of a merchant, archived by the export worker and
+-- downloadable once
+CREATE TABLE exports (
+    id uuid default uuid_generate_v4() PRIMARY KEY UNIQUE,
+    merchant_id uuid NOT NULL,
+    status ExportStatus NOT NULL default 'Pending',
+    exported_rows bigint NOT NULL default 0,
+    total_rows bigint,
+    error text,
+    inserted_at timestamp not null default current_timestamp,
+    updated_at timestamp not null default current_timestamp,
+    completed_at timestamp,
+    downloaded_at timestamp
+);
+
+CREATE INDEX exports_unfinished_index ON exports(inserted_at) WHERE status IN ('Pending', 'Running');
@@ -0,0 +1,13 @@
+-- Progress of the backfills of `bank::backfill`, resumed from their cursor
+CREATE TABLE backfill_progress (
+    name text PRIMARY KEY,
+    -- (inserted_at, id) of the last row of the last batch
+    cursor_inserted_at timestamp,
+    cursor_id uuid,
+    processed_rows bigint NOT NULL DEFAULT 0,
+    updated_rows bigint NOT NULL DEFAULT 0,
+    batches bigint NOT NULL DEFAULT 0,
+    started_at timestamp NOT NULL DEFAULT LOCALTIMESTAMP,
+    updated_at timestamp NOT NULL DEFAULT LOCALTIMESTAMP,
+    completed_at timestamp
+);
@@ -0,0 +1,2 @@
+DROP TABLE availability_minutes;
+DROP TYPE SloEndpoint;
@@ -0,0 +1,14 @@
+CREATE TYPE SloEndpoint AS ENUM ('Payments', 'Refunds');
+
+-- per-minute outcomes of the payment and refund endpoints, rolled up from the
+-- in-process SLO aggregator and trimmed to 90 days
+CREATE TABLE availability_minutes (
+    endpoint SloEndpoint NOT NULL,
+    minute timestamp NOT NULL,
+    successes bigint NOT NULL,
+    errors bigint NOT NULL,
+    p95_latency_ms integer NOT NULL,
+    PRIMARY KEY (endpoint, minute)
+);
+
+CREATE INDEX availability_minutes_minute_index ON availability_minutes(minute);
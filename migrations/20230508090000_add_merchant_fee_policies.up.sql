@@ -0,0 +1,8 @@
+-- merchants without a policy row are charged the bank's default fee policy
+CREATE TABLE merchant_fee_policies (
+    merchant_id uuid PRIMARY KEY,
+    basis_points integer NOT NULL CHECK (basis_points BETWEEN 0 AND 10000),
+    fixed_amount bigint NOT NULL CHECK (fixed_amount >= 0),
+    inserted_at timestamp not null default current_timestamp,
+    updated_at timestamp not null default current_timestamp
+);
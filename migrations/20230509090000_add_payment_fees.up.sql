@@ -0,0 +1,12 @@
+-- fees are computed once, when the funds of a payment are withdrawn, under
+-- the policy of its merchant then
+CREATE TABLE payment_fees (
+    payment_id uuid PRIMARY KEY REFERENCES payments(id),
+    basis_points integer NOT NULL,
+    fixed_amount bigint NOT NULL,
+    percentage_fee bigint NOT NULL,
+    fixed_fee bigint NOT NULL,
+    total_fee bigint NOT NULL,
+    net_amount bigint NOT NULL,
+    inserted_at timestamp not null default current_timestamp
+);
@@ -0,0 +1,3 @@
+ALTER TABLE exports DROP COLUMN download_lease_expires_at;
+ALTER TABLE exports DROP COLUMN download_lease_id;
+ALTER TABLE exports DROP COLUMN claim_id;
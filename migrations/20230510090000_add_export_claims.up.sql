@@ -0,0 +1,10 @@
+-- the claim of the exporter running an export, which names its archive, so
+-- an exporter taking over a stale export doesn't share the archive of the
+-- previous one; archives written so far are named after their export
+ALTER TABLE exports ADD COLUMN claim_id uuid;
+UPDATE exports SET claim_id = id WHERE status <> 'Pending';
+
+-- the download of an archive in progress, which can be retried once the
+-- lease expired, e.g. as the server stopped while sending it
+ALTER TABLE exports ADD COLUMN download_lease_id uuid;
+ALTER TABLE exports ADD COLUMN download_lease_expires_at timestamp;
@@ -0,0 +1 @@
+ALTER TABLE merchant_digests DROP COLUMN notifying_at;
@@ -0,0 +1,3 @@
+-- when a digester started sending a digest, so the digesters of other
+-- replicas don't send it too
+ALTER TABLE merchant_digests ADD COLUMN notifying_at timestamp;
@@ -1,4 +1,22 @@
 pub mod accounts;
+pub mod availability;
+pub mod backfill;
+pub mod clock;
+pub mod currencies;
+pub mod digests;
+pub mod events;
+pub mod expiry;
+pub mod export;
+pub mod exports;
+pub mod fees;
+pub mod journal;
+pub mod merchants;
+pub mod outbox;
+pub mod pagination;
 pub mod payment_instruments;
 pub mod payments;
 pub mod refunds;
+pub mod settlement;
+pub mod tasks;
+pub mod transactions;
+pub mod webhooks;
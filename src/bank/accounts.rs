@@ -1,3 +1,9 @@
+use std::{
+    num::NonZeroU32,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 /// Represents a hold on a bank customer's funds within their account.
@@ -9,10 +15,21 @@ use uuid::Uuid;
 /// reference contains this information.
 #[derive(Debug, Clone, Copy)]
 pub struct HoldRef {
-    #[allow(dead_code)]
     id: Uuid,
 }
 
+impl HoldRef {
+    /// Returns the hold of `id`, e.g. as recorded by `payments::record_hold`.
+    pub fn from_id(id: Uuid) -> Self {
+        Self { id }
+    }
+
+    /// Returns the id under which the hold is journaled.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
 /// Client to interact with a remote service that manages customer accounts.
 #[async_trait::async_trait]
 pub trait AccountService: Clone + Send + Sync + 'static {
@@ -30,7 +47,7 @@ pub trait AccountService: Clone + Send + Sync + 'static {
     ///
     /// In other words, for every call to `place_hold`, there MUST be a matching
     /// call to either `release_hold` or `withdraw_funds`.
-    async fn place_hold(&self, account_number: &str, amount: i32) -> Result<HoldRef, String>;
+    async fn place_hold(&self, account_number: &str, amount: i64) -> Result<HoldRef, String>;
 
     /// Releases a hold on the account.
     ///
@@ -51,6 +68,25 @@ pub trait AccountService: Clone + Send + Sync + 'static {
     /// This is the mechanism by which money is transferred out from the customer's account and
     /// into the merchant's account during the settlement process.
     async fn withdraw_funds(&self, hold_ref: HoldRef) -> Result<(), String>;
+
+    /// Deposits money on the account.
+    ///
+    /// Increases the `account_number` account's current balance by `amount`.
+    ///
+    /// This is the mechanism by which refunded money is credited back to the
+    /// customer's account. Unlike holds, deposits aren't concluded by any
+    /// other call.
+    async fn deposit_funds(&self, account_number: &str, amount: i64) -> Result<(), String>;
+
+    /// Checks that holds can be placed on the account, without placing one.
+    ///
+    /// Fails with the errors of `place_hold` about the account itself, e.g.
+    /// `invalid_account_number`. Services unable to validate accounts accept
+    /// every account.
+    async fn validate_account(&self, account_number: &str) -> Result<(), String> {
+        let _ = account_number;
+        Ok(())
+    }
 }
 
 /// A naive implementation of the `Bank.Accounts.Service` behavior.
@@ -60,17 +96,116 @@ pub trait AccountService: Clone + Send + Sync + 'static {
 /// For the sake of simplicity, there's no tracking of account balances,
 /// held amounts, etc.: we use "magic" values to trigger unhappy paths
 /// instead.
+///
+/// A `ScriptedOutcome` can also be set, e.g. from the sandbox admin endpoint,
+/// to make calls fail with a specific error.
 #[derive(Clone, Default)]
 pub struct DummyService {
-    #[cfg(test)]
-    pub response: Option<String>,
+    scenario: Scenario,
+}
+
+/// Outcome scripted on a `DummyService`, shared by all its clones.
+pub type Scenario = Arc<RwLock<Option<ScriptedOutcome>>>;
+
+/// Account service method targeted by a `ScriptedOutcome`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountMethod {
+    Hold,
+    Release,
+    Withdraw,
+    Deposit,
+}
+
+/// Error returned by a `DummyService` instead of its usual response.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScriptedOutcome {
+    pub error: String,
+    /// Method whose calls fail, every method if `None`.
+    #[serde(default)]
+    pub method: Option<AccountMethod>,
+    /// Number of calls left to fail before the service recovers, unlimited
+    /// if `None`.
+    #[serde(default)]
+    pub remaining: Option<NonZeroU32>,
+}
+
+// outside of tests, outcomes are scripted through the sandbox endpoint
+#[cfg_attr(not(test), allow(dead_code))]
+impl ScriptedOutcome {
+    /// Fails every call with `error`.
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            method: None,
+            remaining: None,
+        }
+    }
+
+    /// Only fails calls to `method`.
+    pub fn with_method(mut self, method: AccountMethod) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Only fails the next `remaining` targeted calls.
+    pub fn with_remaining(mut self, remaining: NonZeroU32) -> Self {
+        self.remaining = Some(remaining);
+        self
+    }
 }
 
 impl DummyService {
     pub const INVALID_ACCOUNT_NUMBER: &str = "00";
-    pub const MIN_VALID_AMOUNT: i32 = 0;
+    pub const MIN_VALID_AMOUNT: i64 = 0;
     #[allow(clippy::inconsistent_digit_grouping)]
-    pub const MAX_VALID_AMOUNT: i32 = 1_000_000_00;
+    pub const MAX_VALID_AMOUNT: i64 = 1_000_000_00;
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn with_scripted_outcome(self, outcome: ScriptedOutcome) -> Self {
+        self.set_scripted_outcome(Some(outcome));
+        self
+    }
+
+    /// Replaces the scripted outcome, `None` restoring the usual responses.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn set_scripted_outcome(&self, outcome: Option<ScriptedOutcome>) {
+        *self
+            .scenario
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = outcome;
+    }
+
+    /// Returns the handle through which the scripted outcome can be changed
+    /// at runtime.
+    pub fn scenario(&self) -> Scenario {
+        self.scenario.clone()
+    }
+
+    /// Returns the scripted error of a call to `method`, if any, using up
+    /// one of the remaining failures.
+    fn scripted_error(&self, method: AccountMethod) -> Option<String> {
+        let mut scenario = self
+            .scenario
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let outcome = scenario.as_mut()?;
+        if outcome.method.is_some_and(|targeted| targeted != method) {
+            return None;
+        }
+
+        let error = outcome.error.clone();
+        match outcome
+            .remaining
+            .map(|remaining| NonZeroU32::new(remaining.get() - 1))
+        {
+            Some(None) => *scenario = None,
+            Some(remaining) => outcome.remaining = remaining,
+            None => {}
+        }
+
+        Some(error)
+    }
 }
 
 #[async_trait::async_trait]
@@ -82,10 +217,9 @@ impl AccountService for DummyService {
     /// - If the `amount` is greater than `DummyService::MAX_VALID_AMOUNT`, returns `insufficient_funds`.
     ///
     /// Returns `HoldRef` otherwise.
-    async fn place_hold(&self, account_number: &str, amount: i32) -> Result<HoldRef, String> {
-        #[cfg(test)]
-        if let Some(response) = &self.response {
-            return Err(response.into());
+    async fn place_hold(&self, account_number: &str, amount: i64) -> Result<HoldRef, String> {
+        if let Some(error) = self.scripted_error(AccountMethod::Hold) {
+            return Err(error);
         }
 
         if account_number == Self::INVALID_ACCOUNT_NUMBER {
@@ -101,11 +235,122 @@ impl AccountService for DummyService {
 
     async fn release_hold(&self, hold_ref: HoldRef) -> Result<(), String> {
         let _ = hold_ref;
-        Ok(())
+        match self.scripted_error(AccountMethod::Release) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
     }
 
     async fn withdraw_funds(&self, hold_ref: HoldRef) -> Result<(), String> {
         let _ = hold_ref;
-        Ok(())
+        match self.scripted_error(AccountMethod::Withdraw) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Deposits money on the account.
+    ///
+    /// - If the `account_number` is `DummyService::INVALID_ACCOUNT_NUMBER`, returns `invalid_account_number`.
+    /// - If the `amount` is negative or greater than `DummyService::MAX_VALID_AMOUNT`, returns `invalid_amount`.
+    async fn deposit_funds(&self, account_number: &str, amount: i64) -> Result<(), String> {
+        if let Some(error) = self.scripted_error(AccountMethod::Deposit) {
+            return Err(error);
+        }
+
+        if account_number == Self::INVALID_ACCOUNT_NUMBER {
+            Err("invalid_account_number".into())
+        } else if !(Self::MIN_VALID_AMOUNT..=Self::MAX_VALID_AMOUNT).contains(&amount) {
+            Err("invalid_amount".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns `invalid_account_number` for
+    /// `DummyService::INVALID_ACCOUNT_NUMBER`.
+    async fn validate_account(&self, account_number: &str) -> Result<(), String> {
+        if account_number == Self::INVALID_ACCOUNT_NUMBER {
+            Err("invalid_account_number".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn hold_ref() -> HoldRef {
+        HoldRef { id: Uuid::new_v4() }
+    }
+
+    #[tokio::test]
+    async fn test_counted_failures_then_recovery() {
+        let service = DummyService::default().with_scripted_outcome(
+            ScriptedOutcome::new("insufficient_funds").with_remaining(NonZeroU32::new(2).unwrap()),
+        );
+
+        for _ in 0..2 {
+            let result = service.place_hold("12345", 1).await;
+            assert_eq!(result.unwrap_err(), "insufficient_funds");
+        }
+        assert!(service.place_hold("12345", 1).await.is_ok());
+        assert!(service.scenario().read().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scripted_outcome_targets_method() {
+        let service = DummyService::default().with_scripted_outcome(
+            ScriptedOutcome::new("account_locked")
+                .with_method(AccountMethod::Withdraw)
+                .with_remaining(NonZeroU32::new(1).unwrap()),
+        );
+
+        // calls to other methods neither fail nor use up the failure
+        assert!(service.place_hold("12345", 1).await.is_ok());
+        assert!(service.release_hold(hold_ref()).await.is_ok());
+
+        let result = service.withdraw_funds(hold_ref()).await;
+        assert_eq!(result.unwrap_err(), "account_locked");
+        assert!(service.withdraw_funds(hold_ref()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_deposit_magic_values() {
+        let service = DummyService::default();
+
+        assert!(service.deposit_funds("12345", 1).await.is_ok());
+        assert_eq!(
+            service
+                .deposit_funds(DummyService::INVALID_ACCOUNT_NUMBER, 1)
+                .await
+                .unwrap_err(),
+            "invalid_account_number"
+        );
+        assert_eq!(
+            service.deposit_funds("12345", -1).await.unwrap_err(),
+            "invalid_amount"
+        );
+        assert_eq!(
+            service
+                .deposit_funds("12345", DummyService::MAX_VALID_AMOUNT + 1)
+                .await
+                .unwrap_err(),
+            "invalid_amount"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scripted_outcome_shared_by_clones() {
+        let service = DummyService::default();
+        let clone = service.clone();
+
+        service.set_scripted_outcome(Some(ScriptedOutcome::new("insufficient_funds")));
+        assert!(clone.place_hold("12345", 1).await.is_err());
+
+        service.set_scripted_outcome(None);
+        assert!(clone.place_hold("12345", 1).await.is_ok());
     }
 }
@@ -0,0 +1,410 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+use super::clock::Clock;
+
+/// Endpoints whose availability is tracked.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "SloEndpoint")]
+pub enum Endpoint {
+    /// Every payment endpoint but the refund ones.
+    Payments,
+    Refunds,
+}
+
+impl Endpoint {
+    pub const ALL: [Endpoint; 2] = [Endpoint::Payments, Endpoint::Refunds];
+}
+
+/// Outcomes of an endpoint during a minute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Minute {
+    pub endpoint: Endpoint,
+    /// Start of the minute, in UTC.
+    pub minute: PrimitiveDateTime,
+    pub successes: i64,
+    /// Requests answered with a server error.
+    pub errors: i64,
+    pub p95_latency_ms: i32,
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    successes: i64,
+    errors: i64,
+    latencies_ms: Vec<u32>,
+}
+
+/// In-process tally of the outcomes of the tracked endpoints, per minute,
+/// rolled up into `availability_minutes` by `RollUp`.
+#[derive(Debug, Default)]
+pub struct SloAggregator {
+    /// Keyed by the Unix minute first, so completed minutes are split off.
+    tallies: Mutex<BTreeMap<(i64, Endpoint), Tally>>,
+}
+
+impl SloAggregator {
+    /// Records a request to `endpoint` answered at `at`.
+    pub fn record(&self, endpoint: Endpoint, success: bool, latency: Duration, at: OffsetDateTime) {
+        let mut tallies = self.tallies.lock().unwrap();
+        let tally = tallies.entry((unix_minute(at), endpoint)).or_default();
+        if success {
+            tally.successes += 1;
+        } else {
+            tally.errors += 1;
+        }
+        tally
+            .latencies_ms
+            .push(latency.as_millis().try_into().unwrap_or(u32::MAX));
+    }
+
+    /// Takes the tallies of the minutes before the one of `now`, which
+    /// requests can't be recorded in anymore.
+    pub fn drain_completed(&self, now: OffsetDateTime) -> Vec<Minute> {
+        let completed = {
+            let mut tallies = self.tallies.lock().unwrap();
+            let current = tallies.split_off(&(unix_minute(now), Endpoint::Payments));
+            std::mem::replace(&mut *tallies, current)
+        };
+
+        completed
+            .into_iter()
+            .map(|((minute, endpoint), mut tally)| Minute {
+                endpoint,
+                minute: minute_start(minute),
+                successes: tally.successes,
+                errors: tally.errors,
+                p95_latency_ms: p95(&mut tally.latencies_ms).try_into().unwrap_or(i32::MAX),
+            })
+            .collect()
+    }
+}
+
+fn unix_minute(at: OffsetDateTime) -> i64 {
+    at.unix_timestamp().div_euclid(60)
+}
+
+fn minute_start(unix_minute: i64) -> PrimitiveDateTime {
+    let at = OffsetDateTime::from_unix_timestamp(unix_minute * 60)
+        .expect("minute out of range")
+        .to_offset(UtcOffset::UTC);
+    PrimitiveDateTime::new(at.date(), at.time())
+}
+
+/// Returns the nearest-rank 95th percentile of `latencies_ms`, 0 if empty.
+fn p95(latencies_ms: &mut [u32]) -> u32 {
+    if latencies_ms.is_empty() {
+        return 0;
+    }
+    latencies_ms.sort_unstable();
+    let rank = (latencies_ms.len() * 95).div_ceil(100);
+    latencies_ms[rank.saturating_sub(1)]
+}
+
+/// Adds `minutes` to the stored ones, as several instances roll up the same
+/// minutes.
+///
+/// The stored p95 is the highest of the instances, an upper bound of the
+/// actual one.
+pub async fn store(pool: &PgPool, minutes: &[Minute]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for minute in minutes {
+        sqlx::query!(
+            r#"
+                INSERT INTO availability_minutes ( endpoint, minute, successes, errors, p95_latency_ms )
+                VALUES ( $1, $2, $3, $4, $5 )
+                ON CONFLICT ( endpoint, minute ) DO UPDATE SET
+                    successes = availability_minutes.successes + EXCLUDED.successes,
+                    errors = availability_minutes.errors + EXCLUDED.errors,
+                    p95_latency_ms = GREATEST(availability_minutes.p95_latency_ms, EXCLUDED.p95_latency_ms)
+            "#,
+            minute.endpoint as Endpoint,
+            minute.minute,
+            minute.successes,
+            minute.errors,
+            minute.p95_latency_ms
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// Returns the stored minutes in `[from, to)`, by endpoint and minute.
+pub async fn list(
+    pool: &PgPool,
+    from: PrimitiveDateTime,
+    to: PrimitiveDateTime,
+) -> Result<Vec<Minute>, sqlx::Error> {
+    sqlx::query_as!(
+        Minute,
+        r#"
+            SELECT endpoint as "endpoint: _", minute, successes, errors, p95_latency_ms
+            FROM availability_minutes
+            WHERE minute >= $1 AND minute < $2
+            ORDER BY endpoint, minute
+        "#,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Deletes the minutes before `before`, returning how many were.
+pub async fn trim(pool: &PgPool, before: PrimitiveDateTime) -> Result<u64, sqlx::Error> {
+    sqlx::query!("DELETE FROM availability_minutes WHERE minute < $1", before)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected())
+}
+
+/// Tells whether an endpoint spends its error budget too fast, the short
+/// window telling it still does and the long one that it isn't a blip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurnRateEvaluator {
+    /// Share of requests which should succeed.
+    pub objective: f64,
+    pub short_window: Duration,
+    pub long_window: Duration,
+    /// Burn rate above which both windows are degraded, 14.4 spending 2% of
+    /// a 30 days budget in an hour.
+    pub threshold: f64,
+}
+
+impl Default for BurnRateEvaluator {
+    fn default() -> Self {
+        Self {
+            objective: 0.999,
+            short_window: Duration::from_secs(5 * 60),
+            long_window: Duration::from_secs(60 * 60),
+            threshold: 14.4,
+        }
+    }
+}
+
+impl BurnRateEvaluator {
+    /// Returns how many times faster than the objective allows the errors
+    /// were made in the `window` before `now`, 0 without requests.
+    pub fn burn_rate(&self, minutes: &[Minute], now: PrimitiveDateTime, window: Duration) -> f64 {
+        let (errors, requests) = minutes
+            .iter()
+            .filter(|minute| minute.minute >= now - window && minute.minute < now)
+            .fold((0, 0), |(errors, requests), minute| {
+                (
+                    errors + minute.errors,
+                    requests + minute.successes + minute.errors,
+                )
+            });
+        if requests == 0 {
+            return 0.0;
+        }
+
+        (errors as f64 / requests as f64) / (1.0 - self.objective)
+    }
+
+    /// Whether the endpoint of `minutes` is degraded at `now`.
+    pub fn is_degraded(&self, minutes: &[Minute], now: PrimitiveDateTime) -> bool {
+        self.burn_rate(minutes, now, self.short_window) > self.threshold
+            && self.burn_rate(minutes, now, self.long_window) > self.threshold
+    }
+}
+
+/// Background task storing the completed minutes of an `SloAggregator`, and
+/// trimming the stored ones to the retention.
+pub struct RollUp {
+    pool: PgPool,
+    aggregator: Arc<SloAggregator>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    retention: Duration,
+}
+
+impl RollUp {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+    /// 90 days, about a quarter of history to answer merchants with.
+    pub const DEFAULT_RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+    pub fn new(pool: PgPool, aggregator: Arc<SloAggregator>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            pool,
+            aggregator,
+            clock,
+            interval: Self::DEFAULT_INTERVAL,
+            retention: Self::DEFAULT_RETENTION,
+        }
+    }
+
+    /// Sets how often completed minutes are stored.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how long minutes are kept.
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Stores the completed minutes, returning how many were.
+    ///
+    /// Minutes which can't be stored are dropped, leaving a gap in the
+    /// series rather than growing the aggregator.
+    pub async fn run_once(&self) -> Result<usize, sqlx::Error> {
+        let minutes = self.aggregator.drain_completed(self.clock.now());
+        store(&self.pool, &minutes).await?;
+        Ok(minutes.len())
+    }
+
+    /// Stores the completed minutes and trims the old ones every interval
+    /// until the task is aborted.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.run_once().await {
+                tracing::error!("failed to roll up availability: {e}");
+            }
+
+            let now = self.clock.now().to_offset(UtcOffset::UTC);
+            let before = PrimitiveDateTime::new(now.date(), now.time()) - self.retention;
+            match trim(&self.pool, before).await {
+                Ok(0) => {}
+                Ok(trimmed) => tracing::info!("trimmed {trimmed} availability minutes"),
+                Err(e) => tracing::error!("failed to trim availability: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn minute(endpoint: Endpoint, minute: i64, successes: i64, errors: i64) -> Minute {
+        Minute {
+            endpoint,
+            minute: minute_start(minute),
+            successes,
+            errors,
+            p95_latency_ms: 0,
+        }
+    }
+
+    #[test]
+    fn test_drain_completed_minutes_only() {
+        let aggregator = SloAggregator::default();
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_040).unwrap();
+        let next_minute = start + Duration::from_secs(60);
+        for latency_ms in 1..=20 {
+            aggregator.record(
+                Endpoint::Payments,
+                latency_ms != 20,
+                Duration::from_millis(latency_ms),
+                start,
+            );
+        }
+        aggregator.record(Endpoint::Refunds, true, Duration::from_millis(5), start);
+        aggregator.record(
+            Endpoint::Payments,
+            true,
+            Duration::from_millis(5),
+            next_minute,
+        );
+
+        let minutes = aggregator.drain_completed(next_minute);
+        assert_eq!(
+            minutes,
+            [
+                Minute {
+                    endpoint: Endpoint::Payments,
+                    minute: minute_start(unix_minute(start)),
+                    successes: 19,
+                    errors: 1,
+                    p95_latency_ms: 19,
+                },
+                Minute {
+                    endpoint: Endpoint::Refunds,
+                    minute: minute_start(unix_minute(start)),
+                    successes: 1,
+                    errors: 0,
+                    p95_latency_ms: 5,
+                },
+            ]
+        );
+        assert_eq!(aggregator.drain_completed(next_minute), []);
+        assert_eq!(
+            aggregator
+                .drain_completed(next_minute + Duration::from_secs(60))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_degraded_while_both_windows_burn() {
+        let evaluator = BurnRateEvaluator::default();
+        // an hour of traffic, the last 10 minutes of which failing half the
+        // requests
+        let minutes: Vec<_> = (0..60)
+            .map(|i| {
+                let errors = if i >= 50 { 5 } else { 0 };
+                minute(Endpoint::Payments, i, 10 - errors, errors)
+            })
+            .collect();
+
+        assert!(evaluator.is_degraded(&minutes, minute_start(60)));
+        // the errors stopped 5 minutes ago
+        assert!(!evaluator.is_degraded(&minutes[..55], minute_start(60)));
+        // a blip doesn't burn the long window
+        let blip: Vec<_> = (0..60)
+            .map(|i| {
+                let errors = if i == 59 { 1 } else { 0 };
+                minute(Endpoint::Payments, i, 1000 - errors, errors)
+            })
+            .collect();
+        assert!(evaluator.burn_rate(&blip, minute_start(60), evaluator.short_window) > 0.0);
+        assert!(!evaluator.is_degraded(&blip, minute_start(60)));
+        assert!(!evaluator.is_degraded(&[], minute_start(60)));
+    }
+
+    #[tokio::test]
+    async fn test_trim_minutes_past_retention() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        // a window of 1970 no other test stores minutes in, cleared of the
+        // minutes of earlier runs
+        trim(&pool, minute_start(3 * 24 * 60))
+            .await
+            .expect("failed to trim minutes");
+        let old = minute(Endpoint::Refunds, 0, 1, 0);
+        let kept = minute(Endpoint::Refunds, 2 * 24 * 60, 1, 0);
+        store(&pool, &[old.clone(), kept.clone()])
+            .await
+            .expect("failed to store minutes");
+
+        trim(&pool, minute_start(24 * 60))
+            .await
+            .expect("failed to trim minutes");
+
+        let minutes = list(&pool, old.minute, minute_start(3 * 24 * 60))
+            .await
+            .expect("failed to list minutes");
+        assert_eq!(minutes, [kept]);
+    }
+}
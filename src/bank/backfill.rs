@@ -0,0 +1,533 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::{PgPool, Postgres};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use super::transactions;
+
+/// Position of a backfill: the `(inserted_at, id)` of the last row of its
+/// last batch, rows being walked in that order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub inserted_at: PrimitiveDateTime,
+    pub id: Uuid,
+}
+
+/// Outcome of a batch of a backfill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batch {
+    /// Cursor of the last row of the batch, `None` once there are no more
+    /// rows to walk.
+    pub last: Option<Cursor>,
+    /// Rows walked by the batch.
+    pub processed_rows: i64,
+    /// Rows the batch had to update, rows already up to date being skipped.
+    pub updated_rows: i64,
+}
+
+/// A data migration run in batches outside of the sqlx migrations, so tables
+/// aren't locked while rewritten.
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// Name the job is run and its progress is recorded with.
+    fn name(&self) -> &str;
+
+    /// Updates the next `batch_size` rows after `after`, from the first row
+    /// if `None`.
+    ///
+    /// The batch is run in the transaction recording the progress, so a
+    /// batch is either recorded or not run at all.
+    async fn run_batch(
+        &self,
+        tx: &mut sqlx::Transaction<'static, Postgres>,
+        after: Option<Cursor>,
+        batch_size: i64,
+    ) -> Result<Batch, sqlx::Error>;
+}
+
+/// Returns the backfills `bankctl backfill run` knows about.
+pub fn registry() -> Vec<Box<dyn Job>> {
+    vec![Box::new(CardFingerprint), Box::new(AccountPrefix)]
+}
+
+/// Returns the registered backfill named `name`.
+pub fn find(name: &str) -> Option<Box<dyn Job>> {
+    registry().into_iter().find(|job| job.name() == name)
+}
+
+/// Fingerprints the card numbers of the payments stored before
+/// `Card::fingerprint`, see `payments_card_fingerprint_inserted_at_index`.
+pub struct CardFingerprint;
+
+#[async_trait]
+impl Job for CardFingerprint {
+    fn name(&self) -> &str {
+        "card_fingerprint"
+    }
+
+    async fn run_batch(
+        &self,
+        tx: &mut sqlx::Transaction<'static, Postgres>,
+        after: Option<Cursor>,
+        batch_size: i64,
+    ) -> Result<Batch, sqlx::Error> {
+        let rows = next_payments(tx, after, batch_size).await?;
+        let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+
+        // masked numbers can't be fingerprinted, they are by `AccountPrefix`
+        let updated = sqlx::query!(
+            r#"
+                UPDATE payments
+                SET card_fingerprint = encode(sha256(convert_to(card_number, 'UTF8')), 'hex')
+                WHERE id = ANY($1) AND card_fingerprint IS NULL AND strpos(card_number, '*') = 0
+            "#,
+            &ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(Batch {
+            last: rows.last().copied(),
+            processed_rows: rows.len() as i64,
+            updated_rows: updated.rows_affected() as i64,
+        })
+    }
+}
+
+/// Masks the card numbers of the payments stored before `Card::masked`, down
+/// to their account prefix and last four digits.
+///
+/// Numbers are fingerprinted first if need be, so it doesn't depend on
+/// `CardFingerprint` having run.
+pub struct AccountPrefix;
+
+#[async_trait]
+impl Job for AccountPrefix {
+    fn name(&self) -> &str {
+        "account_prefix"
+    }
+
+    async fn run_batch(
+        &self,
+        tx: &mut sqlx::Transaction<'static, Postgres>,
+        after: Option<Cursor>,
+        batch_size: i64,
+    ) -> Result<Batch, sqlx::Error> {
+        let rows = next_payments(tx, after, batch_size).await?;
+        let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+
+        let updated = sqlx::query!(
+            r#"
+                UPDATE payments SET
+                    card_fingerprint = coalesce(
+                        card_fingerprint,
+                        encode(sha256(convert_to(card_number, 'UTF8')), 'hex')
+                    ),
+                    card_number = left(card_number, 2)
+                        || repeat('*', length(card_number) - 6)
+                        || right(card_number, 4)
+                WHERE id = ANY($1) AND strpos(card_number, '*') = 0
+            "#,
+            &ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(Batch {
+            last: rows.last().copied(),
+            processed_rows: rows.len() as i64,
+            updated_rows: updated.rows_affected() as i64,
+        })
+    }
+}
+
+/// Returns the cursors of the next `batch_size` payments after `after`.
+async fn next_payments(
+    tx: &mut sqlx::Transaction<'static, Postgres>,
+    after: Option<Cursor>,
+    batch_size: i64,
+) -> Result<Vec<Cursor>, sqlx::Error> {
+    sqlx::query_as!(
+        Cursor,
+        r#"
+            SELECT inserted_at, id FROM payments
+            WHERE $1::timestamp IS NULL OR (inserted_at, id) > ($1, $2::uuid)
+            ORDER BY inserted_at, id
+            LIMIT $3
+        "#,
+        after.map(|cursor| cursor.inserted_at),
+        after.map(|cursor| cursor.id),
+        batch_size
+    )
+    .fetch_all(&mut *tx)
+    .await
+}
+
+/// Recorded progress of a backfill.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub name: String,
+    pub cursor_inserted_at: Option<PrimitiveDateTime>,
+    pub cursor_id: Option<Uuid>,
+    pub processed_rows: i64,
+    pub updated_rows: i64,
+    pub batches: i64,
+    pub started_at: PrimitiveDateTime,
+    pub updated_at: PrimitiveDateTime,
+    /// When the last batch found no more rows, `None` while running or if
+    /// rows were found since.
+    pub completed_at: Option<PrimitiveDateTime>,
+}
+
+impl Progress {
+    fn cursor(&self) -> Option<Cursor> {
+        Some(Cursor {
+            inserted_at: self.cursor_inserted_at?,
+            id: self.cursor_id?,
+        })
+    }
+}
+
+/// Returns the recorded progress of every backfill that was run.
+pub async fn list_progress(pool: &PgPool) -> Result<Vec<Progress>, sqlx::Error> {
+    sqlx::query_as!(
+        Progress,
+        r#"
+            SELECT name, cursor_inserted_at, cursor_id, processed_rows, updated_rows, batches,
+                   started_at, updated_at, completed_at
+            FROM backfill_progress
+            ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the recorded progress of the backfill `name`, unless it never ran.
+pub async fn get_progress(pool: &PgPool, name: &str) -> Result<Option<Progress>, sqlx::Error> {
+    sqlx::query_as!(
+        Progress,
+        r#"
+            SELECT name, cursor_inserted_at, cursor_id, processed_rows, updated_rows, batches,
+                   started_at, updated_at, completed_at
+            FROM backfill_progress
+            WHERE name = $1
+        "#,
+        name
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Runs backfills batch by batch from their recorded cursor, so a backfill
+/// killed midway resumes where it stopped.
+pub struct Backfiller {
+    pool: PgPool,
+    batch_size: i64,
+    throttle: Duration,
+}
+
+impl Backfiller {
+    pub const DEFAULT_BATCH_SIZE: i64 = 1000;
+    /// Pause between two batches, leaving room to the requests.
+    pub const DEFAULT_THROTTLE: Duration = Duration::from_millis(100);
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+            throttle: Self::DEFAULT_THROTTLE,
+        }
+    }
+
+    /// Sets the number of rows walked by a batch.
+    pub fn with_batch_size(mut self, batch_size: i64) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Sets the pause between two batches.
+    pub fn with_throttle(mut self, throttle: Duration) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Runs the batches of `job` until there are no more rows to walk,
+    /// returning its progress.
+    pub async fn run(&self, job: &dyn Job) -> Result<Progress, sqlx::Error> {
+        loop {
+            let progress = self.run_batch(job).await?;
+            if progress.completed_at.is_some() {
+                return Ok(progress);
+            }
+
+            tracing::info!(
+                backfill.name = job.name(),
+                "backfilled {} rows in {} batches",
+                progress.processed_rows,
+                progress.batches
+            );
+            tokio::time::sleep(self.throttle).await;
+        }
+    }
+
+    /// Runs the next batch of `job`, returning its progress.
+    ///
+    /// The progress is locked while the batch runs, so concurrent runs of a
+    /// job take turns rather than walking the same rows.
+    pub async fn run_batch(&self, job: &dyn Job) -> Result<Progress, sqlx::Error> {
+        let mut tx = transactions::begin(&self.pool).await?;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO backfill_progress ( name ) VALUES ( $1 )
+                ON CONFLICT DO NOTHING
+            "#,
+            job.name()
+        )
+        .execute(&mut *tx)
+        .await?;
+        let progress = sqlx::query_as!(
+            Progress,
+            r#"
+                SELECT name, cursor_inserted_at, cursor_id, processed_rows, updated_rows, batches,
+                       started_at, updated_at, completed_at
+                FROM backfill_progress
+                WHERE name = $1
+                FOR UPDATE
+            "#,
+            job.name()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let batch = job
+            .run_batch(&mut tx, progress.cursor(), self.batch_size)
+            .await?;
+
+        let progress = match batch.last {
+            Some(last) => {
+                sqlx::query_as!(
+                    Progress,
+                    r#"
+                        UPDATE backfill_progress SET
+                            cursor_inserted_at = $2,
+                            cursor_id = $3,
+                            processed_rows = processed_rows + $4,
+                            updated_rows = updated_rows + $5,
+                            batches = batches + 1,
+                            updated_at = LOCALTIMESTAMP,
+                            completed_at = NULL
+                        WHERE name = $1
+                        RETURNING name, cursor_inserted_at, cursor_id, processed_rows, updated_rows,
+                                  batches, started_at, updated_at, completed_at
+                    "#,
+                    job.name(),
+                    last.inserted_at,
+                    last.id,
+                    batch.processed_rows,
+                    batch.updated_rows
+                )
+                .fetch_one(&mut *tx)
+                .await?
+            }
+            None => {
+                sqlx::query_as!(
+                    Progress,
+                    r#"
+                        UPDATE backfill_progress SET
+                            updated_at = LOCALTIMESTAMP,
+                            completed_at = coalesce(completed_at, LOCALTIMESTAMP)
+                        WHERE name = $1
+                        RETURNING name, cursor_inserted_at, cursor_id, processed_rows, updated_rows,
+                                  batches, started_at, updated_at, completed_at
+                    "#,
+                    job.name()
+                )
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        tx.commit().await?;
+
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::bank::payment_instruments::Card;
+
+    /// Counts in the metadata of the payments of a merchant how many times
+    /// each was backfilled.
+    struct CountingJob {
+        name: String,
+        merchant_id: Uuid,
+    }
+
+    #[async_trait]
+    impl Job for CountingJob {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn run_batch(
+            &self,
+            tx: &mut sqlx::Transaction<'static, Postgres>,
+            after: Option<Cursor>,
+            batch_size: i64,
+        ) -> Result<Batch, sqlx::Error> {
+            let rows = sqlx::query_as!(
+                Cursor,
+                r#"
+                    SELECT inserted_at, id FROM payments
+                    WHERE merchant_id = $1
+                      AND ($2::timestamp IS NULL OR (inserted_at, id) > ($2, $3::uuid))
+                    ORDER BY inserted_at, id
+                    LIMIT $4
+                "#,
+                self.merchant_id,
+                after.map(|cursor| cursor.inserted_at),
+                after.map(|cursor| cursor.id),
+                batch_size
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+
+            let updated = sqlx::query!(
+                r#"
+                    UPDATE payments
+                    SET metadata = jsonb_build_object(
+                        'backfilled', coalesce((metadata->>'backfilled')::int, 0) + 1
+                    )
+                    WHERE id = ANY($1)
+                "#,
+                &ids
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            Ok(Batch {
+                last: rows.last().copied(),
+                processed_rows: rows.len() as i64,
+                updated_rows: updated.rows_affected() as i64,
+            })
+        }
+    }
+
+    /// Inserts `count` payments of a merchant stored before their card
+    /// number was masked and fingerprinted, returning their card numbers.
+    async fn seed_unmasked(pool: &PgPool, merchant_id: Uuid, count: usize) -> Vec<Card> {
+        let mut cards = Vec::new();
+        for _ in 0..count {
+            let card = Card::new_test();
+            sqlx::query!(
+                r#"
+                    INSERT INTO payments ( amount, card_number, status, merchant_id )
+                    VALUES ( 100, $1, 'Approved', $2 )
+                "#,
+                card.card_number(),
+                merchant_id
+            )
+            .execute(pool)
+            .await
+            .expect("failed to seed payment");
+            cards.push(card);
+        }
+        cards
+    }
+
+    #[tokio::test]
+    async fn test_resumed_backfill_processes_every_row_once() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let merchant_id = Uuid::new_v4();
+        seed_unmasked(&pool, merchant_id, 9).await;
+        let job = Arc::new(CountingJob {
+            name: format!("counting_{merchant_id}"),
+            merchant_id,
+        });
+        let backfiller = Arc::new(
+            Backfiller::new(pool.clone())
+                .with_batch_size(2)
+                .with_throttle(Duration::from_millis(50)),
+        );
+
+        // killed after a couple of batches
+        let run = tokio::spawn({
+            let (backfiller, job) = (backfiller.clone(), job.clone());
+            async move { backfiller.run(job.as_ref()).await }
+        });
+        loop {
+            let progress = get_progress(&pool, job.name())
+                .await
+                .expect("failed to get progress");
+            if progress.is_some_and(|progress| progress.batches >= 2) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        run.abort();
+        let _ = run.await;
+
+        let progress = get_progress(&pool, job.name())
+            .await
+            .expect("failed to get progress")
+            .expect("missing progress");
+        assert!(progress.processed_rows < 9, "{progress:?}");
+        assert_eq!(progress.completed_at, None);
+
+        let progress = backfiller
+            .run(job.as_ref())
+            .await
+            .expect("failed to resume backfill");
+        assert_eq!((progress.processed_rows, progress.updated_rows), (9, 9));
+        assert_eq!(progress.batches, 5);
+        assert!(progress.completed_at.is_some());
+
+        let counts: Vec<Option<i32>> = sqlx::query_scalar(
+            "SELECT (metadata->>'backfilled')::int FROM payments WHERE merchant_id = $1",
+        )
+        .bind(merchant_id)
+        .fetch_all(&pool)
+        .await
+        .expect("failed to get counts");
+        assert_eq!(counts, vec![Some(1); 9]);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_card_fingerprints_and_masks() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let merchant_id = Uuid::new_v4();
+        let cards = seed_unmasked(&pool, merchant_id, 3).await;
+        let backfiller = Backfiller::new(pool.clone()).with_throttle(Duration::ZERO);
+
+        for name in ["card_fingerprint", "account_prefix"] {
+            let job = find(name).expect("unregistered backfill");
+            let progress = backfiller.run(job.as_ref()).await.expect("failed backfill");
+            assert!(progress.completed_at.is_some());
+        }
+
+        for card in cards {
+            let card_number: String = sqlx::query_scalar(
+                "SELECT card_number FROM payments WHERE merchant_id = $1 AND card_fingerprint = $2",
+            )
+            .bind(merchant_id)
+            .bind(card.fingerprint())
+            .fetch_one(&pool)
+            .await
+            .expect("failed to get payment");
+            assert_eq!(card_number, card.masked());
+        }
+    }
+}
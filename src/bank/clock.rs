@@ -0,0 +1,44 @@
+use time::OffsetDateTime;
+
+/// Source of the current time for time-dependent business rules, so tests can
+/// move it instead of waiting.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+/// Clock reading the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Clock only moving when told to.
+    #[derive(Debug)]
+    pub struct ManualClock(Mutex<OffsetDateTime>);
+
+    impl ManualClock {
+        pub fn new(now: OffsetDateTime) -> Self {
+            Self(Mutex::new(now))
+        }
+
+        pub fn set(&self, now: OffsetDateTime) {
+            *self.0.lock().unwrap() = now;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> OffsetDateTime {
+            *self.0.lock().unwrap()
+        }
+    }
+}
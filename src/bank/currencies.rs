@@ -0,0 +1,109 @@
+use std::fmt::Display;
+
+const CURRENCY_CODE_LENGTH: usize = 3;
+
+/// Active ISO 4217 currency codes, sorted alphabetically.
+const ISO_4217_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD",
+    "CDF", "CHF", "CLP", "CNY", "COP", "CRC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD",
+    "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ",
+    "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD", "JOD",
+    "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR",
+    "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR",
+    "MWK", "MXN", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN",
+    "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR",
+    "SDG", "SEK", "SGD", "SHP", "SLE", "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB",
+    "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "UYU", "UZS",
+    "VES", "VND", "VUV", "WST", "XAF", "XCD", "XOF", "XPF", "YER", "ZAR", "ZMW", "ZWL",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CurrencyError {
+    InvalidLength,
+    UnknownCode,
+}
+
+impl Display for CurrencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Represents the ISO 4217 currency a payment amount is expressed in.
+///
+/// Amounts are always stored in the minor unit of their currency (e.g. cents
+/// for "USD").
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Currency(pub String);
+
+impl Currency {
+    /// Currency assumed for requests that don't specify one.
+    pub const DEFAULT: &str = "USD";
+
+    /// Returns the three-letter code of this currency.
+    pub fn code(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_string())
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = CurrencyError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        if code.len() != CURRENCY_CODE_LENGTH {
+            Err(CurrencyError::InvalidLength)
+        } else if ISO_4217_CODES.binary_search(&code.as_str()).is_err() {
+            Err(CurrencyError::UnknownCode)
+        } else {
+            Ok(Self(code))
+        }
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.0
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn should_accept_known_codes() {
+        assert_eq!(
+            Currency::try_from("EUR".to_string()),
+            Ok(Currency("EUR".to_string()))
+        );
+        assert_eq!(Currency::default().code(), "USD");
+    }
+
+    #[test]
+    fn should_reject_unknown_codes() {
+        assert_eq!(
+            Currency::try_from("US".to_string()),
+            Err(CurrencyError::InvalidLength)
+        );
+        assert_eq!(
+            Currency::try_from("usd".to_string()),
+            Err(CurrencyError::UnknownCode)
+        );
+        assert_eq!(
+            Currency::try_from("XYZ".to_string()),
+            Err(CurrencyError::UnknownCode)
+        );
+    }
+
+    #[test]
+    fn should_keep_codes_sorted() {
+        assert!(ISO_4217_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
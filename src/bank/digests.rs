@@ -0,0 +1,679 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::{
+    client::HttpConnector,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Body, Client, Method, Request, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::Serialize;
+use sqlx::{postgres::types::PgInterval, PgPool};
+use time::{Date, PrimitiveDateTime};
+use uuid::Uuid;
+
+use super::{merchants, payments::Status as PaymentStatus, refunds::Status as RefundStatus};
+
+/// Daily summary of the problems of a merchant, so broken webhook endpoints
+/// are noticed before reconciliation day.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Digest {
+    pub id: Uuid,
+    pub merchant_id: Uuid,
+    pub day: Date,
+    /// Webhook deliveries which ran out of attempts during the last day.
+    pub dead_deliveries: i64,
+    /// Payments left processing or authorized for longer than the threshold
+    /// of the `Digester`.
+    pub stuck_payments: i64,
+    /// Refunds whose deposit failed, waiting to be retried.
+    pub failed_refunds: i64,
+    pub inserted_at: PrimitiveDateTime,
+    pub notified_at: Option<PrimitiveDateTime>,
+}
+
+/// Records today's digest of every merchant with something to report, unless
+/// it already has one, returning the number of digests recorded.
+///
+/// Payments are stuck once they didn't change for `stuck_after`.
+pub async fn record(pool: &PgPool, stuck_after: Duration) -> Result<u64, sqlx::Error> {
+    let stuck_after = PgInterval::try_from(stuck_after).map_err(sqlx::Error::Configuration)?;
+
+    sqlx::query!(
+        r#"
+            WITH dead AS (
+                SELECT w.merchant_id, count(*) AS count
+                FROM webhook_deliveries d
+                JOIN webhooks w ON w.id = d.webhook_id
+                WHERE d.abandoned_at > LOCALTIMESTAMP - interval '1 day'
+                  AND w.merchant_id IS NOT NULL
+                GROUP BY w.merchant_id
+            ), stuck AS (
+                SELECT merchant_id, count(*) AS count
+                FROM payments
+                WHERE status IN ($2, $3) AND updated_at < LOCALTIMESTAMP - $1::interval
+                GROUP BY merchant_id
+            ), failed AS (
+                SELECT merchant_id, count(*) AS count
+                FROM refunds
+                WHERE status = $4
+                GROUP BY merchant_id
+            )
+            INSERT INTO merchant_digests ( merchant_id, day, dead_deliveries, stuck_payments, failed_refunds )
+            SELECT merchant_id, LOCALTIMESTAMP::date,
+                   coalesce(dead.count, 0), coalesce(stuck.count, 0), coalesce(failed.count, 0)
+            FROM dead
+            FULL JOIN stuck USING (merchant_id)
+            FULL JOIN failed USING (merchant_id)
+            ON CONFLICT ( merchant_id, day ) DO NOTHING
+        "#,
+        stuck_after,
+        PaymentStatus::Processing as PaymentStatus,
+        PaymentStatus::Authorized as PaymentStatus,
+        RefundStatus::Failed as RefundStatus,
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected())
+}
+
+/// Claims the digests recorded but not sent yet, oldest first, so the
+/// digester of another replica doesn't send them too.
+///
+/// Digests claimed by a digester which stopped for `stale_after`, e.g. as it
+/// crashed, are claimed again.
+pub async fn claim_unnotified(
+    pool: &PgPool,
+    stale_after: Duration,
+) -> Result<Vec<Digest>, sqlx::Error> {
+    let stale_after = PgInterval::try_from(stale_after).map_err(sqlx::Error::Configuration)?;
+
+    sqlx::query_as!(
+        Digest,
+        r#"
+            UPDATE merchant_digests
+            SET notifying_at = current_timestamp
+            WHERE id IN (
+                SELECT id FROM merchant_digests
+                WHERE notified_at IS NULL
+                  AND (notifying_at IS NULL OR notifying_at < LOCALTIMESTAMP - $1::interval)
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, merchant_id, day, dead_deliveries, stuck_payments, failed_refunds,
+                      inserted_at, notified_at
+        "#,
+        stale_after
+    )
+    .fetch_all(pool)
+    .await
+    .map(|mut digests| {
+        digests.sort_by_key(|digest| (digest.inserted_at, digest.id));
+        digests
+    })
+}
+
+/// Releases a claimed digest which couldn't be sent, so it is retried by the
+/// next run of any digester.
+pub async fn release(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE merchant_digests
+            SET notifying_at = NULL
+            WHERE id = $1 AND notified_at IS NULL
+        "#,
+        id
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+pub async fn mark_notified(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE merchant_digests
+            SET notified_at = current_timestamp
+            WHERE id = $1
+        "#,
+        id
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Returns the most recent digest of a merchant, if it ever had one.
+pub async fn latest(pool: &PgPool, merchant_id: Uuid) -> Result<Option<Digest>, sqlx::Error> {
+    sqlx::query_as!(
+        Digest,
+        r#"
+            SELECT id, merchant_id, day, dead_deliveries, stuck_payments, failed_refunds,
+                   inserted_at, notified_at
+            FROM merchant_digests
+            WHERE merchant_id = $1
+            ORDER BY day DESC
+            LIMIT 1
+        "#,
+        merchant_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Transport of the digests to their merchant.
+#[async_trait::async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, digest: &Digest) -> Result<(), String>;
+}
+
+/// Default transport, emitting every digest as a tracing event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogNotifier;
+
+#[async_trait::async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, digest: &Digest) -> Result<(), String> {
+        tracing::info!(
+            merchant.id = %digest.merchant_id,
+            digest.dead_deliveries = digest.dead_deliveries,
+            digest.stuck_payments = digest.stuck_payments,
+            digest.failed_refunds = digest.failed_refunds,
+            "merchant digest"
+        );
+        Ok(())
+    }
+}
+
+/// Email sent through the HTTP API of the mail provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+}
+
+impl Email {
+    /// Renders a digest as a plain text email.
+    pub fn digest(from: String, to: String, digest: &Digest) -> Self {
+        Self {
+            from,
+            to,
+            subject: format!("Your payments digest of {}", digest.day),
+            text: format!(
+                "Dead webhook deliveries over the last day: {}\n\
+                 Payments stuck processing or authorized: {}\n\
+                 Refunds whose deposit failed: {}\n",
+                digest.dead_deliveries, digest.stuck_payments, digest.failed_refunds
+            ),
+        }
+    }
+}
+
+/// Emails digests to the contact address of their merchant, see
+/// `merchants::get_contact_email`.
+///
+/// Digests of merchants without a contact address are only retrievable
+/// through the API.
+///
+/// The mail provider is only reached over HTTPS, as requests carry its API
+/// key.
+pub struct EmailNotifier {
+    pool: PgPool,
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// Endpoint of the mail provider's API, receiving `Email`s as JSON.
+    endpoint: Uri,
+    api_key: String,
+    from: String,
+}
+
+impl EmailNotifier {
+    pub fn new(pool: PgPool, endpoint: Uri, api_key: String, from: String) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_only()
+            .enable_http1()
+            .build();
+
+        Self {
+            pool,
+            client: Client::builder().build(connector),
+            endpoint,
+            api_key,
+            from,
+        }
+    }
+
+    /// Lets the notifier reach a plain HTTP endpoint, e.g. a local test mail
+    /// provider.
+    #[cfg(test)]
+    fn allowing_plain_http(mut self) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        self.client = Client::builder().build(connector);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, digest: &Digest) -> Result<(), String> {
+        let to = merchants::get_contact_email(&self.pool, digest.merchant_id)
+            .await
+            .map_err(|e| format!("failed to get contact email: {e}"))?;
+        let Some(to) = to else {
+            tracing::warn!(merchant.id = %digest.merchant_id, "no contact email for digest");
+            return Ok(());
+        };
+
+        let email = Email::digest(self.from.clone(), to, digest);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.endpoint.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {}", self.api_key))
+            .body(Body::from(
+                serde_json::to_vec(&email).expect("failed to serialize email"),
+            ))
+            .map_err(|e| e.to_string())?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| format!("failed to send email: {e}"))?;
+        if !response.status().is_success() {
+            return Err(format!("mail provider answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Background task recording the daily digest of every merchant with problems
+/// to report, and sending the digests through its `Notifier`.
+pub struct Digester {
+    pool: PgPool,
+    notifier: Arc<dyn Notifier>,
+    interval: Duration,
+    stuck_after: Duration,
+}
+
+impl Digester {
+    /// Digests are recorded once a day, the hourly runs only retrying the
+    /// digests which couldn't be sent.
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+    pub const DEFAULT_STUCK_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+    /// After which the digests claimed by a crashed digester are sent by
+    /// another one, far longer than sending them takes.
+    const CLAIM_STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            notifier: Arc::new(LogNotifier),
+            interval: Self::DEFAULT_INTERVAL,
+            stuck_after: Self::DEFAULT_STUCK_AFTER,
+        }
+    }
+
+    /// Sets the transport of the digests, which are only logged by default.
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Sets how often digests are looked for.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how long a payment can stay processing or authorized before it is
+    /// reported as stuck.
+    pub fn with_stuck_after(mut self, stuck_after: Duration) -> Self {
+        self.stuck_after = stuck_after;
+        self
+    }
+
+    /// Records today's digests and sends the ones not sent yet, which are
+    /// retried on the next run if they can't be.
+    pub async fn run_once(&self) -> Result<(), sqlx::Error> {
+        let recorded = record(&self.pool, self.stuck_after).await?;
+        if recorded > 0 {
+            tracing::info!(digests.recorded = recorded, "recorded merchant digests");
+        }
+
+        for digest in claim_unnotified(&self.pool, Self::CLAIM_STALE_AFTER).await? {
+            match self.notifier.notify(&digest).await {
+                Ok(()) => mark_notified(&self.pool, digest.id).await?,
+                Err(e) => {
+                    tracing::error!(
+                        merchant.id = %digest.merchant_id,
+                        "failed to send digest {}: {e}",
+                        digest.id
+                    );
+                    release(&self.pool, digest.id).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records and sends digests every interval until the task is aborted.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.run_once().await {
+                tracing::error!("failed to record merchant digests: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{net::SocketAddr, sync::Mutex};
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        Json,
+    };
+
+    use super::*;
+    use crate::bank::{
+        events::PayloadVersion,
+        payment_instruments::Card,
+        payments::{self, tests::CARD_REUSE_WINDOW, ProcessingMode, Source},
+        refunds, webhooks,
+    };
+
+    /// Notifier keeping the digests it was given.
+    #[derive(Default)]
+    pub struct RecordingNotifier(pub Mutex<Vec<Digest>>);
+
+    #[async_trait::async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, digest: &Digest) -> Result<(), String> {
+            self.0.lock().unwrap().push(digest.clone());
+            Ok(())
+        }
+    }
+
+    async fn new_payment(pool: &PgPool, merchant_id: Uuid, status: PaymentStatus) -> Uuid {
+        payments::insert(
+            pool,
+            merchant_id,
+            1000,
+            &Card::new_test(),
+            "EUR".to_string(),
+            status,
+            ProcessingMode::Sync,
+            None,
+            None,
+            Source::default(),
+            CARD_REUSE_WINDOW,
+        )
+        .await
+        .expect("failed to insert payment")
+    }
+
+    /// Records a digest of a merchant for today, as `Digester` would.
+    pub async fn insert(pool: &PgPool, merchant_id: Uuid, counts: [i64; 3]) -> Digest {
+        sqlx::query_as!(
+            Digest,
+            r#"
+                INSERT INTO merchant_digests ( merchant_id, day, dead_deliveries, stuck_payments, failed_refunds )
+                VALUES ( $1, LOCALTIMESTAMP::date, $2, $3, $4 )
+                RETURNING id, merchant_id, day, dead_deliveries, stuck_payments, failed_refunds,
+                          inserted_at, notified_at
+            "#,
+            merchant_id,
+            counts[0],
+            counts[1],
+            counts[2]
+        )
+        .fetch_one(pool)
+        .await
+        .expect("failed to insert digest")
+    }
+
+    #[tokio::test]
+    async fn test_digests_report_merchants_with_problems_once_a_day() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let (merchant_a, merchant_b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        // a delivery to the endpoint of merchant A ran out of attempts
+        let payment_id = new_payment(&pool, merchant_a, PaymentStatus::Approved).await;
+        let webhook_id = webhooks::insert(
+            &pool,
+            merchant_a,
+            "http://127.0.0.1:1/hook".to_string(),
+            "secret".to_string(),
+            PayloadVersion::LATEST,
+        )
+        .await
+        .expect("failed to insert webhook");
+        let delivery_id =
+            webhooks::insert_delivery(&pool, webhook_id, payment_id, "payment.approved")
+                .await
+                .expect("failed to insert delivery");
+        webhooks::abandon(&pool, delivery_id)
+            .await
+            .expect("failed to abandon delivery");
+
+        // one of its refunds failed, and one of its payments is stuck
+        let refund_id = match refunds::checked_insert(
+            &pool,
+            payment_id,
+            100,
+            "EUR".to_string(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            "anonymous",
+            refunds::tests::MAX_REFUNDS,
+        )
+        .await
+        .expect("failed to insert refund")
+        {
+            refunds::RefundOutcome::Created(id) => id,
+            outcome => panic!("refund wasn't created: {outcome:?}"),
+        };
+        refunds::set_status(&pool, refund_id, RefundStatus::Failed)
+            .await
+            .expect("failed to fail refund");
+        new_payment(&pool, merchant_a, PaymentStatus::Processing).await;
+
+        // merchant B has nothing to report
+        new_payment(&pool, merchant_b, PaymentStatus::Approved).await;
+
+        let notifier = Arc::new(RecordingNotifier::default());
+        let digester = Digester::new(pool.clone())
+            .with_notifier(notifier.clone())
+            .with_stuck_after(Duration::ZERO);
+        for _ in 0..2 {
+            digester.run_once().await.expect("failed to run digester");
+        }
+
+        let digest = latest(&pool, merchant_a)
+            .await
+            .expect("failed to get digest")
+            .expect("no digest of merchant A");
+        assert_eq!(
+            (
+                digest.dead_deliveries,
+                digest.stuck_payments,
+                digest.failed_refunds
+            ),
+            (1, 1, 1)
+        );
+        assert!(digest.notified_at.is_some());
+        assert_eq!(
+            latest(&pool, merchant_b)
+                .await
+                .expect("failed to get digest"),
+            None
+        );
+
+        // the second run found today's digest already recorded and sent
+        let notified = notifier.0.lock().unwrap();
+        let notified: Vec<_> = notified
+            .iter()
+            .filter(|digest| [merchant_a, merchant_b].contains(&digest.merchant_id))
+            .collect();
+        assert_eq!(notified.len(), 1);
+        assert_eq!(notified[0].id, digest.id);
+    }
+
+    /// Notifier keeping the digests it was given, slowly enough for the runs
+    /// of several digesters to overlap.
+    #[derive(Default)]
+    struct SlowNotifier(Mutex<Vec<Uuid>>);
+
+    #[async_trait::async_trait]
+    impl Notifier for SlowNotifier {
+        async fn notify(&self, digest: &Digest) -> Result<(), String> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.0.lock().unwrap().push(digest.id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_digests_are_sent_by_one_digester() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let mut digest_ids = Vec::new();
+        for _ in 0..3 {
+            digest_ids.push(insert(&pool, Uuid::new_v4(), [1, 0, 0]).await.id);
+        }
+
+        // the digesters of two replicas
+        let notifier = Arc::new(SlowNotifier::default());
+        let digesters =
+            [(); 2].map(|_| Digester::new(pool.clone()).with_notifier(notifier.clone()));
+        let (a, b) = tokio::join!(digesters[0].run_once(), digesters[1].run_once());
+        a.expect("failed to run digester");
+        b.expect("failed to run digester");
+
+        let mut notified: Vec<_> = notifier
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|id| digest_ids.contains(id))
+            .copied()
+            .collect();
+        notified.sort();
+        digest_ids.sort();
+        assert_eq!(notified, digest_ids);
+    }
+
+    /// Requests received by the test mail provider.
+    type Received = Arc<Mutex<Vec<(HeaderMap, serde_json::Value)>>>;
+
+    #[tokio::test]
+    async fn test_email_notifier() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let router = axum::Router::new()
+            .route(
+                "/send",
+                axum::routing::post(
+                    |State(received): State<Received>,
+                     headers: HeaderMap,
+                     Json(email): Json<serde_json::Value>| async move {
+                        received.lock().unwrap().push((headers, email));
+                        StatusCode::ACCEPTED
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(router.into_make_service());
+        let endpoint = format!("http://{}/send", server.local_addr());
+        tokio::spawn(server);
+
+        let notifier = EmailNotifier::new(
+            pool.clone(),
+            endpoint.parse().unwrap(),
+            "key".to_string(),
+            "digests@bank.test".to_string(),
+        );
+        let (merchant_id, other_merchant_id) = (Uuid::new_v4(), Uuid::new_v4());
+        merchants::set_contact_email(&pool, merchant_id, "ops@merchant.test")
+            .await
+            .expect("failed to set contact email");
+
+        let digests = [
+            insert(&pool, merchant_id, [1, 0, 0]).await,
+            insert(&pool, other_merchant_id, [1, 0, 0]).await,
+        ];
+
+        // the API key isn't sent in clear text
+        assert!(notifier.notify(&digests[0]).await.is_err());
+        assert!(received.lock().unwrap().is_empty());
+
+        let notifier = notifier.allowing_plain_http();
+        for digest in digests {
+            notifier
+                .notify(&digest)
+                .await
+                .expect("failed to notify digest");
+        }
+
+        // merchants without a contact address aren't emailed
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (headers, email) = &received[0];
+        assert_eq!(headers[AUTHORIZATION], "Bearer key");
+        assert_eq!(email["to"], "ops@merchant.test");
+        assert_eq!(email["from"], "digests@bank.test");
+    }
+
+    #[test]
+    fn test_digest_email() {
+        let digest = Digest {
+            id: Uuid::new_v4(),
+            merchant_id: Uuid::new_v4(),
+            day: Date::from_calendar_date(2023, time::Month::May, 4).unwrap(),
+            dead_deliveries: 3,
+            stuck_payments: 0,
+            failed_refunds: 1,
+            inserted_at: Date::from_calendar_date(2023, time::Month::May, 4)
+                .unwrap()
+                .midnight(),
+            notified_at: None,
+        };
+
+        let email = Email::digest(
+            "digests@bank.test".to_string(),
+            "ops@merchant.test".to_string(),
+            &digest,
+        );
+        assert_eq!(email.to, "ops@merchant.test");
+        assert_eq!(email.subject, "Your payments digest of 2023-05-04");
+        assert_eq!(
+            email.text,
+            "Dead webhook deliveries over the last day: 3\n\
+             Payments stuck processing or authorized: 0\n\
+             Refunds whose deposit failed: 1\n"
+        );
+    }
+}
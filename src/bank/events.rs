@@ -0,0 +1,243 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{
+    payments::{Payment, Status},
+    refunds::{Reason, Refund},
+};
+
+/// Shape of the events sent to a webhook, chosen when it is registered.
+///
+/// Every version stays supported, so consumers keep receiving the shape
+/// they integrated against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, sqlx::Type)]
+#[repr(i32)]
+pub enum PayloadVersion {
+    /// The payment id and status.
+    V1 = 1,
+    /// Adds the amounts of the payment and its refunds.
+    V2 = 2,
+}
+
+impl PayloadVersion {
+    /// Version of the webhooks registered without choosing one.
+    pub const LATEST: PayloadVersion = PayloadVersion::V2;
+    pub const ALL: [PayloadVersion; 2] = [PayloadVersion::V1, PayloadVersion::V2];
+}
+
+impl From<PayloadVersion> for i32 {
+    fn from(version: PayloadVersion) -> Self {
+        version as i32
+    }
+}
+
+impl TryFrom<i32> for PayloadVersion {
+    type Error = i32;
+
+    fn try_from(version: i32) -> Result<Self, Self::Error> {
+        PayloadVersion::ALL
+            .into_iter()
+            .find(|supported| i32::from(*supported) == version)
+            .ok_or(version)
+    }
+}
+
+/// Event notified to webhooks when a payment enters `status`.
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Id of the delivery, identical across retries.
+    pub id: Uuid,
+    pub event_type: String,
+    /// Status entered by the payment, which may have changed since.
+    pub status: Status,
+    pub payment: Payment,
+    /// Refunds of the payment, oldest first.
+    pub refunds: Vec<Refund>,
+}
+
+#[derive(Serialize)]
+struct PayloadV1<'a> {
+    payload_version: i32,
+    id: Uuid,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    payment_id: Uuid,
+    status: Status,
+}
+
+#[derive(Serialize)]
+struct PayloadV2<'a> {
+    payload_version: i32,
+    id: Uuid,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    payment_id: Uuid,
+    status: Status,
+    amounts: Amounts<'a>,
+    refunds: Vec<RefundSnapshot<'a>>,
+}
+
+/// Amounts of a payment, in minor units of `currency`.
+#[derive(Serialize)]
+struct Amounts<'a> {
+    amount: i64,
+    refunded_amount: i64,
+    currency: &'a str,
+}
+
+#[derive(Serialize)]
+struct RefundSnapshot<'a> {
+    id: Uuid,
+    amount: i64,
+    currency: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<Reason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_detail: Option<&'a str>,
+}
+
+/// Serializes `event` in the shape of `version`.
+pub fn serialize(event: &Event, version: PayloadVersion) -> Vec<u8> {
+    let payload_version = i32::from(version);
+    let result = match version {
+        PayloadVersion::V1 => serde_json::to_vec(&PayloadV1 {
+            payload_version,
+            id: event.id,
+            event_type: &event.event_type,
+            payment_id: event.payment.id,
+            status: event.status,
+        }),
+        PayloadVersion::V2 => serde_json::to_vec(&PayloadV2 {
+            payload_version,
+            id: event.id,
+            event_type: &event.event_type,
+            payment_id: event.payment.id,
+            status: event.status,
+            amounts: Amounts {
+                amount: event.payment.amount,
+                refunded_amount: event.refunds.iter().map(|r| r.amount).sum(),
+                currency: &event.payment.currency,
+            },
+            refunds: event
+                .refunds
+                .iter()
+                .map(|refund| RefundSnapshot {
+                    id: refund.id,
+                    amount: refund.amount,
+                    currency: &refund.currency,
+                    reason: refund.reason,
+                    reason_detail: refund.reason_detail.as_deref(),
+                })
+                .collect(),
+        }),
+    };
+
+    result.expect("failed to serialize webhook event")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use time::{OffsetDateTime, PrimitiveDateTime};
+
+    use super::*;
+    use crate::bank::{payments::Source, refunds::Status as RefundStatus};
+
+    fn new_event() -> Event {
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
+        let payment = Payment {
+            id: Uuid::new_v4(),
+            merchant_id: Uuid::new_v4(),
+            amount: 1205,
+            card_number: "12*********2345".to_string(),
+            currency: "EUR".to_string(),
+            status: Status::Approved,
+            metadata: None,
+            inserted_at: now,
+            updated_at: now,
+            archived_at: None,
+            version: 1,
+            decline_reason: None,
+            customer_reference: None,
+            source: Source::Api,
+        };
+        let refunds = [(42, Some(Reason::Duplicate)), (100, None)]
+            .into_iter()
+            .map(|(amount, reason)| Refund {
+                id: Uuid::new_v4(),
+                payment_id: payment.id,
+                amount,
+                currency: "EUR".to_string(),
+                status: RefundStatus::Settled,
+                reason,
+                reason_detail: None,
+                metadata: None,
+                external_reference: None,
+                initiated_by: "anonymous".to_string(),
+                inserted_at: now,
+                updated_at: now,
+            })
+            .collect();
+
+        Event {
+            id: Uuid::new_v4(),
+            event_type: "payment.approved".to_string(),
+            status: Status::Approved,
+            payment,
+            refunds,
+        }
+    }
+
+    #[test]
+    fn test_serialize_v1() {
+        let event = new_event();
+
+        let payload: serde_json::Value =
+            serde_json::from_slice(&serialize(&event, PayloadVersion::V1)).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "payload_version": 1,
+                "id": event.id,
+                "type": "payment.approved",
+                "payment_id": event.payment.id,
+                "status": "approved",
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_v2() {
+        let event = new_event();
+
+        let payload: serde_json::Value =
+            serde_json::from_slice(&serialize(&event, PayloadVersion::V2)).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "payload_version": 2,
+                "id": event.id,
+                "type": "payment.approved",
+                "payment_id": event.payment.id,
+                "status": "approved",
+                "amounts": { "amount": 1205, "refunded_amount": 142, "currency": "EUR" },
+                "refunds": [
+                    {
+                        "id": event.refunds[0].id,
+                        "amount": 42,
+                        "currency": "EUR",
+                        "reason": "duplicate",
+                    },
+                    { "id": event.refunds[1].id, "amount": 100, "currency": "EUR" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_payload_version() {
+        assert_eq!(PayloadVersion::try_from(1), Ok(PayloadVersion::V1));
+        assert_eq!(PayloadVersion::try_from(2), Ok(PayloadVersion::V2));
+        assert_eq!(PayloadVersion::try_from(3), Err(3));
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+    payments::{self, Status},
+    settlement::Notify,
+};
+
+/// Background task failing the payments stuck in processing, see
+/// `payments::expire_stale`, and repairing the settlements which couldn't be
+/// recorded, see `payments::repair_settlements`.
+pub struct Reaper {
+    id: Uuid,
+    pool: PgPool,
+    notify: Notify,
+    interval: Duration,
+    max_age: Duration,
+}
+
+impl Reaper {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+    /// Far longer than the account service calls of a payment, so payments
+    /// still being processed aren't failed.
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(15 * 60);
+    /// Number of payments claimed at once for their settlement repair.
+    const REPAIR_BATCH_SIZE: i64 = 10;
+    /// After which the repairs claimed by a crashed reaper are applied by
+    /// another one.
+    const REPAIR_CLAIM_TTL: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new(pool: PgPool, notify: Notify) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            pool,
+            notify,
+            interval: Self::DEFAULT_INTERVAL,
+            max_age: Self::DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Sets how often stale payments are looked for.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how long a payment can stay processing before it is failed.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Expires stale payments and repairs settlements every interval until
+    /// the task is aborted.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            match payments::expire_stale(&self.pool, self.max_age).await {
+                Ok(ids) => {
+                    if !ids.is_empty() {
+                        tracing::warn!(payments.expired = ids.len(), "expired stale payments");
+                    }
+                    for payment_id in ids {
+                        (self.notify)(payment_id, Status::Failed);
+                    }
+                }
+                Err(e) => tracing::error!("failed to expire stale payments: {e}"),
+            }
+
+            let repaired = payments::repair_settlements(
+                &self.pool,
+                self.id,
+                Self::REPAIR_BATCH_SIZE,
+                Self::REPAIR_CLAIM_TTL,
+            )
+            .await;
+            match repaired {
+                Ok(repaired) => {
+                    for (payment_id, status) in repaired {
+                        tracing::warn!(payment.id = %payment_id, "repaired payment settlement");
+                        (self.notify)(payment_id, status);
+                    }
+                }
+                Err(e) => tracing::error!("failed to repair settlements: {e}"),
+            }
+        }
+    }
+}
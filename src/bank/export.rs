@@ -0,0 +1,91 @@
+/// Characters which make spreadsheet applications evaluate a cell starting
+/// with them as a formula.
+const FORMULA_PREFIXES: [char; 4] = ['=', '+', '-', '@'];
+
+/// Maximum number of characters of an exported cell, which is the limit of
+/// Excel.
+pub const MAX_FIELD_LENGTH: usize = 32_767;
+
+/// Neutralizes a text cell of a CSV export against formula injection.
+///
+/// Control characters, including line breaks, are removed. A cell starting
+/// with `=`, `+`, `-` or `@` is then prefixed with `'`, so spreadsheet
+/// applications display it as text instead of evaluating it. Cells are
+/// finally truncated to `MAX_FIELD_LENGTH` characters.
+///
+/// Quotes and commas are left as is, the CSV writer quoting the cell. Every
+/// text cell of a CSV export must go through this function, numbers and
+/// timestamps can't start a formula.
+pub fn csv_escape(field: &str) -> String {
+    let field = field.chars().filter(|c| !c.is_control());
+    let escape = field
+        .clone()
+        .next()
+        .filter(|c| FORMULA_PREFIXES.contains(c));
+
+    escape
+        .map(|_| '\'')
+        .into_iter()
+        .chain(field)
+        .take(MAX_FIELD_LENGTH)
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_formula_prefixes() {
+        for field in ["=1+1", "+1", "-1", "@SUM(A1)", "=HYPERLINK(\"http://a\")"] {
+            assert_eq!(csv_escape(field), format!("'{field}"), "{field}");
+        }
+
+        assert_eq!(csv_escape("1-1"), "1-1");
+        assert_eq!(csv_escape("\n=1"), "'=1", "escaped after stripping");
+    }
+
+    #[test]
+    fn test_strip_control_characters() {
+        assert_eq!(csv_escape("a\tb\r\nc\u{7}"), "abc");
+    }
+
+    #[test]
+    fn test_cap_field_length() {
+        let field = "a".repeat(MAX_FIELD_LENGTH + 1);
+        assert_eq!(csv_escape(&field).len(), MAX_FIELD_LENGTH);
+
+        let field = format!("={}", "a".repeat(MAX_FIELD_LENGTH));
+        let escaped = csv_escape(&field);
+        assert_eq!(escaped.chars().count(), MAX_FIELD_LENGTH);
+        assert!(escaped.starts_with("'="));
+    }
+
+    #[test]
+    fn test_round_trip_quotes_and_commas() {
+        let fields = [
+            "say \"hi\", then leave",
+            "a,b",
+            "multi\nline",
+            "-\"quoted\"",
+        ];
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let escaped: Vec<_> = fields.iter().map(|field| csv_escape(field)).collect();
+        writer.write_record(&escaped).expect("failed to write CSV");
+        let bytes = writer.into_inner().expect("failed to flush CSV");
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(bytes.as_slice());
+        let record = reader
+            .records()
+            .next()
+            .expect("missing record")
+            .expect("failed to read CSV");
+        assert_eq!(
+            record.iter().collect::<Vec<_>>(),
+            ["say \"hi\", then leave", "a,b", "multiline", "'-\"quoted\""]
+        );
+    }
+}
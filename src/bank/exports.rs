@@ -0,0 +1,988 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::types::PgInterval, PgPool};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{
+    export::csv_escape,
+    payments::{disputes::Status as DisputeStatus, Status as PaymentStatus},
+    refunds::{Reason, Status as RefundStatus},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "ExportStatus")]
+pub enum Status {
+    /// Waiting for an `Exporter`.
+    Pending,
+    /// Being archived, see `Export::exported_rows`.
+    Running,
+    /// Archived, and downloadable once.
+    Completed,
+    Failed,
+}
+
+/// Export of every payment, refund, dispute and webhook delivery of a
+/// merchant, e.g. when it leaves.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Export {
+    pub id: Uuid,
+    pub merchant_id: Uuid,
+    pub status: Status,
+    /// Rows archived so far.
+    pub exported_rows: i64,
+    /// Rows to archive, counted once the export started.
+    pub total_rows: Option<i64>,
+    /// Why the export failed.
+    pub error: Option<String>,
+    pub inserted_at: PrimitiveDateTime,
+    pub updated_at: PrimitiveDateTime,
+    pub completed_at: Option<PrimitiveDateTime>,
+    /// When the archive was downloaded, after which it can't be again.
+    pub downloaded_at: Option<PrimitiveDateTime>,
+    /// Claim of the exporter which last started the export, naming its
+    /// archive.
+    pub claim_id: Option<Uuid>,
+}
+
+/// Requests an export of the data of a merchant.
+pub async fn insert(pool: &PgPool, merchant_id: Uuid) -> Result<Export, sqlx::Error> {
+    sqlx::query_as!(
+        Export,
+        r#"
+            INSERT INTO exports ( merchant_id )
+            VALUES ( $1 )
+            RETURNING id, merchant_id, status as "status: _", exported_rows, total_rows, error,
+                      inserted_at, updated_at, completed_at, downloaded_at, claim_id
+        "#,
+        merchant_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns an export of a merchant, `None` if it doesn't exist or is another
+/// merchant's.
+pub async fn get(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    id: Uuid,
+) -> Result<Option<Export>, sqlx::Error> {
+    sqlx::query_as!(
+        Export,
+        r#"
+            SELECT id, merchant_id, status as "status: _", exported_rows, total_rows, error,
+                   inserted_at, updated_at, completed_at, downloaded_at, claim_id
+            FROM exports
+            WHERE id = $1 AND merchant_id = $2
+        "#,
+        id,
+        merchant_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Export started by an exporter, which owns it until another one claims it
+/// once stale.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claim {
+    pub export: Export,
+    /// Id of the claim, which the exporter updates the export with.
+    pub id: Uuid,
+    /// Claim of the exporter which stopped recording progress, whose partial
+    /// archive is left behind.
+    pub stale_id: Option<Uuid>,
+}
+
+/// Starts the oldest pending export, or a running one whose exporter stopped
+/// recording progress for `stale_after`, e.g. as it crashed.
+///
+/// The rows of the merchant are counted as the export starts.
+pub async fn claim(pool: &PgPool, stale_after: Duration) -> Result<Option<Claim>, sqlx::Error> {
+    let stale_after = PgInterval::try_from(stale_after).map_err(sqlx::Error::Configuration)?;
+
+    let claimed = sqlx::query!(
+        r#"
+            UPDATE exports e
+            SET status = 'Running',
+                claim_id = uuid_generate_v4(),
+                exported_rows = 0,
+                total_rows = (SELECT count(*) FROM payments WHERE merchant_id = e.merchant_id)
+                    + (SELECT count(*) FROM refunds WHERE merchant_id = e.merchant_id)
+                    + (SELECT count(*) FROM disputes d JOIN payments p ON p.id = d.payment_id
+                       WHERE p.merchant_id = e.merchant_id)
+                    + (SELECT count(*) FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+                       WHERE w.merchant_id = e.merchant_id),
+                updated_at = current_timestamp
+            FROM (
+                SELECT id, claim_id FROM exports
+                WHERE status = 'Pending'
+                   OR (status = 'Running' AND updated_at < LOCALTIMESTAMP - $1::interval)
+                ORDER BY inserted_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            ) stale
+            WHERE e.id = stale.id
+            RETURNING e.id, e.merchant_id, e.status as "status: Status", e.exported_rows,
+                      e.total_rows, e.error, e.inserted_at, e.updated_at, e.completed_at,
+                      e.downloaded_at, e.claim_id as "claim_id!", stale.claim_id as stale_claim_id
+        "#,
+        stale_after
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(claimed.map(|row| Claim {
+        export: Export {
+            id: row.id,
+            merchant_id: row.merchant_id,
+            status: row.status,
+            exported_rows: row.exported_rows,
+            total_rows: row.total_rows,
+            error: row.error,
+            inserted_at: row.inserted_at,
+            updated_at: row.updated_at,
+            completed_at: row.completed_at,
+            downloaded_at: row.downloaded_at,
+            claim_id: Some(row.claim_id),
+        },
+        id: row.claim_id,
+        stale_id: row.stale_claim_id,
+    }))
+}
+
+/// Records the rows archived so far, which also tells the export is still
+/// running.
+///
+/// Returns whether the claim still owns the export, which another exporter
+/// takes over once stale.
+pub async fn record_progress(
+    pool: &PgPool,
+    id: Uuid,
+    claim_id: Uuid,
+    exported_rows: i64,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE exports
+            SET exported_rows = $3,
+                updated_at = current_timestamp
+            WHERE id = $1 AND claim_id = $2 AND status = 'Running'
+        "#,
+        id,
+        claim_id,
+        exported_rows
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Completes an export, unless the claim no longer owns it.
+pub async fn complete(
+    pool: &PgPool,
+    id: Uuid,
+    claim_id: Uuid,
+    exported_rows: i64,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE exports
+            SET status = 'Completed',
+                exported_rows = $3,
+                completed_at = current_timestamp,
+                updated_at = current_timestamp
+            WHERE id = $1 AND claim_id = $2 AND status = 'Running'
+        "#,
+        id,
+        claim_id,
+        exported_rows
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Fails an export, unless the claim no longer owns it.
+pub async fn fail(
+    pool: &PgPool,
+    id: Uuid,
+    claim_id: Uuid,
+    error: &str,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE exports
+            SET status = 'Failed',
+                error = $3,
+                updated_at = current_timestamp
+            WHERE id = $1 AND claim_id = $2 AND status = 'Running'
+        "#,
+        id,
+        claim_id,
+        error
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Outcome of the download of an export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    /// The archive `archive_id` can be read from the sink, the download being
+    /// leased until completed or released.
+    Ready {
+        archive_id: Uuid,
+        lease_id: Uuid,
+    },
+    /// There is no such export of the merchant.
+    NotFound,
+    /// The export isn't completed.
+    NotCompleted {
+        status: Status,
+    },
+    /// Another download of the archive is in progress.
+    Leased,
+    AlreadyDownloaded,
+}
+
+/// Leases the download of a completed export of a merchant for `lease`, so
+/// its archive is only sent to one client at a time.
+///
+/// The export is downloaded once the last chunk of the archive was sent, see
+/// `complete_download`; a download which is interrupted can be retried once
+/// released, or once its lease expired.
+pub async fn lease_download(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    id: Uuid,
+    lease: Duration,
+) -> Result<DownloadOutcome, sqlx::Error> {
+    let lease = PgInterval::try_from(lease).map_err(sqlx::Error::Configuration)?;
+
+    let leased = sqlx::query!(
+        r#"
+            UPDATE exports
+            SET download_lease_id = uuid_generate_v4(),
+                download_lease_expires_at = LOCALTIMESTAMP + $3::interval,
+                updated_at = current_timestamp
+            WHERE id = $1 AND merchant_id = $2 AND status = 'Completed' AND downloaded_at IS NULL
+              AND (download_lease_expires_at IS NULL OR download_lease_expires_at <= LOCALTIMESTAMP)
+            RETURNING coalesce(claim_id, id) as "archive_id!", download_lease_id as "lease_id!"
+        "#,
+        id,
+        merchant_id,
+        lease
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(leased) = leased {
+        return Ok(DownloadOutcome::Ready {
+            archive_id: leased.archive_id,
+            lease_id: leased.lease_id,
+        });
+    }
+
+    Ok(match get(pool, merchant_id, id).await? {
+        None => DownloadOutcome::NotFound,
+        Some(export) if export.status != Status::Completed => DownloadOutcome::NotCompleted {
+            status: export.status,
+        },
+        Some(export) if export.downloaded_at.is_none() => DownloadOutcome::Leased,
+        Some(_) => DownloadOutcome::AlreadyDownloaded,
+    })
+}
+
+/// Marks an export as downloaded once its archive was sent, returning whether
+/// the lease was still held, and not taken over by another download.
+pub async fn complete_download(
+    pool: &PgPool,
+    id: Uuid,
+    lease_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE exports
+            SET downloaded_at = current_timestamp,
+                download_lease_expires_at = NULL,
+                updated_at = current_timestamp
+            WHERE id = $1 AND download_lease_id = $2 AND downloaded_at IS NULL
+        "#,
+        id,
+        lease_id
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Releases the lease of an interrupted download, so it can be retried right
+/// away.
+pub async fn release_download(pool: &PgPool, id: Uuid, lease_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE exports
+            SET download_lease_id = NULL,
+                download_lease_expires_at = NULL,
+                updated_at = current_timestamp
+            WHERE id = $1 AND download_lease_id = $2 AND downloaded_at IS NULL
+        "#,
+        id,
+        lease_id
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Archive being written, which zip archives must be able to seek.
+pub trait Archive: Write + Seek + Send {}
+
+impl<T: Write + Seek + Send> Archive for T {}
+
+/// Storage of the archives of the exports, e.g. a local directory.
+///
+/// An archive is named after the claim of the exporter writing it, so an
+/// exporter taking over a stale export doesn't write to the archive of the
+/// previous one.
+pub trait ExportSink: Send + Sync {
+    /// Creates the archive of a claim of an export.
+    fn create(&self, id: Uuid) -> io::Result<Box<dyn Archive>>;
+
+    /// Opens the archive of a completed export.
+    fn open(&self, id: Uuid) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Removes an archive, once downloaded, failed or left behind by a stale
+    /// exporter.
+    fn remove(&self, id: Uuid) -> io::Result<()>;
+}
+
+/// Stores archives as `<id>.zip` files of a local directory.
+#[derive(Debug, Clone)]
+pub struct LocalDirSink {
+    dir: PathBuf,
+}
+
+impl LocalDirSink {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{id}.zip"))
+    }
+}
+
+impl ExportSink for LocalDirSink {
+    fn create(&self, id: Uuid) -> io::Result<Box<dyn Archive>> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(Box::new(File::create(self.path(id))?))
+    }
+
+    fn open(&self, id: Uuid) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(self.path(id))?))
+    }
+
+    fn remove(&self, id: Uuid) -> io::Result<()> {
+        fs::remove_file(self.path(id))
+    }
+}
+
+/// Part of an archive sent to the task writing it.
+enum Chunk {
+    /// Starts a file of the archive.
+    File(&'static str),
+    /// Appends to the current file.
+    Data(Vec<u8>),
+}
+
+/// Writes the chunks received to a zip archive until the sender is dropped.
+fn write_archive(
+    archive: Box<dyn Archive>,
+    mut receiver: mpsc::Receiver<Chunk>,
+) -> zip::result::ZipResult<()> {
+    let mut zip = ZipWriter::new(archive);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    while let Some(chunk) = receiver.blocking_recv() {
+        match chunk {
+            Chunk::File(name) => zip.start_file(name, options)?,
+            Chunk::Data(data) => zip.write_all(&data)?,
+        }
+    }
+
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+/// Row of a file of an archive, rows being exported in insertion order.
+trait Row: Serialize {
+    /// Columns of the file, in the order of the fields of the row.
+    const HEADERS: &'static [&'static str];
+
+    /// Raw `inserted_at` and id of the row, after which the next batch
+    /// starts.
+    fn cursor(&self) -> (PrimitiveDateTime, Uuid);
+}
+
+/// Cursor of the last exported row of a file, `None` before the first batch.
+type After = Option<(PrimitiveDateTime, Uuid)>;
+
+/// Returns the batch of `limit` rows of a merchant following `after`.
+type Fetch<R> =
+    for<'a> fn(&'a PgPool, Uuid, After, i64) -> BoxFuture<'a, Result<Vec<R>, sqlx::Error>>;
+
+#[derive(Debug, Serialize)]
+struct PaymentRow {
+    id: Uuid,
+    amount: i64,
+    currency: String,
+    status: PaymentStatus,
+    card_number: String,
+    customer_reference: Option<String>,
+    metadata: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    inserted_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    updated_at: OffsetDateTime,
+    #[serde(skip)]
+    cursor: PrimitiveDateTime,
+}
+
+impl Row for PaymentRow {
+    const HEADERS: &'static [&'static str] = &[
+        "id",
+        "amount",
+        "currency",
+        "status",
+        "card_number",
+        "customer_reference",
+        "metadata",
+        "inserted_at",
+        "updated_at",
+    ];
+
+    fn cursor(&self) -> (PrimitiveDateTime, Uuid) {
+        (self.cursor, self.id)
+    }
+}
+
+fn payments(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    after: After,
+    limit: i64,
+) -> BoxFuture<'_, Result<Vec<PaymentRow>, sqlx::Error>> {
+    let (after_inserted_at, after_id) = after.unzip();
+    async move {
+        let rows = sqlx::query_as!(
+            PaymentRow,
+            r#"
+                SELECT id, amount, currency, status as "status: _", card_number, customer_reference,
+                       metadata::text as metadata,
+                       inserted_at AT TIME ZONE 'UTC' as "inserted_at!",
+                       updated_at AT TIME ZONE 'UTC' as "updated_at!",
+                       inserted_at as cursor
+                FROM payments
+                WHERE merchant_id = $1
+                  AND ($2::timestamp IS NULL OR (inserted_at, id) > ($2, $3::uuid))
+                ORDER BY inserted_at, id
+                LIMIT $4
+            "#,
+            merchant_id,
+            after_inserted_at,
+            after_id,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| PaymentRow {
+                customer_reference: row.customer_reference.as_deref().map(csv_escape),
+                metadata: row.metadata.as_deref().map(csv_escape),
+                ..row
+            })
+            .collect())
+    }
+    .boxed()
+}
+
+#[derive(Debug, Serialize)]
+struct RefundRow {
+    id: Uuid,
+    payment_id: Uuid,
+    amount: i64,
+    currency: String,
+    status: RefundStatus,
+    reason: Option<Reason>,
+    reason_detail: Option<String>,
+    external_reference: Option<String>,
+    initiated_by: String,
+    #[serde(with = "time::serde::rfc3339")]
+    inserted_at: OffsetDateTime,
+    #[serde(skip)]
+    cursor: PrimitiveDateTime,
+}
+
+impl Row for RefundRow {
+    const HEADERS: &'static [&'static str] = &[
+        "id",
+        "payment_id",
+        "amount",
+        "currency",
+        "status",
+        "reason",
+        "reason_detail",
+        "external_reference",
+        "initiated_by",
+        "inserted_at",
+    ];
+
+    fn cursor(&self) -> (PrimitiveDateTime, Uuid) {
+        (self.cursor, self.id)
+    }
+}
+
+fn refunds(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    after: After,
+    limit: i64,
+) -> BoxFuture<'_, Result<Vec<RefundRow>, sqlx::Error>> {
+    let (after_inserted_at, after_id) = after.unzip();
+    async move {
+        let rows = sqlx::query_as!(
+            RefundRow,
+            r#"
+                SELECT id, payment_id, amount, currency, status as "status: _",
+                       reason as "reason: _", reason_detail, external_reference, initiated_by,
+                       inserted_at AT TIME ZONE 'UTC' as "inserted_at!",
+                       inserted_at as cursor
+                FROM refunds
+                WHERE merchant_id = $1
+                  AND ($2::timestamp IS NULL OR (inserted_at, id) > ($2, $3::uuid))
+                ORDER BY inserted_at, id
+                LIMIT $4
+            "#,
+            merchant_id,
+            after_inserted_at,
+            after_id,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| RefundRow {
+                reason_detail: row.reason_detail.as_deref().map(csv_escape),
+                external_reference: row.external_reference.as_deref().map(csv_escape),
+                ..row
+            })
+            .collect())
+    }
+    .boxed()
+}
+
+#[derive(Debug, Serialize)]
+struct DisputeRow {
+    id: Uuid,
+    payment_id: Uuid,
+    status: DisputeStatus,
+    #[serde(with = "time::serde::rfc3339")]
+    inserted_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    closed_at: Option<OffsetDateTime>,
+    #[serde(skip)]
+    cursor: PrimitiveDateTime,
+}
+
+impl Row for DisputeRow {
+    const HEADERS: &'static [&'static str] =
+        &["id", "payment_id", "status", "inserted_at", "closed_at"];
+
+    fn cursor(&self) -> (PrimitiveDateTime, Uuid) {
+        (self.cursor, self.id)
+    }
+}
+
+fn disputes(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    after: After,
+    limit: i64,
+) -> BoxFuture<'_, Result<Vec<DisputeRow>, sqlx::Error>> {
+    let (after_inserted_at, after_id) = after.unzip();
+    sqlx::query_as!(
+        DisputeRow,
+        r#"
+            SELECT d.id, d.payment_id, d.status as "status: _",
+                   d.inserted_at AT TIME ZONE 'UTC' as "inserted_at!",
+                   d.closed_at AT TIME ZONE 'UTC' as closed_at,
+                   d.inserted_at as cursor
+            FROM disputes d
+            JOIN payments p ON p.id = d.payment_id
+            WHERE p.merchant_id = $1
+              AND ($2::timestamp IS NULL OR (d.inserted_at, d.id) > ($2, $3::uuid))
+            ORDER BY d.inserted_at, d.id
+            LIMIT $4
+        "#,
+        merchant_id,
+        after_inserted_at,
+        after_id,
+        limit
+    )
+    .fetch_all(pool)
+    .boxed()
+}
+
+#[derive(Debug, Serialize)]
+struct DeliveryRow {
+    id: Uuid,
+    webhook_id: Uuid,
+    payment_id: Uuid,
+    event: String,
+    attempts: i32,
+    last_status_code: Option<i32>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    delivered_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    abandoned_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339")]
+    inserted_at: OffsetDateTime,
+    #[serde(skip)]
+    cursor: PrimitiveDateTime,
+}
+
+impl Row for DeliveryRow {
+    const HEADERS: &'static [&'static str] = &[
+        "id",
+        "webhook_id",
+        "payment_id",
+        "event",
+        "attempts",
+        "last_status_code",
+        "delivered_at",
+        "abandoned_at",
+        "inserted_at",
+    ];
+
+    fn cursor(&self) -> (PrimitiveDateTime, Uuid) {
+        (self.cursor, self.id)
+    }
+}
+
+fn webhook_deliveries(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    after: After,
+    limit: i64,
+) -> BoxFuture<'_, Result<Vec<DeliveryRow>, sqlx::Error>> {
+    let (after_inserted_at, after_id) = after.unzip();
+    sqlx::query_as!(
+        DeliveryRow,
+        r#"
+            SELECT d.id, d.webhook_id, d.payment_id, d.event, d.attempts, d.last_status_code,
+                   d.delivered_at AT TIME ZONE 'UTC' as delivered_at,
+                   d.abandoned_at AT TIME ZONE 'UTC' as abandoned_at,
+                   d.inserted_at AT TIME ZONE 'UTC' as "inserted_at!",
+                   d.inserted_at as cursor
+            FROM webhook_deliveries d
+            JOIN webhooks w ON w.id = d.webhook_id
+            WHERE w.merchant_id = $1
+              AND ($2::timestamp IS NULL OR (d.inserted_at, d.id) > ($2, $3::uuid))
+            ORDER BY d.inserted_at, d.id
+            LIMIT $4
+        "#,
+        merchant_id,
+        after_inserted_at,
+        after_id,
+        limit
+    )
+    .fetch_all(pool)
+    .boxed()
+}
+
+/// Summary of an archive, written last as `export.json`.
+#[derive(Debug, Serialize)]
+struct Manifest {
+    export_id: Uuid,
+    merchant_id: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    exported_at: OffsetDateTime,
+    /// Number of rows of every CSV file.
+    files: BTreeMap<&'static str, i64>,
+}
+
+/// Background task archiving the pending exports through an `ExportSink`.
+pub struct Exporter {
+    pool: PgPool,
+    sink: Arc<dyn ExportSink>,
+    poll_interval: Duration,
+    batch_size: i64,
+}
+
+impl Exporter {
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+    /// Rows read at once, so large merchants aren't held in memory.
+    pub const DEFAULT_BATCH_SIZE: i64 = 500;
+    /// After which the export of a crashed exporter is started over by
+    /// another one, far longer than the time between two batches.
+    const STALE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new(pool: PgPool, sink: Arc<dyn ExportSink>) -> Self {
+        Self {
+            pool,
+            sink,
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets how often pending exports are looked for.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets the number of rows read at once.
+    pub fn with_batch_size(mut self, batch_size: i64) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Archives the oldest pending export, returning it unless there was
+    /// none.
+    pub async fn run_once(&self) -> Result<Option<Uuid>, sqlx::Error> {
+        let Some(claim) = claim(&self.pool, Self::STALE_AFTER).await? else {
+            return Ok(None);
+        };
+        let export = &claim.export;
+        if let Some(stale_id) = claim.stale_id {
+            self.remove_archive(export.id, stale_id);
+        }
+
+        match self.archive(&claim).await {
+            Ok(exported_rows) => {
+                if !complete(&self.pool, export.id, claim.id, exported_rows).await? {
+                    tracing::warn!("export {} was claimed by another exporter", export.id);
+                    self.remove_archive(export.id, claim.id);
+                }
+            }
+            Err(e) => {
+                self.remove_archive(export.id, claim.id);
+                if fail(&self.pool, export.id, claim.id, &e).await? {
+                    tracing::error!(merchant.id = %export.merchant_id, "failed export {}: {e}", export.id);
+                } else {
+                    tracing::warn!("export {} was claimed by another exporter: {e}", export.id);
+                }
+            }
+        }
+
+        Ok(Some(export.id))
+    }
+
+    /// Removes the archive of a claim of an export, which may not have been
+    /// created yet.
+    fn remove_archive(&self, export_id: Uuid, claim_id: Uuid) {
+        match self.sink.remove(claim_id) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("failed to remove archive of export {export_id}: {e}"),
+        }
+    }
+
+    /// Archives pending exports every interval until the task is aborted.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.poll_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            loop {
+                match self.run_once().await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::error!("failed to run export: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the archive of an export, returning the number of rows
+    /// archived.
+    ///
+    /// Rows are read in batches and handed to a blocking task writing the
+    /// archive through a bounded channel, so memory use doesn't depend on the
+    /// size of the merchant.
+    async fn archive(&self, claim: &Claim) -> Result<i64, String> {
+        let archive = self
+            .sink
+            .create(claim.id)
+            .map_err(|e| format!("failed to create archive: {e}"))?;
+        let (sender, receiver) = mpsc::channel(4);
+        let writer = tokio::task::spawn_blocking(move || write_archive(archive, receiver));
+
+        let written = self.write_files(claim, &sender).await;
+        drop(sender);
+
+        // the writer stopping first fails the files with a closed channel,
+        // its own error telling why
+        writer
+            .await
+            .map_err(|e| format!("archive writer panicked: {e}"))?
+            .map_err(|e| format!("failed to write archive: {e}"))?;
+        written
+    }
+
+    async fn write_files(
+        &self,
+        claim: &Claim,
+        sender: &mpsc::Sender<Chunk>,
+    ) -> Result<i64, String> {
+        let export = &claim.export;
+        let mut manifest = Manifest {
+            export_id: export.id,
+            merchant_id: export.merchant_id,
+            exported_at: OffsetDateTime::now_utc(),
+            files: BTreeMap::new(),
+        };
+        let mut exported = 0;
+
+        self.write_file(
+            claim,
+            sender,
+            "payments.csv",
+            payments,
+            &mut manifest,
+            &mut exported,
+        )
+        .await?;
+        self.write_file(
+            claim,
+            sender,
+            "refunds.csv",
+            refunds,
+            &mut manifest,
+            &mut exported,
+        )
+        .await?;
+        self.write_file(
+            claim,
+            sender,
+            "disputes.csv",
+            disputes,
+            &mut manifest,
+            &mut exported,
+        )
+        .await?;
+        self.write_file(
+            claim,
+            sender,
+            "webhook_deliveries.csv",
+            webhook_deliveries,
+            &mut manifest,
+            &mut exported,
+        )
+        .await?;
+
+        let manifest = serde_json::to_vec_pretty(&manifest).expect("failed to serialize manifest");
+        send(sender, Chunk::File("export.json")).await?;
+        send(sender, Chunk::Data(manifest)).await?;
+
+        Ok(exported)
+    }
+
+    /// Writes the rows returned by `fetch` as the CSV file `name`.
+    async fn write_file<R: Row>(
+        &self,
+        claim: &Claim,
+        sender: &mpsc::Sender<Chunk>,
+        name: &'static str,
+        fetch: Fetch<R>,
+        manifest: &mut Manifest,
+        exported: &mut i64,
+    ) -> Result<(), String> {
+        let export = &claim.export;
+        send(sender, Chunk::File(name)).await?;
+        send(sender, Chunk::Data(csv_chunk::<R>(&[], Some(R::HEADERS))?)).await?;
+
+        let mut after = None;
+        let mut rows_of_file = 0;
+        loop {
+            let rows = fetch(&self.pool, export.merchant_id, after, self.batch_size)
+                .await
+                .map_err(|e| format!("failed to read {name}: {e}"))?;
+            let Some(last) = rows.last() else {
+                break;
+            };
+            after = Some(last.cursor());
+
+            let count = rows.len() as i64;
+            send(sender, Chunk::Data(csv_chunk(&rows, None)?)).await?;
+            rows_of_file += count;
+            *exported += count;
+
+            let owned = record_progress(&self.pool, export.id, claim.id, *exported)
+                .await
+                .map_err(|e| format!("failed to record progress: {e}"))?;
+            if !owned {
+                return Err("export was claimed by another exporter".to_string());
+            }
+
+            if count < self.batch_size {
+                break;
+            }
+        }
+
+        manifest.files.insert(name, rows_of_file);
+        Ok(())
+    }
+}
+
+async fn send(sender: &mpsc::Sender<Chunk>, chunk: Chunk) -> Result<(), String> {
+    sender
+        .send(chunk)
+        .await
+        .map_err(|_| "archive writer stopped".to_string())
+}
+
+/// Serializes rows as CSV, after `headers` if given.
+fn csv_chunk<R: Serialize>(rows: &[R], headers: Option<&[&str]>) -> Result<Vec<u8>, String> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    if let Some(headers) = headers {
+        writer
+            .write_record(headers)
+            .map_err(|e| format!("failed to write CSV headers: {e}"))?;
+    }
+    for row in rows {
+        writer
+            .serialize(row)
+            .map_err(|e| format!("failed to write CSV row: {e}"))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| format!("failed to flush CSV rows: {e}"))
+}
@@ -0,0 +1,214 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of basis points in 100%.
+const BASIS_POINTS_SCALE: i128 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeePolicyError {
+    InvalidBasisPoints,
+    NegativeFixedAmount,
+    ParseError(String),
+}
+
+impl Display for FeePolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Fee charged on an approved payment: a rate in basis points of the amount
+/// plus a fixed component in minor units (e.g. 140 bps + 25 for 1.4% + 0.25).
+///
+/// Each merchant can agree to its own, see `merchants::set_fee_policy`.
+///
+/// Money is never computed with floating point: both components are integers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FeePolicy {
+    basis_points: i32,
+    fixed_amount: i64,
+}
+
+impl FeePolicy {
+    pub fn new(basis_points: i32, fixed_amount: i64) -> Result<Self, FeePolicyError> {
+        if !(0..=BASIS_POINTS_SCALE as i32).contains(&basis_points) {
+            Err(FeePolicyError::InvalidBasisPoints)
+        } else if fixed_amount < 0 {
+            Err(FeePolicyError::NegativeFixedAmount)
+        } else {
+            Ok(Self {
+                basis_points,
+                fixed_amount,
+            })
+        }
+    }
+
+    pub fn basis_points(&self) -> i32 {
+        self.basis_points
+    }
+
+    pub fn fixed_amount(&self) -> i64 {
+        self.fixed_amount
+    }
+
+    /// Reads `FEE_BASIS_POINTS` and `FEE_FIXED_AMOUNT`, each defaulting to 0,
+    /// the bank's default policy of the merchants without one, see
+    /// `merchants::get_fee_policy`.
+    pub fn from_env() -> Result<Self, FeePolicyError> {
+        fn read<T>(name: &str) -> Result<T, FeePolicyError>
+        where
+            T: FromStr + Default,
+            T::Err: Display,
+        {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse::<T>()
+                    .map_err(|e| FeePolicyError::ParseError(format!("{name}: {e}"))),
+                Err(_) => Ok(T::default()),
+            }
+        }
+
+        Self::new(read("FEE_BASIS_POINTS")?, read("FEE_FIXED_AMOUNT")?)
+    }
+}
+
+/// Fees withheld from a payment amount, in minor units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FeeBreakdown {
+    pub percentage_fee: i64,
+    pub fixed_fee: i64,
+    pub total_fee: i64,
+    pub net_amount: i64,
+}
+
+/// Divides rounding half up (i.e. half away from zero for the non-negative
+/// operands used here): a fee of exactly half a minor unit is charged.
+///
+/// Half up is used over banker's rounding because fees are computed per
+/// payment and must be reproducible by merchants with a simple formula.
+fn div_round_half_up(numerator: i128, denominator: i128) -> i128 {
+    (numerator + denominator / 2) / denominator
+}
+
+/// Computes the fees of a payment `amount` under `policy`.
+///
+/// The fee never exceeds the amount: the fixed component is capped to what
+/// remains after the percentage component.
+pub fn compute(amount: i64, policy: &FeePolicy) -> FeeBreakdown {
+    // the product of an amount and basis points may overflow an i64
+    let amount = i128::from(amount.max(0));
+
+    let percentage_fee =
+        div_round_half_up(amount * i128::from(policy.basis_points), BASIS_POINTS_SCALE);
+    let fixed_fee = i128::from(policy.fixed_amount).min(amount - percentage_fee);
+    let total_fee = percentage_fee + fixed_fee;
+
+    // every value is bounded by `amount`, which came from an i64
+    FeeBreakdown {
+        percentage_fee: percentage_fee as i64,
+        fixed_fee: fixed_fee as i64,
+        total_fee: total_fee as i64,
+        net_amount: (amount - total_fee) as i64,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use proptest::strategy::Strategy;
+
+    use super::*;
+
+    #[test]
+    fn should_compute_known_fees() {
+        let cases = [
+            // (amount, basis points, fixed, percentage fee, fixed fee)
+            (10_000, 140, 25, 140, 25),
+            (1205, 140, 25, 17, 25),
+            // 0.5 minor unit rounds up
+            (50, 100, 0, 1, 0),
+            // 0.49 minor unit rounds down
+            (49, 100, 0, 0, 0),
+            (0, 140, 25, 0, 0),
+            // fixed component capped to the amount
+            (10, 140, 25, 0, 10),
+            (100, 10_000, 25, 100, 0),
+            (i64::MAX, 10_000, 0, i64::MAX, 0),
+            (i64::MAX, 140, 0, 129_127_208_515_966_861, 0),
+            (123, 0, 0, 0, 0),
+        ];
+
+        for (amount, basis_points, fixed, percentage_fee, fixed_fee) in cases {
+            let policy = FeePolicy::new(basis_points, fixed).unwrap();
+            let fees = compute(amount, &policy);
+
+            assert_eq!(
+                (fees.percentage_fee, fees.fixed_fee),
+                (percentage_fee, fixed_fee),
+                "fees of {amount} at {basis_points} bps + {fixed}"
+            );
+            assert_eq!(fees.total_fee, percentage_fee + fixed_fee);
+            assert_eq!(fees.net_amount, amount - fees.total_fee);
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_policies() {
+        assert_eq!(
+            FeePolicy::new(10_001, 0),
+            Err(FeePolicyError::InvalidBasisPoints)
+        );
+        assert_eq!(
+            FeePolicy::new(-1, 0),
+            Err(FeePolicyError::InvalidBasisPoints)
+        );
+        assert_eq!(
+            FeePolicy::new(100, -1),
+            Err(FeePolicyError::NegativeFixedAmount)
+        );
+    }
+
+    /// Valid policies, from free to the whole amount plus a large fixed fee.
+    fn policies() -> impl Strategy<Value = FeePolicy> {
+        (0..=10_000i32, 0..=100_000i64).prop_map(|(basis_points, fixed_amount)| {
+            FeePolicy::new(basis_points, fixed_amount).expect("failed to create policy")
+        })
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_fee_never_exceeds_amount(policy in policies(), amount in 0..=i64::MAX) {
+            let fees = compute(amount, &policy);
+
+            proptest::prop_assert!(fees.total_fee <= amount, "{:?} for {}", fees, amount);
+            proptest::prop_assert_eq!(fees.total_fee, fees.percentage_fee + fees.fixed_fee);
+            proptest::prop_assert_eq!(fees.net_amount, amount - fees.total_fee);
+        }
+
+        #[test]
+        fn test_fee_is_monotonic_in_amount(
+            policy in policies(),
+            amount in 0..i64::MAX / 2,
+            increase in 0..i64::MAX / 2,
+        ) {
+            proptest::prop_assert!(
+                compute(amount, &policy).total_fee <= compute(amount + increase, &policy).total_fee,
+                "{:?} for {} and {}", policy, amount, amount + increase
+            );
+        }
+
+        #[test]
+        fn test_percentage_fee_rounds_half_up(basis_points in 0..=10_000i32, amount in 0..=i64::MAX) {
+            let policy = FeePolicy::new(basis_points, 0).unwrap();
+            let fee = i128::from(compute(amount, &policy).percentage_fee);
+
+            // the exact fee times the scale, compared without dividing
+            let exact = i128::from(amount) * i128::from(basis_points);
+            let error = fee * BASIS_POINTS_SCALE - exact;
+            proptest::prop_assert!(
+                -BASIS_POINTS_SCALE / 2 < error && error <= BASIS_POINTS_SCALE / 2,
+                "{} for {} at {} bps", fee, amount, basis_points
+            );
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::future::Future;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use super::accounts::{AccountService, HoldRef};
+
+/// Journal entry of a call made to the account service.
+///
+/// Every call is journaled right before it is made and its outcome recorded
+/// right after, so the journal proves that each successful hold was
+/// concluded by exactly one successful release or withdrawal. A call whose
+/// outcome is missing was interrupted, and may or may not have gone through.
+#[derive(Debug, Clone, sqlx::FromRow)]
+#[allow(dead_code)]
+pub struct Operation {
+    pub id: Uuid,
+    pub kind: OperationKind,
+    pub payment_id: Option<Uuid>,
+    pub hold_ref: Option<Uuid>,
+    pub started_at: PrimitiveDateTime,
+    pub finished_at: Option<PrimitiveDateTime>,
+    pub outcome: Option<Outcome>,
+    /// Error returned by the account service, if the call failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "AccountOperationKind")]
+pub enum OperationKind {
+    Hold,
+    Release,
+    Withdraw,
+    Deposit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "AccountOperationOutcome")]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+}
+
+/// A hold for which the journal doesn't show exactly one successful release
+/// or withdrawal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub hold_ref: Uuid,
+    pub payment_id: Option<Uuid>,
+    /// Number of successful holds with this reference, which should be 1.
+    pub holds: i64,
+    /// Number of successful releases and withdrawals of the hold.
+    pub terminal_operations: i64,
+}
+
+tokio::task_local! {
+    /// Payment the account service calls of the current task are made for.
+    static PAYMENT_ID: Uuid;
+}
+
+/// Runs `future`, journaling the account service calls it makes as made for
+/// `payment_id`.
+pub async fn for_payment<F: Future>(payment_id: Uuid, future: F) -> F::Output {
+    PAYMENT_ID.scope(payment_id, future).await
+}
+
+fn current_payment_id() -> Option<Uuid> {
+    PAYMENT_ID.try_with(|payment_id| *payment_id).ok()
+}
+
+/// Journals the start of an operation, returning its id.
+pub async fn start(
+    pool: &PgPool,
+    kind: OperationKind,
+    payment_id: Option<Uuid>,
+    hold_ref: Option<Uuid>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+            INSERT INTO account_operations ( kind, payment_id, hold_ref )
+            VALUES ( $1, $2, $3 )
+            RETURNING id
+        "#,
+        kind as OperationKind,
+        payment_id,
+        hold_ref,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Journals the outcome of the operation `id`.
+///
+/// `hold_ref` is the reference of the hold placed by a successful hold
+/// operation, it is kept as is if `None`.
+pub async fn finish(
+    pool: &PgPool,
+    id: Uuid,
+    hold_ref: Option<Uuid>,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let outcome = match error {
+        Some(_) => Outcome::Failed,
+        None => Outcome::Succeeded,
+    };
+
+    sqlx::query!(
+        r#"
+            UPDATE account_operations
+            SET hold_ref = COALESCE($2, hold_ref), finished_at = clock_timestamp(), outcome = $3, error = $4
+            WHERE id = $1
+        "#,
+        id,
+        hold_ref,
+        outcome as Outcome,
+        error,
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Lists the operations made for `payment_id`, in the order they started.
+#[cfg_attr(not(test), allow(dead_code))]
+pub async fn list(pool: &PgPool, payment_id: Uuid) -> Result<Vec<Operation>, sqlx::Error> {
+    sqlx::query_as!(
+        Operation,
+        r#"
+            SELECT
+                id,
+                kind as "kind: _",
+                payment_id,
+                hold_ref,
+                started_at,
+                finished_at,
+                outcome as "outcome: _",
+                error
+            FROM account_operations
+            WHERE payment_id = $1
+            ORDER BY started_at, id
+        "#,
+        payment_id,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Checks that every successful hold, of `payment_id` if given, is concluded
+/// by exactly one successful release or withdrawal, and that only held funds
+/// are released or withdrawn.
+///
+/// Holds of payments still processing or authorized aren't expected to be
+/// concluded yet.
+// run by audits, nothing reports violations on its own yet
+#[allow(dead_code)]
+pub async fn violations(
+    pool: &PgPool,
+    payment_id: Option<Uuid>,
+) -> Result<Vec<Violation>, sqlx::Error> {
+    sqlx::query_as!(
+        Violation,
+        r#"
+            WITH pairs AS (
+                SELECT
+                    hold_ref,
+                    (array_agg(payment_id) FILTER (WHERE kind = 'Hold'))[1] AS payment_id,
+                    COUNT(*) FILTER (WHERE kind = 'Hold') AS holds,
+                    COUNT(*) FILTER (WHERE kind <> 'Hold') AS terminal_operations
+                FROM account_operations
+                WHERE outcome = 'Succeeded' AND hold_ref IS NOT NULL
+                GROUP BY hold_ref
+            )
+            SELECT
+                pairs.hold_ref as "hold_ref!",
+                pairs.payment_id,
+                pairs.holds as "holds!",
+                pairs.terminal_operations as "terminal_operations!"
+            FROM pairs
+            LEFT JOIN payments ON payments.id = pairs.payment_id
+            WHERE ($1::uuid IS NULL OR pairs.payment_id = $1)
+              AND (
+                pairs.holds <> 1
+                OR pairs.terminal_operations > 1
+                OR (
+                    pairs.terminal_operations = 0
+                    AND payments.status IS DISTINCT FROM 'Processing'
+                    AND payments.status IS DISTINCT FROM 'Authorized'
+                )
+              )
+            ORDER BY pairs.hold_ref
+        "#,
+        payment_id,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Account service journaling every call made through it.
+///
+/// Calls are journaled as made for the payment set by `for_payment`, if
+/// any. A call that can't be journaled isn't made, and fails with
+/// `journal_unavailable`.
+#[derive(Clone)]
+pub struct JournaledService<T> {
+    pool: PgPool,
+    service: T,
+}
+
+impl<T> JournaledService<T> {
+    pub fn new(pool: PgPool, service: T) -> Self {
+        Self { pool, service }
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn inner(&self) -> &T {
+        &self.service
+    }
+
+    async fn start(&self, kind: OperationKind, hold_ref: Option<HoldRef>) -> Result<Uuid, String> {
+        start(
+            &self.pool,
+            kind,
+            current_payment_id(),
+            hold_ref.map(|hold_ref| hold_ref.id()),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to journal {kind:?} operation: {e}");
+            "journal_unavailable".to_string()
+        })
+    }
+
+    /// Journals the outcome of a call, which is returned as is since the call
+    /// was made either way.
+    async fn finish<R>(
+        &self,
+        id: Uuid,
+        hold_ref: Option<HoldRef>,
+        result: Result<R, String>,
+    ) -> Result<R, String> {
+        let error = result.as_ref().err().map(String::as_str);
+        if let Err(e) = finish(&self.pool, id, hold_ref.map(|r| r.id()), error).await {
+            tracing::error!("failed to journal the outcome of operation {id}: {e}");
+        }
+
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: AccountService> AccountService for JournaledService<T> {
+    async fn place_hold(&self, account_number: &str, amount: i64) -> Result<HoldRef, String> {
+        let id = self.start(OperationKind::Hold, None).await?;
+        let result = self.service.place_hold(account_number, amount).await;
+        let hold_ref = result.as_ref().ok().copied();
+        self.finish(id, hold_ref, result).await
+    }
+
+    async fn release_hold(&self, hold_ref: HoldRef) -> Result<(), String> {
+        let id = self.start(OperationKind::Release, Some(hold_ref)).await?;
+        let result = self.service.release_hold(hold_ref).await;
+        self.finish(id, None, result).await
+    }
+
+    async fn withdraw_funds(&self, hold_ref: HoldRef) -> Result<(), String> {
+        let id = self.start(OperationKind::Withdraw, Some(hold_ref)).await?;
+        let result = self.service.withdraw_funds(hold_ref).await;
+        self.finish(id, None, result).await
+    }
+
+    async fn deposit_funds(&self, account_number: &str, amount: i64) -> Result<(), String> {
+        let id = self.start(OperationKind::Deposit, None).await?;
+        let result = self.service.deposit_funds(account_number, amount).await;
+        self.finish(id, None, result).await
+    }
+
+    // validations don't move money, so they aren't journaled
+    async fn validate_account(&self, account_number: &str) -> Result<(), String> {
+        self.service.validate_account(account_number).await
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank::{
+        accounts::{AccountMethod, DummyService, ScriptedOutcome},
+        payments::{self, tests::new_processing_payment},
+    };
+
+    fn journaled_service(pool: &PgPool) -> JournaledService<DummyService> {
+        JournaledService::new(pool.clone(), DummyService::default())
+    }
+
+    #[tokio::test]
+    async fn test_journal_calls_of_payment() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let service = journaled_service(&pool);
+        let payment_id = new_processing_payment(&pool).await;
+
+        let hold_ref = for_payment(payment_id, service.place_hold("12345", 1))
+            .await
+            .expect("failed to place hold");
+        for_payment(payment_id, service.withdraw_funds(hold_ref))
+            .await
+            .expect("failed to withdraw funds");
+
+        let operations = list(&pool, payment_id)
+            .await
+            .expect("failed to list operations");
+        let kinds: Vec<_> = operations.iter().map(|o| o.kind).collect();
+        assert_eq!(kinds, [OperationKind::Hold, OperationKind::Withdraw]);
+        assert!(operations.iter().all(|o| o.payment_id == Some(payment_id)
+            && o.hold_ref == Some(hold_ref.id())
+            && o.finished_at
+                .is_some_and(|finished_at| finished_at >= o.started_at)));
+
+        // the hold is concluded, whatever the payment status
+        payments::transition(
+            &pool,
+            payment_id,
+            payments::Status::Processing,
+            payments::Status::Failed,
+        )
+        .await
+        .expect("failed to transition payment");
+        let violations = violations(&pool, Some(payment_id))
+            .await
+            .expect("failed to check journal");
+        assert_eq!(violations, []);
+    }
+
+    #[tokio::test]
+    async fn test_report_unconcluded_and_twice_concluded_holds() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let service = journaled_service(&pool);
+        let payment_id = new_processing_payment(&pool).await;
+
+        let hold_ref = for_payment(payment_id, service.place_hold("12345", 1))
+            .await
+            .expect("failed to place hold");
+        let violations_of_payment = || violations(&pool, Some(payment_id));
+        // the payment may still conclude its hold
+        assert_eq!(violations_of_payment().await.unwrap(), []);
+
+        payments::transition(
+            &pool,
+            payment_id,
+            payments::Status::Processing,
+            payments::Status::Failed,
+        )
+        .await
+        .expect("failed to transition payment");
+        let expected = |terminal_operations| Violation {
+            hold_ref: hold_ref.id(),
+            payment_id: Some(payment_id),
+            holds: 1,
+            terminal_operations,
+        };
+        assert_eq!(violations_of_payment().await.unwrap(), [expected(0)]);
+
+        for _ in 0..2 {
+            for_payment(payment_id, service.release_hold(hold_ref))
+                .await
+                .expect("failed to release hold");
+        }
+        assert_eq!(violations_of_payment().await.unwrap(), [expected(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_journal_failed_calls() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let service = journaled_service(&pool);
+        service.inner().set_scripted_outcome(Some(
+            ScriptedOutcome::new("service_unavailable").with_method(AccountMethod::Hold),
+        ));
+        let payment_id = new_processing_payment(&pool).await;
+
+        let result = for_payment(payment_id, service.place_hold("12345", 1)).await;
+        assert_eq!(result.unwrap_err(), "service_unavailable");
+
+        let operations = list(&pool, payment_id)
+            .await
+            .expect("failed to list operations");
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].outcome, Some(Outcome::Failed));
+        assert_eq!(operations[0].error.as_deref(), Some("service_unavailable"));
+        assert_eq!(operations[0].hold_ref, None);
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::fees::FeePolicy;
+
+/// Refund rules a merchant agreed to, enforced on every refund of its
+/// payments.
+///
+/// Merchants without a stored policy get the bank's default refund window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RefundPolicy {
+    /// Days after a payment during which it can be refunded, the bank's
+    /// default if `None`.
+    pub refund_window_days: Option<i32>,
+}
+
+impl RefundPolicy {
+    /// Returns the period after a payment during which it can be refunded,
+    /// `default` unless the merchant agreed to another one.
+    pub fn refund_window(&self, default: Duration) -> Duration {
+        match self.refund_window_days {
+            Some(days) => Duration::from_secs(u64::try_from(days).unwrap_or(0) * 24 * 60 * 60),
+            None => default,
+        }
+    }
+}
+
+pub async fn get_refund_policy(
+    pool: &PgPool,
+    merchant_id: Uuid,
+) -> Result<RefundPolicy, sqlx::Error> {
+    let refund_window_days = sqlx::query!(
+        r#"
+            SELECT refund_window_days FROM merchant_refund_policies
+            WHERE merchant_id = $1
+        "#,
+        merchant_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .and_then(|record| record.refund_window_days);
+
+    Ok(RefundPolicy { refund_window_days })
+}
+
+/// Stores the refund policy of a merchant, replacing its current one.
+#[cfg_attr(not(test), allow(dead_code))]
+pub async fn set_refund_policy(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    policy: RefundPolicy,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO merchant_refund_policies ( merchant_id, refund_window_days )
+            VALUES ( $1, $2 )
+            ON CONFLICT ( merchant_id ) DO UPDATE
+            SET refund_window_days = EXCLUDED.refund_window_days,
+                updated_at = current_timestamp
+        "#,
+        merchant_id,
+        policy.refund_window_days
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Returns the fee policy a merchant agreed to, `None` if it is charged the
+/// bank's default one.
+pub async fn get_fee_policy(
+    pool: &PgPool,
+    merchant_id: Uuid,
+) -> Result<Option<FeePolicy>, sqlx::Error> {
+    let Some(record) = sqlx::query!(
+        r#"
+            SELECT basis_points, fixed_amount FROM merchant_fee_policies
+            WHERE merchant_id = $1
+        "#,
+        merchant_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    // the checks of the table mirror the ones of `FeePolicy::new`
+    FeePolicy::new(record.basis_points, record.fixed_amount)
+        .map(Some)
+        .map_err(|e| sqlx::Error::Decode(format!("invalid fee policy: {e}").into()))
+}
+
+/// Stores the fee policy of a merchant, replacing its current one.
+pub async fn set_fee_policy(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    policy: FeePolicy,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO merchant_fee_policies ( merchant_id, basis_points, fixed_amount )
+            VALUES ( $1, $2, $3 )
+            ON CONFLICT ( merchant_id ) DO UPDATE
+            SET basis_points = EXCLUDED.basis_points,
+                fixed_amount = EXCLUDED.fixed_amount,
+                updated_at = current_timestamp
+        "#,
+        merchant_id,
+        policy.basis_points(),
+        policy.fixed_amount()
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Returns the address the digests of a merchant are emailed to, if it gave
+/// one.
+pub async fn get_contact_email(
+    pool: &PgPool,
+    merchant_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            SELECT email FROM merchant_contacts
+            WHERE merchant_id = $1
+        "#,
+        merchant_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map(|record| record.map(|record| record.email))
+}
+
+/// Stores the address the digests of a merchant are emailed to, replacing
+/// its current one.
+#[cfg_attr(not(test), allow(dead_code))]
+pub async fn set_contact_email(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO merchant_contacts ( merchant_id, email )
+            VALUES ( $1, $2 )
+            ON CONFLICT ( merchant_id ) DO UPDATE
+            SET email = EXCLUDED.email,
+                updated_at = current_timestamp
+        "#,
+        merchant_id,
+        email
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refund_window() {
+        let default = Duration::from_secs(90 * 24 * 60 * 60);
+        let policy = RefundPolicy {
+            refund_window_days: Some(180),
+        };
+
+        assert_eq!(
+            policy.refund_window(default),
+            Duration::from_secs(180 * 24 * 60 * 60)
+        );
+        assert_eq!(RefundPolicy::default().refund_window(default), default);
+    }
+
+    #[tokio::test]
+    async fn test_set_fee_policy() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let merchant_id = Uuid::new_v4();
+
+        assert_eq!(
+            get_fee_policy(&pool, merchant_id)
+                .await
+                .expect("failed to get fee policy"),
+            None
+        );
+
+        for policy in [FeePolicy::new(140, 25), FeePolicy::new(0, 30)] {
+            let policy = policy.unwrap();
+            set_fee_policy(&pool, merchant_id, policy)
+                .await
+                .expect("failed to set fee policy");
+            assert_eq!(
+                get_fee_policy(&pool, merchant_id)
+                    .await
+                    .expect("failed to get fee policy"),
+                Some(policy)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_refund_policy() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let merchant_id = Uuid::new_v4();
+
+        let policy = get_refund_policy(&pool, merchant_id)
+            .await
+            .expect("failed to get refund policy");
+        assert_eq!(policy, RefundPolicy::default());
+
+        for refund_window_days in [Some(180), None] {
+            let policy = RefundPolicy { refund_window_days };
+            set_refund_policy(&pool, merchant_id, policy)
+                .await
+                .expect("failed to set refund policy");
+            assert_eq!(
+                get_refund_policy(&pool, merchant_id)
+                    .await
+                    .expect("failed to get refund policy"),
+                policy
+            );
+        }
+    }
+}
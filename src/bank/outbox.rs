@@ -0,0 +1,366 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgExecutor, PgPool};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use super::{payments::Status, transactions};
+
+/// Kind of a domain event published to downstream consumers, e.g. fraud
+/// detection or analytics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventType {
+    /// A payment was recorded, in its initial status.
+    #[serde(rename = "payment.created")]
+    PaymentCreated,
+    /// A payment moved from one status to another.
+    #[serde(rename = "payment.status_changed")]
+    PaymentStatusChanged,
+    /// A refund was recorded, its money not deposited yet.
+    #[serde(rename = "refund.created")]
+    RefundCreated,
+    /// The money of a refund was deposited.
+    #[serde(rename = "refund.settled")]
+    RefundSettled,
+}
+
+impl EventType {
+    pub const REFUNDS: [EventType; 2] = [EventType::RefundCreated, EventType::RefundSettled];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::PaymentCreated => "payment.created",
+            EventType::PaymentStatusChanged => "payment.status_changed",
+            EventType::RefundCreated => "refund.created",
+            EventType::RefundSettled => "refund.settled",
+        }
+    }
+}
+
+/// Payload of an event recorded in the outbox.
+pub trait Payload: Serialize {
+    fn event_type(&self) -> EventType;
+}
+
+/// Payload of the events about payments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentPayload {
+    pub event_type: EventType,
+    pub payment_id: Uuid,
+    /// Status the payment left, `None` for created payments.
+    pub old_status: Option<Status>,
+    pub new_status: Status,
+}
+
+impl Payload for PaymentPayload {
+    fn event_type(&self) -> EventType {
+        self.event_type
+    }
+}
+
+impl PaymentPayload {
+    pub fn new(payment_id: Uuid, old_status: Option<Status>, new_status: Status) -> Self {
+        let event_type = match old_status {
+            Some(_) => EventType::PaymentStatusChanged,
+            None => EventType::PaymentCreated,
+        };
+
+        Self {
+            event_type,
+            payment_id,
+            old_status,
+            new_status,
+        }
+    }
+}
+
+/// Payload of the events about refunds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefundPayload {
+    pub event_type: EventType,
+    pub refund_id: Uuid,
+    pub payment_id: Uuid,
+    pub amount: i64,
+    /// Amount of the payment still refundable once the event happened.
+    pub remaining_amount: i64,
+}
+
+impl Payload for RefundPayload {
+    fn event_type(&self) -> EventType {
+        self.event_type
+    }
+}
+
+/// An event of the outbox, published once by a `Publisher`.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct OutboxEvent {
+    pub id: Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub inserted_at: PrimitiveDateTime,
+    pub dispatched_at: Option<PrimitiveDateTime>,
+}
+
+/// Records an event in the outbox.
+///
+/// Callers are expected to pass the transaction that writes the payment or
+/// refund row, so the event is published if and only if the change is
+/// committed.
+pub async fn record(
+    executor: impl PgExecutor<'_>,
+    payload: &impl Payload,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO outbox_events ( event_type, payload )
+            VALUES ( $1, $2 )
+            RETURNING id
+        "#,
+        payload.event_type().as_str(),
+        serde_json::to_value(payload).expect("failed to serialize outbox payload")
+    )
+    .fetch_one(executor)
+    .await
+    .map(|record| record.id)
+}
+
+/// Transport of the published events.
+pub type Sink = Arc<dyn Fn(&OutboxEvent) + Send + Sync>;
+
+/// Default transport, emitting every event as a tracing event.
+pub fn log_event(event: &OutboxEvent) {
+    tracing::info!(
+        outbox.event_id = %event.id,
+        outbox.event_type = event.event_type,
+        outbox.payload = %event.payload,
+        "published outbox event"
+    );
+}
+
+/// Background task publishing the events of the outbox, oldest first.
+///
+/// Publishers claim batches of pending events with `FOR UPDATE SKIP LOCKED`,
+/// so concurrent publishers never publish the same event, and mark them
+/// dispatched in the claiming transaction. Events of a publisher crashing
+/// mid-batch are released by the rollback and published again.
+pub struct Publisher {
+    pool: PgPool,
+    sink: Sink,
+    poll_interval: Duration,
+    batch_size: i64,
+}
+
+impl Publisher {
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+    pub const DEFAULT_BATCH_SIZE: i64 = 100;
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            sink: Arc::new(log_event),
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets the transport of the published events, a tracing event by
+    /// default.
+    pub fn with_sink(mut self, sink: Sink) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Sets the largest number of events claimed at once.
+    pub fn with_batch_size(mut self, batch_size: i64) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Sets how often the outbox is polled when it has no pending events.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Publishes the pending events until the task is aborted.
+    pub async fn run(self) {
+        loop {
+            match self.publish_batch().await {
+                Ok(0) => tokio::time::sleep(self.poll_interval).await,
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("failed to publish outbox events: {e}");
+                    tokio::time::sleep(self.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Claims a batch of pending events, publishes them and marks them
+    /// dispatched, returning how many were published.
+    pub async fn publish_batch(&self) -> Result<usize, sqlx::Error> {
+        let mut tx = transactions::begin(&self.pool).await?;
+
+        let events = sqlx::query_as!(
+            OutboxEvent,
+            r#"
+                SELECT id, event_type, payload, inserted_at, dispatched_at FROM outbox_events
+                WHERE dispatched_at IS NULL
+                ORDER BY inserted_at, id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            "#,
+            self.batch_size
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        for event in &events {
+            (self.sink)(event);
+        }
+
+        let ids: Vec<Uuid> = events.iter().map(|event| event.id).collect();
+        sqlx::query!(
+            r#"
+                UPDATE outbox_events SET dispatched_at = LOCALTIMESTAMP
+                WHERE id = ANY($1)
+            "#,
+            &ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(events.len())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use super::*;
+    use crate::bank::{
+        currencies::Currency,
+        payment_instruments::Card,
+        payments::{self, tests::*, DeclineReason, ProcessingMode, Source},
+    };
+
+    /// Returns the events recorded about a payment, oldest first.
+    pub async fn list_for_payment(
+        pool: &PgPool,
+        payment_id: Uuid,
+    ) -> Result<Vec<OutboxEvent>, sqlx::Error> {
+        sqlx::query_as!(
+            OutboxEvent,
+            r#"
+                SELECT id, event_type, payload, inserted_at, dispatched_at FROM outbox_events
+                WHERE payload->>'payment_id' = $1
+                ORDER BY inserted_at, id
+            "#,
+            payment_id.to_string()
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    async fn new_declined_payment(pool: &PgPool) -> Uuid {
+        let id = payments::insert(
+            pool,
+            MERCHANT_ID,
+            PAYMENT_AMOUNT,
+            &Card::new_test(),
+            Currency::default().into(),
+            Status::Processing,
+            ProcessingMode::Sync,
+            None,
+            None,
+            Source::default(),
+            CARD_REUSE_WINDOW,
+        )
+        .await
+        .expect("failed to insert payment");
+
+        payments::decline(
+            pool,
+            id,
+            Status::Processing,
+            DeclineReason::InsufficientFunds,
+        )
+        .await
+        .expect("failed to decline payment");
+        id
+    }
+
+    #[tokio::test]
+    async fn test_record_events_of_declined_payment() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to create postgres pool");
+        let payment_id = new_declined_payment(&pool).await;
+
+        let events = list_for_payment(&pool, payment_id)
+            .await
+            .expect("failed to list outbox events");
+        let payloads: Vec<PaymentPayload> = events
+            .iter()
+            .map(|event| serde_json::from_value(event.payload.clone()).unwrap())
+            .collect();
+        assert_eq!(
+            payloads,
+            vec![
+                PaymentPayload::new(payment_id, None, Status::Processing),
+                PaymentPayload::new(payment_id, Some(Status::Processing), Status::Declined),
+            ]
+        );
+        assert_eq!(events[1].event_type, "payment.status_changed");
+        assert_eq!(events[1].payload["new_status"], "declined");
+    }
+
+    #[tokio::test]
+    async fn test_publish_events_once_under_concurrent_publishers() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to create postgres pool");
+        let mut payment_ids = Vec::new();
+        for _ in 0..10 {
+            payment_ids.push(new_declined_payment(&pool).await);
+        }
+
+        let published = Arc::new(Mutex::new(HashMap::<Uuid, usize>::new()));
+        let publishers = (0..4).map(|_| {
+            let published = published.clone();
+            let publisher = Publisher::new(pool.clone())
+                .with_batch_size(3)
+                .with_sink(Arc::new(move |event| {
+                    *published.lock().unwrap().entry(event.id).or_default() += 1;
+                }));
+
+            tokio::spawn(async move {
+                while publisher.publish_batch().await.expect("failed to publish") > 0 {}
+            })
+        });
+        for publisher in publishers.collect::<Vec<_>>() {
+            publisher.await.expect("publisher panicked");
+        }
+
+        let published = published.lock().unwrap().clone();
+        for payment_id in payment_ids {
+            let events = list_for_payment(&pool, payment_id)
+                .await
+                .expect("failed to list outbox events");
+            assert_eq!(events.len(), 2);
+
+            for event in events {
+                assert!(event.dispatched_at.is_some(), "{event:?}");
+                assert_eq!(published.get(&event.id), Some(&1), "{event:?}");
+            }
+        }
+    }
+}
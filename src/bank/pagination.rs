@@ -0,0 +1,50 @@
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+/// Position of a row in a listing ordered by insertion time then id, or
+/// first by another column when `sort` is set.
+///
+/// A page starts right after its cursor, so rows inserted while paging
+/// neither shift nor repeat the following pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub inserted_at: PrimitiveDateTime,
+    pub id: Uuid,
+    /// Sort of the listing the cursor comes from, unless it is the listing's
+    /// default one.
+    pub sort: Option<SortPosition>,
+}
+
+/// Sort of a listing, and the position of a cursor within it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortPosition {
+    /// Name of the sort, e.g. `amount.asc`, which only the listing knows.
+    pub sort: String,
+    /// Value of the sorted column at the cursor, as text, unless the listing
+    /// is sorted by insertion time.
+    pub value: Option<String>,
+}
+
+/// Slice of a listing to fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// Rows up to and including this one are skipped.
+    pub after: Option<Cursor>,
+    /// Maximum number of rows to fetch.
+    pub limit: i64,
+}
+
+impl Page {
+    /// Splits a cursor into the parameters bound by the listing queries.
+    pub fn after(&self) -> (Option<PrimitiveDateTime>, Option<Uuid>) {
+        match &self.after {
+            Some(cursor) => (Some(cursor.inserted_at), Some(cursor.id)),
+            None => (None, None),
+        }
+    }
+
+    /// Returns the value of the sorted column at the cursor, if any.
+    pub fn after_sort_value(&self) -> Option<&str> {
+        self.after.as_ref()?.sort.as_ref()?.value.as_deref()
+    }
+}
@@ -1,7 +1,11 @@
 use std::{fmt::Display, num::ParseIntError};
 
+use sha2::{Digest, Sha256};
+
 const CARD_NUMBER_LENGTH: usize = 15;
 const ACCOUNT_PREFIX_LENGTH: usize = 2;
+/// Number of trailing digits left visible by `Card::masked`.
+const UNMASKED_SUFFIX_LENGTH: usize = 4;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CardError {
@@ -55,6 +59,34 @@ impl Card {
     pub fn card_number(&self) -> &str {
         &self.0
     }
+
+    /// Returns the card number with every digit but the account number and
+    /// the last four replaced by `*`, e.g. `23*********1234`.
+    ///
+    /// Only the masked card number is stored and returned to merchants.
+    pub fn masked(&self) -> String {
+        let visible_from = self.0.len().saturating_sub(UNMASKED_SUFFIX_LENGTH);
+        let (hidden, suffix) = self.0.split_at(visible_from);
+        let (prefix, hidden) = hidden.split_at(ACCOUNT_PREFIX_LENGTH.min(hidden.len()));
+
+        format!("{prefix}{}{suffix}", "*".repeat(hidden.len()))
+    }
+
+    /// Returns the hex encoded SHA-256 of the card number, identifying the
+    /// card without storing its number.
+    pub fn fingerprint(&self) -> String {
+        hex::encode(Sha256::digest(self.0.as_bytes()))
+    }
+}
+
+/// Returns the account number left visible in a card number masked by
+/// `Card::masked`.
+pub fn masked_account_number(masked_card_number: &str) -> Option<&str> {
+    let account_number = masked_card_number.get(..ACCOUNT_PREFIX_LENGTH)?;
+    account_number
+        .bytes()
+        .all(|b| b.is_ascii_digit())
+        .then_some(account_number)
 }
 
 #[cfg(test)]
@@ -92,4 +124,23 @@ pub mod tests {
             Self::try_from(card_number).expect("failed to parse card_number")
         }
     }
+
+    #[test]
+    fn should_mask_all_but_account_number_and_last_four_digits() {
+        let card = Card::try_from("123456789012345".to_string()).unwrap();
+
+        assert_eq!(card.masked(), "12*********2345");
+        assert_eq!(masked_account_number(&card.masked()), Some("12"));
+        assert_eq!(masked_account_number("**"), None);
+    }
+
+    #[test]
+    fn should_fingerprint_card_number() {
+        let card = Card::try_from("123456789012345".to_string()).unwrap();
+        let other_card = Card::try_from("123456789012346".to_string()).unwrap();
+
+        assert_eq!(card.fingerprint().len(), 64);
+        assert_eq!(card.fingerprint(), card.clone().fingerprint());
+        assert_ne!(card.fingerprint(), other_card.fingerprint());
+    }
 }
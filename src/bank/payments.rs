@@ -1,8 +1,32 @@
-use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use std::{fmt::Display, time::Duration};
+
+use futures::stream::BoxStream;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Serialize,
+};
+use sqlx::{
+    postgres::{types::PgInterval, PgExecutor, PgHasArrayType, PgTypeInfo},
+    PgPool,
+};
 use time::PrimitiveDateTime;
 use uuid::Uuid;
 
+use super::{
+    accounts::AccountService,
+    fees::{FeeBreakdown, FeePolicy},
+    outbox::{self, PaymentPayload},
+    pagination::{Cursor, Page, SortPosition},
+    payment_instruments::Card,
+    refunds::{Reason, Refund, Status as RefundStatus},
+    settlement::{self, Settlement},
+    tasks::{self, TaskKind},
+    transactions,
+};
+
+pub mod disputes;
+pub mod notes;
+
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize, Deserialize, sqlx::Type)]
 #[serde(rename_all = "snake_case")]
 pub enum Status {
@@ -14,6 +38,331 @@ pub enum Status {
     Declined,
     /// The payment was unable to complete (e.g. banking system crashed).
     Failed,
+    /// A hold was placed on the customer's funds, which weren't withdrawn yet.
+    Authorized,
+    /// The hold of an authorized payment was released without withdrawing.
+    Voided,
+}
+
+impl Status {
+    /// Returns whether a payment may move from this status to `next`.
+    ///
+    /// Approved, Declined and Voided payments are final. Failed payments are
+    /// processed again when retried.
+    pub fn can_transition_to(&self, next: Status) -> bool {
+        use Status::*;
+
+        matches!(
+            (self, next),
+            (Processing, Authorized | Declined | Failed)
+                | (Authorized, Approved | Declined | Failed | Voided)
+                | (Failed, Processing)
+        )
+    }
+
+    /// Returns whether the outcome of the payment isn't known yet.
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Status::Processing | Status::Authorized)
+    }
+}
+
+// lets lists of statuses be bound to `ANY($1)`
+impl PgHasArrayType for Status {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_status")
+    }
+}
+
+/// Amount of a payment or refund, in minor units of its currency.
+///
+/// Deserialized from either an integer of minor units, e.g. `1205`, or a
+/// string of major units with at most two fraction digits, e.g. `"12.05"`.
+/// Always serialized as an integer of minor units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(pub i64);
+
+/// Number of minor units in a major unit, e.g. cents in a dollar.
+const MINOR_UNITS: i64 = 100;
+/// Maximum number of digits after the decimal point of a string amount.
+const MAX_FRACTION_DIGITS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountError {
+    /// Not a decimal number, e.g. `1e5`, or a negative zero, e.g. `-0.00`.
+    Invalid,
+    /// More fraction digits than minor units can hold, e.g. `12.345`.
+    TooPrecise,
+    /// More minor units than an `i64` holds.
+    TooLarge,
+}
+
+impl Display for AmountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid => write!(f, "amount should be a decimal number"),
+            Self::TooPrecise => write!(
+                f,
+                "amount should have at most {MAX_FRACTION_DIGITS} fraction digits"
+            ),
+            Self::TooLarge => write!(f, "amount is too large"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Amount {
+    type Error = AmountError;
+
+    fn try_from(amount: &str) -> Result<Self, Self::Error> {
+        let (is_negative, unsigned) = match amount.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, amount),
+        };
+        let (units, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+
+        // digits only, on both sides of the point if there is one
+        let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !is_digits(units) || (unsigned.contains('.') && !is_digits(fraction)) {
+            return Err(AmountError::Invalid);
+        }
+        if fraction.len() > MAX_FRACTION_DIGITS {
+            return Err(AmountError::TooPrecise);
+        }
+
+        // the fraction is padded to minor units, e.g. `12.5` is 1250
+        let fraction = format!("{fraction:0<MAX_FRACTION_DIGITS$}");
+        let minor_units = units
+            .parse::<i64>()
+            .ok()
+            .and_then(|units| units.checked_mul(MINOR_UNITS))
+            .and_then(|units| units.checked_add(fraction.parse::<i64>().ok()?))
+            .ok_or(AmountError::TooLarge)?;
+
+        match (is_negative, minor_units) {
+            (true, 0) => Err(AmountError::Invalid),
+            (true, minor_units) => Ok(Self(-minor_units)),
+            (false, minor_units) => Ok(Self(minor_units)),
+        }
+    }
+}
+
+impl From<i64> for Amount {
+    fn from(minor_units: i64) -> Self {
+        Self(minor_units)
+    }
+}
+
+impl From<Amount> for i64 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl<'de> Visitor<'de> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "an integer of minor units or a decimal string")
+            }
+
+            fn visit_i64<E: de::Error>(self, minor_units: i64) -> Result<Amount, E> {
+                Ok(Amount(minor_units))
+            }
+
+            fn visit_u64<E: de::Error>(self, minor_units: u64) -> Result<Amount, E> {
+                i64::try_from(minor_units)
+                    .map(Amount)
+                    .map_err(|_| E::custom(AmountError::TooLarge))
+            }
+
+            fn visit_str<E: de::Error>(self, amount: &str) -> Result<Amount, E> {
+                Amount::try_from(amount).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+/// Why the account service declined or failed a payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "DeclineReason")]
+pub enum DeclineReason {
+    InsufficientFunds,
+    InvalidAccountNumber,
+    InvalidAmount,
+    /// The account service failed or was unavailable, or the payment never
+    /// completed, see `expire_stale`.
+    ServiceFailure,
+    /// An operator gave up on the payment, see `bulk_transition`.
+    OperatorRepair,
+}
+
+impl DeclineReason {
+    /// Returns the status of the payments declined or failed for this reason.
+    pub fn status(&self) -> Status {
+        match self {
+            DeclineReason::InsufficientFunds | DeclineReason::InvalidAccountNumber => {
+                Status::Declined
+            }
+            DeclineReason::InvalidAmount
+            | DeclineReason::ServiceFailure
+            | DeclineReason::OperatorRepair => Status::Failed,
+        }
+    }
+}
+
+/// How the hold placed on the funds of a payment was concluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "SettlementOutcome")]
+pub enum SettlementOutcome {
+    Withdrawn,
+    Released,
+}
+
+/// Time the account service took for the calls settling a payment, in
+/// milliseconds, only known for the calls made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Latency {
+    pub hold_ms: Option<i32>,
+    pub withdraw_ms: Option<i32>,
+}
+
+impl Latency {
+    /// Returns `elapsed` in whole milliseconds, saturating.
+    pub fn millis(elapsed: Duration) -> i32 {
+        i32::try_from(elapsed.as_millis()).unwrap_or(i32::MAX)
+    }
+}
+
+/// Percentiles of the latencies of the account service calls, in
+/// milliseconds, `None` when no call was measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub hold_p50_ms: Option<i32>,
+    pub hold_p95_ms: Option<i32>,
+    pub withdraw_p50_ms: Option<i32>,
+    pub withdraw_p95_ms: Option<i32>,
+}
+
+/// Channel through which a payment was made, e.g. to break down metrics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "PaymentSource")]
+pub enum Source {
+    Web,
+    Mobile,
+    Backoffice,
+    /// Direct API calls, the channel of payments not telling theirs.
+    #[default]
+    Api,
+}
+
+impl Source {
+    pub const ALL: [Source; 4] = [Source::Web, Source::Mobile, Source::Backoffice, Source::Api];
+}
+
+/// When the account service calls of a payment are made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingMode {
+    /// While handling the request creating the payment.
+    #[default]
+    Sync,
+    /// By the settlement worker, after the payment was created.
+    Async,
+}
+
+/// SQLSTATE of unique constraint violations.
+const UNIQUE_VIOLATION: &str = "23505";
+/// SQLSTATE of check constraint violations.
+const CHECK_VIOLATION: &str = "23514";
+
+/// Error of the reads and writes of payments.
+#[derive(Debug)]
+pub enum PaymentRepoError {
+    /// There is no such payment.
+    NotFound,
+    /// The card was already used within the reuse window, see `insert`.
+    DuplicateCard,
+    /// The payment isn't in the expected status anymore.
+    Conflict {
+        current_status: Status,
+    },
+    /// The payment breaks a check constraint of the schema, named by
+    /// `constraint`, e.g. `payments_amount_positive`, which mirror the
+    /// validation of the handlers.
+    Invalid {
+        constraint: String,
+    },
+    Storage(sqlx::Error),
+}
+
+impl std::fmt::Display for PaymentRepoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Classifies database errors: missing rows are missing payments, unique
+/// violations are cards used twice, e.g. by concurrent inserts, and check
+/// violations are invalid payments.
+impl From<sqlx::Error> for PaymentRepoError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => PaymentRepoError::NotFound,
+            sqlx::Error::Database(e) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                PaymentRepoError::DuplicateCard
+            }
+            sqlx::Error::Database(e) if e.code().as_deref() == Some(CHECK_VIOLATION) => {
+                PaymentRepoError::Invalid {
+                    constraint: e.constraint().unwrap_or_default().to_string(),
+                }
+            }
+            e => PaymentRepoError::Storage(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransitionError {
+    /// The state machine doesn't allow the transition.
+    Illegal { from: Status, to: Status },
+    /// The transition is legal but would move funds, which only the account
+    /// service can, so it can't be made by `bulk_transition`.
+    Unrepairable { from: Status, to: Status },
+    /// The payment doesn't exist, isn't in the expected status anymore, or
+    /// couldn't be updated.
+    Repo(PaymentRepoError),
+}
+
+impl std::fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<PaymentRepoError> for TransitionError {
+    fn from(error: PaymentRepoError) -> Self {
+        TransitionError::Repo(error)
+    }
+}
+
+impl From<sqlx::Error> for TransitionError {
+    fn from(error: sqlx::Error) -> Self {
+        TransitionError::Repo(error.into())
+    }
 }
 
 // Struct representing a payment.
@@ -23,84 +372,2673 @@ pub enum Status {
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct Payment {
     pub id: Uuid,
-    pub amount: i32,
+    pub merchant_id: Uuid,
+    pub amount: i64,
+    /// Card number masked by `Card::masked`, the full number isn't stored.
     pub card_number: String,
+    pub currency: String,
     pub status: Status,
+    /// Merchant supplied key-value data, returned as is.
+    pub metadata: Option<serde_json::Value>,
     pub inserted_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
+    /// When the payment was hidden from listings by `archive`.
+    pub archived_at: Option<PrimitiveDateTime>,
+    /// Incremented by every change of the payment, see `transition` and
+    /// `archive`.
+    pub version: i32,
+    /// Why the payment was declined or failed, set by `decline`.
+    pub decline_reason: Option<DeclineReason>,
+    /// Merchant supplied id of their customer, see `ListFilter`.
+    pub customer_reference: Option<String>,
+    pub source: Source,
 }
 
+/// Criteria of the payments returned by `list`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListFilter {
+    /// Only returns payments of this merchant.
+    pub merchant_id: Option<Uuid>,
+    /// Only returns payments whose metadata contains this JSON object.
+    pub metadata: Option<serde_json::Value>,
+    /// Only returns payments of this customer reference.
+    pub customer_reference: Option<String>,
+    /// Only returns payments made through this channel.
+    pub source: Option<Source>,
+    /// Only returns payments in one of these statuses, whatever their status
+    /// if empty.
+    pub statuses: Vec<Status>,
+    /// Also returns archived payments.
+    pub include_archived: bool,
+}
+
+impl ListFilter {
+    /// Returns whether the filter matches every payment, archived ones
+    /// included.
+    pub fn is_unfiltered(&self) -> bool {
+        *self
+            == ListFilter {
+                include_archived: true,
+                ..ListFilter::default()
+            }
+    }
+}
+
+/// How the number of payments returned by `count_with_kind` was computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CountKind {
+    Exact,
+    /// Estimated by `estimated_count`.
+    Estimated,
+}
+
+/// Column `list` sorts payments by, ties being ordered by insertion time then
+/// id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    InsertedAt,
+    Amount,
+    /// Sorted by the name of the status.
+    Status,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Order of the payments returned by `list`, newest first by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub order: SortOrder,
+}
+
+impl Sort {
+    /// Returns the name of the sort recorded in cursors, `None` for the
+    /// default sort, whose cursors predate sorting.
+    pub fn name(&self) -> Option<String> {
+        if *self == Sort::default() {
+            return None;
+        }
+
+        let key = match self.key {
+            SortKey::InsertedAt => "inserted_at",
+            SortKey::Amount => "amount",
+            SortKey::Status => "status",
+        };
+        let order = match self.order {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        };
+        Some(format!("{key}.{order}"))
+    }
+
+    /// Returns the position of `payment` in the payments sorted this way.
+    pub fn cursor(&self, payment: &Payment) -> Cursor {
+        let value = match self.key {
+            SortKey::InsertedAt => None,
+            SortKey::Amount => Some(payment.amount.to_string()),
+            // the labels of the Status type are the names of its variants
+            SortKey::Status => Some(format!("{:?}", payment.status)),
+        };
+
+        Cursor {
+            inserted_at: payment.inserted_at,
+            id: payment.id,
+            sort: self.name().map(|sort| SortPosition { sort, value }),
+        }
+    }
+
+    /// Returns the sorted column, and the parameter holding its value at the
+    /// cursor, as SQL.
+    ///
+    /// Only these constant strings are interpolated in the query of `list`.
+    fn column(&self) -> (&'static str, &'static str) {
+        match self.key {
+            SortKey::InsertedAt => ("inserted_at", "$2"),
+            SortKey::Amount => ("amount", "$7::text::bigint"),
+            SortKey::Status => ("status::text", "$7::text"),
+        }
+    }
+}
+
+/// Inserts a payment unless the same card was used for a payment inserted
+/// within `reuse_window`.
+///
+/// Only the masked card number is stored, along with the card fingerprint
+/// used to detect reused cards.
+///
+/// Fails with `DuplicateCard` when the card was already used. Concurrent
+/// inserts for the same card are serialized by a transaction-scoped advisory
+/// lock, so only one of them can succeed. Amounts the handlers should have
+/// rejected, e.g. negative ones, fail with `Invalid`.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    skip_all,
+    fields(payment.id, payment.amount = amount, payment.status = ?status)
+)]
 pub async fn insert(
     pool: &PgPool,
-    amount: i32,
-    card_number: String,
+    merchant_id: Uuid,
+    amount: i64,
+    card: &Card,
+    currency: String,
     status: Status,
-) -> Result<Uuid, sqlx::Error> {
-    sqlx::query!(
-        r#"INSERT INTO payments ( amount, card_number, status ) VALUES ( $1, $2, $3 ) RETURNING id"#,
+    processing_mode: ProcessingMode,
+    metadata: Option<serde_json::Value>,
+    customer_reference: Option<&str>,
+    source: Source,
+    reuse_window: Duration,
+) -> Result<Uuid, PaymentRepoError> {
+    let reuse_window = PgInterval::try_from(reuse_window).map_err(sqlx::Error::Configuration)?;
+
+    let fingerprint = card.fingerprint();
+
+    let mut tx = transactions::begin(pool).await?;
+
+    sqlx::query!(r#"SELECT pg_advisory_xact_lock(hashtext($1))"#, fingerprint)
+        .execute(&mut *tx)
+        .await?;
+
+    let id = sqlx::query!(
+        r#"
+            INSERT INTO payments ( amount, card_fingerprint, card_number, currency, status, metadata, merchant_id, processing_mode, customer_reference, source )
+            SELECT $1, $2::bpchar, $9, $3, $4, $6::jsonb, $7, $8, $10, $11
+            WHERE NOT EXISTS (
+              SELECT * FROM payments
+              WHERE card_fingerprint = $2 AND inserted_at > LOCALTIMESTAMP - $5::interval
+            )
+            RETURNING id
+        "#,
         amount,
-        card_number,
-        status as Status
+        fingerprint,
+        currency,
+        status as Status,
+        reuse_window,
+        metadata,
+        merchant_id,
+        processing_mode as ProcessingMode,
+        card.masked(),
+        customer_reference,
+        source as Source
     )
-    .fetch_one(pool)
-    .await
-    .map(|record| record.id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(PaymentRepoError::DuplicateCard)?
+    .id;
+
+    outbox::record(&mut *tx, &PaymentPayload::new(id, None, status)).await?;
+
+    tx.commit().await?;
+
+    tracing::Span::current().record("payment.id", tracing::field::display(id));
+
+    Ok(id)
 }
 
-pub async fn update(pool: &PgPool, id: Uuid, status: Status) -> Result<Uuid, sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE payments SET status = $2 WHERE id = $1 RETURNING id"#,
+/// Payment to record and settle through `process_payment`.
+#[derive(Debug, Clone)]
+pub struct NewPayment<'a> {
+    pub merchant_id: Uuid,
+    pub amount: i64,
+    pub card: &'a Card,
+    pub currency: String,
+    pub processing_mode: ProcessingMode,
+    pub metadata: Option<serde_json::Value>,
+    pub customer_reference: Option<&'a str>,
+    pub source: Source,
+}
+
+/// Payment recorded by `process_payment`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Processed {
+    pub id: Uuid,
+    /// Outcome of the settlement of sync payments, async ones being settled
+    /// by the settlement worker.
+    pub settlement: Option<Settlement>,
+}
+
+#[derive(Debug)]
+pub enum ProcessError {
+    /// The payment wasn't recorded, e.g. as its card was already used.
+    Insert(PaymentRepoError),
+    /// The payment was recorded, but its settlement couldn't be.
+    Settle {
+        payment_id: Uuid,
+        error: TransitionError,
+    },
+}
+
+/// Records a payment as processing, committed before any account service
+/// call, then settles sync payments, see `settlement::settle`.
+///
+/// A crash after the insert leaves the payment processing, which the expiry
+/// reaper fails, and a settlement whose approval can't be recorded after the
+/// funds moved is left to the reaper to repair, see `record_repair`.
+#[tracing::instrument(
+    skip_all,
+    fields(payment.amount = new.amount, payment.processing_mode = ?new.processing_mode)
+)]
+pub async fn process_payment<T: AccountService>(
+    pool: &PgPool,
+    account_service: &T,
+    notify: &(dyn Fn(Uuid, Status) + Send + Sync),
+    new: NewPayment<'_>,
+    reuse_window: Duration,
+    fee_policy: &FeePolicy,
+) -> Result<Processed, ProcessError> {
+    let id = insert(
+        pool,
+        new.merchant_id,
+        new.amount,
+        new.card,
+        new.currency,
+        Status::Processing,
+        new.processing_mode,
+        new.metadata,
+        new.customer_reference,
+        new.source,
+        reuse_window,
+    )
+    .await
+    .map_err(ProcessError::Insert)?;
+
+    // the settlement worker makes the account service calls of async payments
+    if new.processing_mode == ProcessingMode::Async {
+        return Ok(Processed {
+            id,
+            settlement: None,
+        });
+    }
+
+    let settlement = settlement::settle(
+        pool,
+        account_service,
+        notify,
+        fee_policy,
         id,
-        status as Status
+        new.card.account_number(),
+        new.amount,
+    )
+    .await
+    .map_err(|error| ProcessError::Settle {
+        payment_id: id,
+        error,
+    })?;
+
+    Ok(Processed {
+        id,
+        settlement: Some(settlement),
+    })
+}
+
+/// Returns the latest payment made with the card of `fingerprint`, see
+/// `Card::fingerprint`, e.g. the one that made `insert` refuse the card.
+///
+/// The lookup is covered by the `payments_card_fingerprint_inserted_at_index`
+/// index.
+#[tracing::instrument(skip_all)]
+pub async fn get_by_card_fingerprint(
+    pool: &PgPool,
+    fingerprint: &str,
+) -> Result<Option<Payment>, PaymentRepoError> {
+    let payment = sqlx::query_as!(
+        Payment,
+        r#"
+            SELECT id, merchant_id, amount, card_number, currency, metadata, inserted_at, updated_at, archived_at, version, decline_reason as "decline_reason: _", customer_reference, source as "source: _", status as "status: _"  FROM payments
+            WHERE card_fingerprint = $1
+            ORDER BY inserted_at DESC, id DESC
+            LIMIT 1
+        "#,
+        fingerprint
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(payment)
+}
+
+/// Returns whether a payment was made with the card within `reuse_window`,
+/// in which case `insert` would refuse it.
+pub async fn card_used(
+    pool: &PgPool,
+    card: &Card,
+    reuse_window: Duration,
+) -> Result<bool, sqlx::Error> {
+    let reuse_window = PgInterval::try_from(reuse_window).map_err(sqlx::Error::Configuration)?;
+
+    sqlx::query_scalar!(
+        r#"
+            SELECT EXISTS (
+              SELECT * FROM payments
+              WHERE card_fingerprint = $1 AND inserted_at > LOCALTIMESTAMP - $2::interval
+            ) as "used!"
+        "#,
+        card.fingerprint(),
+        reuse_window
     )
     .fetch_one(pool)
     .await
-    .map(|record| record.id)
 }
 
-pub async fn get(pool: &PgPool, id: Uuid) -> Result<Payment, sqlx::Error> {
-    sqlx::query_as!(
+/// Moves a payment from the `from` status to `to`.
+///
+/// The update only applies while the payment is still in the `from` status,
+/// so concurrent or stale transitions fail atomically, with a `Conflict`
+/// holding the status the payment moved to meanwhile. The transition is
+/// recorded in the payment's history within the same transaction, so the
+/// history can never disagree with the payment row.
+#[tracing::instrument(skip_all, fields(payment.id = %id, payment.status = ?to))]
+pub async fn transition(
+    pool: &PgPool,
+    id: Uuid,
+    from: Status,
+    to: Status,
+) -> Result<Uuid, TransitionError> {
+    record_transition(pool, id, from, to, None, Latency::default()).await
+}
+
+/// Moves a payment as by `transition`, also recording the `latency` of the
+/// account service calls made to settle it.
+#[tracing::instrument(skip_all, fields(payment.id = %id, payment.status = ?to))]
+pub async fn transition_with_latency(
+    pool: &PgPool,
+    id: Uuid,
+    from: Status,
+    to: Status,
+    latency: Latency,
+) -> Result<Uuid, TransitionError> {
+    record_transition(pool, id, from, to, None, latency).await
+}
+
+/// Moves a payment from the `from` status to the status of `reason`,
+/// recording why it was declined or failed.
+///
+/// Returns the new status of the payment.
+#[tracing::instrument(skip_all, fields(payment.id = %id, payment.decline_reason = ?reason))]
+pub async fn decline(
+    pool: &PgPool,
+    id: Uuid,
+    from: Status,
+    reason: DeclineReason,
+) -> Result<Status, TransitionError> {
+    let to = reason.status();
+    record_transition(pool, id, from, to, Some(reason), Latency::default()).await?;
+    Ok(to)
+}
+
+/// Declines a payment as by `decline`, also recording the `latency` of the
+/// account service calls made to settle it.
+#[tracing::instrument(skip_all, fields(payment.id = %id, payment.decline_reason = ?reason))]
+pub async fn decline_with_latency(
+    pool: &PgPool,
+    id: Uuid,
+    from: Status,
+    reason: DeclineReason,
+    latency: Latency,
+) -> Result<Status, TransitionError> {
+    let to = reason.status();
+    record_transition(pool, id, from, to, Some(reason), latency).await?;
+    Ok(to)
+}
+
+/// Moves a payment as described by `transition`, replacing its decline
+/// reason, which only the payments just declined or failed have.
+///
+/// The latencies measured are recorded, keeping the ones recorded by earlier
+/// transitions otherwise.
+async fn record_transition(
+    pool: &PgPool,
+    id: Uuid,
+    from: Status,
+    to: Status,
+    decline_reason: Option<DeclineReason>,
+    latency: Latency,
+) -> Result<Uuid, TransitionError> {
+    if !from.can_transition_to(to) {
+        return Err(TransitionError::Illegal { from, to });
+    }
+
+    let mut tx = transactions::begin(pool).await?;
+
+    // the update locks the payment row, so captures and voids wait for the
+    // refunds inserted meanwhile, which lock it too, see `refunds::checked_insert`
+    let updated = sqlx::query!(
+        r#"
+            UPDATE payments SET status = $3, decline_reason = $4, updated_at = current_timestamp,
+              version = version + 1,
+              hold_latency_ms = COALESCE($5, hold_latency_ms),
+              withdraw_latency_ms = COALESCE($6, withdraw_latency_ms)
+            WHERE id = $1 AND status = $2
+            RETURNING id
+        "#,
+        id,
+        from as Status,
+        to as Status,
+        decline_reason as Option<DeclineReason>,
+        latency.hold_ms,
+        latency.withdraw_ms
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(id) = updated.map(|record| record.id) else {
+        let current_status = sqlx::query_scalar!(
+            r#"SELECT status as "status: Status" FROM payments WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        return Err(match current_status {
+            Some(current_status) => PaymentRepoError::Conflict { current_status },
+            None => PaymentRepoError::NotFound,
+        }
+        .into());
+    };
+
+    record_event(&mut *tx, id, from, to).await?;
+    outbox::record(&mut *tx, &PaymentPayload::new(id, Some(from), to)).await?;
+
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+/// Fails the payments left processing for longer than `older_than`, e.g.
+/// because the process crashed before their account service calls completed,
+/// recording their transitions in their histories.
+///
+/// Only payments processed synchronously are expired: the settlement worker
+/// takes over the asynchronous ones left by a crashed worker.
+///
+/// Returns the ids of the expired payments.
+#[tracing::instrument(skip_all, fields(payments.expired))]
+pub async fn expire_stale(pool: &PgPool, older_than: Duration) -> Result<Vec<Uuid>, sqlx::Error> {
+    let older_than = PgInterval::try_from(older_than).map_err(sqlx::Error::Configuration)?;
+
+    // the outbox payload of every payment is the template's, with its id
+    let template = PaymentPayload::new(Uuid::nil(), Some(Status::Processing), Status::Failed);
+
+    // the payments, their events and outbox events are written by a single
+    // statement
+    let ids = sqlx::query!(
+        r#"
+            WITH expired AS (
+              UPDATE payments SET status = $2, decline_reason = $5, updated_at = current_timestamp,
+                version = version + 1
+              WHERE status = $1 AND processing_mode = $3
+                AND inserted_at < LOCALTIMESTAMP - $4::interval
+              RETURNING id
+            ), outbox AS (
+              INSERT INTO outbox_events ( event_type, payload )
+              SELECT $6, $7::jsonb || jsonb_build_object('payment_id', id) FROM expired
+            )
+            INSERT INTO payment_events ( payment_id, from_status, to_status )
+            SELECT id, $1, $2 FROM expired
+            RETURNING payment_id
+        "#,
+        Status::Processing as Status,
+        Status::Failed as Status,
+        ProcessingMode::Sync as ProcessingMode,
+        older_than,
+        DeclineReason::ServiceFailure as DeclineReason,
+        template.event_type.as_str(),
+        serde_json::to_value(&template).expect("failed to serialize outbox payload")
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|record| record.payment_id)
+    .collect::<Vec<_>>();
+
+    tracing::Span::current().record("payments.expired", ids.len());
+
+    Ok(ids)
+}
+
+/// Moves the payments of `ids` still in the `from` status to `to`, e.g. to
+/// fail the payments left processing by a bank outage.
+///
+/// Only processing payments can be declined or failed this way, recorded as
+/// `DeclineReason::OperatorRepair`, since other transitions would capture or
+/// release holds without calling the account service. Holds placed for the
+/// payments moved are left to the reconciliation report.
+///
+/// The payments, their histories and outbox events are all updated by a
+/// single statement, and the payments in another status are left as they
+/// are. Returns the ids of the payments moved.
+#[tracing::instrument(skip_all, fields(payment.status = ?to, payments.transitioned))]
+pub async fn bulk_transition(
+    pool: &PgPool,
+    ids: &[Uuid],
+    from: Status,
+    to: Status,
+) -> Result<Vec<Uuid>, TransitionError> {
+    if !from.can_transition_to(to) {
+        return Err(TransitionError::Illegal { from, to });
+    }
+    if from != Status::Processing || !matches!(to, Status::Declined | Status::Failed) {
+        return Err(TransitionError::Unrepairable { from, to });
+    }
+
+    // the outbox payload of every payment is the template's, with its id
+    let template = PaymentPayload::new(Uuid::nil(), Some(from), to);
+
+    let ids = sqlx::query!(
+        r#"
+            WITH transitioned AS (
+              UPDATE payments SET status = $3, decline_reason = 'OperatorRepair', updated_at = current_timestamp,
+                version = version + 1
+              WHERE id = ANY($1) AND status = $2
+              RETURNING id
+            ), outbox AS (
+              INSERT INTO outbox_events ( event_type, payload )
+              SELECT $4, $5::jsonb || jsonb_build_object('payment_id', id) FROM transitioned
+            )
+            INSERT INTO payment_events ( payment_id, from_status, to_status )
+            SELECT id, $2, $3 FROM transitioned
+            RETURNING payment_id
+        "#,
+        ids,
+        from as Status,
+        to as Status,
+        template.event_type.as_str(),
+        serde_json::to_value(&template).expect("failed to serialize outbox payload")
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|record| record.payment_id)
+    .collect::<Vec<_>>();
+
+    tracing::Span::current().record("payments.transitioned", ids.len());
+
+    Ok(ids)
+}
+
+#[tracing::instrument(skip_all, fields(payment.id = %id))]
+pub async fn get(pool: &PgPool, id: Uuid) -> Result<Payment, PaymentRepoError> {
+    let payment = sqlx::query_as!(
             Payment,
             r#"
-                SELECT id, amount, card_number, inserted_at, updated_at, status as "status: _"  FROM payments
+                SELECT id, merchant_id, amount, card_number, currency, metadata, inserted_at, updated_at, archived_at, version, decline_reason as "decline_reason: _", customer_reference, source as "source: _", status as "status: _"  FROM payments
                 WHERE id = $1
             "#,
             id
         )
         .fetch_one(pool)
-        .await
+        .await?;
+
+    Ok(payment)
 }
 
-#[cfg(test)]
-pub mod tests {
+/// A payment and the total amount of its refunds which didn't fail and
+/// weren't canceled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentWithRefundTotals {
+    pub payment: Payment,
+    pub refunded_amount: i64,
+}
 
-    use super::*;
-    use crate::bank::payment_instruments::Card;
+impl PaymentWithRefundTotals {
+    /// Returns the amount that can still be refunded, as checked by
+    /// `refunds::checked_insert`. Only approved payments can be refunded.
+    pub fn refundable_amount(&self) -> i64 {
+        if self.payment.status != Status::Approved {
+            return 0;
+        }
 
-    pub const PAYMENT_AMOUNT: i32 = 123;
-    pub const PAYMENT_STATUS: Status = Status::Approved;
+        (self.payment.amount - self.refunded_amount).max(0)
+    }
+}
 
-    impl Payment {
-        pub async fn new_test(pool: &PgPool) -> Result<Payment, sqlx::Error> {
-            let card = Card::new_test();
+impl std::borrow::Borrow<Payment> for PaymentWithRefundTotals {
+    fn borrow(&self) -> &Payment {
+        &self.payment
+    }
+}
 
-            let id = insert(pool, PAYMENT_AMOUNT, card.into(), PAYMENT_STATUS).await?;
+/// Returns a payment with the total amount of its refunds.
+#[tracing::instrument(skip_all, fields(payment.id = %id))]
+pub async fn get_with_refund_totals(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<PaymentWithRefundTotals, PaymentRepoError> {
+    let record = sqlx::query!(
+        r#"
+            SELECT p.id, p.merchant_id, p.amount, p.card_number, p.currency, p.metadata,
+                   p.inserted_at, p.updated_at, p.archived_at, p.version,
+                   p.decline_reason as "decline_reason: DeclineReason", p.customer_reference,
+                   p.source as "source: Source", p.status as "status: Status",
+                   COALESCE(SUM(r.amount) FILTER (WHERE r.status NOT IN ('Failed', 'Abandoned', 'Canceled')), 0)::bigint as "refunded_amount!"
+            FROM payments p
+            LEFT JOIN refunds r ON r.payment_id = p.id
+            WHERE p.id = $1
+            GROUP BY p.id
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
 
-            get(pool, id).await
-        }
+    Ok(PaymentWithRefundTotals {
+        payment: Payment {
+            id: record.id,
+            merchant_id: record.merchant_id,
+            amount: record.amount,
+            card_number: record.card_number,
+            currency: record.currency,
+            status: record.status,
+            metadata: record.metadata,
+            inserted_at: record.inserted_at,
+            updated_at: record.updated_at,
+            archived_at: record.archived_at,
+            version: record.version,
+            decline_reason: record.decline_reason,
+            customer_reference: record.customer_reference,
+            source: record.source,
+        },
+        refunded_amount: record.refunded_amount,
+    })
+}
+
+/// A payment and its refunds, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentWithRefunds {
+    pub payment: Payment,
+    pub refunds: Vec<Refund>,
+}
+
+impl std::borrow::Borrow<Payment> for PaymentWithRefunds {
+    fn borrow(&self) -> &Payment {
+        &self.payment
     }
+}
 
-    #[tokio::test]
-    async fn test_payment() {
-        let pool = crate::pg_pool()
-            .await
-            .expect("failed to connect to postgres");
+/// Returns a payment with its refunds, read in a single query.
+#[tracing::instrument(skip_all, fields(payment.id = %id))]
+pub async fn get_with_refunds(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<PaymentWithRefunds, PaymentRepoError> {
+    let records = sqlx::query!(
+        r#"
+            SELECT p.id, p.merchant_id, p.amount, p.card_number, p.currency, p.metadata,
+                   p.inserted_at, p.updated_at, p.archived_at, p.version,
+                   p.decline_reason as "decline_reason: DeclineReason", p.customer_reference,
+                   p.source as "source: Source", p.status as "status: Status",
+                   r.id as "refund_id?", r.amount as "refund_amount?", r.currency as "refund_currency?",
+                   r.status as "refund_status?: RefundStatus",
+                   r.reason as "refund_reason?: Reason", r.reason_detail as "refund_reason_detail?",
+                   r.metadata as "refund_metadata?", r.external_reference as "refund_external_reference?",
+                   r.initiated_by as "refund_initiated_by?",
+                   r.inserted_at as "refund_inserted_at?",
+                   r.updated_at as "refund_updated_at?"
+            FROM payments p
+            LEFT JOIN refunds r ON r.payment_id = p.id
+            WHERE p.id = $1
+            ORDER BY r.inserted_at, r.id
+        "#,
+        id
+    )
+    .fetch_all(pool)
+    .await?;
 
-        let payment = Payment::new_test(&pool)
-            .await
-            .expect("failed to create payment");
+    // every row holds the payment, and one of its refunds if it has any
+    let refunds = records
+        .iter()
+        .filter_map(|record| {
+            Some(Refund {
+                id: record.refund_id?,
+                payment_id: record.id,
+                amount: record.refund_amount?,
+                currency: record.refund_currency.clone()?,
+                status: record.refund_status?,
+                reason: record.refund_reason,
+                reason_detail: record.refund_reason_detail.clone(),
+                metadata: record.refund_metadata.clone(),
+                external_reference: record.refund_external_reference.clone(),
+                initiated_by: record.refund_initiated_by.clone()?,
+                inserted_at: record.refund_inserted_at?,
+                updated_at: record.refund_updated_at?,
+            })
+        })
+        .collect();
+    let record = records
+        .into_iter()
+        .next()
+        .ok_or(PaymentRepoError::NotFound)?;
 
-        assert_eq!(payment.amount, PAYMENT_AMOUNT);
-        assert_eq!(payment.status, PAYMENT_STATUS);
+    Ok(PaymentWithRefunds {
+        payment: Payment {
+            id: record.id,
+            merchant_id: record.merchant_id,
+            amount: record.amount,
+            card_number: record.card_number,
+            currency: record.currency,
+            status: record.status,
+            metadata: record.metadata,
+            inserted_at: record.inserted_at,
+            updated_at: record.updated_at,
+            archived_at: record.archived_at,
+            version: record.version,
+            decline_reason: record.decline_reason,
+            customer_reference: record.customer_reference,
+            source: record.source,
+        },
+        refunds,
+    })
+}
+
+/// Archives a payment, hiding it from `list` and `stream` unless archived
+/// payments are included. The payment itself is kept.
+///
+/// Only archives the payment while it is at `expected_version`, if given, so
+/// clients can make sure they act on the payment they last read.
+///
+/// Returns the archived payment, whose `archived_at` doesn't change once set,
+/// or `None` if the payment is pending, whose outcome is still unknown, or no
+/// longer at `expected_version`.
+#[tracing::instrument(skip_all, fields(payment.id = %id))]
+pub async fn archive(
+    pool: &PgPool,
+    id: Uuid,
+    expected_version: Option<i32>,
+) -> Result<Option<Payment>, sqlx::Error> {
+    sqlx::query_as!(
+        Payment,
+        r#"
+            UPDATE payments SET
+              archived_at = coalesce(archived_at, LOCALTIMESTAMP),
+              updated_at = CASE WHEN archived_at IS NULL THEN current_timestamp ELSE updated_at END,
+              version = CASE WHEN archived_at IS NULL THEN version + 1 ELSE version END
+            -- pending statuses, see `Status::is_pending`
+            WHERE id = $1 AND status NOT IN ('Processing', 'Authorized')
+              AND ($2::integer IS NULL OR version = $2)
+            RETURNING id, merchant_id, amount, card_number, currency, metadata, inserted_at, updated_at, archived_at, version, decline_reason as "decline_reason: _", customer_reference, source as "source: _", status as "status: _"
+        "#,
+        id,
+        expected_version
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[derive(Debug)]
+pub enum DeleteError {
+    NotFound,
+    /// The payment was refunded, so it can't be deleted.
+    HasRefunds {
+        count: i64,
+    },
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for DeleteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<sqlx::Error> for DeleteError {
+    fn from(error: sqlx::Error) -> Self {
+        DeleteError::Database(error)
+    }
+}
+
+/// Deletes a payment for good, with the records kept about it, unless it
+/// has refunds. Meant for cleaning up test data, payments are otherwise
+/// archived.
+///
+/// The payment row is locked first, so no refund is inserted meanwhile.
+#[tracing::instrument(skip_all, fields(payment.id = %id))]
+pub async fn delete(pool: &PgPool, id: Uuid) -> Result<(), DeleteError> {
+    let mut tx = transactions::begin(pool).await?;
+
+    sqlx::query!("SELECT id FROM payments WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(DeleteError::NotFound)?;
+
+    let count = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM refunds WHERE payment_id = $1"#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if count > 0 {
+        return Err(DeleteError::HasRefunds { count });
+    }
+
+    for query in [
+        sqlx::query!("DELETE FROM payment_events WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM payment_notes WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM disputes WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM webhook_deliveries WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM worker_claims WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM account_operations WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM settlement_repairs WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM payment_fees WHERE payment_id = $1", id),
+        sqlx::query!("DELETE FROM payments WHERE id = $1", id),
+    ] {
+        query.execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Returns a page of the payments matching `filter`, in the order of `sort`.
+///
+/// Metadata is matched with the `@>` containment operator, so nested values
+/// of the filter must be present in the payment's metadata.
+///
+/// The cursor of `page` must come from a listing of the same sort, see
+/// `Sort::cursor`.
+pub async fn list(
+    pool: &PgPool,
+    filter: &ListFilter,
+    sort: Sort,
+    page: &Page,
+) -> Result<Vec<Payment>, PaymentRepoError> {
+    let (after_inserted_at, after_id) = page.after();
+
+    // the sorted column isn't known at compile time, so the query isn't
+    // checked against the database schema
+    let (column, after_value) = sort.column();
+    let (direction, comparison) = match sort.order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+    let query = format!(
+        r#"
+            SELECT id, merchant_id, amount, card_number, currency, metadata, inserted_at, updated_at, archived_at, version, decline_reason, customer_reference, source, status FROM payments
+            WHERE ($1::jsonb IS NULL OR metadata @> $1::jsonb)
+              AND ($5::uuid IS NULL OR merchant_id = $5)
+              AND ($6 OR archived_at IS NULL)
+              AND ($8::text IS NULL OR customer_reference = $8)
+              AND (cardinality($9::status[]) = 0 OR status = ANY($9))
+              AND ($10::PaymentSource IS NULL OR source = $10)
+              AND ($2::timestamp IS NULL
+                OR ({column}, inserted_at, id) {comparison} ({after_value}, $2, $3::uuid))
+            ORDER BY {column} {direction}, inserted_at {direction}, id {direction}
+            LIMIT $4
+        "#
+    );
+
+    sqlx::query_as::<_, Payment>(&query)
+        .bind(&filter.metadata)
+        .bind(after_inserted_at)
+        .bind(after_id)
+        .bind(page.limit)
+        .bind(filter.merchant_id)
+        .bind(filter.include_archived)
+        .bind(page.after_sort_value())
+        .bind(&filter.customer_reference)
+        .bind(&filter.statuses)
+        .bind(filter.source)
+        .fetch_all(pool)
+        .await
+        .map_err(PaymentRepoError::from)
+}
+
+/// Maximum number of payments returned by `find_by_card_suffix`.
+pub const MAX_CARD_SUFFIX_MATCHES: i64 = 100;
+
+/// Finds the payments of `merchant_id` whose card number ends with the four
+/// digits of `suffix`, newest first.
+///
+/// The suffix is compared with `right(card_number, 4)`, which the
+/// `payments_card_suffix_index` index covers, so at most
+/// `MAX_CARD_SUFFIX_MATCHES` payments are returned.
+pub async fn find_by_card_suffix(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    suffix: &str,
+) -> Result<Vec<Payment>, sqlx::Error> {
+    sqlx::query_as!(
+        Payment,
+        r#"
+            SELECT id, merchant_id, amount, card_number, currency, metadata, inserted_at, updated_at, archived_at, version, decline_reason as "decline_reason: _", customer_reference, source as "source: _", status as "status: _" FROM payments
+            WHERE right(card_number, 4) = $1 AND merchant_id = $2
+            ORDER BY inserted_at DESC, id DESC
+            LIMIT $3
+        "#,
+        suffix,
+        merchant_id,
+        MAX_CARD_SUFFIX_MATCHES
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Streams every payment matching `filter`, newest first.
+///
+/// Rows are fetched from a cursor as the stream is polled, so exports of any
+/// size are never buffered in memory.
+pub fn stream<'a>(
+    pool: &'a PgPool,
+    filter: &'a ListFilter,
+) -> BoxStream<'a, Result<Payment, sqlx::Error>> {
+    sqlx::query_as!(
+        Payment,
+        r#"
+            SELECT id, merchant_id, amount, card_number, currency, metadata, inserted_at, updated_at, archived_at, version, decline_reason as "decline_reason: _", customer_reference, source as "source: _", status as "status: _" FROM payments
+            WHERE ($1::jsonb IS NULL OR metadata @> $1::jsonb)
+              AND ($2::uuid IS NULL OR merchant_id = $2)
+              AND ($3 OR archived_at IS NULL)
+              AND ($4::text IS NULL OR customer_reference = $4)
+              AND (cardinality($5::status[]) = 0 OR status = ANY($5))
+              AND ($6::PaymentSource IS NULL OR source = $6)
+            ORDER BY inserted_at DESC, id DESC
+        "#,
+        filter.metadata,
+        filter.merchant_id,
+        filter.include_archived,
+        filter.customer_reference,
+        filter.statuses.as_slice() as &[Status],
+        filter.source as Option<Source>
+    )
+    .fetch(pool)
+}
+
+/// Counts the payments matching `filter`, as listed by `list`.
+pub async fn count(pool: &PgPool, filter: &ListFilter) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+            SELECT count(*) as "count!" FROM payments
+            WHERE ($1::jsonb IS NULL OR metadata @> $1::jsonb)
+              AND ($2::uuid IS NULL OR merchant_id = $2)
+              AND ($3 OR archived_at IS NULL)
+              AND ($4::text IS NULL OR customer_reference = $4)
+              AND (cardinality($5::status[]) = 0 OR status = ANY($5))
+              AND ($6::PaymentSource IS NULL OR source = $6)
+        "#,
+        filter.metadata,
+        filter.merchant_id,
+        filter.include_archived,
+        filter.customer_reference,
+        filter.statuses.as_slice() as &[Status],
+        filter.source as Option<Source>
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Estimates the number of payments from the planner statistics, without
+/// scanning the table.
+///
+/// The statistics are refreshed by autovacuum and `ANALYZE`, and the estimate
+/// is 0 until the table was first analyzed.
+pub async fn estimated_count(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+            SELECT GREATEST(reltuples, 0)::bigint as "count!" FROM pg_class
+            WHERE oid = 'payments'::regclass
+        "#
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Counts the payments matching `filter` as `count` does, unless `exact` is
+/// false and the filter matches every payment, whose count is estimated by
+/// `estimated_count` instead.
+///
+/// Filtered counts are always exact, as the statistics can't estimate them.
+pub async fn count_with_kind(
+    pool: &PgPool,
+    filter: &ListFilter,
+    exact: bool,
+) -> Result<(i64, CountKind), sqlx::Error> {
+    if !exact && filter.is_unfiltered() {
+        return Ok((estimated_count(pool).await?, CountKind::Estimated));
+    }
+
+    Ok((count(pool, filter).await?, CountKind::Exact))
+}
+
+/// Width of the time buckets used by `aggregate`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl Granularity {
+    /// Returns the `date_trunc` field truncating timestamps to a bucket.
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+        }
+    }
+}
+
+/// Number and total amount of the payments of a status and currency within a
+/// time bucket.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Aggregate {
+    pub bucket: PrimitiveDateTime,
+    pub currency: String,
+    pub status: Status,
+    pub count: i64,
+    pub total_amount: i64,
+}
+
+/// Number and total amount of the payments of a status and currency made
+/// through a source.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct SourceAggregate {
+    pub source: Source,
+    pub currency: String,
+    pub status: Status,
+    pub count: i64,
+    pub total_amount: i64,
+}
+
+/// Aggregates the payments of a merchant inserted in `[from, to)` per source,
+/// currency and status, ordered by source, currency then status.
+///
+/// Sources without payments are omitted.
+pub async fn aggregate_by_source(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    from: PrimitiveDateTime,
+    to: PrimitiveDateTime,
+) -> Result<Vec<SourceAggregate>, sqlx::Error> {
+    sqlx::query_as!(
+        SourceAggregate,
+        r#"
+            SELECT
+              source as "source: _",
+              currency,
+              status as "status: _",
+              COUNT(*) as "count!",
+              SUM(amount)::bigint as "total_amount!"
+            FROM payments
+            WHERE merchant_id = $1 AND inserted_at >= $2 AND inserted_at < $3
+            GROUP BY 1, 2, 3
+            ORDER BY 1, 2, 3
+        "#,
+        merchant_id,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Aggregates the payments of a merchant inserted in `[from, to)` per time
+/// bucket, currency and status, ordered by bucket, currency then status.
+///
+/// Buckets without payments are omitted.
+pub async fn aggregate(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    from: PrimitiveDateTime,
+    to: PrimitiveDateTime,
+    granularity: Granularity,
+) -> Result<Vec<Aggregate>, sqlx::Error> {
+    sqlx::query_as!(
+        Aggregate,
+        r#"
+            SELECT
+              date_trunc($4, inserted_at) as "bucket!",
+              currency,
+              status as "status: _",
+              COUNT(*) as "count!",
+              SUM(amount)::bigint as "total_amount!"
+            FROM payments
+            WHERE merchant_id = $1 AND inserted_at >= $2 AND inserted_at < $3
+            GROUP BY 1, 2, 3
+            ORDER BY 1, 2, 3
+        "#,
+        merchant_id,
+        from,
+        to,
+        granularity.as_str()
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the latency of the account service calls recorded for a payment.
+pub async fn get_latency(pool: &PgPool, id: Uuid) -> Result<Latency, PaymentRepoError> {
+    let latency = sqlx::query_as!(
+        Latency,
+        r#"
+            SELECT hold_latency_ms as hold_ms, withdraw_latency_ms as withdraw_ms
+            FROM payments WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(latency)
+}
+
+/// Computes the median and 95th percentile of the account service latencies
+/// of the payments of a merchant inserted in `[from, to)`.
+pub async fn latency_percentiles(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    from: PrimitiveDateTime,
+    to: PrimitiveDateTime,
+) -> Result<LatencyPercentiles, sqlx::Error> {
+    // payments without a latency, e.g. declined before their hold, are
+    // ignored by the percentiles
+    sqlx::query_as!(
+        LatencyPercentiles,
+        r#"
+            SELECT
+              round(percentile_cont(0.5) WITHIN GROUP (ORDER BY hold_latency_ms))::integer as hold_p50_ms,
+              round(percentile_cont(0.95) WITHIN GROUP (ORDER BY hold_latency_ms))::integer as hold_p95_ms,
+              round(percentile_cont(0.5) WITHIN GROUP (ORDER BY withdraw_latency_ms))::integer as withdraw_p50_ms,
+              round(percentile_cont(0.95) WITHIN GROUP (ORDER BY withdraw_latency_ms))::integer as withdraw_p95_ms
+            FROM payments
+            WHERE merchant_id = $1 AND inserted_at >= $2 AND inserted_at < $3
+        "#,
+        merchant_id,
+        from,
+        to
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Records the hold placed on the funds of a payment, which `reconcile`
+/// expects to be concluded by `record_settlement`.
+pub async fn record_hold(pool: &PgPool, id: Uuid, hold_ref: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE payments SET hold_ref = $2 WHERE id = $1",
+        id,
+        hold_ref
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns the hold recorded for a payment by `record_hold`, if any.
+pub async fn get_hold_ref(pool: &PgPool, id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT hold_ref FROM payments WHERE id = $1", id)
+        .fetch_one(pool)
+        .await
+}
+
+/// Funds held for an authorized payment, as read by the claimant of its hold,
+/// see `TaskKind::hold`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldFunds {
+    pub merchant_id: Uuid,
+    pub status: Status,
+    pub amount: i64,
+    pub version: i32,
+    pub hold_ref: Option<Uuid>,
+    pub settlement_outcome: Option<SettlementOutcome>,
+}
+
+/// Returns the funds held for a payment, its hold being settled once
+/// `settlement_outcome` is recorded.
+pub async fn get_held_funds(pool: &PgPool, id: Uuid) -> Result<HeldFunds, PaymentRepoError> {
+    sqlx::query_as!(
+        HeldFunds,
+        r#"
+            SELECT merchant_id, status as "status: _", amount, version, hold_ref,
+              settlement_outcome as "settlement_outcome: _"
+            FROM payments WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(PaymentRepoError::NotFound)
+}
+
+/// Records the fees of a payment, computed under `policy` when its funds were
+/// withdrawn.
+///
+/// The fees of a payment are only recorded once, so a later change of the
+/// policy of its merchant doesn't change them.
+pub async fn record_fees(
+    pool: &PgPool,
+    id: Uuid,
+    policy: FeePolicy,
+    fees: FeeBreakdown,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO payment_fees
+              ( payment_id, basis_points, fixed_amount, percentage_fee, fixed_fee, total_fee, net_amount )
+            VALUES ( $1, $2, $3, $4, $5, $6, $7 )
+            ON CONFLICT ( payment_id ) DO NOTHING
+        "#,
+        id,
+        policy.basis_points(),
+        policy.fixed_amount(),
+        fees.percentage_fee,
+        fees.fixed_fee,
+        fees.total_fee,
+        fees.net_amount
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns the fees recorded for a payment by `record_fees`, if any.
+pub async fn get_fees(pool: &PgPool, id: Uuid) -> Result<Option<FeeBreakdown>, sqlx::Error> {
+    sqlx::query_as!(
+        FeeBreakdown,
+        r#"
+            SELECT percentage_fee, fixed_fee, total_fee, net_amount
+            FROM payment_fees WHERE payment_id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Lowers the amount of an authorized payment to `amount`, whose funds are
+/// held by `hold_ref`.
+///
+/// Only amends the payment while it is authorized at `expected_version`,
+/// returning `None` otherwise.
+pub async fn amend(
+    pool: &PgPool,
+    id: Uuid,
+    expected_version: i32,
+    amount: i64,
+    hold_ref: Uuid,
+) -> Result<Option<Payment>, sqlx::Error> {
+    sqlx::query_as!(
+        Payment,
+        r#"
+            UPDATE payments SET amount = $3, hold_ref = $4, updated_at = current_timestamp,
+              version = version + 1
+            WHERE id = $1 AND status = 'Authorized' AND version = $2
+            RETURNING id, merchant_id, amount, card_number, currency, metadata, inserted_at, updated_at, archived_at, version, decline_reason as "decline_reason: _", customer_reference, source as "source: _", status as "status: _"
+        "#,
+        id,
+        expected_version,
+        amount,
+        hold_ref
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Records that the hold of a payment was withdrawn or released.
+pub async fn record_settlement(
+    pool: &PgPool,
+    id: Uuid,
+    outcome: SettlementOutcome,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE payments SET settlement_outcome = $2 WHERE id = $1",
+        id,
+        outcome as SettlementOutcome
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records that authorized payment `id` must be moved to `status`, as its
+/// funds moved but the transition couldn't be recorded, see
+/// `repair_settlements`.
+pub async fn record_repair(pool: &PgPool, id: Uuid, status: Status) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO settlement_repairs ( payment_id, status ) VALUES ( $1, $2 )",
+        id,
+        status as Status
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Moves the payments recorded by `record_repair` to their status, returning
+/// the repaired payments with their new status.
+///
+/// Payments are claimed for `worker_id` in batches of `batch_size`, see
+/// `tasks::claim_batch`, so concurrent reapers never repair one twice.
+/// Repairs are marked done once applied. Repairs which can't be applied, e.g.
+/// as the payment left the authorized status some other way, are left
+/// unrepaired and marked failed, for `reconcile` to report them.
+#[tracing::instrument(skip_all, fields(payments.repaired))]
+pub async fn repair_settlements(
+    pool: &PgPool,
+    worker_id: Uuid,
+    batch_size: i64,
+    claim_ttl: Duration,
+) -> Result<Vec<(Uuid, Status)>, sqlx::Error> {
+    let kind = TaskKind::settlement_repair();
+
+    let mut repaired = Vec::new();
+    loop {
+        let claims = tasks::claim_batch(pool, &kind, worker_id, batch_size, claim_ttl).await?;
+        if claims.payment_ids.is_empty() {
+            break;
+        }
+
+        for payment_id in claims.payment_ids {
+            if let Some(status) = repair_settlement(pool, payment_id).await? {
+                repaired.push((payment_id, status));
+            }
+
+            if !tasks::complete(pool, &kind, payment_id, worker_id).await? {
+                tracing::warn!("lost the settlement repair claim of payment {payment_id}");
+            }
+        }
+    }
+
+    tracing::Span::current().record("payments.repaired", repaired.len());
+
+    Ok(repaired)
+}
+
+/// Applies the oldest pending repair of a claimed payment, returning the
+/// status it was moved to, if any.
+async fn repair_settlement(pool: &PgPool, payment_id: Uuid) -> Result<Option<Status>, sqlx::Error> {
+    let Some(repair) = sqlx::query!(
+        r#"
+            SELECT id, status as "status: Status"
+            FROM settlement_repairs
+            WHERE payment_id = $1 AND repaired_at IS NULL AND failed_at IS NULL
+            ORDER BY inserted_at
+            LIMIT 1
+        "#,
+        payment_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    match transition(pool, payment_id, Status::Authorized, repair.status).await {
+        Ok(_) => {
+            sqlx::query!(
+                "UPDATE settlement_repairs SET repaired_at = clock_timestamp() WHERE id = $1",
+                repair.id
+            )
+            .execute(pool)
+            .await?;
+
+            Ok(Some(repair.status))
+        }
+        Err(TransitionError::Repo(PaymentRepoError::Storage(e))) => Err(e),
+        Err(e) => {
+            tracing::error!("payment {payment_id} left for repair can't be repaired: {e}");
+            sqlx::query!(
+                r#"
+                    UPDATE settlement_repairs SET failed_at = clock_timestamp(), failure = $2
+                    WHERE id = $1
+                "#,
+                repair.id,
+                e.to_string()
+            )
+            .execute(pool)
+            .await?;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Kind of mismatch between a payment and the account service calls made for
+/// it, found by `reconcile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// The payment was approved, but its funds weren't withdrawn.
+    ApprovedWithoutWithdraw,
+    /// The payment was declined or failed, but its hold wasn't released.
+    DeclinedWithHoldNotReleased,
+    /// The payment was left processing for longer than expected.
+    ProcessingStale,
+    /// The funds of the payment moved, but it couldn't be repaired to the
+    /// status recorded by `record_repair`.
+    RepairFailed,
+}
+
+/// A payment whose status doesn't match the account service calls made for
+/// it.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Mismatch {
+    pub discrepancy: Discrepancy,
+    pub payment_id: Uuid,
+    pub status: Status,
+    pub hold_ref: Option<Uuid>,
+    pub settlement_outcome: Option<SettlementOutcome>,
+    pub inserted_at: PrimitiveDateTime,
+}
+
+/// Returns the payments inserted in `[from, to)` whose status doesn't match
+/// their recorded hold and settlement, ordered by discrepancy then insertion
+/// time.
+///
+/// Payments are stale once processing for longer than `stale_after`.
+pub async fn reconcile(
+    pool: &PgPool,
+    from: PrimitiveDateTime,
+    to: PrimitiveDateTime,
+    stale_after: Duration,
+) -> Result<Vec<Mismatch>, sqlx::Error> {
+    let stale_after = PgInterval::try_from(stale_after).map_err(sqlx::Error::Configuration)?;
+
+    sqlx::query_as!(
+        Mismatch,
+        r#"
+            SELECT discrepancy as "discrepancy!: _", id as payment_id, status as "status: _",
+              hold_ref, settlement_outcome as "settlement_outcome: _", inserted_at
+            FROM (
+              SELECT *,
+                CASE
+                  WHEN EXISTS (
+                    SELECT 1 FROM settlement_repairs r
+                    WHERE r.payment_id = payments.id AND r.failed_at IS NOT NULL
+                      AND r.repaired_at IS NULL
+                  )
+                    THEN 'repair_failed'
+                  WHEN status = 'Approved' AND settlement_outcome IS DISTINCT FROM 'Withdrawn'
+                    THEN 'approved_without_withdraw'
+                  WHEN status IN ('Declined', 'Failed') AND hold_ref IS NOT NULL
+                    AND settlement_outcome IS NULL
+                    THEN 'declined_with_hold_not_released'
+                  WHEN status = 'Processing' AND inserted_at < LOCALTIMESTAMP - $3::interval
+                    THEN 'processing_stale'
+                END as discrepancy
+              FROM payments
+              WHERE inserted_at >= $1 AND inserted_at < $2
+            ) as payments
+            WHERE discrepancy IS NOT NULL
+            ORDER BY discrepancy, inserted_at, id
+        "#,
+        from,
+        to,
+        stale_after
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// A status transition of a payment.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct PaymentEvent {
+    pub id: Uuid,
+    pub payment_id: Uuid,
+    pub from_status: Status,
+    pub to_status: Status,
+    pub inserted_at: PrimitiveDateTime,
+}
+
+/// Records a status transition of a payment.
+///
+/// Callers are expected to pass the transaction that updates the payment row.
+pub async fn record_event(
+    executor: impl PgExecutor<'_>,
+    payment_id: Uuid,
+    from_status: Status,
+    to_status: Status,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO payment_events ( payment_id, from_status, to_status )
+            VALUES ( $1, $2, $3 )
+            RETURNING id
+        "#,
+        payment_id,
+        from_status as Status,
+        to_status as Status
+    )
+    .fetch_one(executor)
+    .await
+    .map(|record| record.id)
+}
+
+/// Returns a page of the status transitions of a payment, oldest first.
+pub async fn list_events(
+    pool: &PgPool,
+    payment_id: Uuid,
+    page: &Page,
+) -> Result<Vec<PaymentEvent>, sqlx::Error> {
+    let (after_inserted_at, after_id) = page.after();
+
+    sqlx::query_as!(
+        PaymentEvent,
+        r#"
+            SELECT id, payment_id, from_status as "from_status: _", to_status as "to_status: _", inserted_at
+            FROM payment_events
+            WHERE payment_id = $1
+              AND ($2::timestamp IS NULL OR (inserted_at, id) > ($2, $3::uuid))
+            ORDER BY inserted_at, id
+            LIMIT $4
+        "#,
+        payment_id,
+        after_inserted_at,
+        after_id,
+        page.limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use crate::bank::{currencies::Currency, refunds};
+
+    /// Merchant of the payments inserted by tests.
+    pub const MERCHANT_ID: Uuid = Uuid::from_u128(0x5e1e_c7ed_0000_4000_8000_0000_0000_0001);
+    pub const PAYMENT_AMOUNT: i64 = 123;
+    pub const PAYMENT_STATUS: Status = Status::Approved;
+    pub const CARD_REUSE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+    pub const FIRST_PAGE: Page = Page {
+        after: None,
+        limit: 100,
+    };
+
+    impl Payment {
+        pub async fn new_test(pool: &PgPool) -> Result<Payment, PaymentRepoError> {
+            let card = Card::new_test();
+
+            let id = insert(
+                pool,
+                MERCHANT_ID,
+                PAYMENT_AMOUNT,
+                &card,
+                Currency::default().into(),
+                PAYMENT_STATUS,
+                ProcessingMode::Sync,
+                None,
+                None,
+                Source::default(),
+                CARD_REUSE_WINDOW,
+            )
+            .await?;
+
+            get(pool, id).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payment() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+
+        assert_eq!(payment.amount, PAYMENT_AMOUNT);
+        assert_eq!(payment.status, PAYMENT_STATUS);
+        assert_eq!(payment.currency, Currency::DEFAULT);
+    }
+
+    #[tokio::test]
+    async fn test_list_by_amount_beyond_i32() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let merchant_id = Uuid::new_v4();
+        let base = i64::from(i32::MAX);
+        for amount in [base * 3, base + 1, base * 2] {
+            insert(
+                &pool,
+                merchant_id,
+                amount,
+                &Card::new_test(),
+                Currency::default().into(),
+                Status::Approved,
+                ProcessingMode::Sync,
+                None,
+                None,
+                Source::default(),
+                CARD_REUSE_WINDOW,
+            )
+            .await
+            .expect("failed to insert payment");
+        }
+
+        let filter = ListFilter {
+            merchant_id: Some(merchant_id),
+            ..ListFilter::default()
+        };
+        let sort = Sort {
+            key: SortKey::Amount,
+            order: SortOrder::Asc,
+        };
+        let mut page = Page {
+            after: None,
+            limit: 1,
+        };
+        let mut amounts = Vec::new();
+        loop {
+            let payments = list(&pool, &filter, sort, &page)
+                .await
+                .expect("failed to list payments");
+            let Some(last) = payments.last() else {
+                break;
+            };
+            amounts.push(last.amount);
+            page.after = Some(sort.cursor(last));
+        }
+        assert_eq!(amounts, [base + 1, base * 2, base * 3]);
+    }
+
+    #[tokio::test]
+    async fn test_amounts_beyond_i32() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        // a merchant of its own, so payments of other tests are left out
+        let merchant_id = Uuid::new_v4();
+        let amount = i64::from(i32::MAX) * 3;
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let id = insert(
+                &pool,
+                merchant_id,
+                amount,
+                &Card::new_test(),
+                Currency::default().into(),
+                Status::Approved,
+                ProcessingMode::Sync,
+                None,
+                None,
+                Source::default(),
+                CARD_REUSE_WINDOW,
+            )
+            .await
+            .expect("failed to insert payment");
+            ids.push(id);
+        }
+
+        let payment = get_with_refund_totals(&pool, ids[0])
+            .await
+            .expect("failed to get payment");
+        assert_eq!(payment.payment.amount, amount);
+        assert_eq!(payment.refundable_amount(), amount);
+
+        let inserted_at = time::Date::from_calendar_date(2023, time::Month::January, 1)
+            .unwrap()
+            .midnight();
+        sqlx::query!(
+            "UPDATE payments SET inserted_at = $2 WHERE id = ANY($1)",
+            &ids,
+            inserted_at
+        )
+        .execute(&pool)
+        .await
+        .expect("failed to backdate payments");
+        let aggregates = aggregate(
+            &pool,
+            merchant_id,
+            inserted_at,
+            inserted_at + time::Duration::days(1),
+            Granularity::Day,
+        )
+        .await
+        .expect("failed to aggregate payments");
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].total_amount, amount * 2);
+    }
+
+    pub async fn new_processing_payment(pool: &PgPool) -> Uuid {
+        insert(
+            pool,
+            MERCHANT_ID,
+            PAYMENT_AMOUNT,
+            &Card::new_test(),
+            Currency::default().into(),
+            Status::Processing,
+            ProcessingMode::Sync,
+            None,
+            None,
+            Source::default(),
+            CARD_REUSE_WINDOW,
+        )
+        .await
+        .expect("failed to insert payment")
+    }
+
+    #[tokio::test]
+    async fn test_get_with_refunds() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        let with_refunds = get_with_refunds(&pool, payment.id)
+            .await
+            .expect("failed to get payment");
+        assert_eq!(with_refunds.payment, payment);
+        assert!(with_refunds.refunds.is_empty());
+
+        for amount in [20, 30] {
+            let outcome = refunds::checked_insert(
+                &pool,
+                payment.id,
+                amount,
+                payment.currency.clone(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                "anonymous",
+                refunds::tests::MAX_REFUNDS,
+            )
+            .await
+            .expect("failed to insert refund");
+            assert!(matches!(outcome, refunds::RefundOutcome::Created(_)));
+        }
+        let with_refunds = get_with_refunds(&pool, payment.id)
+            .await
+            .expect("failed to get payment");
+        assert_eq!(with_refunds.payment, payment);
+        assert_eq!(
+            with_refunds.refunds,
+            refunds::list(&pool, payment.id)
+                .await
+                .expect("failed to list refunds")
+        );
+        assert_eq!(with_refunds.refunds.len(), 2);
+
+        let result = get_with_refunds(&pool, Uuid::new_v4()).await;
+        assert!(matches!(result, Err(PaymentRepoError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let approve = |id| {
+            let pool = pool.clone();
+            async move {
+                transition(&pool, id, Status::Processing, Status::Authorized).await?;
+                transition(&pool, id, Status::Authorized, Status::Approved).await
+            }
+        };
+
+        let withdrawn = new_processing_payment(&pool).await;
+        record_hold(&pool, withdrawn, Uuid::new_v4()).await.unwrap();
+        record_settlement(&pool, withdrawn, SettlementOutcome::Withdrawn)
+            .await
+            .unwrap();
+        approve(withdrawn).await.unwrap();
+
+        let not_withdrawn = new_processing_payment(&pool).await;
+        record_hold(&pool, not_withdrawn, Uuid::new_v4())
+            .await
+            .unwrap();
+        approve(not_withdrawn).await.unwrap();
+
+        let released = new_processing_payment(&pool).await;
+        record_hold(&pool, released, Uuid::new_v4()).await.unwrap();
+        record_settlement(&pool, released, SettlementOutcome::Released)
+            .await
+            .unwrap();
+        decline(
+            &pool,
+            released,
+            Status::Processing,
+            DeclineReason::InsufficientFunds,
+        )
+        .await
+        .unwrap();
+
+        let not_released = new_processing_payment(&pool).await;
+        let hold_ref = Uuid::new_v4();
+        record_hold(&pool, not_released, hold_ref).await.unwrap();
+        decline(
+            &pool,
+            not_released,
+            Status::Processing,
+            DeclineReason::ServiceFailure,
+        )
+        .await
+        .unwrap();
+
+        // declined before any hold was placed
+        let without_hold = new_processing_payment(&pool).await;
+        decline(
+            &pool,
+            without_hold,
+            Status::Processing,
+            DeclineReason::InsufficientFunds,
+        )
+        .await
+        .unwrap();
+
+        let stale = new_processing_payment(&pool).await;
+        let recent = new_processing_payment(&pool).await;
+        sqlx::query!(
+            "UPDATE payments SET inserted_at = inserted_at - interval '2 hours' WHERE id = $1",
+            stale
+        )
+        .execute(&pool)
+        .await
+        .expect("failed to backdate payment");
+
+        let now = time::OffsetDateTime::now_utc();
+        let day = time::Duration::days(1);
+        let window = |datetime: time::OffsetDateTime| {
+            PrimitiveDateTime::new(datetime.date(), datetime.time())
+        };
+        let mismatches = reconcile(
+            &pool,
+            window(now - day),
+            window(now + day),
+            Duration::from_secs(60 * 60),
+        )
+        .await
+        .expect("failed to reconcile payments");
+
+        let discrepancy = |id| {
+            mismatches
+                .iter()
+                .find(|mismatch| mismatch.payment_id == id)
+                .map(|mismatch| mismatch.discrepancy)
+        };
+        assert_eq!(discrepancy(withdrawn), None);
+        assert_eq!(
+            discrepancy(not_withdrawn),
+            Some(Discrepancy::ApprovedWithoutWithdraw)
+        );
+        assert_eq!(discrepancy(released), None);
+        assert_eq!(
+            discrepancy(not_released),
+            Some(Discrepancy::DeclinedWithHoldNotReleased)
+        );
+        assert_eq!(discrepancy(without_hold), None);
+        assert_eq!(discrepancy(stale), Some(Discrepancy::ProcessingStale));
+        assert_eq!(discrepancy(recent), None);
+
+        let mismatch = mismatches
+            .iter()
+            .find(|mismatch| mismatch.payment_id == not_released)
+            .unwrap();
+        assert_eq!(mismatch.status, Status::Failed);
+        assert_eq!(mismatch.hold_ref, Some(hold_ref));
+        assert_eq!(mismatch.settlement_outcome, None);
+
+        // payments outside the window aren't reconciled
+        let mismatches = reconcile(
+            &pool,
+            window(now + day),
+            window(now + day + day),
+            Duration::from_secs(60 * 60),
+        )
+        .await
+        .expect("failed to reconcile payments");
+        assert!(!mismatches
+            .iter()
+            .any(|mismatch| mismatch.payment_id == not_withdrawn));
+    }
+
+    async fn new_payment_left_for_repair(pool: &PgPool, status: Status) -> Uuid {
+        let id = new_processing_payment(pool).await;
+        transition(pool, id, Status::Processing, Status::Authorized)
+            .await
+            .expect("failed to authorize payment");
+        record_repair(pool, id, status)
+            .await
+            .expect("failed to record repair");
+        id
+    }
+
+    #[tokio::test]
+    async fn test_repair_settlements_leaves_failed_repairs_for_reconciliation() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        // authorized payments can't move back to processing
+        let id = new_payment_left_for_repair(&pool, Status::Processing).await;
+
+        let repaired = repair_settlements(&pool, Uuid::new_v4(), 10, Duration::from_secs(60))
+            .await
+            .expect("failed to repair settlements");
+        assert!(repaired.iter().all(|(payment_id, _)| *payment_id != id));
+
+        let repair = sqlx::query!(
+            "SELECT repaired_at, failed_at, failure FROM settlement_repairs WHERE payment_id = $1",
+            id
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to get repair");
+        assert_eq!(repair.repaired_at, None);
+        assert!(repair.failed_at.is_some());
+        assert!(repair
+            .failure
+            .is_some_and(|failure| failure.contains("Illegal")));
+        let payment = get(&pool, id).await.unwrap();
+        assert_eq!(payment.status, Status::Authorized);
+
+        let now = time::OffsetDateTime::now_utc();
+        let day = time::Duration::days(1);
+        let window = |datetime: time::OffsetDateTime| {
+            PrimitiveDateTime::new(datetime.date(), datetime.time())
+        };
+        let mismatches = reconcile(
+            &pool,
+            window(now - day),
+            window(now + day),
+            Duration::from_secs(60 * 60),
+        )
+        .await
+        .expect("failed to reconcile payments");
+        let mismatch = mismatches
+            .iter()
+            .find(|mismatch| mismatch.payment_id == id)
+            .expect("missing mismatch");
+        assert_eq!(mismatch.discrepancy, Discrepancy::RepairFailed);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_repair_settlements_repair_once() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let mut ids = Vec::new();
+        for _ in 0..6 {
+            ids.push(new_payment_left_for_repair(&pool, Status::Approved).await);
+        }
+
+        // batches of one, so both reapers contend for the same payments
+        let repair = || repair_settlements(&pool, Uuid::new_v4(), 1, Duration::from_secs(60));
+        let (first, second) = tokio::join!(repair(), repair());
+        let repaired: Vec<_> = first
+            .expect("failed to repair settlements")
+            .into_iter()
+            .chain(second.expect("failed to repair settlements"))
+            .filter(|(payment_id, _)| ids.contains(payment_id))
+            .collect();
+
+        assert_eq!(repaired.len(), ids.len());
+        for &id in &ids {
+            assert!(repaired.contains(&(id, Status::Approved)));
+            let payment = get(&pool, id).await.unwrap();
+            assert_eq!(payment.status, Status::Approved);
+
+            let repair = sqlx::query!(
+                "SELECT repaired_at, failed_at FROM settlement_repairs WHERE payment_id = $1",
+                id
+            )
+            .fetch_one(&pool)
+            .await
+            .expect("failed to get repair");
+            assert!(repair.repaired_at.is_some());
+            assert_eq!(repair.failed_at, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expire_stale() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let stale = new_processing_payment(&pool).await;
+        let recent = new_processing_payment(&pool).await;
+        sqlx::query!(
+            "UPDATE payments SET inserted_at = inserted_at - interval '2 hours' WHERE id = $1",
+            stale
+        )
+        .execute(&pool)
+        .await
+        .expect("failed to backdate payment");
+
+        let expired = expire_stale(&pool, Duration::from_secs(60 * 60))
+            .await
+            .expect("failed to expire payments");
+        assert!(expired.contains(&stale));
+        assert!(!expired.contains(&recent));
+
+        let payment = get(&pool, stale).await.expect("failed to get payment");
+        assert_eq!(payment.status, Status::Failed);
+        assert_eq!(payment.decline_reason, Some(DeclineReason::ServiceFailure));
+        let events = list_events(&pool, stale, &FIRST_PAGE)
+            .await
+            .expect("failed to list events");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].from_status, Status::Processing);
+        assert_eq!(events[0].to_status, Status::Failed);
+        let outbox_events = outbox::tests::list_for_payment(&pool, stale)
+            .await
+            .expect("failed to list outbox events");
+        assert_eq!(
+            outbox_events.last().map(|event| &event.payload),
+            Some(
+                &serde_json::to_value(PaymentPayload::new(
+                    stale,
+                    Some(Status::Processing),
+                    Status::Failed
+                ))
+                .unwrap()
+            )
+        );
+
+        let payment = get(&pool, recent).await.expect("failed to get payment");
+        assert_eq!(payment.status, Status::Processing);
+
+        // expired payments aren't expired twice
+        let expired = expire_stale(&pool, Duration::from_secs(60 * 60))
+            .await
+            .expect("failed to expire payments");
+        assert!(!expired.contains(&stale));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_transition() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let stuck = new_processing_payment(&pool).await;
+        let declined = new_processing_payment(&pool).await;
+        transition(&pool, declined, Status::Processing, Status::Declined)
+            .await
+            .expect("failed to decline payment");
+        let unknown = Uuid::new_v4();
+
+        let transitioned = bulk_transition(
+            &pool,
+            &[stuck, declined, unknown],
+            Status::Processing,
+            Status::Failed,
+        )
+        .await
+        .expect("failed to transition payments");
+        assert_eq!(transitioned, vec![stuck]);
+
+        let payment = get(&pool, stuck).await.expect("failed to get payment");
+        assert_eq!(payment.status, Status::Failed);
+        assert_eq!(payment.decline_reason, Some(DeclineReason::OperatorRepair));
+        let events = list_events(&pool, stuck, &FIRST_PAGE)
+            .await
+            .expect("failed to list events");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].from_status, Status::Processing);
+        assert_eq!(events[0].to_status, Status::Failed);
+        let outbox_events = outbox::tests::list_for_payment(&pool, stuck)
+            .await
+            .expect("failed to list outbox events");
+        assert_eq!(
+            outbox_events.last().map(|event| &event.payload),
+            Some(
+                &serde_json::to_value(PaymentPayload::new(
+                    stuck,
+                    Some(Status::Processing),
+                    Status::Failed
+                ))
+                .unwrap()
+            )
+        );
+
+        let payment = get(&pool, declined).await.expect("failed to get payment");
+        assert_eq!(payment.status, Status::Declined);
+        let events = list_events(&pool, declined, &FIRST_PAGE)
+            .await
+            .expect("failed to list events");
+        assert_eq!(events.len(), 1);
+
+        let result = bulk_transition(&pool, &[stuck], Status::Failed, Status::Approved).await;
+        assert!(matches!(
+            result,
+            Err(TransitionError::Illegal {
+                from: Status::Failed,
+                to: Status::Approved
+            })
+        ));
+
+        let authorized = new_processing_payment(&pool).await;
+        transition(&pool, authorized, Status::Processing, Status::Authorized)
+            .await
+            .expect("failed to authorize payment");
+        for to in [Status::Approved, Status::Voided] {
+            let result = bulk_transition(&pool, &[authorized], Status::Authorized, to).await;
+            assert!(
+                matches!(result, Err(TransitionError::Unrepairable { .. })),
+                "{to:?}"
+            );
+        }
+        let payment = get(&pool, authorized).await.expect("failed to get payment");
+        assert_eq!(payment.status, Status::Authorized);
+    }
+
+    #[test]
+    fn test_amount_from_str() {
+        for (amount, expected) in [
+            ("1205", Ok(Amount(120500))),
+            ("12.05", Ok(Amount(1205))),
+            ("12.5", Ok(Amount(1250))),
+            ("0.01", Ok(Amount(1))),
+            ("-12.05", Ok(Amount(-1205))),
+            ("0", Ok(Amount(0))),
+            ("12.345", Err(AmountError::TooPrecise)),
+            ("12.050", Err(AmountError::TooPrecise)),
+            ("1e5", Err(AmountError::Invalid)),
+            ("12.", Err(AmountError::Invalid)),
+            (".5", Err(AmountError::Invalid)),
+            ("+12", Err(AmountError::Invalid)),
+            (" 12", Err(AmountError::Invalid)),
+            ("", Err(AmountError::Invalid)),
+            ("-0", Err(AmountError::Invalid)),
+            ("-0.00", Err(AmountError::Invalid)),
+            ("-00.0", Err(AmountError::Invalid)),
+            ("21474836.48", Ok(Amount(2147483648))),
+            ("92233720368547758.07", Ok(Amount(i64::MAX))),
+            ("92233720368547758.08", Err(AmountError::TooLarge)),
+        ] {
+            assert_eq!(Amount::try_from(amount), expected, "{amount:?}");
+        }
+    }
+
+    #[test]
+    fn test_deserialize_amount() {
+        let amount = |json| serde_json::from_value::<Amount>(json);
+
+        assert_eq!(amount(serde_json::json!(1205)).unwrap(), Amount(1205));
+        assert_eq!(amount(serde_json::json!("12.05")).unwrap(), Amount(1205));
+        assert_eq!(
+            amount(serde_json::json!("12.345")).unwrap_err().to_string(),
+            "amount should have at most 2 fraction digits"
+        );
+        assert!(amount(serde_json::json!(12.05)).is_err());
+        assert_eq!(
+            amount(serde_json::json!(i64::from(i32::MAX) + 1)).unwrap(),
+            Amount(2147483648)
+        );
+        assert!(amount(serde_json::json!(u64::MAX)).is_err());
+        assert_eq!(serde_json::to_value(Amount(1205)).unwrap(), 1205);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_amount_round_trips(minor_units in -i64::MAX..=i64::MAX) {
+            let amount = Amount(minor_units);
+
+            let json = serde_json::to_string(&amount).unwrap();
+            proptest::prop_assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+
+            let sign = if minor_units < 0 { "-" } else { "" };
+            let (units, cents) = (minor_units.abs() / 100, minor_units.abs() % 100);
+            let decimal = format!("{sign}{units}.{cents:02}");
+            proptest::prop_assert_eq!(Amount::try_from(decimal.as_str()), Ok(amount));
+        }
+
+        #[test]
+        fn test_amount_rejects_extra_fraction_digits(
+            units in 0..1000i32,
+            fraction in "[0-9]{3,6}",
+        ) {
+            proptest::prop_assert_eq!(
+                Amount::try_from(format!("{units}.{fraction}").as_str()),
+                Err(AmountError::TooPrecise)
+            );
+        }
+
+        #[test]
+        fn test_amount_rejects_exponents(mantissa in 0..1000i32, exponent in -5..5i32) {
+            for amount in [format!("{mantissa}e{exponent}"), format!("{mantissa}E{exponent}")] {
+                proptest::prop_assert_eq!(
+                    Amount::try_from(amount.as_str()),
+                    Err(AmountError::Invalid)
+                );
+            }
+        }
+
+        #[test]
+        fn test_amount_rejects_negative_zero(zeros in "0{1,4}(\\.0{1,2})?") {
+            let amount = format!("-{zeros}");
+            proptest::prop_assert_eq!(Amount::try_from(amount.as_str()), Err(AmountError::Invalid));
+        }
+    }
+
+    #[test]
+    fn test_transition_matrix() {
+        use Status::*;
+
+        let statuses = [Processing, Authorized, Approved, Declined, Failed, Voided];
+        let legal = [
+            (Processing, Authorized),
+            (Processing, Declined),
+            (Processing, Failed),
+            (Authorized, Approved),
+            (Authorized, Declined),
+            (Authorized, Failed),
+            (Authorized, Voided),
+            (Failed, Processing),
+        ];
+
+        for from in statuses {
+            for to in statuses {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    legal.contains(&(from, to)),
+                    "{from:?} -> {to:?}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transition_records_event() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let id = new_processing_payment(&pool).await;
+
+        transition(&pool, id, Status::Processing, Status::Failed)
+            .await
+            .expect("failed to transition payment");
+
+        let events = list_events(&pool, id, &FIRST_PAGE)
+            .await
+            .expect("failed to list events");
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].from_status, Status::Processing);
+        assert_eq!(events[0].to_status, Status::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_declined_payment_cannot_be_approved() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let id = new_processing_payment(&pool).await;
+        transition(&pool, id, Status::Processing, Status::Declined)
+            .await
+            .expect("failed to decline payment");
+
+        let result = transition(&pool, id, Status::Declined, Status::Approved).await;
+        assert!(matches!(result, Err(TransitionError::Illegal { .. })));
+
+        // a stale caller still believing the payment is authorized
+        let result = transition(&pool, id, Status::Authorized, Status::Approved).await;
+        assert!(matches!(
+            result,
+            Err(TransitionError::Repo(PaymentRepoError::Conflict {
+                current_status: Status::Declined
+            }))
+        ));
+
+        let payment = get(&pool, id).await.expect("failed to get payment");
+        assert_eq!(payment.status, Status::Declined);
+
+        let events = list_events(&pool, id, &FIRST_PAGE)
+            .await
+            .expect("failed to list events");
+        assert_eq!(events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_refuses_refunded_payments() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let id = new_processing_payment(&pool).await;
+        transition(&pool, id, Status::Processing, Status::Declined)
+            .await
+            .expect("failed to decline payment");
+        delete(&pool, id).await.expect("failed to delete payment");
+        assert!(matches!(
+            get(&pool, id).await,
+            Err(PaymentRepoError::NotFound)
+        ));
+        assert!(matches!(
+            delete(&pool, id).await,
+            Err(DeleteError::NotFound)
+        ));
+
+        let refund = refunds::Refund::new_test(&pool)
+            .await
+            .expect("failed to insert refund");
+        let result = delete(&pool, refund.payment_id).await;
+        assert!(
+            matches!(result, Err(DeleteError::HasRefunds { count: 1 })),
+            "{result:?}"
+        );
+
+        // the database refuses it too
+        let result = sqlx::query!("DELETE FROM payments WHERE id = $1", refund.payment_id)
+            .execute(&pool)
+            .await;
+        assert!(
+            matches!(&result, Err(sqlx::Error::Database(e)) if e.constraint() == Some("refunds_payment_id_fkey")),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_insert_refuses_card_reused_within_window() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let card = Card::new_test();
+        let insert_card = |window| {
+            insert(
+                &pool,
+                MERCHANT_ID,
+                PAYMENT_AMOUNT,
+                &card,
+                Currency::default().into(),
+                PAYMENT_STATUS,
+                ProcessingMode::Sync,
+                None,
+                None,
+                Source::default(),
+                window,
+            )
+        };
+
+        let id = insert_card(CARD_REUSE_WINDOW)
+            .await
+            .expect("failed to insert payment");
+        let payment = get(&pool, id).await.expect("failed to get payment");
+        assert_eq!(payment.card_number, card.masked());
+
+        let result = insert_card(CARD_REUSE_WINDOW).await;
+        assert!(
+            matches!(result, Err(PaymentRepoError::DuplicateCard)),
+            "card reused within the window"
+        );
+        let original = get_by_card_fingerprint(&pool, &card.fingerprint())
+            .await
+            .expect("failed to get payment by card");
+        assert_eq!(original, Some(payment));
+
+        let id = insert_card(Duration::ZERO)
+            .await
+            .expect("card reused outside the window");
+        let latest = get_by_card_fingerprint(&pool, &card.fingerprint())
+            .await
+            .expect("failed to get payment by card");
+        assert_eq!(latest.map(|payment| payment.id), Some(id));
+    }
+
+    #[tokio::test]
+    async fn test_repo_errors() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let missing_id = Uuid::new_v4();
+
+        assert!(matches!(
+            get(&pool, missing_id).await,
+            Err(PaymentRepoError::NotFound)
+        ));
+        assert!(matches!(
+            get_with_refund_totals(&pool, missing_id).await,
+            Err(PaymentRepoError::NotFound)
+        ));
+        assert!(matches!(
+            transition(&pool, missing_id, Status::Processing, Status::Declined).await,
+            Err(TransitionError::Repo(PaymentRepoError::NotFound))
+        ));
+
+        // errors of the database are classified where they are converted
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to insert payment");
+        let duplicate = sqlx::query("INSERT INTO payments SELECT * FROM payments WHERE id = $1")
+            .bind(payment.id)
+            .execute(&pool)
+            .await
+            .expect_err("duplicate payment inserted");
+        assert!(matches!(
+            PaymentRepoError::from(duplicate),
+            PaymentRepoError::DuplicateCard
+        ));
+        let missing = sqlx::query_scalar::<_, Uuid>("SELECT id FROM payments WHERE id = $1")
+            .bind(missing_id)
+            .fetch_one(&pool)
+            .await
+            .expect_err("missing payment found");
+        assert!(matches!(
+            PaymentRepoError::from(missing),
+            PaymentRepoError::NotFound
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_schema_refuses_invalid_payments() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        for amount in [0, -1] {
+            let result = insert(
+                &pool,
+                MERCHANT_ID,
+                amount,
+                &Card::new_test(),
+                Currency::default().into(),
+                Status::Processing,
+                ProcessingMode::Sync,
+                None,
+                None,
+                Source::default(),
+                CARD_REUSE_WINDOW,
+            )
+            .await;
+            assert!(
+                matches!(
+                    &result,
+                    Err(PaymentRepoError::Invalid { constraint })
+                        if constraint == "payments_amount_positive"
+                ),
+                "{amount}: {result:?}"
+            );
+        }
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to insert payment");
+        let error = sqlx::query("UPDATE payments SET card_number = '' WHERE id = $1")
+            .bind(payment.id)
+            .execute(&pool)
+            .await
+            .expect_err("empty card number stored");
+        assert!(matches!(
+            PaymentRepoError::from(error),
+            PaymentRepoError::Invalid { constraint } if constraint == "payments_card_number_length"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_filters_by_metadata() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let order_id = Uuid::new_v4().to_string();
+        let metadata = serde_json::json!({
+            "order_id": order_id,
+            "customer": { "tier": "gold", "tags": ["a", "b"] },
+        });
+
+        let id = insert(
+            &pool,
+            MERCHANT_ID,
+            PAYMENT_AMOUNT,
+            &Card::new_test(),
+            Currency::default().into(),
+            PAYMENT_STATUS,
+            ProcessingMode::Sync,
+            Some(metadata.clone()),
+            None,
+            Source::default(),
+            CARD_REUSE_WINDOW,
+        )
+        .await
+        .expect("failed to insert payment");
+        Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+
+        let list_with = |metadata| {
+            let filter = ListFilter {
+                metadata: Some(metadata),
+                ..ListFilter::default()
+            };
+            let pool = pool.clone();
+            async move { list(&pool, &filter, Sort::default(), &FIRST_PAGE).await }
+        };
+
+        let payments = list_with(serde_json::json!({ "order_id": order_id }))
+            .await
+            .expect("failed to list payments");
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].id, id);
+        assert_eq!(payments[0].metadata, Some(metadata));
+
+        let payments = list_with(serde_json::json!({
+            "order_id": order_id,
+            "customer": { "tier": "gold" },
+        }))
+        .await
+        .expect("failed to list payments");
+        assert_eq!(payments.len(), 1, "nested values should match");
+
+        let payments = list_with(serde_json::json!({ "order_id": "other" }))
+            .await
+            .expect("failed to list payments");
+        assert!(payments.iter().all(|payment| payment.id != id));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_per_status() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        // a merchant of its own, so payments of other tests are left out
+        let (merchant_id, other_merchant_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let day = time::Date::from_calendar_date(2023, time::Month::January, 1)
+            .unwrap()
+            .midnight();
+        let next_day = day + time::Duration::days(1);
+
+        for (merchant_id, hours, amount, currency, status) in [
+            (merchant_id, 1, 100, "USD", Status::Approved),
+            (merchant_id, 2, 200, "USD", Status::Approved),
+            (merchant_id, 2, 50, "USD", Status::Declined),
+            (merchant_id, 25, 300, "USD", Status::Approved),
+            (merchant_id, 26, 70, "EUR", Status::Approved),
+            (other_merchant_id, 1, 1000, "USD", Status::Approved),
+        ] {
+            let payment = Payment::new_test(&pool)
+                .await
+                .expect("failed to create payment");
+            sqlx::query!(
+                r#"
+                    UPDATE payments SET merchant_id = $2, inserted_at = $3, amount = $4,
+                      currency = $5, status = $6
+                    WHERE id = $1
+                "#,
+                payment.id,
+                merchant_id,
+                day + time::Duration::hours(hours),
+                amount,
+                currency,
+                status as Status
+            )
+            .execute(&pool)
+            .await
+            .expect("failed to backdate payment");
+        }
+
+        let aggregates = aggregate(
+            &pool,
+            merchant_id,
+            day,
+            day + time::Duration::days(2),
+            Granularity::Day,
+        )
+        .await
+        .expect("failed to aggregate payments");
+
+        let aggregate_of = |bucket, currency: &str, status, count, total_amount| Aggregate {
+            bucket,
+            currency: currency.to_string(),
+            status,
+            count,
+            total_amount,
+        };
+        assert_eq!(
+            aggregates,
+            vec![
+                aggregate_of(day, "USD", Status::Approved, 2, 300),
+                aggregate_of(day, "USD", Status::Declined, 1, 50),
+                aggregate_of(next_day, "EUR", Status::Approved, 1, 70),
+                aggregate_of(next_day, "USD", Status::Approved, 1, 300),
+            ]
+        );
+
+        let aggregates = aggregate(&pool, merchant_id, day, next_day, Granularity::Hour)
+            .await
+            .expect("failed to aggregate payments");
+
+        assert_eq!(
+            aggregates,
+            vec![
+                aggregate_of(
+                    day + time::Duration::hours(1),
+                    "USD",
+                    Status::Approved,
+                    1,
+                    100
+                ),
+                aggregate_of(
+                    day + time::Duration::hours(2),
+                    "USD",
+                    Status::Approved,
+                    1,
+                    200
+                ),
+                aggregate_of(
+                    day + time::Duration::hours(2),
+                    "USD",
+                    Status::Declined,
+                    1,
+                    50
+                ),
+            ]
+        );
     }
 }
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use crate::bank::transactions::{self, Transaction};
+
+/// A dispute of a payment by its client, e.g. a chargeback request.
+///
+/// Payments with an open dispute can't be refunded, so the client isn't paid
+/// back twice once the dispute is settled.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Dispute {
+    pub id: Uuid,
+    pub payment_id: Uuid,
+    pub status: Status,
+    pub inserted_at: PrimitiveDateTime,
+    pub closed_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "DisputeStatus")]
+pub enum Status {
+    Open,
+    /// Closed for good, the payment can be refunded again.
+    Closed,
+}
+
+/// Outcome of the opening of a dispute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenOutcome {
+    Opened(Dispute),
+    /// There is no such payment.
+    PaymentNotFound,
+    /// The payment is already disputed by dispute `dispute_id`.
+    AlreadyOpen {
+        dispute_id: Uuid,
+    },
+}
+
+/// Opens a dispute of a payment, unless it is already disputed.
+///
+/// Disputes are opened while holding the row of the payment, like refunds
+/// are inserted, so a refund made concurrently either sees the dispute or is
+/// recorded before it.
+#[tracing::instrument(skip_all, fields(payment.id = %payment_id))]
+pub async fn open(pool: &PgPool, payment_id: Uuid) -> Result<OpenOutcome, sqlx::Error> {
+    let mut tx = transactions::begin(pool).await?;
+
+    let locked = sqlx::query!(
+        "SELECT id FROM payments WHERE id = $1 FOR UPDATE",
+        payment_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if locked.is_none() {
+        return Ok(OpenOutcome::PaymentNotFound);
+    }
+
+    if let Some(dispute_id) = find_open(&mut tx, payment_id).await? {
+        return Ok(OpenOutcome::AlreadyOpen { dispute_id });
+    }
+
+    let dispute = sqlx::query_as!(
+        Dispute,
+        r#"
+            INSERT INTO disputes ( payment_id )
+            VALUES ( $1 )
+            RETURNING id, payment_id, status as "status: _", inserted_at, closed_at
+        "#,
+        payment_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(OpenOutcome::Opened(dispute))
+}
+
+/// Returns the id of the open dispute of a payment, if any.
+///
+/// Called with the payment row locked, see `open`.
+pub async fn find_open(
+    tx: &mut Transaction,
+    payment_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT id FROM disputes WHERE payment_id = $1 AND status = 'Open'",
+        payment_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+}
+
+/// Closes a dispute, which lets its payment be refunded again. Closing a
+/// closed dispute changes nothing.
+pub async fn close(pool: &PgPool, id: Uuid) -> Result<Dispute, sqlx::Error> {
+    sqlx::query_as!(
+        Dispute,
+        r#"
+            UPDATE disputes
+            SET status = 'Closed', closed_at = COALESCE(closed_at, clock_timestamp())
+            WHERE id = $1
+            RETURNING id, payment_id, status as "status: _", inserted_at, closed_at
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get(pool: &PgPool, id: Uuid) -> Result<Dispute, sqlx::Error> {
+    sqlx::query_as!(
+        Dispute,
+        r#"
+            SELECT id, payment_id, status as "status: _", inserted_at, closed_at
+            FROM disputes
+            WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Lists the disputes of a payment, oldest first.
+///
+/// Payments are disputed rarely, so their disputes are all listed at once.
+pub async fn list(pool: &PgPool, payment_id: Uuid) -> Result<Vec<Dispute>, sqlx::Error> {
+    sqlx::query_as!(
+        Dispute,
+        r#"
+            SELECT id, payment_id, status as "status: _", inserted_at, closed_at
+            FROM disputes
+            WHERE payment_id = $1
+            ORDER BY inserted_at, id
+        "#,
+        payment_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank::payments::Payment;
+
+    #[tokio::test]
+    async fn test_disputes() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to create postgres pool");
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to insert payment");
+
+        let OpenOutcome::Opened(first) = open(&pool, payment.id)
+            .await
+            .expect("failed to open dispute")
+        else {
+            panic!("dispute wasn't opened");
+        };
+        assert_eq!(first.status, Status::Open);
+        assert_eq!(first.closed_at, None);
+
+        // a payment is disputed once at a time
+        let outcome = open(&pool, payment.id)
+            .await
+            .expect("failed to open dispute");
+        assert_eq!(
+            outcome,
+            OpenOutcome::AlreadyOpen {
+                dispute_id: first.id
+            }
+        );
+
+        let closed = close(&pool, first.id)
+            .await
+            .expect("failed to close dispute");
+        assert_eq!(closed.status, Status::Closed);
+        assert!(closed.closed_at.is_some());
+        let closed_again = close(&pool, first.id)
+            .await
+            .expect("failed to close dispute");
+        assert_eq!(closed_again, closed);
+
+        let OpenOutcome::Opened(second) = open(&pool, payment.id)
+            .await
+            .expect("failed to open dispute")
+        else {
+            panic!("dispute wasn't opened");
+        };
+        let disputes = list(&pool, payment.id)
+            .await
+            .expect("failed to list disputes");
+        assert_eq!(disputes, vec![closed, second]);
+
+        let outcome = open(&pool, Uuid::new_v4())
+            .await
+            .expect("failed to open dispute");
+        assert_eq!(outcome, OpenOutcome::PaymentNotFound);
+    }
+}
@@ -0,0 +1,97 @@
+use sqlx::PgPool;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use crate::bank::pagination::Page;
+
+/// A note left on a payment by a support agent, e.g. "customer called,
+/// dispute opened".
+///
+/// Notes are append-only: once written, they are never updated or deleted.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Note {
+    pub id: Uuid,
+    pub payment_id: Uuid,
+    pub author: String,
+    pub body: String,
+    pub inserted_at: PrimitiveDateTime,
+}
+
+/// Maximum length of the body of a note, in characters.
+pub const MAX_BODY_LENGTH: usize = 2000;
+
+/// Writes a note on a payment, returning it as stored.
+#[tracing::instrument(skip_all, fields(payment.id = %payment_id))]
+pub async fn insert(
+    pool: &PgPool,
+    payment_id: Uuid,
+    author: &str,
+    body: &str,
+) -> Result<Note, sqlx::Error> {
+    sqlx::query_as!(
+        Note,
+        r#"
+            INSERT INTO payment_notes ( payment_id, author, body )
+            VALUES ( $1, $2, $3 )
+            RETURNING id, payment_id, author, body, inserted_at
+        "#,
+        payment_id,
+        author,
+        body
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Returns a page of the notes of a payment, oldest first.
+pub async fn list(pool: &PgPool, payment_id: Uuid, page: &Page) -> Result<Vec<Note>, sqlx::Error> {
+    let (after_inserted_at, after_id) = page.after();
+
+    sqlx::query_as!(
+        Note,
+        r#"
+            SELECT id, payment_id, author, body, inserted_at
+            FROM payment_notes
+            WHERE payment_id = $1
+              AND ($2::timestamp IS NULL OR (inserted_at, id) > ($2, $3::uuid))
+            ORDER BY inserted_at, id
+            LIMIT $4
+        "#,
+        payment_id,
+        after_inserted_at,
+        after_id,
+        page.limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank::payments::{tests::FIRST_PAGE, Payment};
+
+    #[tokio::test]
+    async fn test_notes() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to create postgres pool");
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to insert payment");
+
+        let first = insert(&pool, payment.id, "alice", "customer called")
+            .await
+            .expect("failed to insert note");
+        let second = insert(&pool, payment.id, "bob", "dispute opened")
+            .await
+            .expect("failed to insert note");
+        assert_eq!(first.author, "alice");
+        assert_eq!(first.body, "customer called");
+
+        let notes = list(&pool, payment.id, &FIRST_PAGE)
+            .await
+            .expect("failed to list notes");
+        assert_eq!(notes, vec![first, second]);
+    }
+}
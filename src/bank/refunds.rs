@@ -1,7 +1,20 @@
+use std::time::Duration;
+
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use time::PrimitiveDateTime;
+use time::{OffsetDateTime, PrimitiveDateTime};
 use uuid::Uuid;
 
+use super::{
+    outbox::{self, EventType, RefundPayload},
+    pagination::Page,
+    payments::{disputes, Granularity, Payment, Status as PaymentStatus},
+    transactions::{self, Transaction},
+};
+
+pub mod retries;
+
 /// Module and schema representing a refund.
 ///
 /// A refund is always tied to a specific payment record, but it is possible
@@ -10,37 +23,114 @@ use uuid::Uuid;
 /// payment record, the but sum of all refunded amounts for a given payment can
 /// never surpass the original payment amount.
 ///
-/// If a refund is persisted in the database, it is considered effective: the
-/// bank's client will have the money credited to their account.
-#[derive(Debug, Clone, sqlx::FromRow)]
+/// A refund is always expressed in the currency of its payment.
+///
+/// A refund is only effective once settled, when the money was deposited
+/// on the bank's client account, see `Status`.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct Refund {
     pub id: Uuid,
     pub payment_id: Uuid,
-    pub amount: i32,
+    pub amount: i64,
+    pub currency: String,
+    pub status: Status,
+    pub reason: Option<Reason>,
+    /// Free text explanation of the `Other` reason.
+    pub reason_detail: Option<String>,
+    /// Merchant supplied key-value data, returned as is.
+    pub metadata: Option<serde_json::Value>,
+    /// Merchant's id of the refund, e.g. of their accounting entry, unique
+    /// among the refunds of the merchant.
+    pub external_reference: Option<String>,
+    /// Principal which requested the refund, see `Principal`.
+    pub initiated_by: String,
     pub inserted_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
 }
 
-pub async fn insert(pool: &PgPool, payment_id: Uuid, amount: i32) -> Result<Uuid, sqlx::Error> {
+/// Whether the money of a refund was deposited.
+///
+/// Pending refunds count against the refundable amount of their payment like
+/// settled ones, so it is never committed twice. Failed, abandoned and
+/// canceled ones don't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "RefundStatus")]
+pub enum Status {
+    /// Recorded, the deposit wasn't made yet.
+    Pending,
+    Settled,
+    /// The account service failed to deposit the money, which can be
+    /// retried, see `retries`.
+    Failed,
+    /// Canceled by an operator before it settled, for good.
+    Canceled,
+    /// Failed too many times to be retried, for good.
+    Abandoned,
+}
+
+impl Status {
+    pub const ALL: [Status; 5] = [
+        Status::Pending,
+        Status::Settled,
+        Status::Failed,
+        Status::Canceled,
+        Status::Abandoned,
+    ];
+
+    /// Whether the refund counts toward the refunded amount of its payment,
+    /// as summed by `payments::get_with_refund_totals`.
+    pub fn is_refunded(self) -> bool {
+        matches!(self, Status::Pending | Status::Settled)
+    }
+}
+
+/// Why a refund was issued, as stated by the merchant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "RefundReason")]
+pub enum Reason {
+    Duplicate,
+    Fraudulent,
+    RequestedByCustomer,
+    /// Explained by the reason detail of the refund.
+    Other,
+}
+
+/// Inserts a refund of `payment_id` without the checks of `checked_insert`,
+/// so tests can seed refunds in any state.
+#[cfg(test)]
+pub async fn insert(
+    pool: &PgPool,
+    payment_id: Uuid,
+    amount: i64,
+    currency: String,
+    reason: Option<Reason>,
+    reason_detail: Option<&str>,
+) -> Result<Uuid, sqlx::Error> {
     sqlx::query!(
         r#"
-            INSERT INTO refunds ( payment_id, amount )
-            VALUES ( $1, $2 )
+            INSERT INTO refunds ( payment_id, merchant_id, amount, currency, reason, reason_detail )
+            SELECT $1, merchant_id, $2, $3, $4, $5 FROM payments WHERE id = $1
             RETURNING id
         "#,
         payment_id,
         amount,
+        currency,
+        reason as Option<Reason>,
+        reason_detail,
     )
     .fetch_one(pool)
     .await
     .map(|record| record.id)
 }
 
+#[tracing::instrument(skip_all, fields(refund.id = %id))]
 pub async fn get(pool: &PgPool, id: Uuid) -> Result<Refund, sqlx::Error> {
     sqlx::query_as!(
         Refund,
         r#"
-            SELECT id, payment_id, amount, inserted_at, updated_at FROM refunds
+            SELECT id, payment_id, amount, currency, status as "status: _", reason as "reason: _", reason_detail, metadata, external_reference, initiated_by, inserted_at, updated_at FROM refunds
             WHERE id = $1
         "#,
         id
@@ -49,53 +139,711 @@ pub async fn get(pool: &PgPool, id: Uuid) -> Result<Refund, sqlx::Error> {
     .await
 }
 
-pub async fn checked_insert(
+/// Returns the refunds of a payment, oldest first.
+#[tracing::instrument(skip_all, fields(payment.id = %payment_id))]
+pub async fn list(pool: &PgPool, payment_id: Uuid) -> Result<Vec<Refund>, sqlx::Error> {
+    list_with_status(pool, payment_id, None).await
+}
+
+/// Lists the refunds of a payment, oldest first, only those of `status` if
+/// given.
+pub async fn list_with_status(
     pool: &PgPool,
     payment_id: Uuid,
-    refund_amount: i32,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    sqlx::query!(
+    status: Option<Status>,
+) -> Result<Vec<Refund>, sqlx::Error> {
+    sqlx::query_as!(
+        Refund,
         r#"
-          INSERT into refunds ( payment_id, amount )
-          SELECT $1, $2
-          WHERE EXISTS (
-            SELECT ( t2.amount - SUM(t1.amount) ) 
-            FROM refunds t1 
-            JOIN payments t2 on t1.payment_id = t2.id 
-            WHERE t1.payment_id = $1 
-            GROUP BY t1.payment_id, t2.amount
-            HAVING t2.amount - SUM(t1.amount) >= $2::integer
-          ) OR (
-            NOT EXISTS (
-              SELECT * FROM refunds WHERE payment_id = $1
-            )
-            AND EXISTS (
-              SELECT * FROM payments WHERE id = $1 AND amount >= $2
+            SELECT id, payment_id, amount, currency, status as "status: _", reason as "reason: _", reason_detail, metadata, external_reference, initiated_by, inserted_at, updated_at FROM refunds
+            WHERE payment_id = $1 AND ($2::RefundStatus IS NULL OR status = $2)
+            ORDER BY inserted_at, id
+        "#,
+        payment_id,
+        status as Option<Status>
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Filter of the refunds listed by `list_all`, every field narrowing it down.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListFilter {
+    /// Only returns refunds of the payments of this merchant.
+    pub merchant_id: Option<Uuid>,
+    /// Only returns refunds inserted at or after this time.
+    pub from: Option<PrimitiveDateTime>,
+    /// Only returns refunds inserted before this time.
+    pub to: Option<PrimitiveDateTime>,
+    /// Only returns refunds of at least this amount.
+    pub min_amount: Option<i64>,
+    pub status: Option<Status>,
+    /// Only returns the refund of this external reference.
+    pub external_reference: Option<String>,
+    /// Only returns the refunds requested by this principal.
+    pub initiated_by: Option<String>,
+}
+
+/// A refund listed with the payment it refunds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefundWithPayment {
+    pub refund: Refund,
+    /// Masked card number of the payment.
+    pub card_number: String,
+    pub payment_status: PaymentStatus,
+}
+
+/// Lists the refunds of every payment, newest first.
+pub async fn list_all(
+    pool: &PgPool,
+    filter: &ListFilter,
+    page: &Page,
+) -> Result<Vec<RefundWithPayment>, sqlx::Error> {
+    let (after_inserted_at, after_id) = page.after();
+
+    let records = sqlx::query!(
+        r#"
+            SELECT r.id, r.payment_id, r.amount, r.currency, r.status as "status: Status",
+                   r.reason as "reason: Reason", r.reason_detail, r.metadata, r.external_reference,
+                   r.initiated_by, r.inserted_at, r.updated_at,
+                   p.card_number, p.status as "payment_status: PaymentStatus"
+            FROM refunds r
+            JOIN payments p ON p.id = r.payment_id
+            WHERE ($1::uuid IS NULL OR p.merchant_id = $1)
+              AND ($2::timestamp IS NULL OR r.inserted_at >= $2)
+              AND ($3::timestamp IS NULL OR r.inserted_at < $3)
+              AND ($4::bigint IS NULL OR r.amount >= $4)
+              AND ($5::RefundStatus IS NULL OR r.status = $5)
+              AND ($6::timestamp IS NULL OR (r.inserted_at, r.id) < ($6, $7::uuid))
+              AND ($9::text IS NULL OR r.external_reference = $9)
+              AND ($10::text IS NULL OR r.initiated_by = $10)
+            ORDER BY r.inserted_at DESC, r.id DESC
+            LIMIT $8
+        "#,
+        filter.merchant_id,
+        filter.from,
+        filter.to,
+        filter.min_amount,
+        filter.status as Option<Status>,
+        after_inserted_at,
+        after_id,
+        page.limit,
+        filter.external_reference,
+        filter.initiated_by,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| RefundWithPayment {
+            refund: Refund {
+                id: record.id,
+                payment_id: record.payment_id,
+                amount: record.amount,
+                currency: record.currency,
+                status: record.status,
+                reason: record.reason,
+                reason_detail: record.reason_detail,
+                metadata: record.metadata,
+                external_reference: record.external_reference,
+                initiated_by: record.initiated_by,
+                inserted_at: record.inserted_at,
+                updated_at: record.updated_at,
+            },
+            card_number: record.card_number,
+            payment_status: record.payment_status,
+        })
+        .collect())
+}
+
+/// Streams the refunds matching `filter`, newest first, without loading them
+/// all in memory.
+pub fn stream<'a>(
+    pool: &'a PgPool,
+    filter: &'a ListFilter,
+) -> BoxStream<'a, Result<Refund, sqlx::Error>> {
+    sqlx::query_as!(
+        Refund,
+        r#"
+            SELECT id, payment_id, amount, currency, status as "status: _", reason as "reason: _", reason_detail, metadata, external_reference, initiated_by, inserted_at, updated_at FROM refunds
+            WHERE ($1::uuid IS NULL OR merchant_id = $1)
+              AND ($2::timestamp IS NULL OR inserted_at >= $2)
+              AND ($3::timestamp IS NULL OR inserted_at < $3)
+              AND ($4::bigint IS NULL OR amount >= $4)
+              AND ($5::RefundStatus IS NULL OR status = $5)
+              AND ($6::text IS NULL OR external_reference = $6)
+              AND ($7::text IS NULL OR initiated_by = $7)
+            ORDER BY inserted_at DESC, id DESC
+        "#,
+        filter.merchant_id,
+        filter.from,
+        filter.to,
+        filter.min_amount,
+        filter.status as Option<Status>,
+        filter.external_reference,
+        filter.initiated_by,
+    )
+    .fetch(pool)
+}
+
+/// Number and amount of the refunds of a time bucket and currency, next to
+/// the amount of the payments approved in it.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Aggregate {
+    pub bucket: PrimitiveDateTime,
+    pub currency: String,
+    pub count: i64,
+    pub refunded_amount: i64,
+    pub captured_amount: i64,
+}
+
+impl Aggregate {
+    /// Returns the share of the captured amount which was refunded, unknown
+    /// without captured payments.
+    pub fn refund_rate(&self) -> Option<f64> {
+        (self.captured_amount > 0)
+            .then(|| self.refunded_amount as f64 / self.captured_amount as f64)
+    }
+}
+
+/// Aggregates the refunds of a merchant inserted in `[from, to)` per time
+/// bucket and currency, with the approved payments inserted in the same
+/// bucket, ordered by bucket then currency.
+///
+/// Failed, abandoned and canceled refunds don't count. Buckets with neither refunds nor
+/// approved payments are omitted.
+pub async fn aggregate(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    from: PrimitiveDateTime,
+    to: PrimitiveDateTime,
+    granularity: Granularity,
+) -> Result<Vec<Aggregate>, sqlx::Error> {
+    sqlx::query_as!(
+        Aggregate,
+        r#"
+            WITH refunded AS (
+              SELECT date_trunc($4, inserted_at) as bucket, currency, COUNT(*) as count,
+                SUM(amount)::bigint as amount
+              FROM refunds
+              WHERE merchant_id = $1 AND inserted_at >= $2 AND inserted_at < $3
+                AND status NOT IN ('Failed', 'Abandoned', 'Canceled')
+              GROUP BY 1, 2
+            ), captured AS (
+              SELECT date_trunc($4, inserted_at) as bucket, currency, SUM(amount)::bigint as amount
+              FROM payments
+              WHERE merchant_id = $1 AND inserted_at >= $2 AND inserted_at < $3
+                AND status = 'Approved'
+              GROUP BY 1, 2
             )
-          )
-          RETURNING id
+            SELECT
+              COALESCE(r.bucket, c.bucket) as "bucket!",
+              COALESCE(r.currency, c.currency) as "currency!",
+              COALESCE(r.count, 0) as "count!",
+              -- the sums are numerics, cast back to bigints, which fail rather
+              -- than overflow
+              COALESCE(r.amount, 0) as "refunded_amount!",
+              COALESCE(c.amount, 0) as "captured_amount!"
+            FROM refunded r
+            FULL JOIN captured c ON c.bucket = r.bucket AND c.currency = r.currency
+            ORDER BY 1, 2
+        "#,
+        merchant_id,
+        from,
+        to,
+        granularity.as_str()
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Records whether the money of refund `id` was deposited, recording a
+/// `refund.settled` event in the outbox when it was. Canceled refunds are
+/// left canceled.
+///
+/// Pending refunds are settled or failed after an attempt to deposit them,
+/// which is counted in `deposit_attempts`.
+#[tracing::instrument(skip_all, fields(refund.id = %id))]
+pub async fn set_status(pool: &PgPool, id: Uuid, status: Status) -> Result<(), sqlx::Error> {
+    let mut tx = transactions::begin(pool).await?;
+
+    let refund = sqlx::query!(
+        r#"
+            UPDATE refunds
+            SET status = $2,
+                deposit_attempts = deposit_attempts + (status = 'Pending' AND $2 IN ('Settled', 'Failed'))::int,
+                updated_at = current_timestamp
+            WHERE id = $1 AND status <> $2 AND status NOT IN ('Canceled', 'Abandoned')
+            RETURNING payment_id, amount
+        "#,
+        id,
+        status as Status,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    // settlements are notified once, when the refund enters the status
+    if let (Some(refund), Status::Settled) = (refund, status) {
+        if let Some(payment) = lock_payment(&mut tx, refund.payment_id).await? {
+            let remaining_amount =
+                remaining_amount(&mut tx, refund.payment_id, payment.amount).await?;
+            let payload = RefundPayload {
+                event_type: EventType::RefundSettled,
+                refund_id: id,
+                payment_id: refund.payment_id,
+                amount: refund.amount,
+                remaining_amount,
+            };
+            outbox::record(&mut *tx, &payload).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Cancels refund `id` if it is still pending, returning it canceled.
+///
+/// The refund stops counting against its payment as soon as the change is
+/// committed, as the refundable amount is summed from the statuses.
+#[tracing::instrument(skip_all, fields(refund.id = %id))]
+pub async fn cancel(pool: &PgPool, id: Uuid) -> Result<Option<Refund>, sqlx::Error> {
+    sqlx::query_as!(
+        Refund,
+        r#"
+            UPDATE refunds
+            SET status = 'Canceled', updated_at = current_timestamp
+            WHERE id = $1 AND status = 'Pending'
+            RETURNING id, payment_id, amount, currency, status as "status: _", reason as "reason: _", reason_detail, metadata, external_reference, initiated_by, inserted_at, updated_at
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns the refund of `payment_id` recorded under `idempotency_key`, if
+/// any.
+#[tracing::instrument(skip_all, fields(payment.id = %payment_id))]
+pub async fn find_by_idempotency_key(
+    pool: &PgPool,
+    payment_id: Uuid,
+    idempotency_key: &str,
+) -> Result<Option<Refund>, sqlx::Error> {
+    sqlx::query_as!(
+        Refund,
+        r#"
+            SELECT id, payment_id, amount, currency, status as "status: _", reason as "reason: _", reason_detail, metadata, external_reference, initiated_by, inserted_at, updated_at FROM refunds
+            WHERE payment_id = $1 AND idempotency_key = $2
         "#,
         payment_id,
-        refund_amount
+        idempotency_key
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns the refund of a payment of `merchant_id` with `external_reference`,
+/// if any.
+#[tracing::instrument(skip_all, fields(merchant.id = %merchant_id))]
+pub async fn find_by_external_reference(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    external_reference: &str,
+) -> Result<Option<Refund>, sqlx::Error> {
+    sqlx::query_as!(
+        Refund,
+        r#"
+            SELECT id, payment_id, amount, currency, status as "status: _", reason as "reason: _", reason_detail, metadata, external_reference, initiated_by, inserted_at, updated_at FROM refunds
+            WHERE merchant_id = $1 AND external_reference = $2
+        "#,
+        merchant_id,
+        external_reference
     )
     .fetch_optional(pool)
     .await
-    .map(|record| record.map(|r| r.id))
+}
+
+/// The refund window of a payment ended before its refund was requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefundWindowExpired {
+    /// Last instant at which the payment could be refunded.
+    pub cutoff: OffsetDateTime,
+}
+
+/// Checks that `payment` can still be refunded at `now`, at most `window`
+/// after it was made, the last instant of the window included.
+///
+/// Merchant refunds are always checked, refunds made on behalf of the bank
+/// can skip it.
+pub fn check_refund_window(
+    payment: &Payment,
+    window: Duration,
+    now: OffsetDateTime,
+) -> Result<(), RefundWindowExpired> {
+    let cutoff = payment.inserted_at.assume_utc() + window;
+    if now > cutoff {
+        return Err(RefundWindowExpired { cutoff });
+    }
+
+    Ok(())
+}
+
+/// Outcome of a refund checked against the remaining refundable amount of
+/// its payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefundOutcome {
+    Created(Uuid),
+    /// The refund would exceed what is left to refund of the payment.
+    ExceedsRemaining {
+        remaining: i64,
+    },
+    /// The payment already has as many refunds as allowed, canceled ones
+    /// aside.
+    CountLimitReached {
+        limit: u32,
+    },
+    /// There is no such payment in the currency of the refund.
+    PaymentNotFound,
+    /// The payment isn't approved, e.g. its hold was voided meanwhile.
+    NotRefundable {
+        status: PaymentStatus,
+    },
+    /// The payment has an open dispute.
+    Disputed,
+    /// A refund of the payment was already recorded under the idempotency
+    /// key, which the unique index keeps true of concurrent inserts.
+    DuplicateIdempotencyKey,
+    /// Refund `refund_id` of the merchant already has the external
+    /// reference.
+    DuplicateExternalReference {
+        refund_id: Uuid,
+    },
+}
+
+/// Unique index of the external references of the refunds of a merchant.
+const EXTERNAL_REFERENCE_INDEX: &str = "refunds_merchant_id_external_reference_index";
+
+/// Inserts a pending refund unless it would exceed the remaining refundable
+/// amount of the payment or its `max_refunds` refunds, or its currency differs
+/// from the payment's currency.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(payment.id = %payment_id, refund.amount = refund_amount))]
+pub async fn checked_insert(
+    pool: &PgPool,
+    payment_id: Uuid,
+    refund_amount: i64,
+    currency: String,
+    reason: Option<Reason>,
+    reason_detail: Option<&str>,
+    idempotency_key: Option<&str>,
+    metadata: Option<serde_json::Value>,
+    external_reference: Option<&str>,
+    initiated_by: &str,
+    max_refunds: u32,
+) -> Result<RefundOutcome, sqlx::Error> {
+    let mut tx = transactions::begin(pool).await?;
+
+    let validated =
+        match validate(&mut tx, payment_id, refund_amount, &currency, max_refunds).await? {
+            Ok(validated) => validated,
+            Err(rejected) => return Ok(rejected),
+        };
+
+    let inserted = sqlx::query!(
+        r#"
+          INSERT into refunds ( payment_id, merchant_id, amount, currency, reason, reason_detail, idempotency_key, metadata, external_reference, initiated_by )
+          VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10 )
+          ON CONFLICT ( payment_id, idempotency_key ) DO NOTHING
+          RETURNING id
+        "#,
+        payment_id,
+        validated.merchant_id,
+        refund_amount,
+        currency,
+        reason as Option<Reason>,
+        reason_detail,
+        idempotency_key,
+        metadata,
+        external_reference,
+        initiated_by,
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+    let id = match inserted {
+        Ok(record) => record.map(|record| record.id),
+        // the failed insert aborted the transaction, so the refund already
+        // holding the reference is read once it is rolled back
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some(EXTERNAL_REFERENCE_INDEX) => {
+            drop(tx);
+            let reference = external_reference.unwrap_or_default();
+            return match find_by_external_reference(pool, validated.merchant_id, reference).await? {
+                Some(refund) => Ok(RefundOutcome::DuplicateExternalReference {
+                    refund_id: refund.id,
+                }),
+                None => Err(sqlx::Error::Database(e)),
+            };
+        }
+        Err(e) => return Err(e),
+    };
+
+    if let Some(id) = id {
+        let payload = RefundPayload {
+            event_type: EventType::RefundCreated,
+            refund_id: id,
+            payment_id,
+            amount: refund_amount,
+            remaining_amount: validated.remaining_after,
+        };
+        outbox::record(&mut *tx, &payload).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(id.map_or(
+        RefundOutcome::DuplicateIdempotencyKey,
+        RefundOutcome::Created,
+    ))
+}
+
+/// A refund of a payment found acceptable by `validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validated {
+    pub merchant_id: Uuid,
+    /// What would be left to refund of the payment after the refund.
+    pub remaining_after: i64,
+}
+
+/// Checks a refund against its payment, which is locked for the rest of
+/// `tx`, returning the outcome rejecting it if it can't be made.
+///
+/// Refunds of a payment are serialized on its row, so each one sees the
+/// refunds recorded before it, the status changes of the payment, which
+/// update the row, and its disputes, opened while holding the row.
+pub async fn validate(
+    tx: &mut Transaction,
+    payment_id: Uuid,
+    refund_amount: i64,
+    currency: &str,
+    max_refunds: u32,
+) -> Result<Result<Validated, RefundOutcome>, sqlx::Error> {
+    let payment = lock_payment(tx, payment_id).await?;
+    let Some(payment) = payment.filter(|payment| payment.currency == currency) else {
+        return Ok(Err(RefundOutcome::PaymentNotFound));
+    };
+    if payment.status != PaymentStatus::Approved {
+        return Ok(Err(RefundOutcome::NotRefundable {
+            status: payment.status,
+        }));
+    }
+    if disputes::find_open(tx, payment_id).await?.is_some() {
+        return Ok(Err(RefundOutcome::Disputed));
+    }
+
+    if refund_count(tx, payment_id).await? >= i64::from(max_refunds) {
+        return Ok(Err(RefundOutcome::CountLimitReached { limit: max_refunds }));
+    }
+
+    let remaining = remaining_amount(tx, payment_id, payment.amount).await?;
+    if refund_amount > remaining {
+        return Ok(Err(RefundOutcome::ExceedsRemaining {
+            remaining: remaining.max(0),
+        }));
+    }
+
+    Ok(Ok(Validated {
+        merchant_id: payment.merchant_id,
+        remaining_after: remaining - refund_amount,
+    }))
+}
+
+/// Checks a refund as `checked_insert` would, without recording anything.
+pub async fn preview(
+    pool: &PgPool,
+    payment_id: Uuid,
+    refund_amount: i64,
+    currency: &str,
+    max_refunds: u32,
+) -> Result<Result<Validated, RefundOutcome>, sqlx::Error> {
+    let mut tx = transactions::begin(pool).await?;
+    let validated = validate(&mut tx, payment_id, refund_amount, currency, max_refunds).await;
+    // rolled back, which releases the lock on the payment
+    drop(tx);
+    validated
+}
+
+/// Outcome of a refund of whatever is left of a payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullRefundOutcome {
+    Created {
+        id: Uuid,
+        amount: i64,
+    },
+    /// There is no such payment, or it is already fully refunded.
+    NothingToRefund,
+    /// The payment isn't approved, e.g. its hold was voided meanwhile.
+    NotRefundable {
+        status: PaymentStatus,
+    },
+    /// The payment has an open dispute.
+    Disputed,
+    /// The payment already has as many refunds as allowed, canceled ones
+    /// aside.
+    CountLimitReached {
+        limit: u32,
+    },
+}
+
+/// Refunds whatever is left of the payment, unless it already has
+/// `max_refunds` refunds.
+pub async fn insert_full(
+    pool: &PgPool,
+    payment_id: Uuid,
+    initiated_by: &str,
+    max_refunds: u32,
+) -> Result<FullRefundOutcome, sqlx::Error> {
+    let mut tx = transactions::begin(pool).await?;
+
+    let Some(payment) = lock_payment(&mut tx, payment_id).await? else {
+        return Ok(FullRefundOutcome::NothingToRefund);
+    };
+    if payment.status != PaymentStatus::Approved {
+        return Ok(FullRefundOutcome::NotRefundable {
+            status: payment.status,
+        });
+    }
+    if disputes::find_open(&mut tx, payment_id).await?.is_some() {
+        return Ok(FullRefundOutcome::Disputed);
+    }
+
+    let remaining = remaining_amount(&mut tx, payment_id, payment.amount).await?;
+    if remaining <= 0 {
+        return Ok(FullRefundOutcome::NothingToRefund);
+    }
+
+    if refund_count(&mut tx, payment_id).await? >= i64::from(max_refunds) {
+        return Ok(FullRefundOutcome::CountLimitReached { limit: max_refunds });
+    }
+
+    let id = sqlx::query!(
+        r#"
+          INSERT into refunds ( payment_id, merchant_id, amount, currency, initiated_by )
+          VALUES ( $1, $2, $3, $4, $5 )
+          RETURNING id
+        "#,
+        payment_id,
+        payment.merchant_id,
+        remaining,
+        payment.currency,
+        initiated_by,
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .id;
+
+    let payload = RefundPayload {
+        event_type: EventType::RefundCreated,
+        refund_id: id,
+        payment_id,
+        amount: remaining,
+        remaining_amount: 0,
+    };
+    outbox::record(&mut *tx, &payload).await?;
+
+    tx.commit().await?;
+
+    Ok(FullRefundOutcome::Created {
+        id,
+        amount: remaining,
+    })
+}
+
+struct LockedPayment {
+    merchant_id: Uuid,
+    amount: i64,
+    currency: String,
+    status: PaymentStatus,
+}
+
+async fn lock_payment(
+    tx: &mut Transaction,
+    payment_id: Uuid,
+) -> Result<Option<LockedPayment>, sqlx::Error> {
+    sqlx::query_as!(
+        LockedPayment,
+        r#"SELECT merchant_id, amount, currency, status as "status: _" FROM payments WHERE id = $1 FOR UPDATE"#,
+        payment_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+}
+
+/// Returns what is left to refund of a payment of `amount`, which is
+/// negative if it was refunded more than paid. Failed, abandoned and
+/// canceled refunds don't count.
+async fn remaining_amount(
+    tx: &mut Transaction,
+    payment_id: Uuid,
+    amount: i64,
+) -> Result<i64, sqlx::Error> {
+    // refunds never exceed the payment's bigint amount, so their numeric sum
+    // fits a bigint
+    let refunded = sqlx::query!(
+        r#"
+          SELECT COALESCE(SUM(amount), 0)::bigint as "refunded!"
+          FROM refunds
+          WHERE payment_id = $1 AND status NOT IN ('Failed', 'Abandoned', 'Canceled')
+        "#,
+        payment_id
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .refunded;
+
+    Ok(amount - refunded)
+}
+
+/// Returns the number of refunds of a payment, canceled ones aside.
+async fn refund_count(tx: &mut Transaction, payment_id: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+          SELECT count(*) as "count!"
+          FROM refunds
+          WHERE payment_id = $1 AND status <> 'Canceled'
+        "#,
+        payment_id
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map(|record| record.count)
 }
 
 #[cfg(test)]
 pub mod tests {
 
     use super::*;
-    use crate::bank::payments::Payment;
+    use crate::bank::payments::tests::{MERCHANT_ID, PAYMENT_AMOUNT};
 
-    pub const REFUND_AMOUNT: i32 = 42;
+    pub const REFUND_AMOUNT: i64 = 42;
+    pub const MAX_REFUNDS: u32 = 10;
 
     impl Refund {
         pub async fn new_test(pool: &PgPool) -> Result<Refund, sqlx::Error> {
-            let payment = Payment::new_test(pool).await?;
+            let payment = Payment::new_test(pool)
+                .await
+                .expect("failed to insert payment");
 
-            let id = insert(pool, payment.id, REFUND_AMOUNT).await?;
+            let id = insert(
+                pool,
+                payment.id,
+                REFUND_AMOUNT,
+                payment.currency,
+                None,
+                None,
+            )
+            .await?;
 
             get(pool, id).await
         }
@@ -113,4 +861,668 @@ pub mod tests {
 
         assert_eq!(refund.amount, REFUND_AMOUNT);
     }
+
+    #[tokio::test]
+    async fn test_checked_insert_refuses_other_currency() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        assert_eq!(payment.currency, "USD");
+
+        let outcome = checked_insert(
+            &pool,
+            payment.id,
+            REFUND_AMOUNT,
+            "EUR".to_string(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            "anonymous",
+            MAX_REFUNDS,
+        )
+        .await
+        .expect("failed to insert refund");
+        assert_eq!(outcome, RefundOutcome::PaymentNotFound);
+
+        let outcome = checked_insert(
+            &pool,
+            payment.id,
+            REFUND_AMOUNT,
+            payment.currency,
+            Some(Reason::Duplicate),
+            None,
+            None,
+            None,
+            None,
+            "anonymous",
+            MAX_REFUNDS,
+        )
+        .await
+        .expect("failed to insert refund");
+        let RefundOutcome::Created(refund_id) = outcome else {
+            panic!("refund refused: {outcome:?}");
+        };
+        let refund = get(&pool, refund_id).await.expect("failed to get refund");
+        assert_eq!(refund.reason, Some(Reason::Duplicate));
+    }
+
+    #[tokio::test]
+    async fn test_insert_records_reason_detail() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        let id = insert(
+            &pool,
+            payment.id,
+            REFUND_AMOUNT,
+            payment.currency,
+            Some(Reason::Other),
+            Some("goodwill gesture"),
+        )
+        .await
+        .expect("failed to insert refund");
+
+        let refunds = list(&pool, payment.id)
+            .await
+            .expect("failed to list refunds");
+        assert_eq!(refunds.len(), 1);
+        assert_eq!(refunds[0].id, id);
+        assert_eq!(refunds[0].reason, Some(Reason::Other));
+        assert_eq!(
+            refunds[0].reason_detail.as_deref(),
+            Some("goodwill gesture")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_insert_refuses_non_positive_amount() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        for amount in [0, -1] {
+            let result = insert(
+                &pool,
+                payment.id,
+                amount,
+                payment.currency.clone(),
+                None,
+                None,
+            )
+            .await;
+            assert!(result.is_err(), "{amount}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refund_window() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        let window = Duration::from_secs(90 * 24 * 60 * 60);
+        let cutoff = payment.inserted_at.assume_utc() + window;
+
+        assert_eq!(check_refund_window(&payment, window, cutoff), Ok(()));
+        assert_eq!(
+            check_refund_window(&payment, window, cutoff + Duration::from_secs(1)),
+            Err(RefundWindowExpired { cutoff })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_external_reference_is_unique_per_merchant() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let reference = Uuid::new_v4().to_string();
+        let mut outcomes = Vec::new();
+        for _ in 0..2 {
+            let payment = Payment::new_test(&pool)
+                .await
+                .expect("failed to create payment");
+            let outcome = checked_insert(
+                &pool,
+                payment.id,
+                REFUND_AMOUNT,
+                payment.currency,
+                None,
+                None,
+                None,
+                Some(serde_json::json!({ "entry": 1 })),
+                Some(&reference),
+                "anonymous",
+                MAX_REFUNDS,
+            )
+            .await
+            .expect("failed to insert refund");
+            outcomes.push(outcome);
+        }
+
+        let RefundOutcome::Created(refund_id) = outcomes[0] else {
+            panic!("refund refused: {:?}", outcomes[0]);
+        };
+        assert_eq!(
+            outcomes[1],
+            RefundOutcome::DuplicateExternalReference { refund_id }
+        );
+
+        let refund = find_by_external_reference(&pool, MERCHANT_ID, &reference)
+            .await
+            .expect("failed to find refund")
+            .expect("missing refund");
+        assert_eq!(refund.id, refund_id);
+        assert_eq!(refund.metadata, Some(serde_json::json!({ "entry": 1 })));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_with_payments() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        // a merchant of its own, so refunds of other tests are left out
+        let (merchant_id, other_merchant_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let day = time::Date::from_calendar_date(2023, time::Month::January, 1)
+            .unwrap()
+            .midnight();
+
+        // payments of the first day, with their refunds, then one of the
+        // second day without refunds
+        for (merchant_id, hours, amount, currency, status, refunds) in [
+            (
+                merchant_id,
+                1,
+                1000,
+                "USD",
+                PaymentStatus::Approved,
+                &[(100, Status::Settled), (150, Status::Pending)][..],
+            ),
+            (
+                merchant_id,
+                2,
+                600,
+                "USD",
+                PaymentStatus::Approved,
+                &[(500, Status::Failed)][..],
+            ),
+            (merchant_id, 3, 300, "USD", PaymentStatus::Declined, &[][..]),
+            (
+                merchant_id,
+                4,
+                200,
+                "EUR",
+                PaymentStatus::Approved,
+                &[(50, Status::Settled)][..],
+            ),
+            (
+                merchant_id,
+                25,
+                400,
+                "USD",
+                PaymentStatus::Approved,
+                &[][..],
+            ),
+            (
+                other_merchant_id,
+                1,
+                900,
+                "USD",
+                PaymentStatus::Approved,
+                &[(900, Status::Settled)][..],
+            ),
+        ] {
+            let payment = Payment::new_test(&pool)
+                .await
+                .expect("failed to create payment");
+            let inserted_at = day + time::Duration::hours(hours);
+            sqlx::query!(
+                r#"
+                    UPDATE payments SET merchant_id = $2, inserted_at = $3, amount = $4,
+                      currency = $5, status = $6
+                    WHERE id = $1
+                "#,
+                payment.id,
+                merchant_id,
+                inserted_at,
+                amount,
+                currency,
+                status as PaymentStatus
+            )
+            .execute(&pool)
+            .await
+            .expect("failed to backdate payment");
+
+            for &(amount, status) in refunds {
+                let id = insert(&pool, payment.id, amount, currency.to_string(), None, None)
+                    .await
+                    .expect("failed to insert refund");
+                sqlx::query!(
+                    "UPDATE refunds SET inserted_at = $2, status = $3 WHERE id = $1",
+                    id,
+                    inserted_at,
+                    status as Status
+                )
+                .execute(&pool)
+                .await
+                .expect("failed to backdate refund");
+            }
+        }
+
+        let to = day + time::Duration::days(2);
+        let aggregates = aggregate(&pool, merchant_id, day, to, Granularity::Day)
+            .await
+            .expect("failed to aggregate refunds");
+
+        assert_eq!(
+            aggregates,
+            vec![
+                Aggregate {
+                    bucket: day,
+                    currency: "EUR".to_string(),
+                    count: 1,
+                    refunded_amount: 50,
+                    captured_amount: 200,
+                },
+                Aggregate {
+                    bucket: day,
+                    currency: "USD".to_string(),
+                    count: 2,
+                    refunded_amount: 250,
+                    captured_amount: 1600,
+                },
+                Aggregate {
+                    bucket: day + time::Duration::days(1),
+                    currency: "USD".to_string(),
+                    count: 0,
+                    refunded_amount: 0,
+                    captured_amount: 400,
+                },
+            ]
+        );
+        assert_eq!(aggregates[0].refund_rate(), Some(0.25));
+        assert_eq!(aggregates[1].refund_rate(), Some(250.0 / 1600.0));
+        assert_eq!(aggregates[2].refund_rate(), Some(0.0));
+
+        let aggregates = aggregate(&pool, merchant_id, day, to, Granularity::Hour)
+            .await
+            .expect("failed to aggregate refunds");
+        assert_eq!(aggregates.len(), 4);
+        assert_eq!(aggregates[0].refund_rate(), Some(0.25));
+    }
+
+    #[tokio::test]
+    async fn test_refunds_serialize_with_capture_and_void() {
+        use crate::bank::payments::{self, TransitionError};
+
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let authorized = || async {
+            let payment = Payment::new_test(&pool)
+                .await
+                .expect("failed to create payment");
+            sqlx::query!(
+                "UPDATE payments SET status = 'Authorized' WHERE id = $1",
+                payment.id
+            )
+            .execute(&pool)
+            .await
+            .expect("failed to authorize payment");
+            payment
+        };
+        let refund = |payment: Payment| {
+            let pool = pool.clone();
+            async move {
+                checked_insert(
+                    &pool,
+                    payment.id,
+                    payment.amount,
+                    payment.currency,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    "anonymous",
+                    MAX_REFUNDS,
+                )
+                .await
+                .expect("failed to insert refund")
+            }
+        };
+
+        // voided payments are never refunded, whichever comes first
+        let payment = authorized().await;
+        let (voided, outcome) = tokio::join!(
+            payments::transition(
+                &pool,
+                payment.id,
+                PaymentStatus::Authorized,
+                PaymentStatus::Voided
+            ),
+            refund(payment.clone()),
+        );
+        assert!(voided.is_ok());
+        assert!(
+            matches!(outcome, RefundOutcome::NotRefundable { .. }),
+            "{outcome:?}"
+        );
+
+        // refunds racing a capture see the payment either authorized, and
+        // are refused, or captured, and are recorded at most in full
+        for _ in 0..5 {
+            let payment = authorized().await;
+            let (captured, outcomes) = tokio::join!(
+                payments::transition(
+                    &pool,
+                    payment.id,
+                    PaymentStatus::Authorized,
+                    PaymentStatus::Approved
+                ),
+                futures::future::join_all((0..2).map(|_| refund(payment.clone()))),
+            );
+            assert!(captured.is_ok());
+
+            let created = outcomes
+                .iter()
+                .filter(|outcome| matches!(outcome, RefundOutcome::Created(_)))
+                .count();
+            assert!(created <= 1, "{outcomes:?}");
+            let refunded: i64 = list(&pool, payment.id)
+                .await
+                .expect("failed to list refunds")
+                .iter()
+                .map(|refund| refund.amount)
+                .sum();
+            assert!(refunded <= payment.amount);
+
+            // captured payments can't be voided anymore
+            let voided = payments::transition(
+                &pool,
+                payment.id,
+                PaymentStatus::Approved,
+                PaymentStatus::Voided,
+            )
+            .await;
+            assert!(matches!(voided, Err(TransitionError::Illegal { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_refunds_dont_count_against_payment() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        for (amount, status) in [
+            (10, Status::Pending),
+            (20, Status::Settled),
+            (40, Status::Failed),
+        ] {
+            let id = insert(
+                &pool,
+                payment.id,
+                amount,
+                payment.currency.clone(),
+                None,
+                None,
+            )
+            .await
+            .expect("failed to insert refund");
+            set_status(&pool, id, status)
+                .await
+                .expect("failed to set status");
+        }
+
+        let remaining = PAYMENT_AMOUNT - 10 - 20;
+        let outcome = checked_insert(
+            &pool,
+            payment.id,
+            remaining + 1,
+            payment.currency.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            "anonymous",
+            MAX_REFUNDS,
+        )
+        .await
+        .expect("failed to insert refund");
+        assert_eq!(outcome, RefundOutcome::ExceedsRemaining { remaining });
+
+        let failed = list_with_status(&pool, payment.id, Some(Status::Failed))
+            .await
+            .expect("failed to list refunds");
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].amount, 40);
+
+        let outcome = insert_full(&pool, payment.id, "anonymous", MAX_REFUNDS)
+            .await
+            .expect("failed to insert refund");
+        assert!(
+            matches!(outcome, FullRefundOutcome::Created { amount, .. } if amount == remaining),
+            "{outcome:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refund_count_limit() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        let mut refund_ids = Vec::new();
+        for _ in 0..2 {
+            let outcome = checked_insert(
+                &pool,
+                payment.id,
+                1,
+                payment.currency.clone(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                "anonymous",
+                2,
+            )
+            .await
+            .expect("failed to insert refund");
+            let RefundOutcome::Created(id) = outcome else {
+                panic!("refund refused: {outcome:?}");
+            };
+            refund_ids.push(id);
+        }
+
+        let outcome = checked_insert(
+            &pool,
+            payment.id,
+            1,
+            payment.currency.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            "anonymous",
+            2,
+        )
+        .await
+        .expect("failed to insert refund");
+        assert_eq!(outcome, RefundOutcome::CountLimitReached { limit: 2 });
+        let outcome = insert_full(&pool, payment.id, "anonymous", 2)
+            .await
+            .expect("failed to insert refund");
+        assert_eq!(outcome, FullRefundOutcome::CountLimitReached { limit: 2 });
+
+        // canceled refunds don't count
+        cancel(&pool, refund_ids[0])
+            .await
+            .expect("failed to cancel refund");
+        let outcome = insert_full(&pool, payment.id, "anonymous", 2)
+            .await
+            .expect("failed to insert refund");
+        assert!(
+            matches!(outcome, FullRefundOutcome::Created { .. }),
+            "{outcome:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_preview_records_nothing() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+
+        let previewed = preview(
+            &pool,
+            payment.id,
+            REFUND_AMOUNT,
+            &payment.currency,
+            MAX_REFUNDS,
+        )
+        .await
+        .expect("failed to preview refund");
+        assert_eq!(
+            previewed,
+            Ok(Validated {
+                merchant_id: payment.merchant_id,
+                remaining_after: PAYMENT_AMOUNT - REFUND_AMOUNT,
+            })
+        );
+
+        let previewed = preview(
+            &pool,
+            payment.id,
+            PAYMENT_AMOUNT + 1,
+            &payment.currency,
+            MAX_REFUNDS,
+        )
+        .await
+        .expect("failed to preview refund");
+        assert_eq!(
+            previewed,
+            Err(RefundOutcome::ExceedsRemaining {
+                remaining: PAYMENT_AMOUNT
+            })
+        );
+
+        let previewed = preview(&pool, payment.id, REFUND_AMOUNT, &payment.currency, 0)
+            .await
+            .expect("failed to preview refund");
+        assert_eq!(
+            previewed,
+            Err(RefundOutcome::CountLimitReached { limit: 0 })
+        );
+
+        let refunds = list(&pool, payment.id)
+            .await
+            .expect("failed to list refunds");
+        assert!(refunds.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_record_events_of_settled_refund() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        let outcome = checked_insert(
+            &pool,
+            payment.id,
+            REFUND_AMOUNT,
+            payment.currency.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            "anonymous",
+            MAX_REFUNDS,
+        )
+        .await
+        .expect("failed to insert refund");
+        let RefundOutcome::Created(id) = outcome else {
+            panic!("refund refused: {outcome:?}");
+        };
+        for _ in 0..2 {
+            set_status(&pool, id, Status::Settled)
+                .await
+                .expect("failed to set status");
+        }
+
+        let events = outbox::tests::list_for_payment(&pool, payment.id)
+            .await
+            .expect("failed to list outbox events");
+        let payloads: Vec<RefundPayload> = events
+            .iter()
+            .filter(|event| event.event_type.starts_with("refund."))
+            .map(|event| serde_json::from_value(event.payload.clone()).unwrap())
+            .collect();
+        let remaining_amount = PAYMENT_AMOUNT - REFUND_AMOUNT;
+        assert_eq!(
+            payloads,
+            [EventType::RefundCreated, EventType::RefundSettled].map(|event_type| {
+                RefundPayload {
+                    event_type,
+                    refund_id: id,
+                    payment_id: payment.id,
+                    amount: REFUND_AMOUNT,
+                    remaining_amount,
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_status() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let refund = Refund::new_test(&pool)
+            .await
+            .expect("failed to create refund");
+        assert_eq!(refund.status, Status::Pending);
+
+        set_status(&pool, refund.id, Status::Failed)
+            .await
+            .expect("failed to set status");
+        let refund = get(&pool, refund.id).await.expect("failed to get refund");
+        assert_eq!(refund.status, Status::Failed);
+    }
 }
@@ -0,0 +1,450 @@
+use std::time::Duration;
+
+use sqlx::{postgres::types::PgInterval, PgPool};
+use uuid::Uuid;
+
+use super::{lock_payment, remaining_amount, set_status, Status};
+use crate::bank::{
+    accounts::AccountService,
+    journal, payment_instruments,
+    payments::disputes,
+    transactions::{self, Transaction},
+};
+
+/// Failed refund moved back to pending for its deposit to be retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restarted {
+    pub id: Uuid,
+    pub payment_id: Uuid,
+    pub amount: i64,
+    /// Masked card number of the payment, see
+    /// `payment_instruments::masked_account_number`.
+    pub card_number: String,
+}
+
+/// Outcome of the restart of a failed refund.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestartOutcome {
+    Restarted(Restarted),
+    /// There is no such refund.
+    NotFound,
+    /// Only failed refunds are retried.
+    NotFailed {
+        status: Status,
+    },
+    /// The payment is under dispute, see `disputes`.
+    Disputed,
+    /// The refund no longer fits in what is left to refund of its payment,
+    /// e.g. as another refund was made since it failed.
+    ExceedsRemaining {
+        remaining: i64,
+    },
+}
+
+/// Moves failed refund `id` back to pending, so it counts against its
+/// payment again while its deposit is retried.
+///
+/// The refund is checked against its payment like a new one, as failed
+/// refunds stop counting against their payment. The refund row is locked
+/// before the payment row, like in `set_status`.
+async fn restart(tx: &mut Transaction, id: Uuid) -> Result<RestartOutcome, sqlx::Error> {
+    let Some(refund) = sqlx::query!(
+        r#"
+            SELECT r.payment_id, r.amount, r.status as "status: Status", p.card_number
+            FROM refunds r
+            JOIN payments p ON p.id = r.payment_id
+            WHERE r.id = $1
+            FOR UPDATE OF r
+        "#,
+        id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Ok(RestartOutcome::NotFound);
+    };
+
+    if refund.status != Status::Failed {
+        return Ok(RestartOutcome::NotFailed {
+            status: refund.status,
+        });
+    }
+
+    let Some(payment) = lock_payment(tx, refund.payment_id).await? else {
+        return Ok(RestartOutcome::NotFound);
+    };
+    if disputes::find_open(tx, refund.payment_id).await?.is_some() {
+        return Ok(RestartOutcome::Disputed);
+    }
+    let remaining = remaining_amount(tx, refund.payment_id, payment.amount).await?;
+    if refund.amount > remaining {
+        return Ok(RestartOutcome::ExceedsRemaining {
+            remaining: remaining.max(0),
+        });
+    }
+
+    sqlx::query!(
+        "UPDATE refunds SET status = 'Pending', updated_at = current_timestamp WHERE id = $1",
+        id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(RestartOutcome::Restarted(Restarted {
+        id,
+        payment_id: refund.payment_id,
+        amount: refund.amount,
+        card_number: refund.card_number,
+    }))
+}
+
+/// Moves failed refund `id` back to pending for a manual retry of its
+/// deposit, see `restart`.
+#[tracing::instrument(skip_all, fields(refund.id = %id))]
+pub async fn retry(pool: &PgPool, id: Uuid) -> Result<RestartOutcome, sqlx::Error> {
+    let mut tx = transactions::begin(pool).await?;
+
+    let outcome = restart(&mut tx, id).await?;
+    if let RestartOutcome::Restarted(_) = outcome {
+        tx.commit().await?;
+    }
+
+    Ok(outcome)
+}
+
+/// Claims up to `limit` refunds failed less than `max_age` after they were
+/// made, and at least `retry_delay` after their last deposit attempt,
+/// moving them back to pending.
+///
+/// Refunds no longer fitting in their payment are abandoned, while those of
+/// disputed payments are left failed, to be retried once the dispute is
+/// closed. Refunds claimed concurrently by another retrier are skipped.
+pub async fn claim(
+    pool: &PgPool,
+    max_age: Duration,
+    retry_delay: Duration,
+    limit: i64,
+) -> Result<Vec<Restarted>, sqlx::Error> {
+    let max_age = PgInterval::try_from(max_age).map_err(sqlx::Error::Configuration)?;
+    let retry_delay = PgInterval::try_from(retry_delay).map_err(sqlx::Error::Configuration)?;
+    let mut tx = transactions::begin(pool).await?;
+
+    let ids = sqlx::query_scalar!(
+        r#"
+            SELECT id FROM refunds
+            WHERE status = 'Failed'
+              AND inserted_at > LOCALTIMESTAMP - $1::interval
+              AND updated_at <= LOCALTIMESTAMP - $2::interval
+            ORDER BY updated_at
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
+        "#,
+        max_age,
+        retry_delay,
+        limit
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut restarted = Vec::with_capacity(ids.len());
+    for id in ids {
+        match restart(&mut tx, id).await? {
+            RestartOutcome::Restarted(refund) => restarted.push(refund),
+            RestartOutcome::ExceedsRemaining { remaining } => {
+                tracing::warn!(refund.id = %id, remaining, "abandoned refund exceeding its payment");
+                abandon(&mut tx, id).await?;
+            }
+            RestartOutcome::NotFound
+            | RestartOutcome::NotFailed { .. }
+            | RestartOutcome::Disputed => {}
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(restarted)
+}
+
+async fn abandon(tx: &mut Transaction, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE refunds SET status = 'Abandoned', updated_at = current_timestamp WHERE id = $1",
+        id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Records a failed deposit attempt of pending refund `id`, abandoning it
+/// once `max_attempts` deposits were attempted. Returns the new status of
+/// the refund, if it was pending.
+#[tracing::instrument(skip_all, fields(refund.id = %id))]
+pub async fn record_failure(
+    pool: &PgPool,
+    id: Uuid,
+    max_attempts: i32,
+) -> Result<Option<Status>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+            UPDATE refunds
+            SET status = CASE WHEN deposit_attempts + 1 >= $2 THEN 'Abandoned' ELSE 'Failed' END::RefundStatus,
+                deposit_attempts = deposit_attempts + 1,
+                updated_at = current_timestamp
+            WHERE id = $1 AND status = 'Pending'
+            RETURNING status as "status: Status"
+        "#,
+        id,
+        max_attempts
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Background task retrying the deposit of failed refunds, until it
+/// succeeds or `max_attempts` deposits were attempted.
+pub struct Retrier<T> {
+    pool: PgPool,
+    account_service: T,
+    interval: Duration,
+    max_age: Duration,
+    retry_delay: Duration,
+    max_attempts: i32,
+    batch_size: i64,
+}
+
+impl<T: AccountService> Retrier<T> {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+    /// Refunds failed for longer are left to operators.
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Leaves the account service some time to recover between attempts.
+    pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+    pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+    pub const DEFAULT_BATCH_SIZE: i64 = 10;
+
+    pub fn new(pool: PgPool, account_service: T) -> Self {
+        Self {
+            pool,
+            account_service,
+            interval: Self::DEFAULT_INTERVAL,
+            max_age: Self::DEFAULT_MAX_AGE,
+            retry_delay: Self::DEFAULT_RETRY_DELAY,
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets how often failed refunds are looked for.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how many deposits of a refund are attempted, the first one
+    /// included, before it is abandoned.
+    pub fn with_max_attempts(mut self, max_attempts: i32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Retries failed refunds every interval until the task is aborted.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            match self.retry_batch().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(refunds.retried = count, "retried failed refunds"),
+                Err(e) => tracing::error!("failed to retry refunds: {e}"),
+            }
+        }
+    }
+
+    /// Claims and retries a batch of failed refunds, returning how many were
+    /// claimed.
+    pub async fn retry_batch(&self) -> Result<usize, sqlx::Error> {
+        let refunds = claim(&self.pool, self.max_age, self.retry_delay, self.batch_size).await?;
+
+        for refund in &refunds {
+            self.deposit(refund).await?;
+        }
+
+        Ok(refunds.len())
+    }
+
+    async fn deposit(&self, refund: &Restarted) -> Result<(), sqlx::Error> {
+        let refund_id = refund.id;
+
+        // the account number is left visible in masked card numbers
+        let deposit = match payment_instruments::masked_account_number(&refund.card_number) {
+            Some(account_number) => {
+                journal::for_payment(
+                    refund.payment_id,
+                    self.account_service
+                        .deposit_funds(account_number, refund.amount),
+                )
+                .await
+            }
+            None => Err(format!(
+                "payment {} has an invalid card number",
+                refund.payment_id
+            )),
+        };
+
+        match deposit {
+            Ok(()) => {
+                set_status(&self.pool, refund_id, Status::Settled).await?;
+                tracing::info!(refund.id = %refund_id, "settled retried refund");
+            }
+            Err(error) => {
+                let status = record_failure(&self.pool, refund_id, self.max_attempts).await?;
+                tracing::warn!(refund.id = %refund_id, refund.status = ?status, "failed to deposit refund: {error}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+    use crate::bank::{
+        accounts::{AccountMethod, DummyService, ScriptedOutcome},
+        payments::tests::PAYMENT_AMOUNT,
+        refunds::{self, tests::REFUND_AMOUNT, Refund},
+    };
+
+    pub async fn deposit_attempts(pool: &PgPool, id: Uuid) -> i32 {
+        sqlx::query_scalar!("SELECT deposit_attempts FROM refunds WHERE id = $1", id)
+            .fetch_one(pool)
+            .await
+            .expect("failed to get deposit attempts")
+    }
+
+    /// Moves `column` of a refund back past the retry delay and the max age,
+    /// so the retrier leaves the fresh refunds of other tests alone.
+    async fn backdate(pool: &PgPool, id: Uuid, column: &str) {
+        sqlx::query(&format!(
+            "UPDATE refunds SET {column} = {column} - interval '25 hours' WHERE id = $1"
+        ))
+        .bind(id)
+        .execute(pool)
+        .await
+        .expect("failed to backdate refund");
+    }
+
+    async fn new_failed_refund(pool: &PgPool) -> Uuid {
+        let refund = Refund::new_test(pool)
+            .await
+            .expect("failed to insert refund");
+        set_status(pool, refund.id, Status::Failed)
+            .await
+            .expect("failed to fail refund");
+        refund.id
+    }
+
+    fn failing_deposits(remaining: Option<u32>) -> DummyService {
+        let mut outcome =
+            ScriptedOutcome::new("service_unavailable").with_method(AccountMethod::Deposit);
+        if let Some(remaining) = remaining.and_then(NonZeroU32::new) {
+            outcome = outcome.with_remaining(remaining);
+        }
+        DummyService::default().with_scripted_outcome(outcome)
+    }
+
+    // a single test, as the retriers of concurrent tests would claim each
+    // other's refunds
+    #[tokio::test]
+    async fn test_retry_failed_deposits() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to create postgres pool");
+
+        // the service fails twice, then recovers
+        let retrier = Retrier::new(pool.clone(), failing_deposits(Some(2)));
+        let id = new_failed_refund(&pool).await;
+        assert_eq!(deposit_attempts(&pool, id).await, 1);
+        for _ in 0..3 {
+            // refunds aren't retried before the retry delay
+            retrier
+                .retry_batch()
+                .await
+                .expect("failed to retry refunds");
+            backdate(&pool, id, "updated_at").await;
+            retrier
+                .retry_batch()
+                .await
+                .expect("failed to retry refunds");
+        }
+        let refund = refunds::get(&pool, id).await.unwrap();
+        assert_eq!(refund.status, Status::Settled);
+        assert_eq!(deposit_attempts(&pool, id).await, 4);
+
+        // refunds are abandoned after max_attempts deposits
+        let retrier = Retrier::new(pool.clone(), failing_deposits(None)).with_max_attempts(2);
+        let id = new_failed_refund(&pool).await;
+        backdate(&pool, id, "updated_at").await;
+        retrier
+            .retry_batch()
+            .await
+            .expect("failed to retry refunds");
+        let refund = refunds::get(&pool, id).await.unwrap();
+        assert_eq!(refund.status, Status::Abandoned);
+        assert_eq!(deposit_attempts(&pool, id).await, 2);
+
+        // refunds failed long ago are left to operators
+        let id = new_failed_refund(&pool).await;
+        backdate(&pool, id, "inserted_at").await;
+        backdate(&pool, id, "updated_at").await;
+        retrier
+            .retry_batch()
+            .await
+            .expect("failed to retry refunds");
+        let refund = refunds::get(&pool, id).await.unwrap();
+        assert_eq!(refund.status, Status::Failed);
+        assert_eq!(deposit_attempts(&pool, id).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_checks_remaining_amount() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to create postgres pool");
+        let id = new_failed_refund(&pool).await;
+        let refund = refunds::get(&pool, id).await.unwrap();
+
+        // the payment was refunded by another refund since this one failed
+        let amount = PAYMENT_AMOUNT - REFUND_AMOUNT + 1;
+        refunds::insert(
+            &pool,
+            refund.payment_id,
+            amount,
+            refund.currency,
+            None,
+            None,
+        )
+        .await
+        .expect("failed to insert refund");
+
+        let outcome = retry(&pool, id).await.expect("failed to retry refund");
+        assert_eq!(
+            outcome,
+            RestartOutcome::ExceedsRemaining {
+                remaining: REFUND_AMOUNT - 1
+            }
+        );
+        let refund = refunds::get(&pool, id).await.unwrap();
+        assert_eq!(refund.status, Status::Failed);
+
+        let outcome = retry(&pool, Uuid::new_v4())
+            .await
+            .expect("failed to retry refund");
+        assert_eq!(outcome, RestartOutcome::NotFound);
+    }
+}
@@ -0,0 +1,561 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+    accounts::AccountService,
+    accounts::HoldRef,
+    fees::{self, FeePolicy},
+    journal, merchants, payment_instruments,
+    payments::{self, Latency, PaymentRepoError, SettlementOutcome, Status, TransitionError},
+    tasks::{self, TaskKind},
+};
+use crate::errors::PaymentError;
+
+/// Called with the new status of a payment after each of its transitions,
+/// e.g. to notify webhooks.
+pub type Notify = Arc<dyn Fn(Uuid, Status) + Send + Sync>;
+
+/// Number of attempts at recording the approval of a payment whose funds
+/// were withdrawn, before it is left to the expiry reaper, see
+/// `payments::record_repair`.
+const APPROVAL_ATTEMPTS: u32 = 3;
+const APPROVAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Delay between the attempts at claiming the hold of a payment, e.g. while
+/// it is amended.
+const HOLD_CLAIM_RETRY_DELAY: Duration = Duration::from_millis(50);
+/// Long enough for the account service calls of an amendment or a
+/// withdrawal, after which the hold of a crashed claimant can be claimed.
+pub const HOLD_CLAIM_TTL: Duration = Duration::from_secs(60);
+
+#[cfg(test)]
+tokio::task_local! {
+    /// Number of approvals left to fail as if the pool was closed, injected
+    /// by tests between the withdrawal and the approval of a payment.
+    pub static FAILING_APPROVALS: std::cell::Cell<u32>;
+}
+
+/// Outcome of `withdraw`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Withdrawal {
+    /// The held funds were withdrawn, `amount` being the amount of the
+    /// payment when they were.
+    Withdrawn { amount: i64 },
+    /// The funds couldn't be withdrawn, and the hold was released.
+    Failed { error: String },
+}
+
+/// Outcome of `settle`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settlement {
+    pub status: Status,
+    /// Error of the account service call that declined or failed the payment.
+    pub error: Option<String>,
+}
+
+/// Places a hold on the funds of a processing payment and withdraws them,
+/// moving the payment to its final status.
+///
+/// A hold that can't be withdrawn is released, so the funds aren't left held.
+/// Once the funds are withdrawn, the approval is retried, and the payment
+/// recorded for repair if it still can't be approved. The latencies of the
+/// hold and withdrawal are recorded along with the statuses they lead to, and
+/// the fees of the payment once its funds are withdrawn.
+pub async fn settle<T: AccountService>(
+    pool: &PgPool,
+    account_service: &T,
+    notify: &(dyn Fn(Uuid, Status) + Send + Sync),
+    fee_policy: &FeePolicy,
+    payment_id: Uuid,
+    account_number: &str,
+    amount: i64,
+) -> Result<Settlement, TransitionError> {
+    let transition = |from, to, latency| async move {
+        payments::transition_with_latency(pool, payment_id, from, to, latency).await?;
+        notify(payment_id, to);
+        Ok::<_, TransitionError>(())
+    };
+    let decline = |from, error: String, latency| async move {
+        let reason = PaymentError::from(&error).reason;
+        let status =
+            payments::decline_with_latency(pool, payment_id, from, reason, latency).await?;
+        notify(payment_id, status);
+        Ok(Settlement {
+            status,
+            error: Some(error),
+        })
+    };
+
+    // the hold is recorded for `reconcile` and amendments, but failing to
+    // record it doesn't stop the settlement, the hold being known here
+    let record_hold = |hold_ref| async move {
+        if let Err(e) = payments::record_hold(pool, payment_id, hold_ref).await {
+            tracing::error!("failed to record hold of payment {payment_id}: {e}");
+        }
+    };
+
+    // account service calls are journaled as made for the payment
+    journal::for_payment(payment_id, async {
+        let mut latency = Latency::default();
+
+        let started = Instant::now();
+        let hold = account_service.place_hold(account_number, amount).await;
+        latency.hold_ms = Some(Latency::millis(started.elapsed()));
+        let hold_ref = match hold {
+            Ok(hold_ref) => hold_ref,
+            Err(error) => return decline(Status::Processing, error, latency).await,
+        };
+        record_hold(hold_ref.id()).await;
+        transition(Status::Processing, Status::Authorized, latency).await?;
+
+        // the payment may be amended meanwhile, so the hold withdrawn is the
+        // one recorded then
+        let amount =
+            match withdraw(pool, account_service, payment_id, hold_ref, &mut latency).await? {
+                Withdrawal::Withdrawn { amount } => amount,
+                Withdrawal::Failed { error } => {
+                    return decline(Status::Authorized, error, latency).await
+                }
+            };
+        record_fees(pool, payment_id, amount, fee_policy).await;
+        approve(pool, payment_id, || {
+            transition(Status::Authorized, Status::Approved, latency)
+        })
+        .await?;
+
+        Ok(Settlement {
+            status: Status::Approved,
+            error: None,
+        })
+    })
+    .await
+}
+
+/// Withdraws the funds held for an authorized payment, releasing its hold if
+/// they can't be, and records the latency of the withdrawal in `latency`.
+///
+/// The hold is claimed first, waiting for the amendment of the payment
+/// claiming it, if any, and the hold and amount are read under the claim:
+/// `placed`, the hold placed for the payment, is only withdrawn unless an
+/// amendment recorded another one. The claim is completed once the hold is
+/// settled, so the payment can't be amended anymore.
+pub async fn withdraw<T: AccountService>(
+    pool: &PgPool,
+    account_service: &T,
+    payment_id: Uuid,
+    placed: HoldRef,
+    latency: &mut Latency,
+) -> Result<Withdrawal, TransitionError> {
+    let kind = TaskKind::hold();
+    let claimant = Uuid::new_v4();
+    while !tasks::claim(pool, &kind, payment_id, claimant, HOLD_CLAIM_TTL)
+        .await
+        .map_err(PaymentRepoError::from)?
+    {
+        let held = payments::get_held_funds(pool, payment_id).await?;
+        if held.status != Status::Authorized || held.settlement_outcome.is_some() {
+            return Err(PaymentRepoError::Conflict {
+                current_status: held.status,
+            }
+            .into());
+        }
+        tokio::time::sleep(HOLD_CLAIM_RETRY_DELAY).await;
+    }
+
+    let held = match payments::get_held_funds(pool, payment_id).await {
+        Ok(held) => held,
+        Err(e) => {
+            if let Err(e) = tasks::release(pool, &kind, payment_id, claimant).await {
+                tracing::error!("failed to release hold claim of payment {payment_id}: {e}");
+            }
+            return Err(e.into());
+        }
+    };
+    let hold_ref = held.hold_ref.map(HoldRef::from_id).unwrap_or(placed);
+
+    let started = Instant::now();
+    let withdrawal = account_service.withdraw_funds(hold_ref).await;
+    latency.withdraw_ms = Some(Latency::millis(started.elapsed()));
+    let (outcome, withdrawal) = match withdrawal {
+        Ok(()) => (
+            Some(SettlementOutcome::Withdrawn),
+            Withdrawal::Withdrawn {
+                amount: held.amount,
+            },
+        ),
+        Err(error) => match account_service.release_hold(hold_ref).await {
+            Ok(()) => (
+                Some(SettlementOutcome::Released),
+                Withdrawal::Failed { error },
+            ),
+            Err(e) => {
+                tracing::error!("failed to release hold of payment {payment_id}: {e}");
+                (None, Withdrawal::Failed { error })
+            }
+        },
+    };
+
+    // the outcome is only recorded for `reconcile`, so failing to record it
+    // doesn't stop the settlement
+    if let Some(outcome) = outcome {
+        if let Err(e) = payments::record_settlement(pool, payment_id, outcome).await {
+            tracing::error!("failed to record settlement of payment {payment_id}: {e}");
+        }
+    }
+    match tasks::complete(pool, &kind, payment_id, claimant).await {
+        Ok(true) => {}
+        Ok(false) => tracing::warn!("lost the hold claim of payment {payment_id}"),
+        Err(e) => tracing::error!("failed to complete hold claim of payment {payment_id}: {e}"),
+    }
+
+    Ok(withdrawal)
+}
+
+/// Records the fees of a payment whose funds were withdrawn, under the fee
+/// policy of its merchant, or `default_policy` if it has none.
+///
+/// The money moved, so failing to record them doesn't stop the settlement.
+async fn record_fees(pool: &PgPool, payment_id: Uuid, amount: i64, default_policy: &FeePolicy) {
+    let policy = match payments::get(pool, payment_id).await {
+        Ok(payment) => merchants::get_fee_policy(pool, payment.merchant_id)
+            .await
+            .map_err(PaymentRepoError::from),
+        Err(e) => Err(e),
+    };
+    let policy = match policy {
+        Ok(policy) => policy.unwrap_or(*default_policy),
+        Err(e) => {
+            tracing::error!("failed to get fee policy of payment {payment_id}: {e}");
+            return;
+        }
+    };
+
+    let fees = fees::compute(amount, &policy);
+    if let Err(e) = payments::record_fees(pool, payment_id, policy, fees).await {
+        tracing::error!(?fees, "failed to record fees of payment {payment_id}: {e}");
+    }
+}
+
+/// Records the approval of a payment whose funds were withdrawn through
+/// `transition`, retrying storage errors.
+///
+/// The money moved, so a payment which still can't be approved is recorded
+/// for repair rather than left authorized.
+async fn approve<F, Fut>(
+    pool: &PgPool,
+    payment_id: Uuid,
+    transition: F,
+) -> Result<(), TransitionError>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<(), TransitionError>>,
+{
+    let mut attempt = 1;
+    loop {
+        #[cfg(test)]
+        let result = if FAILING_APPROVALS
+            .try_with(|failing| failing.replace(failing.get().saturating_sub(1)) > 0)
+            .unwrap_or(false)
+        {
+            Err(sqlx::Error::PoolClosed.into())
+        } else {
+            transition().await
+        };
+        #[cfg(not(test))]
+        let result = transition().await;
+
+        let error = match result {
+            Err(TransitionError::Repo(PaymentRepoError::Storage(error))) => error,
+            result => return result,
+        };
+        if attempt < APPROVAL_ATTEMPTS {
+            tracing::warn!("failed to approve payment {payment_id}, attempt {attempt}: {error}");
+            tokio::time::sleep(APPROVAL_RETRY_DELAY * attempt).await;
+            attempt += 1;
+            continue;
+        }
+
+        tracing::error!("failed to approve withdrawn payment {payment_id}: {error}");
+        if let Err(e) = payments::record_repair(pool, payment_id, Status::Approved).await {
+            tracing::error!("failed to record repair of payment {payment_id}: {e}");
+        }
+        return Err(PaymentRepoError::Storage(error).into());
+    }
+}
+
+/// Background worker settling the payments processed asynchronously.
+pub struct Worker<T> {
+    id: Uuid,
+    pool: PgPool,
+    account_service: T,
+    notify: Notify,
+    /// Fee policy of the merchants without one of their own.
+    fee_policy: FeePolicy,
+    batch_size: i64,
+    poll_interval: Duration,
+    claim_ttl: Duration,
+}
+
+impl<T: AccountService> Worker<T> {
+    pub const DEFAULT_BATCH_SIZE: i64 = 10;
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+    /// Long enough for the account service calls of a whole batch, after
+    /// which the payments of a crashed worker are settled by another one.
+    pub const DEFAULT_CLAIM_TTL: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new(pool: PgPool, account_service: T, notify: Notify) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            pool,
+            account_service,
+            notify,
+            fee_policy: FeePolicy::default(),
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+            claim_ttl: Self::DEFAULT_CLAIM_TTL,
+        }
+    }
+
+    /// Sets the fee policy of the merchants without one of their own.
+    pub fn with_fee_policy(mut self, fee_policy: FeePolicy) -> Self {
+        self.fee_policy = fee_policy;
+        self
+    }
+
+    /// Sets how long the worker waits for new payments once none is left.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Settles payments until the task is aborted.
+    pub async fn run(self) {
+        loop {
+            match self.settle_batch().await {
+                Ok(0) => tokio::time::sleep(self.poll_interval).await,
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("failed to settle payments: {e}");
+                    tokio::time::sleep(self.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Claims and settles a batch of payments, returning how many were
+    /// claimed.
+    pub async fn settle_batch(&self) -> Result<usize, sqlx::Error> {
+        let kind = TaskKind::settlement();
+        let claims =
+            tasks::claim_batch(&self.pool, &kind, self.id, self.batch_size, self.claim_ttl).await?;
+
+        for &payment_id in &claims.payment_ids {
+            self.settle_payment(payment_id).await?;
+
+            // a payment whose settlement went wrong isn't retried, so no
+            // second hold is placed on its funds
+            if !tasks::complete(&self.pool, &kind, payment_id, self.id).await? {
+                tracing::warn!("lost the settlement claim of payment {payment_id}");
+            }
+        }
+
+        Ok(claims.payment_ids.len())
+    }
+
+    async fn settle_payment(&self, payment_id: Uuid) -> Result<(), sqlx::Error> {
+        let payment = match payments::get(&self.pool, payment_id).await {
+            Ok(payment) => payment,
+            Err(PaymentRepoError::Storage(e)) => return Err(e),
+            Err(e) => {
+                tracing::error!("failed to get payment {payment_id}: {e}");
+                return Ok(());
+            }
+        };
+
+        // the account number is left visible in masked card numbers
+        let Some(account_number) = payment_instruments::masked_account_number(&payment.card_number)
+        else {
+            tracing::error!("payment {payment_id} has an invalid card number");
+            return Ok(());
+        };
+
+        let result = settle(
+            &self.pool,
+            &self.account_service,
+            &*self.notify,
+            &self.fee_policy,
+            payment_id,
+            account_number,
+            payment.amount,
+        )
+        .await;
+
+        match result {
+            Ok(settlement) => tracing::info!(
+                payment.id = %payment_id,
+                payment.status = ?settlement.status,
+                "settled payment"
+            ),
+            Err(TransitionError::Repo(PaymentRepoError::Storage(e))) => return Err(e),
+            Err(e) => tracing::error!("failed to settle payment {payment_id}: {e:?}"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank::{
+        accounts::DummyService,
+        currencies::Currency,
+        payment_instruments::Card,
+        payments::{
+            tests::{new_processing_payment, CARD_REUSE_WINDOW, MERCHANT_ID, PAYMENT_AMOUNT},
+            ProcessingMode, Source,
+        },
+    };
+
+    async fn new_async_payment(pool: &PgPool) -> Uuid {
+        payments::insert(
+            pool,
+            MERCHANT_ID,
+            PAYMENT_AMOUNT,
+            &Card::new_test(),
+            Currency::default().into(),
+            Status::Processing,
+            ProcessingMode::Async,
+            None,
+            None,
+            Source::default(),
+            CARD_REUSE_WINDOW,
+        )
+        .await
+        .expect("failed to insert payment")
+    }
+
+    /// Settles batches until none is left, as other tests may enqueue
+    /// payments concurrently.
+    async fn settle_all(worker: &Worker<DummyService>) {
+        while worker.settle_batch().await.expect("failed to settle batch") > 0 {}
+    }
+
+    #[tokio::test]
+    async fn test_settle_async_payments_only() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let notified = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let notify: Notify = {
+            let notified = notified.clone();
+            Arc::new(move |payment_id, status| {
+                notified.lock().unwrap().push((payment_id, status));
+            })
+        };
+        let worker = Worker::new(pool.clone(), DummyService::default(), notify);
+
+        let async_payment_id = new_async_payment(&pool).await;
+        let sync_payment_id = new_processing_payment(&pool).await;
+        settle_all(&worker).await;
+
+        let payment = payments::get(&pool, async_payment_id).await.unwrap();
+        assert_eq!(payment.status, Status::Approved);
+        let notified: Vec<_> = notified
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(payment_id, _)| *payment_id == async_payment_id)
+            .map(|(_, status)| *status)
+            .collect();
+        assert_eq!(notified, [Status::Authorized, Status::Approved]);
+
+        let payment = payments::get(&pool, sync_payment_id).await.unwrap();
+        assert_eq!(payment.status, Status::Processing);
+    }
+
+    async fn process_failing_approvals(pool: &PgPool, failing: u32) -> Result<Uuid, Uuid> {
+        let card = Card::new_test();
+        let new_payment = payments::NewPayment {
+            merchant_id: MERCHANT_ID,
+            amount: PAYMENT_AMOUNT,
+            card: &card,
+            currency: Currency::default().into(),
+            processing_mode: ProcessingMode::Sync,
+            metadata: None,
+            customer_reference: None,
+            source: Source::default(),
+        };
+        let account_service = DummyService::default();
+        let fee_policy = FeePolicy::default();
+        let processed = FAILING_APPROVALS.scope(
+            std::cell::Cell::new(failing),
+            payments::process_payment(
+                pool,
+                &account_service,
+                &|_, _| {},
+                new_payment,
+                CARD_REUSE_WINDOW,
+                &fee_policy,
+            ),
+        );
+
+        match processed.await {
+            Ok(processed) => Ok(processed.id),
+            Err(payments::ProcessError::Settle {
+                payment_id,
+                error: TransitionError::Repo(PaymentRepoError::Storage(sqlx::Error::PoolClosed)),
+            }) => Err(payment_id),
+            Err(e) => panic!("unexpected error: {e:?}"),
+        }
+    }
+
+    async fn pending_repairs(pool: &PgPool, payment_id: Uuid) -> i64 {
+        sqlx::query_scalar!(
+            r#"
+                SELECT count(*) as "count!" FROM settlement_repairs
+                WHERE payment_id = $1 AND repaired_at IS NULL
+            "#,
+            payment_id
+        )
+        .fetch_one(pool)
+        .await
+        .expect("failed to count repairs")
+    }
+
+    #[tokio::test]
+    async fn test_repair_withdrawn_payments_left_authorized() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        // approvals are retried
+        let payment_id = process_failing_approvals(&pool, APPROVAL_ATTEMPTS - 1)
+            .await
+            .expect("payment wasn't approved");
+        let payment = payments::get(&pool, payment_id).await.unwrap();
+        assert_eq!(payment.status, Status::Approved);
+        assert_eq!(pending_repairs(&pool, payment_id).await, 0);
+
+        // the funds were withdrawn, so the payment is left for repair
+        let payment_id = process_failing_approvals(&pool, APPROVAL_ATTEMPTS)
+            .await
+            .expect_err("payment was approved");
+        let payment = payments::get(&pool, payment_id).await.unwrap();
+        assert_eq!(payment.status, Status::Authorized);
+        assert_eq!(pending_repairs(&pool, payment_id).await, 1);
+
+        let repaired =
+            payments::repair_settlements(&pool, Uuid::new_v4(), 10, Duration::from_secs(60))
+                .await
+                .expect("failed to repair settlements");
+        assert!(repaired.contains(&(payment_id, Status::Approved)));
+        let payment = payments::get(&pool, payment_id).await.unwrap();
+        assert_eq!(payment.status, Status::Approved);
+        assert_eq!(pending_repairs(&pool, payment_id).await, 0);
+    }
+}
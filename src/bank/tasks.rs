@@ -0,0 +1,516 @@
+use std::time::Duration;
+
+use opentelemetry::{global, Context, KeyValue};
+use sqlx::{postgres::types::PgInterval, PgPool};
+use uuid::Uuid;
+
+use super::{
+    payments::{ProcessingMode, Status},
+    transactions,
+};
+use crate::telemetry;
+
+/// Counter of the payments claimed again after their claim expired, e.g.
+/// because its worker crashed.
+pub const RECOVERED_CLAIMS_METRIC: &str = "task.claims.recovered";
+/// Counter of the payments claimed by another worker between the read and
+/// the claim, so skipped.
+pub const CONTENDED_CLAIMS_METRIC: &str = "task.claims.contended";
+
+/// Kind of background work done on payments, e.g. capturing authorized
+/// payments.
+///
+/// Claims are scoped to a kind: a payment claimed by an auto-capture worker
+/// can still be claimed by a reconciler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskKind {
+    pub name: String,
+    /// Status of the payments this kind of work applies to.
+    pub eligible_status: Status,
+    /// Processing mode of the payments this kind of work applies to, any if
+    /// `None`.
+    pub eligible_processing_mode: Option<ProcessingMode>,
+    /// Whether this kind of work only applies to the payments with a pending
+    /// settlement repair, see `payments::record_repair`.
+    pub requires_pending_repair: bool,
+}
+
+impl TaskKind {
+    /// Captures payments left authorized.
+    // no worker does this kind of work yet
+    #[allow(dead_code)]
+    pub fn auto_capture() -> Self {
+        Self {
+            name: "auto_capture".to_string(),
+            eligible_status: Status::Authorized,
+            eligible_processing_mode: None,
+            requires_pending_repair: false,
+        }
+    }
+
+    /// Moves authorized payments whose funds moved to their final status,
+    /// see `payments::repair_settlements`.
+    pub fn settlement_repair() -> Self {
+        Self {
+            name: "settlement_repair".to_string(),
+            eligible_status: Status::Authorized,
+            eligible_processing_mode: None,
+            requires_pending_repair: true,
+        }
+    }
+
+    /// Settles payments stuck in processing.
+    // no worker does this kind of work yet
+    #[allow(dead_code)]
+    pub fn reconciliation() -> Self {
+        Self {
+            name: "reconciliation".to_string(),
+            eligible_status: Status::Processing,
+            eligible_processing_mode: None,
+            requires_pending_repair: false,
+        }
+    }
+
+    /// Replaces or withdraws the hold of an authorized payment, which
+    /// amendments and settlements claim one at a time, see `claim`.
+    ///
+    /// Settlements complete their claim, so a withdrawn hold is never amended.
+    pub fn hold() -> Self {
+        Self {
+            name: "hold".to_string(),
+            eligible_status: Status::Authorized,
+            eligible_processing_mode: None,
+            requires_pending_repair: false,
+        }
+    }
+
+    /// Makes the account service calls of payments processed asynchronously.
+    pub fn settlement() -> Self {
+        Self {
+            name: "settlement".to_string(),
+            eligible_status: Status::Processing,
+            eligible_processing_mode: Some(ProcessingMode::Async),
+            requires_pending_repair: false,
+        }
+    }
+}
+
+/// Payments claimed by a call to `claim_batch`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Claims {
+    pub payment_ids: Vec<Uuid>,
+    /// Number of claimed payments whose previous claim had expired, e.g.
+    /// because its worker crashed.
+    pub recovered: usize,
+    /// Number of candidate payments claimed by another worker in the meantime.
+    pub contended: usize,
+}
+
+/// Claims up to `limit` eligible payments for `worker_id`, oldest first.
+///
+/// Claimed payments are invisible to the other workers of the same kind until
+/// the claim is completed, released, or expires after `ttl`. Payments locked
+/// by a concurrent claim are skipped rather than waited for.
+pub async fn claim_batch(
+    pool: &PgPool,
+    kind: &TaskKind,
+    worker_id: Uuid,
+    limit: i64,
+    ttl: Duration,
+) -> Result<Claims, sqlx::Error> {
+    let ttl = PgInterval::try_from(ttl).map_err(sqlx::Error::Configuration)?;
+
+    let mut tx = transactions::begin(pool).await?;
+
+    let candidates = sqlx::query_scalar!(
+        r#"
+            SELECT p.id FROM payments p
+            LEFT JOIN worker_claims c ON c.kind = $1 AND c.payment_id = p.id
+            WHERE p.status = $2
+              AND ($4::ProcessingMode IS NULL OR p.processing_mode = $4)
+              AND (NOT $5 OR EXISTS (
+                SELECT 1 FROM settlement_repairs r
+                WHERE r.payment_id = p.id AND r.repaired_at IS NULL AND r.failed_at IS NULL
+              ))
+              AND (c.payment_id IS NULL OR (c.completed_at IS NULL AND c.expires_at <= LOCALTIMESTAMP))
+            ORDER BY p.inserted_at, p.id
+            LIMIT $3
+            FOR UPDATE OF p SKIP LOCKED
+        "#,
+        kind.name,
+        kind.eligible_status as Status,
+        limit,
+        kind.eligible_processing_mode as Option<ProcessingMode>,
+        kind.requires_pending_repair
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // a claim committed after the candidates were read is not overwritten
+    let claimed = sqlx::query!(
+        r#"
+            INSERT INTO worker_claims ( kind, payment_id, claimed_by, expires_at )
+            SELECT $1, payment_id, $3, LOCALTIMESTAMP + $4::interval
+            FROM UNNEST($2::uuid[]) AS payment_id
+            ON CONFLICT ( kind, payment_id ) DO UPDATE
+            SET claimed_by = EXCLUDED.claimed_by,
+                expires_at = EXCLUDED.expires_at,
+                attempts = worker_claims.attempts + 1,
+                updated_at = current_timestamp
+            WHERE worker_claims.completed_at IS NULL AND worker_claims.expires_at <= LOCALTIMESTAMP
+            RETURNING payment_id, attempts
+        "#,
+        kind.name,
+        &candidates,
+        worker_id,
+        ttl
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let claims = Claims {
+        recovered: claimed.iter().filter(|claim| claim.attempts > 1).count(),
+        contended: candidates.len() - claimed.len(),
+        payment_ids: claimed.into_iter().map(|claim| claim.payment_id).collect(),
+    };
+
+    tracing::info!(
+        task.kind = kind.name,
+        task.claimed = claims.payment_ids.len(),
+        task.recovered = claims.recovered,
+        task.contended = claims.contended,
+        "claimed payments"
+    );
+    record_claims(kind, &claims);
+
+    Ok(claims)
+}
+
+/// Claims payment `payment_id` for `worker_id`, like `claim_batch`, if it is
+/// eligible to `kind`.
+///
+/// Returns `false` if the payment isn't eligible, or is claimed by another
+/// worker, or its work was completed.
+pub async fn claim(
+    pool: &PgPool,
+    kind: &TaskKind,
+    payment_id: Uuid,
+    worker_id: Uuid,
+    ttl: Duration,
+) -> Result<bool, sqlx::Error> {
+    let ttl = PgInterval::try_from(ttl).map_err(sqlx::Error::Configuration)?;
+
+    // the status is read and the claim written by the same statement
+    sqlx::query!(
+        r#"
+            INSERT INTO worker_claims ( kind, payment_id, claimed_by, expires_at )
+            SELECT $1, id, $3, LOCALTIMESTAMP + $4::interval
+            FROM payments
+            WHERE id = $2 AND status = $5
+              AND ($6::ProcessingMode IS NULL OR processing_mode = $6)
+            ON CONFLICT ( kind, payment_id ) DO UPDATE
+            SET claimed_by = EXCLUDED.claimed_by,
+                expires_at = EXCLUDED.expires_at,
+                attempts = worker_claims.attempts + 1,
+                updated_at = current_timestamp
+            WHERE worker_claims.completed_at IS NULL AND worker_claims.expires_at <= LOCALTIMESTAMP
+        "#,
+        kind.name,
+        payment_id,
+        worker_id,
+        ttl,
+        kind.eligible_status as Status,
+        kind.eligible_processing_mode as Option<ProcessingMode>
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() == 1)
+}
+
+/// Counts the recovered and contended claims of `kind`, per kind.
+fn record_claims(kind: &TaskKind, claims: &Claims) {
+    // instruments are created from the meter provider current at each call,
+    // installed by `telemetry::init_tracing`
+    let meter = global::meter(telemetry::METER_NAME);
+    let cx = Context::current();
+    let attributes = [KeyValue::new("task.kind", kind.name.clone())];
+
+    meter
+        .u64_counter(RECOVERED_CLAIMS_METRIC)
+        .with_description("Payments claimed again after their claim expired")
+        .init()
+        .add(&cx, claims.recovered as u64, &attributes);
+    meter
+        .u64_counter(CONTENDED_CLAIMS_METRIC)
+        .with_description("Payments skipped as another worker claimed them first")
+        .init()
+        .add(&cx, claims.contended as u64, &attributes);
+}
+
+/// Marks the work on a payment as done, so it's never claimed again for
+/// `kind`.
+///
+/// Returns `false` if the claim was lost, e.g. it expired and another worker
+/// claimed the payment.
+pub async fn complete(
+    pool: &PgPool,
+    kind: &TaskKind,
+    payment_id: Uuid,
+    worker_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE worker_claims
+            SET completed_at = current_timestamp, updated_at = current_timestamp
+            WHERE kind = $1 AND payment_id = $2 AND claimed_by = $3 AND completed_at IS NULL
+        "#,
+        kind.name,
+        payment_id,
+        worker_id
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() == 1)
+}
+
+/// Gives up a claim so that another worker can claim the payment right away.
+///
+/// Returns `false` if the claim was lost.
+pub async fn release(
+    pool: &PgPool,
+    kind: &TaskKind,
+    payment_id: Uuid,
+    worker_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE worker_claims
+            SET expires_at = LOCALTIMESTAMP, updated_at = current_timestamp
+            WHERE kind = $1 AND payment_id = $2 AND claimed_by = $3 AND completed_at IS NULL
+        "#,
+        kind.name,
+        payment_id,
+        worker_id
+    )
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use opentelemetry::sdk::{
+        export::metrics::{
+            aggregation::{cumulative_temporality_selector, Sum},
+            InstrumentationLibraryReader,
+        },
+        metrics::{
+            aggregators::SumAggregator, controllers, controllers::BasicController, processors,
+            sdk_api::NumberKind, selectors,
+        },
+    };
+
+    use super::*;
+    use crate::bank::{
+        currencies::Currency,
+        payment_instruments::Card,
+        payments::{self, tests::CARD_REUSE_WINDOW, Source},
+    };
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    /// Installs a global meter provider whose counters can be read back by
+    /// `counted`.
+    fn install_meter_provider() -> BasicController {
+        let controller = controllers::basic(processors::factory(
+            selectors::simple::inexpensive(),
+            cumulative_temporality_selector(),
+        ))
+        .build();
+        global::set_meter_provider(controller.clone());
+        controller
+    }
+
+    /// Returns the total of the counter `metric` for `kind`.
+    fn counted(controller: &BasicController, metric: &str, kind: &TaskKind) -> u64 {
+        controller
+            .collect(&Context::current())
+            .expect("failed to collect metrics");
+
+        let mut total = 0;
+        controller
+            .try_for_each(&mut |_, reader| {
+                reader.try_for_each(&cumulative_temporality_selector(), &mut |record| {
+                    let of_kind = record.attributes().iter().any(|(key, value)| {
+                        key.as_str() == "task.kind" && value.as_str() == kind.name
+                    });
+                    let sum = record
+                        .aggregator()
+                        .and_then(|aggregator| aggregator.as_any().downcast_ref::<SumAggregator>());
+                    if let (true, true, Some(sum)) =
+                        (record.descriptor().name() == metric, of_kind, sum)
+                    {
+                        total += sum.sum()?.to_u64(&NumberKind::U64);
+                    }
+                    Ok(())
+                })
+            })
+            .expect("failed to read metrics");
+        total
+    }
+
+    /// A kind of its own, so claims of other tests are left out.
+    fn test_kind() -> TaskKind {
+        TaskKind {
+            name: format!("test_{}", Uuid::new_v4()),
+            eligible_status: Status::Voided,
+            eligible_processing_mode: None,
+            requires_pending_repair: false,
+        }
+    }
+
+    async fn seed(pool: &PgPool, count: usize) -> Vec<Uuid> {
+        let mut ids = Vec::new();
+        for _ in 0..count {
+            let id = payments::insert(
+                pool,
+                payments::tests::MERCHANT_ID,
+                payments::tests::PAYMENT_AMOUNT,
+                &Card::new_test(),
+                Currency::default().into(),
+                Status::Voided,
+                ProcessingMode::Sync,
+                None,
+                None,
+                Source::default(),
+                CARD_REUSE_WINDOW,
+            )
+            .await
+            .expect("failed to insert payment");
+            ids.push(id);
+        }
+        ids
+    }
+
+    /// Processes claimed payments in small batches until none is left.
+    async fn run_worker(pool: PgPool, kind: TaskKind, processed: Arc<Mutex<HashMap<Uuid, usize>>>) {
+        let worker_id = Uuid::new_v4();
+
+        loop {
+            let claims = claim_batch(&pool, &kind, worker_id, 3, TTL)
+                .await
+                .expect("failed to claim payments");
+            if claims.payment_ids.is_empty() {
+                break;
+            }
+
+            for payment_id in claims.payment_ids {
+                *processed.lock().unwrap().entry(payment_id).or_default() += 1;
+                tokio::task::yield_now().await;
+
+                let completed = complete(&pool, &kind, payment_id, worker_id)
+                    .await
+                    .expect("failed to complete claim");
+                assert!(completed, "claim of {payment_id} was lost");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_workers_process_payments_once() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let ids = seed(&pool, 10).await;
+        let kind = test_kind();
+        let processed = Arc::new(Mutex::new(HashMap::new()));
+
+        tokio::join!(
+            run_worker(pool.clone(), kind.clone(), processed.clone()),
+            run_worker(pool.clone(), kind.clone(), processed.clone()),
+        );
+
+        let processed = processed.lock().unwrap();
+        for id in ids {
+            assert_eq!(processed.get(&id), Some(&1), "{id} processed once");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expired_claim_is_claimed_again() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let ids = seed(&pool, 1).await;
+        let kind = test_kind();
+        let crashed_worker = Uuid::new_v4();
+        let worker = Uuid::new_v4();
+
+        let claims = claim_batch(&pool, &kind, crashed_worker, 1000, Duration::ZERO)
+            .await
+            .expect("failed to claim payments");
+        assert!(claims.payment_ids.contains(&ids[0]));
+
+        let claims = claim_batch(&pool, &kind, worker, 1000, TTL)
+            .await
+            .expect("failed to claim payments");
+        assert!(claims.payment_ids.contains(&ids[0]));
+        assert!(claims.recovered >= 1);
+
+        let claims = claim_batch(&pool, &kind, crashed_worker, 1000, TTL)
+            .await
+            .expect("failed to claim payments");
+        assert!(!claims.payment_ids.contains(&ids[0]), "claim is still live");
+
+        assert!(!complete(&pool, &kind, ids[0], crashed_worker)
+            .await
+            .unwrap());
+        assert!(release(&pool, &kind, ids[0], worker).await.unwrap());
+
+        let claims = claim_batch(&pool, &kind, crashed_worker, 1000, TTL)
+            .await
+            .expect("failed to claim payments");
+        assert!(claims.payment_ids.contains(&ids[0]), "claim was released");
+    }
+
+    #[tokio::test]
+    async fn test_claims_are_counted_per_kind() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let controller = install_meter_provider();
+        seed(&pool, 2).await;
+        let kind = test_kind();
+
+        let first = claim_batch(&pool, &kind, Uuid::new_v4(), 1000, Duration::ZERO)
+            .await
+            .expect("failed to claim payments");
+        let (second, third) = tokio::join!(
+            claim_batch(&pool, &kind, Uuid::new_v4(), 1000, TTL),
+            claim_batch(&pool, &kind, Uuid::new_v4(), 1000, TTL),
+        );
+        let (second, third) = (second.unwrap(), third.unwrap());
+
+        let recovered = second.recovered + third.recovered;
+        assert_eq!(recovered, first.payment_ids.len());
+        assert_eq!(
+            counted(&controller, RECOVERED_CLAIMS_METRIC, &kind),
+            recovered as u64
+        );
+        assert_eq!(
+            counted(&controller, CONTENDED_CLAIMS_METRIC, &kind),
+            (first.contended + second.contended + third.contended) as u64
+        );
+        assert_eq!(
+            counted(&controller, RECOVERED_CLAIMS_METRIC, &test_kind()),
+            0
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    cell::Cell,
+    future::Future,
+    ops::{Deref, DerefMut},
+};
+
+use sqlx::{PgPool, Postgres};
+
+use super::accounts::{AccountService, HoldRef};
+
+tokio::task_local! {
+    /// Number of database transactions open in the current request.
+    static OPEN_TRANSACTIONS: Cell<usize>;
+}
+
+/// Runs `future` while tracking the transactions it opens with `begin`.
+///
+/// Transactions opened outside of a tracked future aren't checked.
+pub async fn track<F: Future>(future: F) -> F::Output {
+    OPEN_TRANSACTIONS.scope(Cell::new(0), future).await
+}
+
+/// Returns the number of transactions open in the current tracked future.
+pub fn open_transactions() -> usize {
+    OPEN_TRANSACTIONS
+        .try_with(|open| open.get())
+        .unwrap_or_default()
+}
+
+fn add_open_transactions(delta: isize) {
+    let _ = OPEN_TRANSACTIONS.try_with(|open| open.set(open.get().saturating_add_signed(delta)));
+}
+
+/// A Postgres transaction counted as open until it is committed or dropped.
+///
+/// Use it as an executor through `&mut *tx`.
+pub struct Transaction {
+    tx: Option<sqlx::Transaction<'static, Postgres>>,
+}
+
+/// Begins a tracked transaction.
+pub async fn begin(pool: &PgPool) -> Result<Transaction, sqlx::Error> {
+    let tx = pool.begin().await?;
+    add_open_transactions(1);
+
+    Ok(Transaction { tx: Some(tx) })
+}
+
+impl Transaction {
+    pub async fn commit(mut self) -> Result<(), sqlx::Error> {
+        // `Drop` stops tracking the transaction once it's been taken out
+        self.tx
+            .take()
+            .expect("transaction already finished")
+            .commit()
+            .await
+    }
+}
+
+impl Deref for Transaction {
+    type Target = sqlx::Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        self.tx.as_ref().expect("transaction already finished")
+    }
+}
+
+impl DerefMut for Transaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.tx.as_mut().expect("transaction already finished")
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        add_open_transactions(-1);
+    }
+}
+
+/// Fails loudly when an account service call is about to be awaited while a
+/// transaction is open.
+///
+/// Holding a pooled connection across a remote call caps throughput to the
+/// pool size divided by the remote latency, so this panics in debug builds
+/// (and therefore in tests) and logs in release builds.
+fn assert_no_open_transaction(operation: &str) {
+    let open = open_transactions();
+    if open == 0 {
+        return;
+    }
+
+    if cfg!(debug_assertions) {
+        panic!("{operation} called with {open} database transaction(s) open");
+    } else {
+        tracing::error!("{operation} called with {open} database transaction(s) open");
+    }
+}
+
+/// Account service checking that no transaction is open during its calls.
+#[derive(Clone, Default)]
+pub struct CheckedService<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T: AccountService> AccountService for CheckedService<T> {
+    async fn place_hold(&self, account_number: &str, amount: i64) -> Result<HoldRef, String> {
+        assert_no_open_transaction("place_hold");
+        self.0.place_hold(account_number, amount).await
+    }
+
+    async fn release_hold(&self, hold_ref: HoldRef) -> Result<(), String> {
+        assert_no_open_transaction("release_hold");
+        self.0.release_hold(hold_ref).await
+    }
+
+    async fn withdraw_funds(&self, hold_ref: HoldRef) -> Result<(), String> {
+        assert_no_open_transaction("withdraw_funds");
+        self.0.withdraw_funds(hold_ref).await
+    }
+
+    async fn deposit_funds(&self, account_number: &str, amount: i64) -> Result<(), String> {
+        assert_no_open_transaction("deposit_funds");
+        self.0.deposit_funds(account_number, amount).await
+    }
+
+    async fn validate_account(&self, account_number: &str) -> Result<(), String> {
+        assert_no_open_transaction("validate_account");
+        self.0.validate_account(account_number).await
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank::accounts::DummyService;
+
+    #[tokio::test]
+    async fn test_track_open_transactions() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        track(async {
+            let tx = begin(&pool).await.expect("failed to begin transaction");
+            assert_eq!(open_transactions(), 1);
+
+            tx.commit().await.expect("failed to commit transaction");
+            assert_eq!(open_transactions(), 0);
+
+            let tx = begin(&pool).await.expect("failed to begin transaction");
+            drop(tx);
+            assert_eq!(open_transactions(), 0);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "place_hold called with 1 database transaction(s) open")]
+    async fn test_account_call_within_transaction_panics() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+        let service = CheckedService(DummyService::default());
+
+        track(async {
+            let _tx = begin(&pool).await.expect("failed to begin transaction");
+            let _ = service.place_hold("12345", 1).await;
+        })
+        .await;
+    }
+}
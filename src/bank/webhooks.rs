@@ -0,0 +1,218 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::events::PayloadVersion;
+
+/// A merchant endpoint notified of the status changes of the merchant's
+/// payments.
+///
+/// Every notification sent to `url` is signed with `secret`.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: String,
+    /// Shape of the events sent to `url`.
+    pub payload_version: PayloadVersion,
+}
+
+pub async fn insert(
+    pool: &PgPool,
+    merchant_id: Uuid,
+    url: String,
+    secret: String,
+    payload_version: PayloadVersion,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO webhooks ( merchant_id, url, secret, payload_version )
+            VALUES ( $1, $2, $3, $4 )
+            RETURNING id
+        "#,
+        merchant_id,
+        url,
+        secret,
+        payload_version as PayloadVersion
+    )
+    .fetch_one(pool)
+    .await
+    .map(|record| record.id)
+}
+
+/// Returns the webhooks registered by a merchant.
+pub async fn list(pool: &PgPool, merchant_id: Uuid) -> Result<Vec<Webhook>, sqlx::Error> {
+    sqlx::query_as!(
+        Webhook,
+        r#"
+            SELECT id, url, secret, payload_version as "payload_version: PayloadVersion"
+            FROM webhooks
+            WHERE merchant_id = $1
+            ORDER BY inserted_at, id
+        "#,
+        merchant_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the webhooks registered by the merchant of a payment, which are
+/// the only ones notified of it.
+pub async fn list_for_payment(
+    pool: &PgPool,
+    payment_id: Uuid,
+) -> Result<Vec<Webhook>, sqlx::Error> {
+    sqlx::query_as!(
+        Webhook,
+        r#"
+            SELECT w.id, w.url, w.secret, w.payload_version as "payload_version: PayloadVersion"
+            FROM webhooks w
+            JOIN payments p ON p.merchant_id = w.merchant_id
+            WHERE p.id = $1
+            ORDER BY w.inserted_at, w.id
+        "#,
+        payment_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Records that `event` is about to be delivered to a webhook.
+pub async fn insert_delivery(
+    pool: &PgPool,
+    webhook_id: Uuid,
+    payment_id: Uuid,
+    event: &str,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO webhook_deliveries ( webhook_id, payment_id, event )
+            VALUES ( $1, $2, $3 )
+            RETURNING id
+        "#,
+        webhook_id,
+        payment_id,
+        event
+    )
+    .fetch_one(pool)
+    .await
+    .map(|record| record.id)
+}
+
+/// Records a delivery attempt and the status code it got, if any.
+///
+/// A delivery is considered delivered once an attempt got a 2xx response.
+pub async fn record_attempt(
+    pool: &PgPool,
+    id: Uuid,
+    status_code: Option<i32>,
+    delivered: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE webhook_deliveries
+            SET attempts = attempts + 1,
+                last_status_code = $2,
+                delivered_at = CASE WHEN $3 THEN current_timestamp END,
+                updated_at = current_timestamp
+            WHERE id = $1
+        "#,
+        id,
+        status_code,
+        delivered
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Records that a delivery ran out of attempts without being delivered, see
+/// `digests`.
+pub async fn abandon(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE webhook_deliveries
+            SET abandoned_at = current_timestamp,
+                updated_at = current_timestamp
+            WHERE id = $1 AND delivered_at IS NULL
+        "#,
+        id
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use time::PrimitiveDateTime;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+    pub struct Delivery {
+        pub event: String,
+        pub attempts: i32,
+        pub last_status_code: Option<i32>,
+        pub delivered_at: Option<PrimitiveDateTime>,
+    }
+
+    pub async fn list_deliveries(
+        pool: &PgPool,
+        webhook_id: Uuid,
+        payment_id: Uuid,
+    ) -> Result<Vec<Delivery>, sqlx::Error> {
+        sqlx::query_as!(
+            Delivery,
+            r#"
+                SELECT event, attempts, last_status_code, delivered_at
+                FROM webhook_deliveries
+                WHERE webhook_id = $1 AND payment_id = $2
+                ORDER BY inserted_at, id
+            "#,
+            webhook_id,
+            payment_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_record_attempt() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to connect to postgres");
+
+        let payment = crate::bank::payments::Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        let webhook_id = insert(
+            &pool,
+            payment.merchant_id,
+            "http://localhost/hook".into(),
+            "secret".into(),
+            PayloadVersion::LATEST,
+        )
+        .await
+        .expect("failed to insert webhook");
+        let id = insert_delivery(&pool, webhook_id, payment.id, "payment.approved")
+            .await
+            .expect("failed to insert delivery");
+
+        record_attempt(&pool, id, Some(500), false)
+            .await
+            .expect("failed to record attempt");
+        record_attempt(&pool, id, Some(200), true)
+            .await
+            .expect("failed to record attempt");
+
+        let deliveries = list_deliveries(&pool, webhook_id, payment.id)
+            .await
+            .expect("failed to list deliveries");
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event, "payment.approved");
+        assert_eq!(deliveries[0].attempts, 2);
+        assert_eq!(deliveries[0].last_status_code, Some(200));
+        assert!(deliveries[0].delivered_at.is_some());
+    }
+}
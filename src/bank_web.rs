@@ -1,87 +1,754 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use axum::{
-    routing::{get, post},
-    Router,
+    body::{self, Body, Empty, Full},
+    extract::FromRef,
+    http::{
+        header::{HeaderName, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RETRY_AFTER},
+        HeaderValue, Method, Request, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{AppendHeaders, IntoResponse, Response},
+    routing::{get, post, put},
+    Extension, Json, Router,
 };
+use http_body::Limited;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use tower_http::{compression::CompressionLayer, limit::RequestBodyLimitLayer};
 
-use crate::bank::accounts::AccountService;
+use crate::{
+    bank::{
+        accounts::{AccountService, DummyService, Scenario},
+        availability::{RollUp, SloAggregator},
+        clock::{Clock, SystemClock},
+        digests::Digester,
+        expiry,
+        exports::{ExportSink, Exporter, LocalDirSink},
+        fees::FeePolicy,
+        journal::JournaledService,
+        outbox,
+        refunds::retries,
+        settlement,
+        transactions::{self, CheckedService},
+    },
+    errors::{ProblemDetails, PROBLEM_JSON_CONTENT_TYPE},
+    telemetry,
+};
 
+mod admin;
+mod backfills;
+mod content_type;
+mod digests;
+mod disputes;
+mod etag;
+mod export;
+mod exports;
+mod fee_policies;
+mod fieldset;
+mod json;
+mod merchant;
+mod methods;
+mod notes;
+mod pagination;
+mod path;
 mod payments;
+mod rate_limit;
+mod receipts;
+mod reconciliation;
 mod refunds;
+mod sandbox;
+mod status;
+mod webhooks;
+
+pub use admin::AdminToken;
+use json::ApiVersion;
+pub use rate_limit::{RateLimit, RateLimiter, TokenBuckets};
+pub use webhooks::RetryPolicy;
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ErrorResponseBody {
     error: String,
+    /// Machine readable error code, for errors merchants are expected to handle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    /// Additional information about the error, e.g. the accepted values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
 }
 impl ErrorResponseBody {
     pub fn new(s: &'static str) -> Self {
         Self {
             error: s.to_string(),
+            code: None,
+            details: None,
         }
     }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Returns the problem details of the error, answered with `status`.
+    pub fn problem(&self, status: StatusCode) -> ProblemDetails {
+        ProblemDetails::new(status, self.code.as_deref(), self.error.clone())
+            .with_extensions(self.details.as_ref())
+    }
+}
+
+/// Delay after which clients are told to retry requests that failed because
+/// the database was unavailable.
+const STORAGE_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Error of requests that failed before changing anything because the
+/// database was unavailable, answered with a `Retry-After` header by
+/// `retry_after_unavailable`.
+fn storage_unavailable() -> (StatusCode, Json<ErrorResponseBody>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ErrorResponseBody::new("storage unavailable").with_code("storage_unavailable")),
+    )
+}
+
+/// Error of requests whose body is larger than the configured limit.
+fn body_too_large() -> (StatusCode, Json<ErrorResponseBody>) {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(ErrorResponseBody::new("request body too large").with_code("payload_too_large")),
+    )
+}
+
+/// `Location` header of a created resource.
+type Location = AppendHeaders<[(HeaderName, String); 1]>;
+
+fn location(path: String) -> Location {
+    AppendHeaders([(LOCATION, path)])
+}
+
+/// How payment requests of a zero amount are answered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZeroAmountPolicy {
+    /// An empty 204, without recording a payment.
+    #[default]
+    NoContent,
+    /// A 400, like negative amounts.
+    Reject,
 }
 
 #[derive(Clone)]
 pub struct BankWeb<T> {
     pool: PgPool,
-    #[allow(dead_code)]
-    account_service: T,
+    account_service: CheckedService<JournaledService<T>>,
+    fee_policy: FeePolicy,
+    card_reuse_window: Duration,
+    /// Period after a payment during which it can be refunded, unless the
+    /// merchant agreed to another one.
+    refund_window: Duration,
+    /// Largest number of refunds of a payment, canceled ones aside.
+    max_refunds_per_payment: u32,
+    /// Largest amount of a payment, in minor units.
+    max_amount: i64,
+    zero_amount_policy: ZeroAmountPolicy,
+    /// Largest request body, in bytes.
+    max_body_size: usize,
+    webhooks: webhooks::Dispatcher,
+    /// Limits payment requests per card and merchant, unlimited if `None`.
+    rate_limiter: Option<Arc<dyn RateLimiter>>,
+    /// Scripted account service outcome, set in sandbox mode only.
+    sandbox: Option<Scenario>,
+    /// Current time of the time-dependent rules, e.g. refund windows.
+    clock: Arc<dyn Clock>,
+    /// Token required by the admin endpoints, which are all answered with a
+    /// 401 if `None`.
+    admin_token: Option<AdminToken>,
+    /// Storage of the archives of the merchant exports.
+    export_sink: Arc<dyn ExportSink>,
+    /// Outcomes of the payment and refund requests, see `status::track`.
+    slo: Arc<SloAggregator>,
+    /// Status last served by `GET /api/status`.
+    status_cache: Arc<Mutex<Option<status::StatusData>>>,
+}
+
+impl<T> FromRef<BankWeb<T>> for Option<AdminToken> {
+    fn from_ref(bank_web: &BankWeb<T>) -> Self {
+        bank_web.admin_token.clone()
+    }
+}
+
+impl<T> BankWeb<T> {
+    /// Default period during which a card number can't be used again.
+    pub const DEFAULT_CARD_REUSE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Default period during which a payment can be refunded, 90 days.
+    pub const DEFAULT_REFUND_WINDOW: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+    /// Default largest number of refunds of a payment, far above the few
+    /// partial refunds of legitimate merchants.
+    pub const DEFAULT_MAX_REFUNDS_PER_PAYMENT: u32 = 10;
+    /// Default largest amount of a payment, which the account service can
+    /// hold.
+    pub const DEFAULT_MAX_AMOUNT: i64 = DummyService::MAX_VALID_AMOUNT;
+    /// Default largest request body, far larger than any payment request.
+    pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+    /// Default directory of the archives of the merchant exports.
+    pub fn default_export_dir() -> PathBuf {
+        std::env::temp_dir().join("bank-exports")
+    }
 }
 
 impl<T: AccountService> BankWeb<T> {
     pub fn new(pool: PgPool, account_service: T) -> Self {
         Self {
+            webhooks: webhooks::Dispatcher::new(pool.clone()),
+            account_service: CheckedService(JournaledService::new(pool.clone(), account_service)),
             pool,
-            account_service,
+            fee_policy: FeePolicy::default(),
+            card_reuse_window: Self::DEFAULT_CARD_REUSE_WINDOW,
+            refund_window: Self::DEFAULT_REFUND_WINDOW,
+            max_refunds_per_payment: Self::DEFAULT_MAX_REFUNDS_PER_PAYMENT,
+            max_amount: Self::DEFAULT_MAX_AMOUNT,
+            zero_amount_policy: ZeroAmountPolicy::default(),
+            max_body_size: Self::DEFAULT_MAX_BODY_SIZE,
+            rate_limiter: None,
+            sandbox: None,
+            clock: Arc::new(SystemClock),
+            admin_token: None,
+            export_sink: Arc::new(LocalDirSink::new(Self::default_export_dir())),
+            slo: Arc::default(),
+            status_cache: Arc::default(),
         }
     }
 
-    pub fn into_router(self) -> Router {
+    /// Sets the bearer token required by the `/api/admin` endpoints, and
+    /// identifying admin principals elsewhere, see `admin::Principal`.
+    pub fn with_admin_token(mut self, admin_token: AdminToken) -> Self {
+        self.admin_token = Some(admin_token);
+        self
+    }
+
+    /// Sets where the archives of the merchant exports are stored.
+    pub fn with_export_sink(mut self, export_sink: Arc<dyn ExportSink>) -> Self {
+        self.export_sink = export_sink;
+        self
+    }
+
+    /// Sets the fee policy applied to the approved payments of merchants
+    /// without a policy of their own.
+    pub fn with_fee_policy(mut self, fee_policy: FeePolicy) -> Self {
+        self.fee_policy = fee_policy;
+        self
+    }
+
+    /// Sets the period during which a payment with an already used card
+    /// number is rejected.
+    pub fn with_card_reuse_window(mut self, card_reuse_window: Duration) -> Self {
+        self.card_reuse_window = card_reuse_window;
+        self
+    }
+
+    /// Sets the period after a payment during which it can be refunded, for
+    /// merchants without a refund policy of their own.
+    pub fn with_refund_window(mut self, refund_window: Duration) -> Self {
+        self.refund_window = refund_window;
+        self
+    }
+
+    /// Sets the largest number of refunds of a payment, further refunds
+    /// being rejected until one is canceled.
+    pub fn with_max_refunds_per_payment(mut self, max_refunds_per_payment: u32) -> Self {
+        self.max_refunds_per_payment = max_refunds_per_payment;
+        self
+    }
+
+    /// Sets the largest amount of a payment, larger payments being rejected
+    /// before any hold is placed.
+    pub fn with_max_amount(mut self, max_amount: i64) -> Self {
+        self.max_amount = max_amount;
+        self
+    }
+
+    /// Sets how payment requests of a zero amount are answered, an empty 204
+    /// by default.
+    pub fn with_zero_amount_policy(mut self, zero_amount_policy: ZeroAmountPolicy) -> Self {
+        self.zero_amount_policy = zero_amount_policy;
+        self
+    }
+
+    /// Sets the largest request body, larger bodies being answered with a
+    /// 413.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Sets the limiter of payment requests, answered with a 429 once their
+    /// card or merchant exceeds its limit.
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<dyn RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Sets the clock of the time-dependent rules, the system time by
+    /// default.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets how failed webhook deliveries are retried.
+    pub fn with_webhook_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.webhooks = self.webhooks.with_retry_policy(retry_policy);
+        self
+    }
+
+    /// Enables sandbox mode, where the outcome of account service calls can
+    /// be scripted through `PUT /api/admin/sandbox/account_outcome`.
+    ///
+    /// In live mode, the default, the endpoint isn't routed at all.
+    pub fn with_sandbox(mut self, scenario: Scenario) -> Self {
+        self.sandbox = Some(scenario);
+        self
+    }
+
+    /// Returns a worker settling the payments processed asynchronously, whose
+    /// transitions are notified to webhooks.
+    pub fn settlement_worker(&self) -> settlement::Worker<CheckedService<JournaledService<T>>> {
+        let webhooks = self.webhooks.clone();
+        settlement::Worker::new(
+            self.pool.clone(),
+            self.account_service.clone(),
+            Arc::new(move |payment_id, status| webhooks.notify(payment_id, status)),
+        )
+        .with_fee_policy(self.fee_policy)
+    }
+
+    /// Returns a reaper failing the payments stuck in processing, whose
+    /// transitions are notified to webhooks.
+    pub fn expiry_reaper(&self) -> expiry::Reaper {
+        let webhooks = self.webhooks.clone();
+        expiry::Reaper::new(
+            self.pool.clone(),
+            Arc::new(move |payment_id, status| webhooks.notify(payment_id, status)),
+        )
+    }
+
+    /// Returns a retrier of the refunds whose deposit failed.
+    pub fn refund_retrier(&self) -> retries::Retrier<CheckedService<JournaledService<T>>> {
+        retries::Retrier::new(self.pool.clone(), self.account_service.clone())
+    }
+
+    /// Returns a publisher of the events recorded in the outbox, whose
+    /// refund events are delivered to webhooks.
+    pub fn outbox_publisher(&self) -> outbox::Publisher {
+        outbox::Publisher::new(self.pool.clone()).with_sink(self.webhooks.outbox_sink())
+    }
+
+    /// Returns a digester of the problems of every merchant, which are only
+    /// logged unless given another notifier.
+    pub fn digester(&self) -> Digester {
+        Digester::new(self.pool.clone())
+    }
+
+    /// Returns a roll-up of the availability of the payment and refund
+    /// endpoints served by this router, see `GET /api/status`.
+    pub fn availability_roll_up(&self) -> RollUp {
+        RollUp::new(self.pool.clone(), self.slo.clone(), self.clock.clone())
+    }
+
+    /// Returns an exporter archiving the merchant exports to the export sink.
+    pub fn exporter(&self) -> Exporter {
+        Exporter::new(self.pool.clone(), self.export_sink.clone())
+    }
+
+    /// Returns the API routes, served under the prefix of `version`.
+    fn api_routes(version: ApiVersion) -> Router<Self, Limited<Body>> {
+        let prefix = version.prefix();
+
         Router::new()
-            .route("/api/payments", post(payments::post::<T>))
-            .route("/api/payments/:payment_id", get(payments::get::<T>))
             .route(
-                "/api/payments/:payment_id/refunds",
-                post(refunds::post::<T>),
+                &format!("{prefix}/payments"),
+                post(payments::post::<T>).get(payments::list::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/stats"),
+                get(payments::stats::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/search"),
+                get(payments::search::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id"),
+                get(payments::get::<T>)
+                    .delete(payments::archive::<T>)
+                    .patch(payments::amend::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/events"),
+                get(payments::events::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/notes"),
+                post(notes::post::<T>).get(notes::list::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/retry"),
+                post(payments::retry::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/receipt"),
+                get(receipts::get::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/disputes"),
+                post(disputes::post::<T>).get(disputes::list::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/disputes/:dispute_id"),
+                get(disputes::get::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/disputes/:dispute_id/close"),
+                post(disputes::close::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/refunds"),
+                post(refunds::post::<T>).get(refunds::list::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/refunds/preview"),
+                post(refunds::preview::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/refunds/full"),
+                post(refunds::post_full::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/refunds/:refund_id"),
+                get(refunds::get::<T>).delete(refunds::cancel::<T>),
+            )
+            .route(
+                &format!("{prefix}/payments/:payment_id/refunds/:refund_id/retry"),
+                post(refunds::retry::<T>),
+            )
+            .route(&format!("{prefix}/refunds"), get(refunds::list_all::<T>))
+            .route(&format!("{prefix}/refunds/stats"), get(refunds::stats::<T>))
+            .route(&format!("{prefix}/refunds/bulk"), post(refunds::bulk::<T>))
+            .route(
+                &format!("{prefix}/webhooks"),
+                get(webhooks::list::<T>).post(webhooks::post::<T>),
+            )
+            .route(
+                &format!("{prefix}/digests/latest"),
+                get(digests::latest::<T>),
+            )
+            .route(&format!("{prefix}/status"), get(status::get::<T>))
+            .route(&format!("{prefix}/exports"), post(exports::post::<T>))
+            .route(
+                &format!("{prefix}/exports/:export_id"),
+                get(exports::get::<T>),
+            )
+            .route(
+                &format!("{prefix}/exports/:export_id/download"),
+                get(exports::download::<T>),
+            )
+            .route_layer(Extension(version))
+    }
+
+    /// Returns the operator routes, served under `/api/admin` to the holders
+    /// of the admin token only, as they cross merchants.
+    fn admin_routes(&self) -> Router<Self, Limited<Body>> {
+        let mut router = Router::new()
+            .route("/api/admin/reconciliation", get(reconciliation::get::<T>))
+            .route("/api/admin/backfills", get(backfills::list::<T>))
+            .route(
+                "/api/admin/merchants/:merchant_id/fee_policy",
+                get(fee_policies::get::<T>).put(fee_policies::put::<T>),
             )
+            .route("/api/admin/payments/count", get(payments::admin_count::<T>))
             .route(
-                "/api/payments/:payment_id/refunds/:refund_id",
-                get(refunds::get::<T>),
+                "/api/admin/payments/bulk_transition",
+                post(payments::bulk_transition::<T>),
             )
+            .route(
+                "/api/admin/payments/:payment_id",
+                get(payments::admin_get::<T>).delete(payments::delete::<T>),
+            );
+
+        if self.sandbox.is_some() {
+            router = router.route(
+                "/api/admin/sandbox/account_outcome",
+                put(sandbox::put_account_outcome::<T>),
+            );
+        }
+
+        router.route_layer(middleware::from_fn_with_state(
+            self.admin_token.clone(),
+            admin::require_token,
+        ))
+    }
+
+    pub fn into_router(self) -> Router {
+        let max_body_size = self.max_body_size;
+        let rate_limiter = self.rate_limiter.clone();
+        Router::<_, Limited<Body>>::new()
+            .merge(Self::api_routes(ApiVersion::Legacy))
+            .merge(Self::api_routes(ApiVersion::V1))
+            .merge(self.admin_routes())
+            .fallback(methods::not_found)
+            .layer(middleware::from_fn_with_state(
+                rate_limiter,
+                rate_limit::limit_payments,
+            ))
+            .layer(middleware::from_fn(track_transactions))
+            .layer(middleware::from_fn(strip_head_body))
+            .layer(middleware::from_fn(methods::allow))
+            .layer(middleware::from_fn(retry_after_unavailable))
+            .layer(RequestBodyLimitLayer::new(max_body_size))
+            .layer(middleware::from_fn(payload_too_large))
+            .layer(middleware::from_fn(content_type::require_supported))
+            .layer(middleware::from_fn(problem_details))
+            .layer(middleware::from_fn_with_state(self.clone(), status::track))
+            .layer(middleware::from_fn(telemetry::mark_error_outcome))
+            .layer(CompressionLayer::new())
             .layer(axum_tracing_opentelemetry::opentelemetry_tracing_layer())
             .with_state(self)
             .with_state(())
     }
 }
 
+/// Tracks the database transactions opened while handling a request, so
+/// account service calls made with a transaction open are caught.
+async fn track_transactions<B>(request: Request<B>, next: Next<B>) -> Response {
+    transactions::track(next.run(request)).await
+}
+
+/// Tells clients when to retry the requests answered with a 503.
+async fn retry_after_unavailable<B>(request: Request<B>, next: Next<B>) -> Response {
+    let mut response = next.run(request).await;
+
+    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+        response
+            .headers_mut()
+            .entry(RETRY_AFTER)
+            .or_insert_with(|| STORAGE_RETRY_AFTER.as_secs().into());
+    }
+
+    response
+}
+
+/// Replaces the plain text 413 of oversized request bodies, rejected before
+/// reaching any handler, with an error body.
+async fn payload_too_large<B>(request: Request<B>, next: Next<B>) -> Response {
+    let response = next.run(request).await;
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/json");
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_json {
+        return response;
+    }
+
+    body_too_large().into_response()
+}
+
+/// Answers errors with problem details (RFC 7807) to clients accepting
+/// `application/problem+json`, the `ErrorResponseBody` envelope staying the
+/// default.
+///
+/// The problem details of an `ApiError` are carried by its response, those of
+/// other errors are read from their envelope.
+async fn problem_details<B>(request: Request<B>, next: Next<B>) -> Response {
+    let accepts_problem = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(PROBLEM_JSON_CONTENT_TYPE));
+    if !accepts_problem || request.method() == Method::HEAD {
+        return next.run(request).await;
+    }
+
+    let instance = request.uri().path().to_string();
+    let response = next.run(request).await;
+    if !response.status().is_client_error() && !response.status().is_server_error() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let problem = match parts.extensions.remove::<ProblemDetails>() {
+        Some(problem) => problem,
+        None => {
+            let bytes = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    tracing::error!("failed to read error response: {e}");
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            match serde_json::from_slice::<ErrorResponseBody>(&bytes) {
+                Ok(envelope) => envelope.problem(parts.status),
+                // e.g. the plain text errors of axum's extractors
+                Err(_) => return Response::from_parts(parts, body::boxed(Full::from(bytes))),
+            }
+        }
+    };
+
+    let problem = ProblemDetails {
+        instance: Some(instance),
+        ..problem
+    };
+    let Ok(bytes) = serde_json::to_vec(&problem) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(PROBLEM_JSON_CONTENT_TYPE),
+    );
+    Response::from_parts(parts, body::boxed(Full::from(bytes)))
+}
+
+/// Answers HEAD requests, which axum routes to GET handlers, with the headers
+/// of the GET response only.
+async fn strip_head_body<B>(request: Request<B>, next: Next<B>) -> Response {
+    if request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+
+    let (mut parts, body) = next.run(request).await.into_parts();
+    if let Some(length) = http_body::Body::size_hint(&body).exact() {
+        parts.headers.insert(CONTENT_LENGTH, length.into());
+    }
+
+    Response::from_parts(parts, body::boxed(Empty::new()))
+}
+
 #[cfg(test)]
 pub mod tests {
     use axum::{
         body::Bytes,
-        http::{header::CONTENT_TYPE, Method, Request},
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE, IF_MATCH, IF_NONE_MATCH},
+            HeaderValue, Method, Request,
+        },
     };
     use http_body::combinators::UnsyncBoxBody;
+    use merchant::MERCHANT_ID_HEADER;
     use serde::{de::DeserializeOwned, Serialize};
     use tower::ServiceExt;
+    use uuid::Uuid;
 
-    use super::*;
-    use crate::bank::accounts::DummyService;
+    use super::{payments::tests::PaymentRequestBuilder, *};
+    use crate::bank::{
+        accounts::{AccountMethod, DummyService, ScriptedOutcome},
+        payments,
+    };
+
+    /// Merchant on behalf of which the request helpers are sent.
+    pub const TEST_MERCHANT_ID: Uuid = payments::tests::MERCHANT_ID;
+
+    /// Admin token of the test routers, sent by the admin request helpers.
+    pub const TEST_ADMIN_TOKEN: &str = "test-admin-token";
 
     impl BankWeb<DummyService> {
         pub async fn new_test() -> Self {
+            let pool = crate::pg_pool()
+                .await
+                .expect("failed to create postgres pool");
+
             Self {
-                pool: crate::pg_pool()
-                    .await
-                    .expect("failed to create postgres pool"),
-                account_service: DummyService::default(),
+                webhooks: webhooks::Dispatcher::new(pool.clone()),
+                account_service: CheckedService(JournaledService::new(
+                    pool.clone(),
+                    DummyService::default(),
+                )),
+                pool,
+                fee_policy: FeePolicy::default(),
+                card_reuse_window: Self::DEFAULT_CARD_REUSE_WINDOW,
+                refund_window: Self::DEFAULT_REFUND_WINDOW,
+                max_refunds_per_payment: Self::DEFAULT_MAX_REFUNDS_PER_PAYMENT,
+                max_amount: Self::DEFAULT_MAX_AMOUNT,
+                zero_amount_policy: ZeroAmountPolicy::default(),
+                max_body_size: Self::DEFAULT_MAX_BODY_SIZE,
+                rate_limiter: None,
+                sandbox: None,
+                clock: Arc::new(SystemClock),
+                admin_token: Some(AdminToken::new(TEST_ADMIN_TOKEN)),
+                export_sink: Arc::new(LocalDirSink::new(Self::default_export_dir())),
+                slo: Arc::default(),
+                status_cache: Arc::default(),
             }
         }
 
         pub async fn new_test_with_response(response: impl Into<String>) -> Self {
-            let mut bank_web = Self::new_test().await;
-            bank_web.account_service.response = Some(response.into());
+            let bank_web = Self::new_test().await;
             bank_web
+                .account_service
+                .0
+                .inner()
+                .set_scripted_outcome(Some(
+                    ScriptedOutcome::new(response).with_method(AccountMethod::Hold),
+                ));
+            bank_web
+        }
+    }
+
+    /// Router under test, with helpers creating resources through its API on
+    /// behalf of `TEST_MERCHANT_ID`.
+    pub struct TestApp {
+        pub router: Router,
+    }
+
+    impl TestApp {
+        pub async fn new() -> Self {
+            Self::from(BankWeb::new_test().await)
+        }
+
+        pub async fn create_payment(
+            &self,
+            request_body: &super::payments::RequestBody,
+        ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+            post(&self.router, "/api/payments", request_body).await
+        }
+
+        /// Creates a payment of the default amount, which must be approved.
+        pub async fn create_approved_payment(&self) -> super::payments::ResponseBody {
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = self.create_payment(&request_body).await;
+            assert_eq!(response.status(), 201);
+
+            let response_body =
+                deserialize_response_body::<super::payments::ResponseBody>(response).await;
+            assert_eq!(response_body.data.status, payments::Status::Approved);
+            response_body
+        }
+
+        pub async fn create_refund(
+            &self,
+            payment_id: Uuid,
+            request_body: &super::refunds::RequestBody,
+        ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+            let uri = format!("/api/payments/{payment_id}/refunds");
+            post(&self.router, uri, request_body).await
+        }
+    }
+
+    impl<T: AccountService> From<BankWeb<T>> for TestApp {
+        fn from(bank_web: BankWeb<T>) -> Self {
+            Self {
+                router: bank_web.into_router(),
+            }
         }
     }
 
@@ -99,23 +766,149 @@ pub mod tests {
     pub async fn get(
         router: &Router,
         uri: impl AsRef<str>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        get_as(router, uri, TEST_MERCHANT_ID).await
+    }
+
+    pub async fn get_as(
+        router: &Router,
+        uri: impl AsRef<str>,
+        merchant_id: Uuid,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(uri.as_ref())
+            .header(MERCHANT_ID_HEADER, merchant_id.to_string())
+            .body(hyper::Body::empty())
+            .expect("failed to build GET request");
+        send_request(router, request).await
+    }
+
+    pub async fn get_if_none_match(
+        router: &Router,
+        uri: impl AsRef<str>,
+        etag: &HeaderValue,
     ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
         let request = Request::builder()
             .method(Method::GET)
             .uri(uri.as_ref())
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .header(IF_NONE_MATCH, etag)
             .body(hyper::Body::empty())
             .expect("failed to build GET request");
         send_request(router, request).await
     }
 
+    pub async fn delete(
+        router: &Router,
+        uri: impl AsRef<str>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(Method::DELETE)
+            .uri(uri.as_ref())
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .body(hyper::Body::empty())
+            .expect("failed to build DELETE request");
+        send_request(router, request).await
+    }
+
+    pub async fn delete_if_match(
+        router: &Router,
+        uri: impl AsRef<str>,
+        if_match: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(Method::DELETE)
+            .uri(uri.as_ref())
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .header(IF_MATCH, if_match)
+            .body(hyper::Body::empty())
+            .expect("failed to build DELETE request");
+        send_request(router, request).await
+    }
+
     pub async fn post<T: Serialize>(
         router: &Router,
         uri: impl AsRef<str>,
         body: &T,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        post_as(router, uri, body, TEST_MERCHANT_ID).await
+    }
+
+    pub async fn post_as<T: Serialize>(
+        router: &Router,
+        uri: impl AsRef<str>,
+        body: &T,
+        merchant_id: Uuid,
     ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
         let request = Request::builder()
             .method(Method::POST)
             .uri(uri.as_ref())
+            .header(CONTENT_TYPE, "application/json")
+            .header(MERCHANT_ID_HEADER, merchant_id.to_string())
+            .body(
+                serde_json::to_vec(body)
+                    .expect("failed to serialize POST body")
+                    .into(),
+            )
+            .expect("failed to build POST request");
+        send_request(router, request).await
+    }
+
+    pub async fn patch<T: Serialize>(
+        router: &Router,
+        uri: impl AsRef<str>,
+        body: &T,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(Method::PATCH)
+            .uri(uri.as_ref())
+            .header(CONTENT_TYPE, "application/json")
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .body(
+                serde_json::to_vec(body)
+                    .expect("failed to serialize PATCH body")
+                    .into(),
+            )
+            .expect("failed to build PATCH request");
+        send_request(router, request).await
+    }
+
+    /// Returns a request builder carrying the admin token of the test
+    /// routers.
+    pub fn admin_request(method: Method, uri: impl AsRef<str>) -> axum::http::request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(uri.as_ref())
+            .header(AUTHORIZATION, format!("Bearer {TEST_ADMIN_TOKEN}"))
+    }
+
+    pub async fn admin_get(
+        router: &Router,
+        uri: impl AsRef<str>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = admin_request(Method::GET, uri)
+            .body(hyper::Body::empty())
+            .expect("failed to build GET request");
+        send_request(router, request).await
+    }
+
+    pub async fn admin_delete(
+        router: &Router,
+        uri: impl AsRef<str>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = admin_request(Method::DELETE, uri)
+            .body(hyper::Body::empty())
+            .expect("failed to build DELETE request");
+        send_request(router, request).await
+    }
+
+    pub async fn admin_post<T: Serialize>(
+        router: &Router,
+        uri: impl AsRef<str>,
+        body: &T,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = admin_request(Method::POST, uri)
             .header(CONTENT_TYPE, "application/json")
             .body(
                 serde_json::to_vec(body)
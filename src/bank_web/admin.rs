@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderMap, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use super::ErrorResponseBody;
+
+/// Bearer token of the operators allowed to call the admin endpoints.
+#[derive(Clone)]
+pub struct AdminToken(Arc<str>);
+
+impl AdminToken {
+    pub fn new(token: impl Into<Arc<str>>) -> Self {
+        Self(token.into())
+    }
+
+    /// Whether `candidate` is the token, compared in constant time so the
+    /// token can't be guessed byte by byte from response times.
+    fn matches(&self, candidate: &str) -> bool {
+        let (expected, candidate) = (self.0.as_bytes(), candidate.as_bytes());
+        expected.len() == candidate.len()
+            && expected
+                .iter()
+                .zip(candidate)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl std::fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AdminToken(..)")
+    }
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(WWW_AUTHENTICATE, "Bearer")],
+        Json(ErrorResponseBody::new("admin credentials are required").with_code("unauthorized")),
+    )
+        .into_response()
+}
+
+/// Whether the headers carry `token` as `Authorization: Bearer <token>`.
+fn is_authorized(headers: &HeaderMap, token: Option<&AdminToken>) -> bool {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .zip(token)
+        .is_some_and(|(candidate, token)| token.matches(candidate.trim()))
+}
+
+/// Answers admin requests with a 401 unless they carry the admin token as
+/// `Authorization: Bearer <token>`.
+///
+/// Without a configured token, every admin request is answered with a 401.
+pub async fn require_token<B>(
+    State(token): State<Option<AdminToken>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !is_authorized(request.headers(), token.as_ref()) {
+        tracing::warn!(path = request.uri().path(), "unauthorized admin request");
+        return unauthorized();
+    }
+
+    next.run(request).await
+}
+
+/// Principal of a request, recorded with the changes it makes, e.g. as the
+/// initiator of a refund.
+///
+/// Requests carrying the admin token are made by `admin`, every other
+/// request being unauthenticated and so `anonymous`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Principal {
+    Admin,
+    Anonymous,
+}
+
+impl Principal {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Anonymous => "anonymous",
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Principal
+where
+    S: Send + Sync,
+    Option<AdminToken>: FromRef<S>,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = Option::<AdminToken>::from_ref(state);
+        Ok(if is_authorized(&parts.headers, token.as_ref()) {
+            Self::Admin
+        } else {
+            Self::Anonymous
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_token_exactly() {
+        let token = AdminToken::new("s3cret");
+
+        assert!(token.matches("s3cret"));
+        assert!(!token.matches("s3cre"));
+        assert!(!token.matches("s3cret!"));
+        assert!(!token.matches("S3CRET"));
+        assert!(!token.matches(""));
+    }
+}
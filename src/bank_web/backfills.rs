@@ -0,0 +1,115 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{BankWeb, ErrorResponseBody};
+use crate::bank::{
+    accounts::AccountService,
+    backfill::{self, Progress},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProgressData {
+    pub name: String,
+    /// Rows walked so far.
+    pub processed_rows: i64,
+    /// Rows that had to be updated, out of the processed ones.
+    pub updated_rows: i64,
+    pub batches: i64,
+    /// When the backfill was first run, `null` if it never was.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub started_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub updated_at: Option<OffsetDateTime>,
+    /// When the last batch found no more rows to walk.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub completed_at: Option<OffsetDateTime>,
+}
+
+impl ProgressData {
+    fn not_started(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            processed_rows: 0,
+            updated_rows: 0,
+            batches: 0,
+            started_at: None,
+            updated_at: None,
+            completed_at: None,
+        }
+    }
+}
+
+impl From<Progress> for ProgressData {
+    fn from(progress: Progress) -> Self {
+        Self {
+            name: progress.name,
+            processed_rows: progress.processed_rows,
+            updated_rows: progress.updated_rows,
+            batches: progress.batches,
+            started_at: Some(progress.started_at.assume_utc()),
+            updated_at: Some(progress.updated_at.assume_utc()),
+            completed_at: progress.completed_at.map(|at| at.assume_utc()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ListResponseBody {
+    pub data: Vec<ProgressData>,
+}
+
+/// Reports the progress of every registered backfill, run through
+/// `bankctl backfill run <name>`.
+pub async fn list<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+) -> Result<Json<ListResponseBody>, (StatusCode, Json<ErrorResponseBody>)> {
+    let mut recorded = backfill::list_progress(&bank_web.pool).await.map_err(|e| {
+        tracing::error!("failed to list backfill progress: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponseBody::new("can't list backfills")),
+        )
+    })?;
+
+    let data = backfill::registry()
+        .iter()
+        .map(|job| {
+            match recorded
+                .iter()
+                .position(|progress| progress.name == job.name())
+            {
+                Some(i) => recorded.swap_remove(i).into(),
+                None => ProgressData::not_started(job.name()),
+            }
+        })
+        .collect();
+
+    Ok(Json(ListResponseBody { data }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank_web::tests::{admin_get, deserialize_response_body, get};
+
+    #[tokio::test]
+    async fn should_report_progress_of_registered_backfills_to_admins() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let response = get(&router, "/api/admin/backfills").await;
+        assert_eq!(response.status(), 401);
+
+        let response = admin_get(&router, "/api/admin/backfills").await;
+        assert_eq!(response.status(), 200);
+        let data = deserialize_response_body::<ListResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(
+            data.iter()
+                .map(|progress| progress.name.as_str())
+                .collect::<Vec<_>>(),
+            ["card_fingerprint", "account_prefix"]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use axum::{
+    http::{header::CONTENT_TYPE, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body::Body;
+
+use super::ErrorResponseBody;
+
+/// Media types accepted in the body of mutating requests.
+pub const SUPPORTED_CONTENT_TYPES: &[&str] = &["application/json"];
+
+fn is_mutating(method: &Method) -> bool {
+    [Method::POST, Method::PUT, Method::PATCH, Method::DELETE].contains(method)
+}
+
+/// Returns whether a `Content-Type` value names a supported media type,
+/// ignoring parameters such as `charset=utf-8`.
+fn is_supported(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    SUPPORTED_CONTENT_TYPES
+        .iter()
+        .any(|supported| supported.eq_ignore_ascii_case(media_type))
+}
+
+/// Rejects mutating requests whose body isn't in a supported media type with
+/// a 415, instead of leaving it to each extractor.
+///
+/// Requests without a body may omit the `Content-Type` header.
+pub async fn require_supported<B: Body>(request: Request<B>, next: Next<B>) -> Response {
+    if !is_mutating(request.method()) {
+        return next.run(request).await;
+    }
+
+    let supported = match request.headers().get(CONTENT_TYPE) {
+        Some(content_type) => content_type.to_str().is_ok_and(is_supported),
+        None => request.body().size_hint().exact() == Some(0),
+    };
+
+    if supported {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(
+                ErrorResponseBody::new("Unsupported Content-Type")
+                    .with_code("unsupported_media_type")
+                    .with_details(serde_json::json!({
+                        "supported_types": SUPPORTED_CONTENT_TYPES,
+                    })),
+            ),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use axum::{middleware, routing::post, Router};
+
+    use super::*;
+    use crate::bank_web::tests::{deserialize_response_body, send_request};
+
+    fn router() -> Router {
+        Router::new()
+            .route("/", post(|| async { StatusCode::NO_CONTENT }))
+            .layer(middleware::from_fn(require_supported))
+    }
+
+    fn request(content_type: Option<&str>, body: &'static str) -> Request<hyper::Body> {
+        let mut request = Request::builder().method(Method::POST).uri("/");
+        if let Some(content_type) = content_type {
+            request = request.header(CONTENT_TYPE, content_type);
+        }
+
+        request.body(body.into()).expect("failed to build request")
+    }
+
+    #[tokio::test]
+    async fn should_reject_unsupported_content_types() {
+        let router = router();
+
+        for content_type in [Some("text/plain"), Some("application/jsonx"), None] {
+            let response = send_request(&router, request(content_type, "{}")).await;
+            assert_eq!(response.status(), 415, "{content_type:?}");
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                response_body.code.as_deref(),
+                Some("unsupported_media_type")
+            );
+            assert_eq!(
+                response_body.details,
+                Some(serde_json::json!({ "supported_types": ["application/json"] }))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_accept_json_with_parameters() {
+        let router = router();
+
+        for content_type in ["application/json", "Application/JSON; charset=utf-8"] {
+            let response = send_request(&router, request(Some(content_type), "{}")).await;
+            assert_eq!(response.status(), 204, "{content_type}");
+        }
+    }
+
+    #[tokio::test]
+    async fn should_accept_bodyless_requests_without_content_type() {
+        let response = send_request(&router(), request(None, "")).await;
+        assert_eq!(response.status(), 204);
+    }
+}
@@ -0,0 +1,108 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{merchant::MerchantId, BankWeb, ErrorResponseBody};
+use crate::bank::{
+    accounts::AccountService,
+    digests::{self, Digest},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseData {
+    pub id: Uuid,
+    /// Day summarized, as `YYYY-MM-DD`.
+    pub day: String,
+    pub dead_deliveries: i64,
+    pub stuck_payments: i64,
+    pub failed_refunds: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+    /// When the digest was sent to the merchant, `null` until it was.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub notified_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: ResponseData,
+}
+
+impl From<Digest> for ResponseData {
+    fn from(digest: Digest) -> Self {
+        Self {
+            id: digest.id,
+            day: digest.day.to_string(),
+            dead_deliveries: digest.dead_deliveries,
+            stuck_payments: digest.stuck_payments,
+            failed_refunds: digest.failed_refunds,
+            inserted_at: digest.inserted_at.assume_utc(),
+            notified_at: digest.notified_at.map(|at| at.assume_utc()),
+        }
+    }
+}
+
+/// Returns the most recent daily digest of the merchant, answering a 404 if
+/// it never had anything to report.
+pub async fn latest<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+) -> Result<Json<ResponseBody>, (StatusCode, Json<ErrorResponseBody>)> {
+    let digest = digests::latest(&bank_web.pool, merchant_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to get latest digest of merchant {merchant_id}: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponseBody::new("can't get digest")),
+            )
+        })?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponseBody::new("no digest yet").with_code("not_found")),
+        ))?;
+
+    Ok(Json(ResponseBody {
+        data: digest.into(),
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        bank::digests::tests::insert,
+        bank_web::tests::{deserialize_response_body, get_as},
+    };
+
+    #[tokio::test]
+    async fn should_answer_the_latest_digest_of_the_merchant_only() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let (merchant_id, other_merchant_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let digest = insert(&pool, merchant_id, [2, 0, 1]).await;
+
+        let response = get_as(&router, "/api/digests/latest", merchant_id).await;
+        assert_eq!(response.status(), 200);
+        let data = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(data.id, digest.id);
+        assert_eq!(data.day, digest.day.to_string());
+        assert_eq!(
+            (
+                data.dead_deliveries,
+                data.stuck_payments,
+                data.failed_refunds
+            ),
+            (2, 0, 1)
+        );
+        assert_eq!(data.notified_at, None);
+
+        let response = get_as(&router, "/api/digests/latest", other_merchant_id).await;
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,274 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{
+    merchant::MerchantId, path::ApiPath, payments::find_payment, storage_unavailable, BankWeb,
+};
+use crate::bank::{
+    accounts::AccountService,
+    payments::disputes::{self, Dispute, OpenOutcome, Status},
+};
+use crate::errors::ApiError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseData {
+    pub id: Uuid,
+    pub payment_id: Uuid,
+    pub status: Status,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub closed_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: ResponseData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ListResponseBody {
+    pub data: Vec<ResponseData>,
+}
+
+impl From<Dispute> for ResponseData {
+    fn from(dispute: Dispute) -> Self {
+        Self {
+            id: dispute.id,
+            payment_id: dispute.payment_id,
+            status: dispute.status,
+            inserted_at: dispute.inserted_at.assume_utc(),
+            closed_at: dispute.closed_at.map(|closed_at| closed_at.assume_utc()),
+        }
+    }
+}
+
+/// Opens a dispute of a payment, which can't be refunded until the dispute
+/// is closed.
+pub async fn post<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+) -> Result<(StatusCode, Json<ResponseBody>), ApiError> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+
+    let outcome = disputes::open(&bank_web.pool, payment_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to open dispute of payment {payment_id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+
+    match outcome {
+        OpenOutcome::Opened(dispute) => Ok((
+            StatusCode::CREATED,
+            Json(ResponseBody {
+                data: dispute.into(),
+            }),
+        )),
+        OpenOutcome::AlreadyOpen { dispute_id } => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "payment is already under dispute",
+        )
+        .with_code("dispute_already_open")
+        .with_details(serde_json::json!({ "dispute_id": dispute_id }))),
+        OpenOutcome::PaymentNotFound => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "payment doesn't exist",
+        )
+        .with_code("not_found")),
+    }
+}
+
+/// Lists the disputes of a payment, oldest first.
+pub async fn list<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+) -> Result<Json<ListResponseBody>, ApiError> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+
+    let disputes = disputes::list(&bank_web.pool, payment_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to list disputes of payment {payment_id}: {e}");
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't list disputes")
+        })?;
+
+    Ok(Json(ListResponseBody {
+        data: disputes.into_iter().map(ResponseData::from).collect(),
+    }))
+}
+
+/// Returns a dispute of a payment of the merchant, reporting disputes of
+/// other payments as missing.
+async fn find_dispute<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    merchant: MerchantId,
+    payment_id: Uuid,
+    dispute_id: Uuid,
+) -> Result<Dispute, ApiError> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+
+    match disputes::get(&bank_web.pool, dispute_id).await {
+        Ok(dispute) if dispute.payment_id == payment_id => Ok(dispute),
+        Ok(_) | Err(sqlx::Error::RowNotFound) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "dispute doesn't exist",
+        )
+        .with_code("not_found")),
+        Err(e) => {
+            tracing::error!("failed to get dispute {dispute_id}: {e}");
+            Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "can't get dispute",
+            ))
+        }
+    }
+}
+
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath((payment_id, dispute_id)): ApiPath<(Uuid, Uuid)>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    let dispute = find_dispute(&bank_web, merchant, payment_id, dispute_id).await?;
+
+    Ok(Json(ResponseBody {
+        data: dispute.into(),
+    }))
+}
+
+/// Closes a dispute, after which its payment can be refunded again.
+pub async fn close<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath((payment_id, dispute_id)): ApiPath<(Uuid, Uuid)>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    find_dispute(&bank_web, merchant, payment_id, dispute_id).await?;
+
+    let dispute = disputes::close(&bank_web.pool, dispute_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to close dispute {dispute_id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+
+    Ok(Json(ResponseBody {
+        data: dispute.into(),
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank_web::{
+        refunds::tests::RefundRequestBuilder,
+        tests::{deserialize_response_body, get, get_as, post, TestApp},
+        ErrorResponseBody,
+    };
+
+    #[tokio::test]
+    async fn should_block_refunds_while_disputed() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+        let disputes_uri = format!("/api/payments/{payment_id}/disputes");
+        let refunds_uri = format!("/api/payments/{payment_id}/refunds");
+        let refund = RefundRequestBuilder::new().amount(10).build();
+
+        let response = post(&app.router, &disputes_uri, &()).await;
+        assert_eq!(response.status(), 201);
+        let dispute = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(dispute.status, Status::Open);
+
+        let response = post(&app.router, &disputes_uri, &()).await;
+        assert_eq!(response.status(), 409);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("dispute_already_open"));
+
+        let full_refund = serde_json::json!({});
+        for response in [
+            post(&app.router, &refunds_uri, &refund).await,
+            post(&app.router, format!("{refunds_uri}/full"), &full_refund).await,
+        ] {
+            assert_eq!(response.status(), 409);
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(body.code.as_deref(), Some("payment_disputed"));
+        }
+
+        let uri = format!("{disputes_uri}/{}/close", dispute.id);
+        let response = post(&app.router, &uri, &()).await;
+        assert_eq!(response.status(), 200);
+        let closed = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(closed.status, Status::Closed);
+        assert!(closed.closed_at.is_some());
+
+        let response = post(&app.router, &refunds_uri, &refund).await;
+        assert_eq!(response.status(), 201);
+
+        let response = get(&app.router, format!("{disputes_uri}/{}", dispute.id)).await;
+        let body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(body.data, closed);
+        let response = get(&app.router, &disputes_uri).await;
+        let body = deserialize_response_body::<ListResponseBody>(response).await;
+        assert_eq!(body.data, [closed]);
+    }
+
+    #[tokio::test]
+    async fn should_block_refunds_disputed_concurrently() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+        let disputes_uri = format!("/api/payments/{payment_id}/disputes");
+        let refunds_uri = format!("/api/payments/{payment_id}/refunds");
+        let refund = RefundRequestBuilder::new().amount(1).build();
+
+        let (dispute, refunds) = tokio::join!(
+            post(&app.router, &disputes_uri, &()),
+            futures::future::join_all(
+                (0..5).map(|_| async { post(&app.router, &refunds_uri, &refund).await.status() })
+            ),
+        );
+        assert_eq!(dispute.status(), 201);
+
+        // refunds recorded before the dispute are listed, the others blocked
+        let created = refunds.iter().filter(|status| **status == 201).count();
+        assert!(
+            refunds
+                .iter()
+                .all(|status| *status == 201 || *status == 409),
+            "{refunds:?}"
+        );
+        let response = get(&app.router, &refunds_uri).await;
+        let body = deserialize_response_body::<serde_json::Value>(response).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), created);
+
+        let response = post(&app.router, &refunds_uri, &refund).await;
+        assert_eq!(response.status(), 409);
+    }
+
+    #[tokio::test]
+    async fn should_not_return_disputes_of_other_merchants() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+        let disputes_uri = format!("/api/payments/{payment_id}/disputes");
+
+        let response = post(&app.router, &disputes_uri, &()).await;
+        let dispute = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        let uri = format!("{disputes_uri}/{}", dispute.id);
+        let response = get_as(&app.router, uri, Uuid::new_v4()).await;
+        assert_eq!(response.status(), 404);
+
+        let other_payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{other_payment_id}/disputes/{}", dispute.id);
+        assert_eq!(get(&app.router, uri).await.status(), 404);
+    }
+}
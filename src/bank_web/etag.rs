@@ -0,0 +1,130 @@
+use axum::{
+    http::{
+        header::{ETAG, IF_MATCH, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+/// Number of digest bytes kept in entity tags.
+const TAG_LENGTH: usize = 16;
+
+/// Returns the strong entity tag of a resource version, `version` holding
+/// every field whose change alters the representation.
+pub fn entity_tag(version: &str) -> String {
+    let digest = Sha256::digest(version.as_bytes());
+    format!("\"{}\"", hex::encode(&digest[..TAG_LENGTH]))
+}
+
+/// Returns whether the `If-None-Match` header matches `etag`, i.e. the client
+/// already has the current representation.
+///
+/// Weak comparison is used, as required for `If-None-Match`.
+fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Returns `response` with an `ETag` header, or an empty 304 if the client's
+/// copy is still fresh.
+pub fn conditional(headers: &HeaderMap, etag: String, response: impl IntoResponse) -> Response {
+    if is_fresh(headers, &etag) {
+        (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response()
+    } else {
+        ([(ETAG, etag)], response).into_response()
+    }
+}
+
+/// Outcome of the `If-Match` precondition of a mutating request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// The request has no `If-Match` header.
+    Absent,
+    Met,
+    Failed,
+}
+
+/// Evaluates the `If-Match` header against the current `version` of a
+/// resource, which clients send as an entity tag, e.g. `If-Match: "3"`.
+///
+/// Strong comparison is used, as required for `If-Match`, so weak tags never
+/// match.
+pub fn if_match(headers: &HeaderMap, version: i32) -> Precondition {
+    let mut tags = headers
+        .get_all(IF_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .peekable();
+    if tags.peek().is_none() {
+        return Precondition::Absent;
+    }
+
+    let current = format!("\"{version}\"");
+    if tags.any(|tag| tag == "*" || tag == current) {
+        Precondition::Met
+    } else {
+        Precondition::Failed
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn if_none_match(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn should_match_if_none_match_lists() {
+        let etag = entity_tag("version");
+
+        assert!(!is_fresh(&HeaderMap::new(), &etag));
+        assert!(is_fresh(&if_none_match("*"), &etag));
+        assert!(!is_fresh(&if_none_match("\"other\""), &etag));
+
+        let headers = format!("\"other\", W/{etag}");
+        let headers = if_none_match(Box::leak(headers.into_boxed_str()));
+        assert!(is_fresh(&headers, &etag));
+    }
+
+    #[test]
+    fn should_match_if_match_versions() {
+        let if_match_header = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_MATCH, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(if_match(&HeaderMap::new(), 3), Precondition::Absent);
+        assert_eq!(if_match(&if_match_header("\"3\""), 3), Precondition::Met);
+        assert_eq!(
+            if_match(&if_match_header("\"2\", \"3\""), 3),
+            Precondition::Met
+        );
+        assert_eq!(if_match(&if_match_header("*"), 3), Precondition::Met);
+        assert_eq!(if_match(&if_match_header("\"2\""), 3), Precondition::Failed);
+        assert_eq!(
+            if_match(&if_match_header("W/\"3\""), 3),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn should_tag_versions_apart() {
+        assert_eq!(entity_tag("a"), entity_tag("a"));
+        assert_ne!(entity_tag("a"), entity_tag("b"));
+    }
+}
@@ -0,0 +1,131 @@
+use axum::{
+    body::{Bytes, StreamBody},
+    http::{
+        header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use futures::{stream::BoxStream, StreamExt};
+use serde::Serialize;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+
+use crate::errors::ApiError;
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+/// Size above which buffered CSV rows are sent to the client.
+const CSV_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Returns whether a listing is exported as CSV rather than JSON.
+///
+/// The `format` query parameter, `json` or `csv`, takes precedence over the
+/// `Accept` header.
+pub fn csv_requested(format: Option<&str>, headers: &HeaderMap) -> Result<bool, ApiError> {
+    match format {
+        Some("csv") => Ok(true),
+        Some("json") => Ok(false),
+        None => Ok(headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains(CSV_CONTENT_TYPE))),
+        Some(_) => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "format should be json or csv",
+        )),
+    }
+}
+
+/// Returns a writer of CSV rows, headers being written separately.
+fn csv_writer() -> csv::Writer<Vec<u8>> {
+    csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new())
+}
+
+/// Takes the CSV written so far out of `writer`.
+fn take_chunk(writer: &mut csv::Writer<Vec<u8>>) -> Bytes {
+    let written = std::mem::replace(writer, csv_writer());
+    Bytes::from(written.into_inner().expect("failed to flush CSV rows"))
+}
+
+/// Streams the records returned by `stream` for `filter` as a CSV attachment
+/// named after `name`, each record written as a `T` row under `headers`.
+///
+/// Rows are written by a background task into a bounded channel, so memory
+/// use doesn't depend on the number of records. The export stops as soon as
+/// the client goes away, even while waiting for the next record, releasing
+/// the connection `stream` holds. Text cells of `T` must be escaped by
+/// `export::csv_escape`.
+pub fn stream_csv<F, R, T>(
+    name: &'static str,
+    headers: &'static [&'static str],
+    pool: PgPool,
+    filter: F,
+    stream: for<'a> fn(&'a PgPool, &'a F) -> BoxStream<'a, Result<R, sqlx::Error>>,
+) -> Response
+where
+    F: Send + Sync + 'static,
+    R: Into<T> + Send + 'static,
+    T: Serialize,
+{
+    let (sender, mut receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+
+    tokio::spawn(async move {
+        let mut rows = stream(&pool, &filter);
+        let mut writer = csv_writer();
+        writer
+            .write_record(headers)
+            .expect("failed to write CSV headers");
+
+        loop {
+            // the client went away when the receiver is dropped
+            let row = tokio::select! {
+                row = rows.next() => row,
+                () = sender.closed() => return,
+            };
+            let Some(row) = row else {
+                break;
+            };
+
+            let record = match row {
+                Ok(record) => record,
+                Err(e) => {
+                    tracing::error!("failed to export {name}: {e}");
+                    let error = std::io::Error::other(e.to_string());
+                    let _ = sender.send(Err(error)).await;
+                    return;
+                }
+            };
+
+            let row: T = record.into();
+            writer.serialize(row).expect("failed to write CSV row");
+            writer.flush().expect("failed to flush CSV row");
+
+            if writer.get_ref().len() >= CSV_CHUNK_SIZE
+                && sender.send(Ok(take_chunk(&mut writer))).await.is_err()
+            {
+                return;
+            }
+        }
+
+        let _ = sender.send(Ok(take_chunk(&mut writer))).await;
+    });
+
+    let body = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+    let filename = format!("{name}-{}.csv", OffsetDateTime::now_utc().unix_timestamp());
+
+    (
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, format!("{CSV_CONTENT_TYPE}; charset=utf-8")),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        StreamBody::new(body),
+    )
+        .into_response()
+}
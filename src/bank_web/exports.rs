@@ -0,0 +1,572 @@
+use std::{io::Read, sync::Arc, time::Duration};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::State,
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{
+    location, merchant::MerchantId, path::ApiPath, storage_unavailable, BankWeb, Location,
+};
+use crate::bank::{
+    accounts::AccountService,
+    exports::{self, DownloadOutcome, Export, ExportSink, Status},
+};
+use crate::errors::ApiError;
+
+pub const ZIP_CONTENT_TYPE: &str = "application/zip";
+/// Size of the chunks of archive sent to the client.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+/// After which a download which didn't complete nor was released, e.g. as the
+/// server stopped while sending the archive, can be retried.
+const DOWNLOAD_LEASE: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseData {
+    pub id: Uuid,
+    pub status: Status,
+    /// Rows archived so far.
+    pub exported_rows: i64,
+    /// Rows to archive, `null` until the export started.
+    pub total_rows: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Path the archive can be downloaded from, once, when completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_path: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub completed_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: ResponseData,
+}
+
+impl From<Export> for ResponseData {
+    fn from(export: Export) -> Self {
+        let download_path = (export.status == Status::Completed && export.downloaded_at.is_none())
+            .then(|| format!("/api/exports/{}/download", export.id));
+
+        Self {
+            id: export.id,
+            status: export.status,
+            exported_rows: export.exported_rows,
+            total_rows: export.total_rows,
+            error: export.error,
+            download_path,
+            inserted_at: export.inserted_at.assume_utc(),
+            completed_at: export.completed_at.map(|at| at.assume_utc()),
+        }
+    }
+}
+
+fn not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "export doesn't exist").with_code("not_found")
+}
+
+/// Requests an export of every payment, refund, dispute and webhook delivery
+/// of the merchant, archived in the background, see `exports::Exporter`.
+pub async fn post<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+) -> Result<(StatusCode, Location, Json<ResponseBody>), ApiError> {
+    let export = exports::insert(&bank_web.pool, merchant_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to insert export of merchant {merchant_id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        location(format!("/api/exports/{}", export.id)),
+        Json(ResponseBody {
+            data: export.into(),
+        }),
+    ))
+}
+
+/// Reports the progress of an export of the merchant.
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    let export = exports::get(&bank_web.pool, merchant_id, id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to get export {id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?
+        .ok_or_else(not_found)?;
+
+    Ok(Json(ResponseBody {
+        data: export.into(),
+    }))
+}
+
+/// Lease of the download of an export, released when dropped before the
+/// archive was sent, e.g. as the client went away, so it can be retried.
+struct DownloadLease {
+    pool: PgPool,
+    sink: Arc<dyn ExportSink>,
+    export_id: Uuid,
+    archive_id: Uuid,
+    lease_id: Uuid,
+    completed: bool,
+}
+
+impl DownloadLease {
+    /// Marks the export as downloaded once the last chunk of its archive was
+    /// sent, then removes the archive.
+    async fn complete(mut self) {
+        self.completed = true;
+        let id = self.export_id;
+        match exports::complete_download(&self.pool, id, self.lease_id).await {
+            Ok(true) => {
+                let (sink, archive_id) = (self.sink.clone(), self.archive_id);
+                match tokio::task::spawn_blocking(move || sink.remove(archive_id)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => tracing::warn!("failed to remove archive of export {id}: {e}"),
+                    Err(e) => tracing::warn!("archive remover of export {id} panicked: {e}"),
+                }
+            }
+            // the lease expired and another download took over, removing the
+            // archive once sent
+            Ok(false) => tracing::warn!("download lease of export {id} expired"),
+            // the lease expires, and the archive can be downloaded again
+            Err(e) => tracing::error!("failed to complete download of export {id}: {e}"),
+        }
+    }
+}
+
+impl Drop for DownloadLease {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+
+        let (pool, id, lease_id) = (self.pool.clone(), self.export_id, self.lease_id);
+        tokio::spawn(async move {
+            if let Err(e) = exports::release_download(&pool, id, lease_id).await {
+                tracing::warn!("failed to release download lease of export {id}: {e}");
+            }
+        });
+    }
+}
+
+/// Streams the archive of a completed export of the merchant, which is then
+/// removed: an archive is downloaded once, and answers a 410 afterwards.
+///
+/// A download is leased while the archive is sent, answering a 409 to other
+/// ones, and can be retried if interrupted.
+pub async fn download<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Response, ApiError> {
+    let outcome = exports::lease_download(&bank_web.pool, merchant_id, id, DOWNLOAD_LEASE)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to download export {id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+    let (archive_id, lease_id) = match outcome {
+        DownloadOutcome::Ready {
+            archive_id,
+            lease_id,
+        } => (archive_id, lease_id),
+        DownloadOutcome::NotFound => return Err(not_found()),
+        DownloadOutcome::NotCompleted { status } => {
+            return Err(
+                ApiError::new(StatusCode::CONFLICT, "export isn't completed")
+                    .with_code("export_not_completed")
+                    .with_details(serde_json::json!({ "status": status })),
+            )
+        }
+        DownloadOutcome::Leased => {
+            return Err(
+                ApiError::new(StatusCode::CONFLICT, "export is being downloaded")
+                    .with_code("download_in_progress"),
+            )
+        }
+        DownloadOutcome::AlreadyDownloaded => {
+            return Err(
+                ApiError::new(StatusCode::GONE, "export was already downloaded")
+                    .with_code("already_downloaded"),
+            )
+        }
+    };
+
+    // released if the archive can't be opened
+    let lease = DownloadLease {
+        pool: bank_web.pool.clone(),
+        sink: bank_web.export_sink.clone(),
+        export_id: id,
+        archive_id,
+        lease_id,
+        completed: false,
+    };
+    let mut archive = bank_web.export_sink.open(archive_id).map_err(|e| {
+        tracing::error!("failed to open archive of export {id}: {e}");
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't read export")
+    })?;
+
+    // the archive is read by a blocking task into a bounded channel, so it
+    // isn't held in memory
+    let (sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+    tokio::task::spawn_blocking(move || {
+        loop {
+            let mut chunk = vec![0; DOWNLOAD_CHUNK_SIZE];
+            let read = match archive.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    tracing::error!("failed to read archive of export {id}: {e}");
+                    let _ = sender.blocking_send(Err(e));
+                    return;
+                }
+            };
+            chunk.truncate(read);
+            // the client went away when the receiver is dropped
+            if sender.blocking_send(Ok(Bytes::from(chunk))).is_err() {
+                return;
+            }
+        }
+    });
+
+    // the export is downloaded once the body ended after the last chunk,
+    // and the lease released if it is dropped before, or fails to read
+    let body = futures::stream::unfold(
+        (receiver, Some(lease)),
+        |(mut receiver, mut lease)| async move {
+            match receiver.recv().await {
+                Some(Ok(chunk)) => Some((Ok(chunk), (receiver, lease))),
+                Some(Err(e)) => Some((Err(e), (receiver, None))),
+                None => {
+                    if let Some(lease) = lease.take() {
+                        lease.complete().await;
+                    }
+                    None
+                }
+            }
+        },
+    );
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, ZIP_CONTENT_TYPE.to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"export-{id}.zip\""),
+            ),
+        ],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Cursor;
+
+    use axum::Router;
+    use hyper::body::to_bytes;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::{
+        bank::{
+            events::PayloadVersion,
+            payment_instruments::Card,
+            payments::{
+                self, disputes, tests::CARD_REUSE_WINDOW, ProcessingMode, Source,
+                Status as PaymentStatus,
+            },
+            refunds, webhooks,
+        },
+        bank_web::tests::{deserialize_response_body, get_as, post_as},
+    };
+
+    async fn seed(pool: &sqlx::PgPool, merchant_id: Uuid) {
+        let webhook_id = webhooks::insert(
+            pool,
+            merchant_id,
+            "http://127.0.0.1:1/hook".to_string(),
+            "secret".to_string(),
+            PayloadVersion::LATEST,
+        )
+        .await
+        .expect("failed to insert webhook");
+
+        for i in 0..3 {
+            let payment_id = payments::insert(
+                pool,
+                merchant_id,
+                1000 + i,
+                &Card::new_test(),
+                "EUR".to_string(),
+                PaymentStatus::Approved,
+                ProcessingMode::Sync,
+                None,
+                None,
+                Source::default(),
+                CARD_REUSE_WINDOW,
+            )
+            .await
+            .expect("failed to insert payment");
+            webhooks::insert_delivery(pool, webhook_id, payment_id, "payment.approved")
+                .await
+                .expect("failed to insert delivery");
+
+            if i == 0 {
+                refunds::insert(pool, payment_id, 100, "EUR".to_string(), None, None)
+                    .await
+                    .expect("failed to insert refund");
+                disputes::open(pool, payment_id)
+                    .await
+                    .expect("failed to open dispute");
+            }
+        }
+    }
+
+    async fn count(pool: &sqlx::PgPool, query: &str, merchant_id: Uuid) -> usize {
+        let (count,): (i64,) = sqlx::query_as(query)
+            .bind(merchant_id)
+            .fetch_one(pool)
+            .await
+            .expect("failed to count rows");
+        count as usize
+    }
+
+    async fn get_export(router: &Router, id: Uuid, merchant_id: Uuid) -> ResponseData {
+        let response = get_as(router, &format!("/api/exports/{id}"), merchant_id).await;
+        assert_eq!(response.status(), 200);
+        deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+    }
+
+    /// Runs `exporter` until the export is completed, returning it.
+    async fn complete_export(
+        router: &Router,
+        exporter: &exports::Exporter,
+        id: Uuid,
+        merchant_id: Uuid,
+    ) -> ResponseData {
+        // exporters of other tests may archive this export first
+        let mut export = get_export(router, id, merchant_id).await;
+        for _ in 0..50 {
+            if export.status == Status::Completed {
+                break;
+            }
+            exporter.run_once().await.expect("failed to run exporter");
+            export = get_export(router, id, merchant_id).await;
+        }
+        assert_eq!(export.status, Status::Completed);
+        export
+    }
+
+    #[tokio::test]
+    async fn should_export_every_row_of_the_merchant_once() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        // a batch per row, so the rows are read across several batches
+        let exporter = bank_web.exporter().with_batch_size(1);
+        let router = bank_web.into_router();
+        let (merchant_id, other_merchant_id) = (Uuid::new_v4(), Uuid::new_v4());
+        seed(&pool, merchant_id).await;
+        seed(&pool, other_merchant_id).await;
+
+        let response = post_as(&router, "/api/exports", &(), merchant_id).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let export = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(export.status, Status::Pending);
+        assert_eq!(export.download_path, None);
+
+        let response = get_as(
+            &router,
+            &format!("/api/exports/{}/download", export.id),
+            merchant_id,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let export = complete_export(&router, &exporter, export.id, merchant_id).await;
+        assert_eq!(export.total_rows, Some(export.exported_rows));
+        let download_path = export.download_path.expect("missing download path");
+
+        let response = get_as(&router, &download_path, other_merchant_id).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = get_as(&router, &download_path, merchant_id).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], ZIP_CONTENT_TYPE);
+        let bytes = to_bytes(response.into_body())
+            .await
+            .expect("failed to read archive");
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).expect("failed to open archive");
+
+        let mut exported_rows = 0;
+        for (file, query) in [
+            (
+                "payments.csv",
+                "SELECT count(*) FROM payments WHERE merchant_id = $1",
+            ),
+            (
+                "refunds.csv",
+                "SELECT count(*) FROM refunds WHERE merchant_id = $1",
+            ),
+            (
+                "disputes.csv",
+                "SELECT count(*) FROM disputes d JOIN payments p ON p.id = d.payment_id
+                 WHERE p.merchant_id = $1",
+            ),
+            (
+                "webhook_deliveries.csv",
+                "SELECT count(*) FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+                 WHERE w.merchant_id = $1",
+            ),
+        ] {
+            let mut reader =
+                csv::Reader::from_reader(archive.by_name(file).expect("missing CSV file"));
+            let rows = reader
+                .records()
+                .collect::<Result<Vec<_>, _>>()
+                .expect("failed to read CSV");
+            assert_eq!(rows.len(), count(&pool, query, merchant_id).await, "{file}");
+            exported_rows += rows.len() as i64;
+        }
+        assert_eq!(exported_rows, export.exported_rows);
+
+        let manifest: serde_json::Value =
+            serde_json::from_reader(archive.by_name("export.json").expect("missing manifest"))
+                .expect("failed to read manifest");
+        assert_eq!(manifest["merchant_id"], merchant_id.to_string());
+
+        // the archive is gone once downloaded
+        let response = get_as(&router, &download_path, merchant_id).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(
+            get_export(&router, export.id, merchant_id)
+                .await
+                .download_path,
+            None
+        );
+
+        let response = get_as(
+            &router,
+            &format!("/api/exports/{}", export.id),
+            other_merchant_id,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn should_let_interrupted_download_be_retried() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let sink = bank_web.export_sink.clone();
+        let exporter = bank_web.exporter();
+        let router = bank_web.into_router();
+        let merchant_id = Uuid::new_v4();
+        seed(&pool, merchant_id).await;
+
+        let response = post_as(&router, "/api/exports", &(), merchant_id).await;
+        let export = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        let export = complete_export(&router, &exporter, export.id, merchant_id).await;
+        let download_path = export.download_path.expect("missing download path");
+
+        let interrupted = get_as(&router, &download_path, merchant_id).await;
+        assert_eq!(interrupted.status(), StatusCode::OK);
+        let response = get_as(&router, &download_path, merchant_id).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // the client goes away before the archive was sent
+        drop(interrupted);
+        let mut response = get_as(&router, &download_path, merchant_id).await;
+        for _ in 0..50 {
+            if response.status() != StatusCode::CONFLICT {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            response = get_as(&router, &download_path, merchant_id).await;
+        }
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body())
+            .await
+            .expect("failed to read archive");
+        ZipArchive::new(Cursor::new(bytes)).expect("failed to open archive");
+
+        let response = get_as(&router, &download_path, merchant_id).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        let claim_id = exports::get(&pool, merchant_id, export.id)
+            .await
+            .expect("failed to get export")
+            .and_then(|export| export.claim_id)
+            .expect("missing claim");
+        assert!(sink.open(claim_id).is_err(), "archive wasn't removed");
+    }
+
+    #[tokio::test]
+    async fn should_ignore_exporter_which_lost_its_claim() {
+        let pool = BankWeb::new_test().await.pool;
+        let merchant_id = Uuid::new_v4();
+        let (stale_claim_id, claim_id) = (Uuid::new_v4(), Uuid::new_v4());
+        // taken over from the stale exporter, see `exports::claim`
+        let (id,): (Uuid,) = sqlx::query_as(
+            "INSERT INTO exports (merchant_id, status, claim_id) VALUES ($1, 'Running', $2)
+             RETURNING id",
+        )
+        .bind(merchant_id)
+        .bind(claim_id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to insert export");
+
+        let owned = exports::record_progress(&pool, id, stale_claim_id, 1)
+            .await
+            .expect("failed to record progress");
+        assert!(!owned);
+        let completed = exports::complete(&pool, id, stale_claim_id, 1)
+            .await
+            .expect("failed to complete export");
+        assert!(!completed);
+        let failed = exports::fail(&pool, id, stale_claim_id, "archive writer stopped")
+            .await
+            .expect("failed to fail export");
+        assert!(!failed);
+
+        let completed = exports::complete(&pool, id, claim_id, 2)
+            .await
+            .expect("failed to complete export");
+        assert!(completed);
+        let export = exports::get(&pool, merchant_id, id)
+            .await
+            .expect("failed to get export")
+            .expect("missing export");
+        assert_eq!(
+            (export.status, export.exported_rows, export.error),
+            (Status::Completed, 2, None)
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    json::{invalid_body, ApiJson, FieldError},
+    path::ApiPath,
+    storage_unavailable, BankWeb,
+};
+use crate::bank::{
+    accounts::AccountService,
+    fees::{FeePolicy, FeePolicyError},
+    merchants,
+};
+use crate::errors::ApiError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestBody {
+    /// Rate of the amount, in basis points from 0 to 10000.
+    pub basis_points: i32,
+    /// Fixed component in minor units.
+    pub fixed_amount: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FeePolicyData {
+    pub basis_points: i32,
+    pub fixed_amount: i64,
+    /// Whether the merchant is charged the bank's default policy, having
+    /// none of its own.
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: FeePolicyData,
+}
+
+impl FeePolicyData {
+    fn new(policy: FeePolicy, default: bool) -> Self {
+        Self {
+            basis_points: policy.basis_points(),
+            fixed_amount: policy.fixed_amount(),
+            default,
+        }
+    }
+}
+
+/// Returns the fee policy a merchant is charged under.
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    ApiPath(merchant_id): ApiPath<Uuid>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    let policy = merchants::get_fee_policy(&bank_web.pool, merchant_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to get fee policy of {merchant_id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+
+    let data = match policy {
+        Some(policy) => FeePolicyData::new(policy, false),
+        None => FeePolicyData::new(bank_web.fee_policy, true),
+    };
+    Ok(Json(ResponseBody { data }))
+}
+
+/// Sets the fee policy a merchant agreed to, charged on its approved payments
+/// from then on.
+pub async fn put<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    ApiPath(merchant_id): ApiPath<Uuid>,
+    ApiJson(body): ApiJson<RequestBody>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    let policy = FeePolicy::new(body.basis_points, body.fixed_amount).map_err(|e| {
+        let error = match e {
+            FeePolicyError::NegativeFixedAmount => {
+                FieldError::new("fixed_amount", "fixed amount can't be negative")
+            }
+            _ => FieldError::new("basis_points", "basis points must be between 0 and 10000"),
+        };
+        ApiError::from(invalid_body(
+            "invalid fee policy",
+            "invalid_fee_policy",
+            vec![error],
+        ))
+    })?;
+
+    merchants::set_fee_policy(&bank_web.pool, merchant_id, policy)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to set fee policy of {merchant_id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+
+    tracing::info!(%merchant_id, ?policy, "set fee policy");
+    Ok(Json(ResponseBody {
+        data: FeePolicyData::new(policy, false),
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use axum::{
+        http::{header::CONTENT_TYPE, Method},
+        Router,
+    };
+
+    use super::*;
+    use crate::bank_web::tests::{
+        admin_get, admin_request, deserialize_response_body, send_request,
+    };
+
+    async fn put_policy(
+        router: &Router,
+        merchant_id: Uuid,
+        body: &str,
+    ) -> axum::response::Response {
+        let request = admin_request(
+            Method::PUT,
+            format!("/api/admin/merchants/{merchant_id}/fee_policy"),
+        )
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string().into())
+        .expect("failed to build PUT request");
+        send_request(router, request).await
+    }
+
+    #[tokio::test]
+    async fn should_set_fee_policy_of_merchant() {
+        let router = BankWeb::new_test()
+            .await
+            .with_fee_policy(FeePolicy::new(100, 10).unwrap())
+            .into_router();
+        let merchant_id = Uuid::new_v4();
+        let uri = format!("/api/admin/merchants/{merchant_id}/fee_policy");
+
+        let response = admin_get(&router, &uri).await;
+        assert_eq!(response.status(), 200);
+        let data = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(
+            (data.basis_points, data.fixed_amount, data.default),
+            (100, 10, true)
+        );
+
+        let response = put_policy(
+            &router,
+            merchant_id,
+            r#"{"basis_points":140,"fixed_amount":25}"#,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        let response = admin_get(&router, &uri).await;
+        let data = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(
+            (data.basis_points, data.fixed_amount, data.default),
+            (140, 25, false)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_fee_policy() {
+        let router = BankWeb::new_test().await.into_router();
+        let merchant_id = Uuid::new_v4();
+
+        for body in [
+            r#"{"basis_points":10001,"fixed_amount":0}"#,
+            r#"{"basis_points":-1,"fixed_amount":0}"#,
+            r#"{"basis_points":0,"fixed_amount":-1}"#,
+        ] {
+            let response = put_policy(&router, merchant_id, body).await;
+            assert_eq!(response.status(), 422, "{body}");
+        }
+
+        let response = admin_get(
+            &router,
+            &format!("/api/admin/merchants/{merchant_id}/fee_policy"),
+        )
+        .await;
+        let data = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert!(data.default);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{collections::BTreeSet, fmt::Display, marker::PhantomData};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::ErrorResponseBody;
+
+/// Field always returned, whatever the requested fieldset.
+const ID_FIELD: &str = "id";
+
+/// A resource whose fields can be selected with `?fields=`.
+pub trait Sparse: Serialize {
+    /// Names of the fields that can be requested.
+    const FIELDS: &'static [&'static str];
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct FieldsetQuery {
+    fields: Option<String>,
+}
+
+/// Extracts the comma separated `fields` query parameter, e.g.
+/// `?fields=id,status`, listing the fields of a `T` returned to the client.
+///
+/// The fieldset applies to the top-level resource only, and `id` is always
+/// returned. Without the parameter, every field is returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fieldset<T> {
+    /// Requested fields, every field if `None`.
+    fields: Option<BTreeSet<String>>,
+    resource: PhantomData<fn() -> T>,
+}
+
+impl<T: Sparse> Fieldset<T> {
+    /// Parses a comma separated list of fields of `T`.
+    ///
+    /// Unknown fields are returned as an error.
+    pub fn parse(fields: &str) -> Result<Self, Vec<String>> {
+        let fields: BTreeSet<_> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let unknown: Vec<_> = fields
+            .iter()
+            .filter(|field| !T::FIELDS.contains(&field.as_str()))
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(unknown);
+        }
+
+        Ok(Self {
+            fields: Some(fields),
+            resource: PhantomData,
+        })
+    }
+
+    /// Serializes `resource`, keeping the requested fields only.
+    pub fn project(&self, resource: &T) -> serde_json::Value {
+        let mut value = serde_json::to_value(resource).expect("failed to serialize resource");
+
+        if let (Some(fields), Some(object)) = (&self.fields, value.as_object_mut()) {
+            object.retain(|field, _| field == ID_FIELD || fields.contains(field));
+        }
+
+        value
+    }
+}
+
+/// Lists the requested fields, `*` standing for every field, e.g. to tell
+/// representations apart in ETags.
+impl<T> Display for Fieldset<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.fields {
+            Some(fields) => {
+                let fields: Vec<_> = fields.iter().map(String::as_str).collect();
+                write!(f, "{}", fields.join(","))
+            }
+            None => write!(f, "*"),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync, T: Sparse> FromRequestParts<S> for Fieldset<T> {
+    type Rejection = (StatusCode, Json<ErrorResponseBody>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<FieldsetQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponseBody::new("Invalid fields parameter")),
+                )
+            })?;
+
+        let Some(fields) = query.fields else {
+            return Ok(Self {
+                fields: None,
+                resource: PhantomData,
+            });
+        };
+
+        Self::parse(&fields).map_err(|unknown| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ErrorResponseBody::new("fields lists unknown fields")
+                        .with_code("invalid_fields")
+                        .with_details(serde_json::json!({
+                            "unknown_fields": unknown,
+                            "valid_fields": T::FIELDS,
+                        })),
+                ),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq, Serialize)]
+    struct Resource {
+        id: u32,
+        name: &'static str,
+        size: u32,
+    }
+
+    impl Sparse for Resource {
+        const FIELDS: &'static [&'static str] = &["id", "name", "size"];
+    }
+
+    const RESOURCE: Resource = Resource {
+        id: 1,
+        name: "name",
+        size: 2,
+    };
+
+    #[test]
+    fn should_keep_requested_fields_and_id() {
+        let fieldset = Fieldset::<Resource>::parse("size, ,size").unwrap();
+
+        assert_eq!(
+            fieldset.project(&RESOURCE),
+            serde_json::json!({ "id": 1, "size": 2 })
+        );
+        assert_eq!(fieldset.to_string(), "size");
+    }
+
+    #[test]
+    fn should_reject_unknown_fields() {
+        assert_eq!(
+            Fieldset::<Resource>::parse("name,color,weight"),
+            Err(vec!["color".to_string(), "weight".to_string()])
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::FromRequest,
+    http::{Request, StatusCode},
+    BoxError, Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{body_too_large, ErrorResponseBody};
+
+/// Version of the API a request was routed through.
+///
+/// Behavior changes that could break existing integrations only apply to
+/// the versioned routes, legacy routes keep their original behavior.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// Routes under `/api`.
+    #[default]
+    Legacy,
+    /// Routes under `/api/v1`.
+    V1,
+}
+
+impl ApiVersion {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Self::Legacy => "/api",
+            Self::V1 => "/api/v1",
+        }
+    }
+
+    /// Returns whether mutations of existing resources must be conditioned
+    /// on their version with `If-Match`.
+    pub fn requires_if_match(&self) -> bool {
+        *self == Self::V1
+    }
+
+    /// Returns whether fields a request type doesn't know are rejected
+    /// rather than ignored.
+    fn rejects_unknown_fields(&self) -> bool {
+        *self == Self::V1
+    }
+}
+
+/// A problem with one of the fields of a request body.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FieldError {
+    /// Dotted path of the field, e.g. `refund.amount`.
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Returns a 422 listing every problem found in a request body.
+///
+/// A single problem is also reported as the error message, so clients
+/// reading only `error` still get the cause.
+pub fn invalid_body(
+    message: &'static str,
+    code: &'static str,
+    errors: Vec<FieldError>,
+) -> (StatusCode, Json<ErrorResponseBody>) {
+    let mut body = ErrorResponseBody::new(message).with_code(code);
+    if let [error] = errors.as_slice() {
+        body.error = error.message.clone();
+    }
+
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(body.with_details(serde_json::json!({ "errors": errors }))),
+    )
+}
+
+/// Returns a 422 for a body that couldn't be deserialized, whose error names
+/// the offending field, e.g. `payment.amount: invalid type: ...`.
+fn undeserializable_body(
+    message: &'static str,
+    code: &'static str,
+    error: FieldError,
+) -> (StatusCode, Json<ErrorResponseBody>) {
+    // serde_path_to_error names the root of the body `.`
+    let described = match error.field.as_str() {
+        "." => error.message.clone(),
+        field => format!("{field}: {}", error.message),
+    };
+
+    let (status, Json(mut body)) = invalid_body(message, code, vec![error]);
+    body.error = described;
+    (status, Json(body))
+}
+
+/// JSON body extractor reporting deserialization errors as a structured 422
+/// naming the offending field.
+///
+/// Request types should deny unknown fields: they are reported on versioned
+/// routes, and dropped on legacy routes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = (StatusCode, Json<ErrorResponseBody>);
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let version = request
+            .extensions()
+            .get::<ApiVersion>()
+            .copied()
+            .unwrap_or_default();
+
+        let bytes =
+            Bytes::from_request(request, state)
+                .await
+                .map_err(|rejection| match rejection.status() {
+                    StatusCode::PAYLOAD_TOO_LARGE => body_too_large(),
+                    _ => (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponseBody::new("Failed to read request body")),
+                    ),
+                })?;
+        let mut value = serde_json::from_slice::<serde_json::Value>(&bytes).map_err(|e| {
+            let mut body = ErrorResponseBody::new("Request body isn't valid JSON")
+                .with_code("invalid_json")
+                .with_details(serde_json::json!({ "line": e.line(), "column": e.column() }));
+            body.error = format!("Request body isn't valid JSON: {e}");
+            (StatusCode::BAD_REQUEST, Json(body))
+        })?;
+
+        loop {
+            let error = match serde_path_to_error::deserialize::<_, T>(&value) {
+                Ok(data) => return Ok(Self(data)),
+                Err(error) => error,
+            };
+
+            let field = error.path().to_string();
+            let message = error.inner().to_string();
+            let is_unknown_field = message.starts_with("unknown field");
+
+            if !is_unknown_field {
+                return Err(undeserializable_body(
+                    "Invalid request body",
+                    "invalid_body",
+                    FieldError::new(&field, message),
+                ));
+            }
+
+            if version.rejects_unknown_fields() || !remove(&mut value, &field) {
+                return Err(undeserializable_body(
+                    "Unknown field in request body",
+                    "unknown_field",
+                    FieldError::new(&field, message),
+                ));
+            }
+        }
+    }
+}
+
+/// Removes the field at the dotted `path` from `value`, returning whether it
+/// was found.
+fn remove(value: &mut serde_json::Value, path: &str) -> bool {
+    let (parents, field) = match path.rsplit_once('.') {
+        Some((parents, field)) => (Some(parents), field),
+        None => (None, path),
+    };
+
+    let parent = parents
+        .into_iter()
+        .flat_map(|parents| parents.split('.'))
+        .try_fold(value, |value, key| value.get_mut(key));
+
+    parent
+        .and_then(|parent| parent.as_object_mut())
+        .and_then(|parent| parent.remove(field))
+        .is_some()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn should_remove_nested_fields() {
+        let mut value = serde_json::json!({ "refund": { "ammount": 1, "amount": 2 } });
+
+        assert!(remove(&mut value, "refund.ammount"));
+        assert_eq!(value, serde_json::json!({ "refund": { "amount": 2 } }));
+        assert!(!remove(&mut value, "refund.ammount"));
+        assert!(!remove(&mut value, "payment.amount"));
+    }
+}
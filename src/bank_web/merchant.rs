@@ -0,0 +1,47 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use uuid::Uuid;
+
+use super::ErrorResponseBody;
+
+/// Header identifying the merchant a request is made on behalf of.
+pub const MERCHANT_ID_HEADER: &str = "x-merchant-id";
+
+/// Extracts the merchant a request is made on behalf of from the
+/// `X-Merchant-Id` header, which is required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MerchantId(pub Uuid);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for MerchantId {
+    type Rejection = (StatusCode, Json<ErrorResponseBody>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(MERCHANT_ID_HEADER) else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ErrorResponseBody::new("X-Merchant-Id header is required")
+                        .with_code("missing_merchant_id"),
+                ),
+            ));
+        };
+
+        value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .map(Self)
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ErrorResponseBody::new("X-Merchant-Id header should be a UUID")
+                        .with_code("invalid_merchant_id"),
+                ),
+            ))
+    }
+}
@@ -0,0 +1,251 @@
+use axum::{
+    http::{
+        header::{HeaderValue, ALLOW},
+        Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use super::{json::ApiVersion, ErrorResponseBody};
+
+/// Methods supported by the API routes, served under the prefix of every
+/// `ApiVersion`, where `:name` segments match any segment.
+///
+/// Must be kept in sync with `BankWeb::api_routes`.
+const API_ROUTES: &[(&str, &str)] = &[
+    ("/payments", "GET,HEAD,POST"),
+    ("/payments/stats", "GET,HEAD"),
+    ("/payments/search", "GET,HEAD"),
+    ("/payments/:payment_id", "GET,HEAD,DELETE,PATCH"),
+    ("/payments/:payment_id/events", "GET,HEAD"),
+    ("/payments/:payment_id/notes", "GET,HEAD,POST"),
+    ("/payments/:payment_id/receipt", "GET,HEAD"),
+    ("/payments/:payment_id/retry", "POST"),
+    ("/payments/:payment_id/disputes", "GET,HEAD,POST"),
+    ("/payments/:payment_id/disputes/:dispute_id", "GET,HEAD"),
+    ("/payments/:payment_id/disputes/:dispute_id/close", "POST"),
+    ("/payments/:payment_id/refunds", "GET,HEAD,POST"),
+    ("/payments/:payment_id/refunds/preview", "POST"),
+    ("/payments/:payment_id/refunds/full", "POST"),
+    (
+        "/payments/:payment_id/refunds/:refund_id",
+        "GET,HEAD,DELETE",
+    ),
+    ("/payments/:payment_id/refunds/:refund_id/retry", "POST"),
+    ("/refunds", "GET,HEAD"),
+    ("/refunds/stats", "GET,HEAD"),
+    ("/refunds/bulk", "POST"),
+    ("/webhooks", "GET,HEAD,POST"),
+    ("/digests/latest", "GET,HEAD"),
+    ("/status", "GET,HEAD"),
+    ("/exports", "POST"),
+    ("/exports/:export_id", "GET,HEAD"),
+    ("/exports/:export_id/download", "GET,HEAD"),
+];
+
+/// Methods supported by the routes served outside of the API prefixes.
+const OTHER_ROUTES: &[(&str, &str)] = &[
+    ("/api/admin/reconciliation", "GET,HEAD"),
+    ("/api/admin/backfills", "GET,HEAD"),
+    (
+        "/api/admin/merchants/:merchant_id/fee_policy",
+        "GET,HEAD,PUT",
+    ),
+    ("/api/admin/payments/count", "GET,HEAD"),
+    ("/api/admin/payments/bulk_transition", "POST"),
+    ("/api/admin/payments/:payment_id", "GET,HEAD,DELETE"),
+    ("/api/admin/sandbox/account_outcome", "PUT"),
+];
+
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.split('/');
+    let mut path = path.split('/');
+
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(segment)) => {
+                let matched = match expected.strip_prefix(':') {
+                    Some(_) => !segment.is_empty(),
+                    None => expected == segment,
+                };
+                if !matched {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Returns the comma separated methods supported by `path`, if it's routed.
+fn allowed_methods(path: &str) -> Option<&'static str> {
+    let api_routes = [ApiVersion::Legacy, ApiVersion::V1]
+        .into_iter()
+        .filter_map(|version| path.strip_prefix(version.prefix()))
+        .flat_map(|path| {
+            API_ROUTES
+                .iter()
+                .filter(move |(pattern, _)| matches(pattern, path))
+        });
+    let other_routes = OTHER_ROUTES
+        .iter()
+        .filter(|(pattern, _)| matches(pattern, path));
+
+    api_routes
+        .chain(other_routes)
+        .map(|(_, methods)| *methods)
+        .next()
+}
+
+/// Answers requests for a method a known path doesn't support with a JSON
+/// 405, and `OPTIONS` requests with a 204, both listing the supported methods
+/// in the `Allow` header.
+pub async fn allow<B>(request: Request<B>, next: Next<B>) -> Response {
+    let is_options = request.method() == Method::OPTIONS;
+    let methods = allowed_methods(request.uri().path());
+
+    let response = next.run(request).await;
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+
+    // axum answers unsupported methods of routed paths, so `methods` is known
+    let allow = format!("{},OPTIONS", methods.unwrap_or_default());
+    let allow = HeaderValue::from_str(allow.trim_start_matches(','))
+        .expect("methods are valid header values");
+
+    if is_options {
+        return (StatusCode::NO_CONTENT, [(ALLOW, allow)]).into_response();
+    }
+
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(ALLOW, allow)],
+        Json(ErrorResponseBody::new("method not allowed").with_code("method_not_allowed")),
+    )
+        .into_response()
+}
+
+/// Answers requests to unknown paths with a JSON 404.
+pub async fn not_found() -> (StatusCode, Json<ErrorResponseBody>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponseBody::new("not found").with_code("not_found")),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::{body::Bytes, http::header::CONTENT_TYPE, Router};
+    use http_body::combinators::UnsyncBoxBody;
+
+    use super::*;
+    use crate::bank_web::{
+        tests::{deserialize_response_body, send_request},
+        BankWeb,
+    };
+
+    async fn request(
+        router: &Router,
+        method: Method,
+        uri: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from("{}"))
+            .expect("failed to build request");
+        send_request(router, request).await
+    }
+
+    fn allow_header<B>(response: &hyper::Response<B>) -> BTreeSet<String> {
+        response.headers()[ALLOW]
+            .to_str()
+            .unwrap()
+            .split(',')
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        assert_eq!(allowed_methods("/api/payments"), Some("GET,HEAD,POST"));
+        assert_eq!(allowed_methods("/api/v1/payments/search"), Some("GET,HEAD"));
+        assert_eq!(
+            allowed_methods("/api/v1/payments/42/refunds/43"),
+            Some("GET,HEAD,DELETE")
+        );
+        assert_eq!(allowed_methods("/api/payments//refunds"), None);
+        assert_eq!(allowed_methods("/api/v2/payments"), None);
+        assert_eq!(allowed_methods("/api/nope"), None);
+    }
+
+    #[tokio::test]
+    async fn should_route_every_listed_method() {
+        let router = BankWeb::new_test().await.into_router();
+
+        for (pattern, methods) in API_ROUTES {
+            let path = pattern
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(_) => "00000000-0000-0000-0000-000000000000",
+                    None => segment,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+
+            for method in methods.split(',') {
+                let method = Method::from_bytes(method.as_bytes()).unwrap();
+                let response = request(&router, method.clone(), &format!("/api{path}")).await;
+                assert_ne!(response.status(), 405, "{method} {pattern}");
+                assert_ne!(response.status(), 404, "{method} {pattern}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_return_405_with_allowed_methods() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let response = request(&router, Method::PUT, "/api/payments").await;
+        assert_eq!(response.status(), 405);
+        assert_eq!(
+            allow_header(&response),
+            BTreeSet::from(["GET", "HEAD", "OPTIONS", "POST"].map(String::from))
+        );
+
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("method_not_allowed"));
+    }
+
+    #[tokio::test]
+    async fn should_answer_options_with_allowed_methods() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let response = request(&router, Method::OPTIONS, "/api/v1/webhooks").await;
+        assert_eq!(response.status(), 204);
+        assert_eq!(
+            allow_header(&response),
+            BTreeSet::from(["GET", "HEAD", "OPTIONS", "POST"].map(String::from))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_json_404_for_unknown_paths() {
+        let router = BankWeb::new_test().await.into_router();
+
+        for method in [Method::GET, Method::OPTIONS] {
+            let response = request(&router, method.clone(), "/api/nope").await;
+            assert_eq!(response.status(), 404, "{method}");
+
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(body.code.as_deref(), Some("not_found"));
+        }
+    }
+}
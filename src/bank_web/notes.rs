@@ -0,0 +1,230 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{
+    json::{invalid_body, ApiJson, FieldError},
+    merchant::MerchantId,
+    pagination::{Paginated, PaginationParams},
+    path::ApiPath,
+    payments::find_payment,
+    BankWeb, ErrorResponseBody,
+};
+use crate::bank::{
+    accounts::AccountService,
+    pagination::Cursor,
+    payments::notes::{self, Note, MAX_BODY_LENGTH},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestData {
+    /// Name of the support agent writing the note.
+    pub author: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestBody {
+    pub note: RequestData,
+}
+
+impl RequestData {
+    /// Checks the requested note, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        if self.author.trim().is_empty() {
+            errors.push(FieldError::new("note.author", "note author must be given"));
+        }
+
+        if self.body.trim().is_empty() {
+            errors.push(FieldError::new("note.body", "note body must not be empty"));
+        } else if self.body.chars().count() > MAX_BODY_LENGTH {
+            errors.push(FieldError::new(
+                "note.body",
+                format!("note body should be at most {MAX_BODY_LENGTH} characters"),
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseData {
+    pub id: Uuid,
+    pub payment_id: Uuid,
+    pub author: String,
+    pub body: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: ResponseData,
+}
+
+impl From<Note> for ResponseData {
+    fn from(note: Note) -> Self {
+        Self {
+            id: note.id,
+            payment_id: note.payment_id,
+            author: note.author,
+            body: note.body,
+            inserted_at: note.inserted_at.assume_utc(),
+        }
+    }
+}
+
+/// Writes a note on a payment. Notes can't be changed once written.
+pub async fn post<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    ApiJson(body): ApiJson<RequestBody>,
+) -> Result<(StatusCode, Json<ResponseBody>), (StatusCode, Json<ErrorResponseBody>)> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+
+    body.note
+        .validate()
+        .map_err(|errors| invalid_body("Invalid note", "invalid_note", errors))?;
+
+    let note = notes::insert(
+        &bank_web.pool,
+        payment_id,
+        &body.note.author,
+        &body.note.body,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to insert note of payment {payment_id}: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponseBody::new("can't write note")),
+        )
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ResponseBody { data: note.into() }),
+    ))
+}
+
+/// Lists the notes of a payment, oldest first.
+pub async fn list<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    pagination: PaginationParams,
+) -> Result<(StatusCode, Json<Paginated<ResponseData>>), (StatusCode, Json<ErrorResponseBody>)> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+
+    let notes = notes::list(&bank_web.pool, payment_id, &pagination.page())
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to list notes of payment {payment_id}: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponseBody::new("can't list payment notes")),
+            )
+        })?;
+
+    Ok((
+        StatusCode::OK,
+        Json(Paginated::new(
+            notes,
+            &pagination,
+            |note| Cursor {
+                inserted_at: note.inserted_at,
+                id: note.id,
+                sort: None,
+            },
+            ResponseData::from,
+        )),
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::bank_web::tests::{deserialize_response_body, get, get_as, post, TestApp};
+
+    fn note(author: &str, body: &str) -> RequestBody {
+        RequestBody {
+            note: RequestData {
+                author: author.to_string(),
+                body: body.to_string(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn should_write_and_list_notes_in_order() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{payment_id}/notes");
+
+        let mut written = Vec::new();
+        for (author, body) in [("alice", "customer called"), ("bob", "dispute opened")] {
+            let response = post(&app.router, &uri, &note(author, body)).await;
+            assert_eq!(response.status(), 201);
+
+            let response_body = deserialize_response_body::<ResponseBody>(response).await;
+            assert_eq!(response_body.data.payment_id, payment_id);
+            assert_eq!(response_body.data.author, author);
+            assert_eq!(response_body.data.body, body);
+            written.push(response_body.data);
+        }
+
+        let response = get(&app.router, &uri).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(response_body.data, written);
+    }
+
+    #[tokio::test]
+    async fn should_return_404_for_notes_of_missing_payment() {
+        let app = TestApp::new().await;
+        let uri = format!("/api/payments/{}/notes", Uuid::new_v4());
+
+        let response = post(&app.router, &uri, &note("alice", "customer called")).await;
+        assert_eq!(response.status(), 404);
+
+        let response = get(&app.router, &uri).await;
+        assert_eq!(response.status(), 404);
+
+        let payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{payment_id}/notes");
+        let response = get_as(&app.router, uri, Uuid::new_v4()).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_too_long_notes() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{payment_id}/notes");
+
+        let longest = "é".repeat(MAX_BODY_LENGTH);
+        let response = post(&app.router, &uri, &note("alice", &longest)).await;
+        assert_eq!(response.status(), 201);
+
+        let response = post(&app.router, &uri, &note("alice", &format!("{longest}e"))).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_note"));
+        assert_eq!(
+            response_body.details.unwrap()["errors"][0]["field"],
+            "note.body"
+        );
+    }
+}
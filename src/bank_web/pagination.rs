@@ -0,0 +1,263 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use super::ErrorResponseBody;
+use crate::bank::pagination::{Cursor, Page, SortPosition};
+
+/// Number of items per page when no limit is given.
+pub const DEFAULT_LIMIT: i64 = 50;
+/// Largest page a client may ask for.
+pub const MAX_LIMIT: i64 = 100;
+
+/// Number of checksum bytes appended to encoded cursors.
+const CHECKSUM_LENGTH: usize = 4;
+
+/// Response envelope of every list endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Paginated<T> {
+    pub data: Vec<T>,
+    pub page_info: PageInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PageInfo {
+    /// Cursor to pass as `cursor` to get the next page, if any.
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
+    /// Rough number of items across all pages, when cheap to compute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_estimate: Option<i64>,
+}
+
+impl<T> Paginated<T> {
+    /// Builds a page out of rows fetched with `PaginationParams::page`, which
+    /// asks for one extra row to tell whether more pages follow.
+    pub fn new<R>(
+        mut rows: Vec<R>,
+        params: &PaginationParams,
+        cursor_of: impl Fn(&R) -> Cursor,
+        into: impl FnMut(R) -> T,
+    ) -> Self {
+        let has_more = rows.len() as i64 > params.limit;
+        rows.truncate(params.limit as usize);
+
+        let next_cursor = match rows.last() {
+            Some(row) if has_more => Some(encode_cursor(&cursor_of(row))),
+            _ => None,
+        };
+
+        Self {
+            data: rows.into_iter().map(into).collect(),
+            page_info: PageInfo {
+                next_cursor,
+                has_more,
+                total_estimate: None,
+            },
+        }
+    }
+}
+
+/// Encodes a cursor into an opaque string.
+///
+/// Cursors aren't secret, the checksum only catches cursors that were
+/// truncated or edited by hand.
+pub fn encode_cursor(cursor: &Cursor) -> String {
+    let mut payload = format!(
+        "{}_{}",
+        cursor.inserted_at.assume_utc().unix_timestamp_nanos(),
+        cursor.id
+    );
+    // cursors of the default sort keep their original encoding
+    if let Some(sort) = &cursor.sort {
+        payload.push('|');
+        payload.push_str(&sort.sort);
+        if let Some(value) = &sort.value {
+            payload.push('|');
+            payload.push_str(value);
+        }
+    }
+
+    let mut bytes = payload.into_bytes();
+    let checksum = Sha256::digest(&bytes);
+    bytes.extend_from_slice(&checksum[..CHECKSUM_LENGTH]);
+
+    hex::encode(bytes)
+}
+
+/// Decodes a cursor returned by `encode_cursor`.
+pub fn decode_cursor(encoded: &str) -> Option<Cursor> {
+    let bytes = hex::decode(encoded).ok()?;
+    if bytes.len() <= CHECKSUM_LENGTH {
+        return None;
+    }
+
+    let (payload, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LENGTH);
+    if Sha256::digest(payload)[..CHECKSUM_LENGTH] != *checksum {
+        return None;
+    }
+
+    let mut parts = std::str::from_utf8(payload).ok()?.splitn(3, '|');
+    let (nanos, id) = parts.next()?.split_once('_')?;
+    let inserted_at = time::OffsetDateTime::from_unix_timestamp_nanos(nanos.parse().ok()?).ok()?;
+    let sort = parts.next().map(|sort| SortPosition {
+        sort: sort.to_string(),
+        value: parts.next().map(str::to_string),
+    });
+
+    Some(Cursor {
+        inserted_at: PrimitiveDateTime::new(inserted_at.date(), inserted_at.time()),
+        id: id.parse::<Uuid>().ok()?,
+        sort,
+    })
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct PaginationQuery {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Extracts the `cursor` and `limit` query parameters of list endpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaginationParams {
+    pub after: Option<Cursor>,
+    pub limit: i64,
+}
+
+impl PaginationParams {
+    /// Returns the page to fetch, including one extra row used to fill
+    /// `PageInfo::has_more`.
+    pub fn page(&self) -> Page {
+        Page {
+            after: self.after.clone(),
+            limit: self.limit + 1,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for PaginationParams {
+    type Rejection = (StatusCode, Json<ErrorResponseBody>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<PaginationQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponseBody::new("Invalid pagination parameters")),
+                )
+            })?;
+
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ErrorResponseBody::new("limit should be between 1 and 100")
+                        .with_code("invalid_limit"),
+                ),
+            ));
+        }
+
+        let after = match query.cursor {
+            Some(cursor) => Some(decode_cursor(&cursor).ok_or((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponseBody::new("Invalid cursor").with_code("invalid_cursor")),
+            ))?),
+            None => None,
+        };
+
+        Ok(Self { after, limit })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn cursor() -> Cursor {
+        Cursor {
+            inserted_at: time::Date::from_calendar_date(2023, time::Month::March, 14)
+                .unwrap()
+                .with_hms_micro(9, 26, 53, 589_793)
+                .unwrap(),
+            id: Uuid::new_v4(),
+            sort: None,
+        }
+    }
+
+    #[test]
+    fn should_round_trip_cursors() {
+        let cursor = cursor();
+
+        assert_eq!(decode_cursor(&encode_cursor(&cursor)), Some(cursor.clone()));
+
+        for value in [None, Some("1205".to_string())] {
+            let cursor = Cursor {
+                sort: Some(SortPosition {
+                    sort: "amount.asc".to_string(),
+                    value,
+                }),
+                ..cursor.clone()
+            };
+            assert_eq!(decode_cursor(&encode_cursor(&cursor)), Some(cursor));
+        }
+    }
+
+    #[test]
+    fn should_reject_tampered_cursors() {
+        let encoded = encode_cursor(&cursor());
+
+        // flip every hex digit in turn
+        for i in 0..encoded.len() {
+            let mut tampered = encoded.clone().into_bytes();
+            tampered[i] = if tampered[i] == b'0' { b'1' } else { b'0' };
+            let tampered = String::from_utf8(tampered).unwrap();
+
+            assert_eq!(decode_cursor(&tampered), None, "{tampered}");
+        }
+
+        assert_eq!(decode_cursor(&encoded[..encoded.len() - 2]), None);
+        assert_eq!(decode_cursor("not a cursor"), None);
+        assert_eq!(decode_cursor(""), None);
+    }
+
+    #[test]
+    fn should_pin_envelope_shape() {
+        let params = PaginationParams {
+            after: None,
+            limit: 2,
+        };
+        let cursor = cursor();
+        let page = Paginated::new(vec![1, 2, 3], &params, |_| cursor.clone(), |row| row * 10);
+
+        assert_eq!(
+            serde_json::to_value(&page).unwrap(),
+            serde_json::json!({
+                "data": [10, 20],
+                "page_info": {
+                    "next_cursor": encode_cursor(&cursor),
+                    "has_more": true,
+                },
+            })
+        );
+
+        let page = Paginated::new(vec![1], &params, |_| cursor.clone(), |row| row);
+        assert_eq!(
+            serde_json::to_value(&page).unwrap(),
+            serde_json::json!({
+                "data": [1],
+                "page_info": { "next_cursor": null, "has_more": false },
+            })
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use serde::de::DeserializeOwned;
+
+use super::ErrorResponseBody;
+
+/// Path parameters extractor reporting malformed parameters, e.g. ids which
+/// aren't UUIDs, as a 400 in the error envelope of the API rather than as
+/// plain text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApiPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<ErrorResponseBody>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(params) =
+            Path::<T>::from_request_parts(parts, state)
+                .await
+                .map_err(|rejection| {
+                    let mut body = ErrorResponseBody::new("Invalid path parameter")
+                        .with_code("invalid_path_parameter");
+                    body.error = rejection.body_text();
+                    (rejection.status(), Json(body))
+                })?;
+
+        Ok(Self(params))
+    }
+}
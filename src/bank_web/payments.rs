@@ -1,55 +1,422 @@
+use std::borrow::Borrow;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    Json,
+    async_trait,
+    extract::{rejection::QueryRejection, FromRequestParts, Query, State},
+    http::{request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
 use uuid::Uuid;
 
-use super::{BankWeb, ErrorResponseBody};
+use super::{
+    etag::{self, Precondition},
+    export::{csv_requested, stream_csv},
+    fieldset::{Fieldset, Sparse},
+    json::{invalid_body, ApiJson, ApiVersion, FieldError},
+    location,
+    merchant::MerchantId,
+    pagination::{Paginated, PaginationParams},
+    path::ApiPath,
+    storage_unavailable, BankWeb, ErrorResponseBody, Location, ZeroAmountPolicy,
+};
 use crate::bank::{
     accounts::{AccountService, HoldRef},
-    payment_instruments::Card,
-    payments::{self, Status},
+    currencies::Currency,
+    export,
+    fees::FeeBreakdown,
+    journal,
+    pagination::Cursor,
+    payment_instruments::{self, Card},
+    payments::{
+        self, Amount, CountKind, DeclineReason, DeleteError, Granularity, NewPayment, Payment,
+        PaymentRepoError, ProcessError, Processed, ProcessingMode, SortKey, SortOrder, Source,
+        Status, TransitionError,
+    },
+    settlement,
+    tasks::{self, TaskKind},
 };
-use crate::errors::PaymentError;
+use crate::errors::{ApiError, PaymentError};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct RequestData {
-    pub amount: i32,
+    /// Minor units, or a decimal string of major units, see `Amount`.
+    pub amount: Amount,
     pub card_number: String,
+    /// ISO 4217 code, defaults to `Currency::DEFAULT` when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Merchant supplied JSON object, e.g. their own order id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Merchant's id of their customer, by which payments can be listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_reference: Option<String>,
+    /// Channel the payment is made through, `api` when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 
+/// Maximum size of the serialized payment metadata, in bytes.
+pub const MAX_METADATA_SIZE: usize = 4 * 1024;
+
+/// Maximum length of a customer reference, in characters.
+pub const MAX_CUSTOMER_REFERENCE_LENGTH: usize = 64;
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct RequestBody {
     pub payment: RequestData,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AmendRequestData {
+    /// New amount, lower than the authorized one, see `Amount`.
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AmendRequestBody {
+    pub payment: AmendRequestData,
+}
+
+/// Largest number of payments moved at once by `bulk_transition`.
+pub const MAX_BULK_TRANSITIONS: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkTransitionRequestBody {
+    pub payment_ids: Vec<Uuid>,
+    /// Status the payments must still be in to be moved.
+    pub from: Status,
+    pub to: Status,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BulkTransitionData {
+    /// Payments moved to the requested status.
+    pub transitioned: Vec<Uuid>,
+    /// Payments left as they are, as they weren't in the `from` status or
+    /// don't exist, in the order they were requested.
+    pub skipped: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BulkTransitionResponseBody {
+    pub data: BulkTransitionData,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ResponseData {
     pub id: Uuid,
-    pub amount: i32,
+    pub merchant_id: Uuid,
+    pub amount: i64,
     pub card_number: String,
+    pub currency: String,
     pub status: payments::Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Total amount of the refunds of the payment, only returned by GET.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refunded_amount: Option<i64>,
+    /// Amount that can still be refunded, only returned by GET.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refundable_amount: Option<i64>,
+    /// When the payment was archived, archived payments being hidden from
+    /// listings.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub archived_at: Option<OffsetDateTime>,
+    /// Incremented by every change of the payment, and sent back quoted in
+    /// the `If-Match` header of mutations, e.g. `If-Match: "3"`.
+    pub version: i32,
+    /// Why the payment was declined or failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decline_reason: Option<DeclineReason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_reference: Option<String>,
+    pub source: Source,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+impl Sparse for ResponseData {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "merchant_id",
+        "amount",
+        "card_number",
+        "currency",
+        "status",
+        "metadata",
+        "refunded_amount",
+        "refundable_amount",
+        "archived_at",
+        "version",
+        "decline_reason",
+        "customer_reference",
+        "source",
+        "inserted_at",
+        "updated_at",
+    ];
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ResponseBody {
     pub data: ResponseData,
 }
-impl ResponseBody {
-    pub fn new(id: Uuid, amount: i32, card_number: String, status: Status) -> Self {
+
+/// Payment as returned to operators, with the latency of the account service
+/// calls made to settle it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AdminResponseData {
+    #[serde(flatten)]
+    pub payment: ResponseData,
+    /// Milliseconds the hold took, unless the payment was settled before.
+    pub hold_latency_ms: Option<i32>,
+    /// Milliseconds the withdrawal took, unless no withdrawal was attempted.
+    pub withdraw_latency_ms: Option<i32>,
+    /// Fees withheld from an approved payment, computed under the policy of
+    /// its merchant when its funds were withdrawn.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fees: Option<FeeBreakdown>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AdminResponseBody {
+    pub data: AdminResponseData,
+}
+
+impl From<payments::Payment> for ResponseBody {
+    fn from(payment: payments::Payment) -> Self {
         ResponseBody {
-            data: ResponseData {
-                id,
-                amount,
-                card_number,
-                status,
-            },
+            data: payment.into(),
+        }
+    }
+}
+
+impl From<payments::Payment> for ResponseData {
+    fn from(payment: payments::Payment) -> Self {
+        ResponseData {
+            id: payment.id,
+            merchant_id: payment.merchant_id,
+            amount: payment.amount,
+            card_number: payment.card_number,
+            currency: payment.currency,
+            status: payment.status,
+            metadata: payment.metadata,
+            refunded_amount: None,
+            refundable_amount: None,
+            archived_at: payment.archived_at.map(PrimitiveDateTime::assume_utc),
+            version: payment.version,
+            decline_reason: payment.decline_reason,
+            customer_reference: payment.customer_reference,
+            source: payment.source,
+            inserted_at: payment.inserted_at.assume_utc(),
+            updated_at: payment.updated_at.assume_utc(),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ListParams {
+    pub metadata_key: Option<String>,
+    pub metadata_value: Option<String>,
+    pub customer_reference: Option<String>,
+    pub source: Option<String>,
+    /// `json` or `csv`, overriding the `Accept` header.
+    pub format: Option<String>,
+    /// Also lists archived payments.
+    #[serde(default)]
+    pub include_archived: bool,
+    /// `inserted_at`, the default, `amount` or `status`.
+    pub sort: Option<String>,
+    /// `asc` or `desc`, the default.
+    pub order: Option<String>,
+    /// Only returns `{"count": N}`, the number of matching payments, ignoring
+    /// the sort and pagination.
+    #[serde(default)]
+    pub count_only: bool,
+    /// Counts of a merchant's payments are always exact, as estimates only
+    /// exist for the whole table, so `false` is answered with a 400.
+    pub exact: Option<bool>,
+}
+
+/// Query parameters of the counts of payments.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CountParams {
+    /// Counts exactly, the default, or estimates the count when `false`.
+    ///
+    /// Only counts of every payment can be estimated, the counts of a
+    /// merchant's payments remain exact.
+    pub exact: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CountResponseBody {
+    pub count: i64,
+    /// Whether `count` is exact or estimated.
+    pub count_kind: CountKind,
+}
+
+/// The body of a `post` dry run which passed validation.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DryRunResponseBody {
+    pub data: DryRunData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DryRunData {
+    pub valid: bool,
+}
+
+/// Extracts the repeated `status` query parameters of `list`, e.g.
+/// `?status=processing&status=failed`, which `ListParams` can't hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusFilter(pub Vec<Status>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for StatusFilter {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let invalid_status = || {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "status should be processing, approved, declined, failed, authorized or voided",
+            )
+            .with_code("invalid_status")
+        };
+
+        let Query(params) = Query::<Vec<(String, String)>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| invalid_status())?;
+
+        params
+            .into_iter()
+            .filter(|(name, _)| name == "status")
+            .map(|(_, value)| serde_json::from_value(serde_json::Value::String(value)))
+            .collect::<Result<_, _>>()
+            .map(Self)
+            .map_err(|_| invalid_status())
+    }
+}
+
+impl ListParams {
+    /// Returns the requested sort, or `None` if it isn't supported.
+    fn sort(&self) -> Option<payments::Sort> {
+        let key = match self.sort.as_deref() {
+            Some("inserted_at") | None => SortKey::InsertedAt,
+            Some("amount") => SortKey::Amount,
+            Some("status") => SortKey::Status,
+            Some(_) => return None,
+        };
+        let order = match self.order.as_deref() {
+            Some("asc") => SortOrder::Asc,
+            Some("desc") | None => SortOrder::Desc,
+            Some(_) => return None,
+        };
+
+        Some(payments::Sort { key, order })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchParams {
+    /// Last four digits of the card number.
+    pub card_last4: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchResponseBody {
+    /// Matching payments, whose card numbers are masked.
+    pub data: Vec<ResponseData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EventData {
+    pub from_status: payments::Status,
+    pub to_status: payments::Status,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StatsParams {
+    #[serde(with = "time::serde::rfc3339")]
+    pub from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub to: OffsetDateTime,
+    #[serde(default)]
+    pub group_by: StatsGroupBy,
+}
+
+/// How `stats` groups payments, besides by status.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsGroupBy {
+    Hour,
+    #[default]
+    Day,
+    Source,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StatusStats {
+    pub status: payments::Status,
+    pub count: i64,
+    pub total_amount: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BucketStats {
+    /// Start of the time bucket, unless grouped by source.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub bucket: Option<OffsetDateTime>,
+    /// Source of the payments, when grouped by source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    /// Currency of the amounts, which are never summed across currencies.
+    pub currency: String,
+    pub statuses: Vec<StatusStats>,
+}
+
+/// Median and 95th percentile of a latency, in milliseconds, `None` when no
+/// call was measured.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LatencyStats {
+    pub p50: Option<i32>,
+    pub p95: Option<i32>,
+}
+
+/// Latencies of the account service calls over the whole range of `stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AccountLatencyStats {
+    pub hold_ms: LatencyStats,
+    pub withdraw_ms: LatencyStats,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StatsResponseBody {
+    pub data: Vec<BucketStats>,
+    pub latency: AccountLatencyStats,
+}
+
 macro_rules! unwrap_or_return {
     ( $res:expr, $err:expr ) => {
         match $res {
@@ -59,343 +426,4510 @@ macro_rules! unwrap_or_return {
     };
 }
 
-macro_rules! check_and_reverse_payment_status {
-    ($bank_web:ident, $payment_result:ident, $payment_id:ident, $card_number:ident, $amount:ident ) => {
-        if let Err(err_str) = $payment_result {
-            let payment_err = PaymentError::from(&err_str);
-            // update payment status to Declined or Failed, according to the payment_err type
-            payments::update(
-                &$bank_web.pool,
-                $payment_id,
-                payment_err.get_payment_status(),
+fn payment_location(payment_id: Uuid) -> Location {
+    location(format!("/api/payments/{payment_id}"))
+}
+
+/// Returns the payment `payment_id` of `merchant`.
+///
+/// Payments of other merchants are reported as missing with a 404, so their
+/// existence isn't leaked.
+pub async fn find_payment(
+    pool: &PgPool,
+    merchant: MerchantId,
+    payment_id: Uuid,
+) -> Result<Payment, (StatusCode, Json<ErrorResponseBody>)> {
+    found(merchant, payment_id, payments::get(pool, payment_id).await)
+}
+
+/// Returns a looked up payment, reporting payments of other merchants as
+/// missing.
+pub fn found<P: Borrow<Payment>>(
+    merchant: MerchantId,
+    payment_id: Uuid,
+    result: Result<P, PaymentRepoError>,
+) -> Result<P, (StatusCode, Json<ErrorResponseBody>)> {
+    match result {
+        Ok(payment) if payment.borrow().merchant_id == merchant.0 => Ok(payment),
+        Ok(_) | Err(PaymentRepoError::NotFound) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponseBody::new("payment doesn't exist").with_code("not_found")),
+        )),
+        Err(e) => {
+            tracing::error!("failed to get payment {payment_id}: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponseBody::new("can't get payment")),
+            ))
+        }
+    }
+}
+
+/// Reports a failed transition of a payment, illegal or stale transitions as
+/// a 409.
+fn transition_error(payment_id: Uuid, error: TransitionError) -> ApiError {
+    match error {
+        TransitionError::Illegal { from, to } | TransitionError::Unrepairable { from, to } => {
+            tracing::error!("illegal transition of payment {payment_id} from {from:?} to {to:?}");
+            ApiError::new(StatusCode::CONFLICT, "illegal payment status transition")
+        }
+        TransitionError::Repo(PaymentRepoError::Conflict { current_status }) => {
+            ApiError::new(StatusCode::CONFLICT, "payment status changed concurrently")
+                .with_details(serde_json::json!({ "status": current_status }))
+        }
+        TransitionError::Repo(PaymentRepoError::NotFound) => {
+            ApiError::new(StatusCode::NOT_FOUND, "payment doesn't exist").with_code("not_found")
+        }
+        TransitionError::Repo(e) => {
+            tracing::error!("failed to transition payment {payment_id}: {e}");
+            ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "can't update payment status",
             )
-            .await
-            .unwrap();
-            return Ok((
-                payment_err.get_http_status_code(),
-                Json(ResponseBody::new(
-                    Uuid::new_v4(),
-                    $amount,
-                    $card_number,
-                    payment_err.get_payment_status(),
-                )),
-            ));
         }
-    };
+    }
 }
 
+/// Query parameters of `post`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PostParams {
+    /// Whether the payment is answered with a 202 right away, and settled in
+    /// the background.
+    #[serde(default, rename = "async")]
+    pub is_async: bool,
+    /// Whether the request is only validated, without recording a payment or
+    /// placing a hold.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+// the body holds the card number, which must never be recorded
+#[allow(clippy::type_complexity)]
+#[tracing::instrument(
+    skip_all,
+    fields(payment.id, payment.amount, payment.status, account_service.error)
+)]
 pub async fn post<T: AccountService>(
     State(bank_web): State<BankWeb<T>>,
-    Json(body): Json<RequestBody>,
-) -> Result<(StatusCode, Json<ResponseBody>), (StatusCode, Json<ErrorResponseBody>)> {
-    let amount = body.payment.amount;
-    let card_number = body.payment.card_number.to_string();
+    MerchantId(merchant_id): MerchantId,
+    Query(params): Query<PostParams>,
+    ApiJson(body): ApiJson<RequestBody>,
+) -> Result<Response, ApiError> {
+    let amount = i64::from(body.payment.amount);
+    tracing::Span::current().record("payment.amount", amount);
 
-    // payment requests for 0 should return a 204 response
+    // payment requests for 0 return an empty 204, which must not have a body,
+    // unless they are rejected like negative amounts
     if amount == 0 {
-        return Err((
-            StatusCode::NO_CONTENT,
-            Json(ErrorResponseBody::new("Amount shouldn't be 0")),
-        ));
+        return match bank_web.zero_amount_policy {
+            ZeroAmountPolicy::NoContent => Ok(StatusCode::NO_CONTENT.into_response()),
+            ZeroAmountPolicy::Reject => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Amount shouldn't be 0",
+            )
+            .with_code("zero_amount")),
+        };
     }
 
     // payment requests for negative amounts should return a 400 response
     if amount < 0 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponseBody::new("Amount shouldn't be negative")),
-        ));
+        return Err(
+            ApiError::new(StatusCode::BAD_REQUEST, "Amount shouldn't be negative")
+                .with_code("negative_amount"),
+        );
+    }
+
+    // amounts are summed by refunds and stats, which must not overflow
+    if amount > bank_web.max_amount {
+        return Err(
+            ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "amount exceeds maximum")
+                .with_code("amount_too_large")
+                .with_details(serde_json::json!({ "max_amount": bank_web.max_amount })),
+        );
     }
 
     // invalid card formats should return a 422 response
-    let card = match Card::try_from(card_number.clone()) {
+    let card = match Card::try_from(body.payment.card_number) {
         Ok(c) => c,
         Err(_e) => {
-            return Err((
-                StatusCode::UNPROCESSABLE_ENTITY,
-                Json(ErrorResponseBody::new("Bad Card Number format")),
-            ))
+            return Err(
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "Bad Card Number format")
+                    .with_code("invalid_card_number"),
+            )
         }
     };
 
-    // insert Processing Payment
-    let payment_id = unwrap_or_return!(
-        payments::insert(
-            &bank_web.pool,
-            body.payment.amount,
-            body.payment.card_number,
-            payments::Status::Processing
-        )
-        .await,
-        Err((
-            StatusCode::UNPROCESSABLE_ENTITY,
-            Json(ErrorResponseBody::new("card_number already used")),
-        ))
-    );
-    // place hold
-    let payment_result = bank_web
-        .account_service
-        .place_hold(card.account_number(), body.payment.amount)
-        .await;
+    // unknown currencies should return a 422 response
+    let currency = match body.payment.currency {
+        Some(code) => match Currency::try_from(code) {
+            Ok(c) => c,
+            Err(_e) => {
+                return Err(
+                    ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "Unknown currency")
+                        .with_code("invalid_currency"),
+                )
+            }
+        },
+        None => Currency::default(),
+    };
+    let currency = String::from(currency);
 
-    // deal with payment_result
-    check_and_reverse_payment_status!(bank_web, payment_result, payment_id, card_number, amount);
+    // metadata must be a reasonably sized JSON object
+    let metadata = body.payment.metadata;
+    if let Some(metadata) = &metadata {
+        if !metadata.is_object() {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "metadata must be a JSON object",
+            ));
+        }
 
-    payments::update(&bank_web.pool, payment_id, payments::Status::Approved)
-        .await
-        .unwrap();
-    let payment_result = bank_web
-        .account_service
-        .withdraw_funds(payment_result.unwrap())
-        .await;
+        if metadata.to_string().len() > MAX_METADATA_SIZE {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "metadata is too large",
+            ));
+        }
+    }
 
-    // deal with payment_result
-    check_and_reverse_payment_status!(bank_web, payment_result, payment_id, card_number, amount);
+    // empty customer references are stored as missing
+    let customer_reference = body
+        .payment
+        .customer_reference
+        .filter(|reference| !reference.is_empty());
+    if let Some(reference) = &customer_reference {
+        if reference.chars().count() > MAX_CUSTOMER_REFERENCE_LENGTH {
+            return Err(invalid_body(
+                "Invalid request body",
+                "invalid_body",
+                vec![FieldError::new(
+                    "payment.customer_reference",
+                    format!(
+                        "customer_reference should be at most {MAX_CUSTOMER_REFERENCE_LENGTH} characters"
+                    ),
+                )],
+            )
+            .into());
+        }
+    }
 
-    Ok((
-        StatusCode::CREATED,
-        Json(ResponseBody::new(
-            payment_id,
-            amount,
-            card_number,
-            payments::Status::Approved,
-        )),
-    ))
-}
+    let source = body
+        .payment
+        .source
+        .map(parse_source)
+        .transpose()?
+        .unwrap_or_default();
 
-pub async fn get<T: AccountService>(
-    State(bank_web): State<BankWeb<T>>,
-    Path(payment_id): Path<Uuid>,
-) -> Result<(StatusCode, Json<ResponseBody>), (StatusCode, Json<ErrorResponseBody>)> {
-    let payment = payments::get(&bank_web.pool, payment_id).await.unwrap();
+    let processing_mode = if params.is_async {
+        ProcessingMode::Async
+    } else {
+        ProcessingMode::Sync
+    };
 
-    Ok((
-        StatusCode::OK,
-        Json(ResponseBody {
-            data: ResponseData {
-                id: payment.id,
-                amount: payment.amount,
-                card_number: payment.card_number,
-                status: payment.status,
-            },
-        }),
-    ))
-}
+    if params.dry_run {
+        return dry_run(&bank_web, merchant_id, &card).await;
+    }
 
-#[cfg(test)]
-pub mod tests {
+    let notify = |payment_id, status| bank_web.webhooks.notify(payment_id, status);
+    let new_payment = NewPayment {
+        merchant_id,
+        amount,
+        card: &card,
+        currency,
+        processing_mode,
+        metadata,
+        customer_reference: customer_reference.as_deref(),
+        source,
+    };
+    let processed = payments::process_payment(
+        &bank_web.pool,
+        &bank_web.account_service,
+        &notify,
+        new_payment,
+        bank_web.card_reuse_window,
+        &bank_web.fee_policy,
+    )
+    .await;
+    let (payment_id, settlement) = match processed {
+        Ok(Processed { id, settlement }) => (id, settlement),
+        Err(ProcessError::Insert(PaymentRepoError::DuplicateCard)) => {
+            return Err(card_used(&bank_web.pool, merchant_id, &card).await)
+        }
+        // the handler's validation should have refused the payment already
+        Err(ProcessError::Insert(PaymentRepoError::Invalid { constraint })) => {
+            tracing::error!("payment breaks constraint {constraint}");
+            return Err(
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "payment is invalid")
+                    .with_code("invalid_payment")
+                    .with_details(serde_json::json!({ "constraint": constraint })),
+            );
+        }
+        // the payment wasn't recorded, so the client can safely retry
+        Err(ProcessError::Insert(e)) => {
+            tracing::error!("failed to insert payment: {e}");
+            return Err(storage_unavailable().into());
+        }
+        Err(ProcessError::Settle { payment_id, error }) => {
+            return Err(transition_error(payment_id, error))
+        }
+    };
 
-    use super::*;
-    use crate::bank::accounts::{AccountService, DummyService, HoldRef};
-    use crate::{
-        bank::{payment_instruments::Card, payments::Status},
-        bank_web::tests::{deserialize_response_body, get, post},
+    // the settlement worker makes the account service calls of async payments
+    let Some(settlement) = settlement else {
+        let payment = reload_payment(&bank_web.pool, payment_id).await?;
+        record_payment(&payment);
+        return Ok((
+            StatusCode::ACCEPTED,
+            payment_location(payment_id),
+            Json(ResponseBody::from(payment)),
+        )
+            .into_response());
     };
-    use std::sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+
+    let status_code = match &settlement.error {
+        Some(error) => {
+            tracing::Span::current().record("account_service.error", error.as_str());
+            PaymentError::from(error).get_http_status_code()
+        }
+        None => StatusCode::CREATED,
     };
 
-    #[derive(Clone, Default)]
-    struct MockService {
-        dummy: DummyService,
-        place_hold_count: Arc<AtomicUsize>,
-        release_hold_count: Arc<AtomicUsize>,
-        withdraw_funds_count: Arc<AtomicUsize>,
+    let payment = reload_payment(&bank_web.pool, payment_id).await?;
+    record_payment(&payment);
+    let response = (
+        status_code,
+        payment_location(payment_id),
+        Json(ResponseBody::from(payment)),
+    )
+        .into_response();
+
+    // declined payments are answered with their body, unless problem details
+    // are accepted
+    match &settlement.error {
+        Some(error) => Err(ApiError::payment_declined(error, response)),
+        None => Ok(response),
     }
+}
 
-    #[async_trait::async_trait]
-    impl AccountService for MockService {
-        async fn place_hold(&self, account_number: &str, amount: i32) -> Result<HoldRef, String> {
-            self.place_hold_count.fetch_add(1, Ordering::SeqCst);
-            self.dummy.place_hold(account_number, amount).await
-        }
+/// Reports a card used within the reuse window, naming the payment made with
+/// it when it is a payment of the same merchant, so merchants can find the
+/// original of a duplicate.
+async fn card_used(pool: &PgPool, merchant_id: Uuid, card: &Card) -> ApiError {
+    let error = ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "card_number already used");
 
-        async fn release_hold(&self, hold_ref: HoldRef) -> Result<(), String> {
-            self.release_hold_count.fetch_add(1, Ordering::SeqCst);
-            self.dummy.release_hold(hold_ref).await
+    match payments::get_by_card_fingerprint(pool, &card.fingerprint()).await {
+        Ok(Some(payment)) if payment.merchant_id == merchant_id => {
+            error.with_details(serde_json::json!({
+                "payment_id": payment.id,
+                "status": payment.status,
+            }))
         }
-
-        async fn withdraw_funds(&self, hold_ref: HoldRef) -> Result<(), String> {
-            self.withdraw_funds_count.fetch_add(1, Ordering::SeqCst);
-            self.dummy.withdraw_funds(hold_ref).await
+        Ok(_) => error,
+        Err(e) => {
+            tracing::error!("failed to get payment by card: {e}");
+            error
         }
     }
+}
 
-    #[tokio::test]
-    async fn should_not_place_hold_for_payment_with_negative_amount() {
-        let pool = crate::pg_pool().await.unwrap();
-        let mock_service = MockService::default();
-        let router = BankWeb::new(pool, mock_service.clone()).into_router();
-
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: -1,
-                card_number: Card::new_test().into(),
-            },
-        };
+/// Finishes validating a `post` dry run, answering with the error the real
+/// call would produce, if any.
+async fn dry_run<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    merchant_id: Uuid,
+    card: &Card,
+) -> Result<Response, ApiError> {
+    match payments::card_used(&bank_web.pool, card, bank_web.card_reuse_window).await {
+        Ok(false) => {}
+        Ok(true) => return Err(card_used(&bank_web.pool, merchant_id, card).await),
+        Err(e) => {
+            tracing::error!("failed to check card reuse: {e}");
+            return Err(storage_unavailable().into());
+        }
+    }
 
-        let response = post(&router, "/api/payments", &request_body).await;
-        assert_eq!(response.status(), 400);
-        assert_eq!(
-            mock_service.place_hold_count.load(Ordering::SeqCst),
-            0,
-            "should not try to place hold for amount -1"
-        );
+    if let Err(error) = bank_web
+        .account_service
+        .validate_account(card.account_number())
+        .await
+    {
+        tracing::Span::current().record("account_service.error", error.as_str());
+        let payment_error = PaymentError::from(&error);
+        return Err(ApiError::new(
+            payment_error.get_http_status_code(),
+            "payment would be declined",
+        )
+        .with_code("payment_declined")
+        .with_details(serde_json::json!({ "decline_reason": payment_error.reason })));
     }
 
-    #[tokio::test]
-    async fn should_withdraw_funds_on_successful_payment() {
-        let pool = crate::pg_pool().await.unwrap();
-        let mock_service = MockService::default();
-        let router = BankWeb::new(pool, mock_service.clone()).into_router();
+    Ok(Json(DryRunResponseBody {
+        data: DryRunData { valid: true },
+    })
+    .into_response())
+}
 
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: 123,
-                card_number: Card::new_test().into(),
-            },
-        };
+/// Parses the source of a payment, unknown sources being answered with a 422
+/// listing the known ones.
+fn parse_source(source: String) -> Result<Source, ApiError> {
+    serde_json::from_value(serde_json::Value::String(source)).map_err(|_| {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "Unknown source")
+            .with_code("invalid_source")
+            .with_details(serde_json::json!({ "allowed": Source::ALL }))
+    })
+}
 
-        let response = post(&router, "/api/payments", &request_body).await;
-        assert_eq!(response.status(), 201);
-        assert_eq!(mock_service.withdraw_funds_count.load(Ordering::SeqCst), 1);
-    }
+/// Records the payment a request is about on its span.
+fn record_payment(payment: &Payment) {
+    let span = tracing::Span::current();
+    span.record("payment.id", tracing::field::display(payment.id));
+    span.record("payment.amount", payment.amount);
+    span.record("payment.status", tracing::field::debug(payment.status));
+}
 
-    async fn make_payment(router: axum::Router, card: Card) -> hyper::StatusCode {
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: 123,
-                card_number: card.into(),
-            },
-        };
-        let response = post(&router, "/api/payments", &request_body).await;
-        response.status()
-    }
+/// Reads back a payment just written by `post`, so the response holds the
+/// persisted row.
+async fn reload_payment(pool: &PgPool, payment_id: Uuid) -> Result<payments::Payment, ApiError> {
+    payments::get(pool, payment_id).await.map_err(|e| {
+        tracing::error!("failed to read back payment {payment_id}: {e}");
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't get payment")
+    })
+}
 
-    #[tokio::test]
-    async fn should_not_place_holds_for_concurrent_payments() {
-        let pool = crate::pg_pool().await.unwrap();
-        let mock_service = MockService::default();
-        let router = BankWeb::new(pool, mock_service.clone()).into_router();
+/// Returns a payment, or a 304 if the `If-None-Match` header holds its
+/// current ETag.
+#[tracing::instrument(skip_all, fields(payment.id = %payment_id, payment.amount, payment.status))]
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    headers: HeaderMap,
+    fieldset: Fieldset<ResponseData>,
+) -> Result<Response, ApiError> {
+    let payment = found(
+        merchant,
+        payment_id,
+        payments::get_with_refund_totals(&bank_web.pool, payment_id).await,
+    )?;
+    let refunded_amount = payment.refunded_amount;
+    let refundable_amount = payment.refundable_amount();
+    let payment = payment.payment;
+    record_payment(&payment);
 
-        let card = Card::new_test();
+    // each fieldset is a different representation of the payment
+    // refunds don't update the payment, but change its refunded amount
+    let etag = etag::entity_tag(&format!(
+        "{}:{:?}:{}:{refunded_amount}:{fieldset}",
+        payment.id,
+        payment.status,
+        payment.updated_at.assume_utc().unix_timestamp_nanos()
+    ));
 
-        let fut_a = make_payment(router.clone(), card.clone());
-        let fut_b = make_payment(router, card.clone());
-        let (status_a, status_b) = tokio::join!(fut_a, fut_b);
+    Ok(etag::conditional(
+        &headers,
+        etag,
+        Json(serde_json::json!({
+            "data": fieldset.project(&ResponseData {
+                refunded_amount: Some(refunded_amount),
+                refundable_amount: Some(refundable_amount),
+                ..ResponseData::from(payment)
+            }),
+        })),
+    ))
+}
 
-        assert_eq!(status_a.min(status_b), 201, "one payment should succeed");
-        assert_eq!(status_a.max(status_b), 422, "one payment should fail");
+fn version_mismatch() -> ApiError {
+    ApiError::new(
+        StatusCode::PRECONDITION_FAILED,
+        "payment was changed since it was read",
+    )
+    .with_code("version_mismatch")
+}
 
-        assert_eq!(
-            mock_service.place_hold_count.load(Ordering::SeqCst),
-            1,
-            "should not try to place hold for concurrent requests"
-        );
+/// Checks the `If-Match` header of a mutation of `payment`, returning the
+/// version the mutation is conditioned on, if any.
+fn check_if_match(
+    api_version: ApiVersion,
+    headers: &HeaderMap,
+    payment: &Payment,
+) -> Result<Option<i32>, ApiError> {
+    match etag::if_match(headers, payment.version) {
+        Precondition::Met => Ok(Some(payment.version)),
+        Precondition::Failed => Err(version_mismatch()),
+        Precondition::Absent if api_version.requires_if_match() => Err(ApiError::new(
+            StatusCode::PRECONDITION_REQUIRED,
+            "If-Match header is required",
+        )
+        .with_code("if_match_required")),
+        Precondition::Absent => Ok(None),
+    }
+}
+
+/// Archives a payment, hiding it from listings without deleting it.
+///
+/// Pending payments can't be archived until their outcome is known. The
+/// archive is conditioned on the payment's version when `If-Match` is sent,
+/// which `/api/v1` requires.
+pub async fn archive<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    Extension(api_version): Extension<ApiVersion>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<ResponseBody>, ApiError> {
+    let payment = find_payment(&bank_web.pool, merchant, payment_id).await?;
+    let expected_version = check_if_match(api_version, &headers, &payment)?;
+
+    let pending = || {
+        ApiError::new(StatusCode::CONFLICT, "pending payments can't be archived")
+            .with_code("payment_pending")
+    };
+    if payment.status.is_pending() {
+        return Err(pending());
+    }
+
+    match payments::archive(&bank_web.pool, payment_id, expected_version).await {
+        Ok(Some(payment)) => Ok(Json(payment.into())),
+        // payments never become pending again, so the payment was changed
+        // since it was read
+        Ok(None) if expected_version.is_some() => Err(version_mismatch()),
+        Ok(None) => Err(pending()),
+        Err(e) => {
+            tracing::error!("failed to archive payment {payment_id}: {e}");
+            Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "can't archive payment",
+            ))
+        }
+    }
+}
+
+/// Counts the payments of every merchant, archived ones included, estimating
+/// the count unless `exact` is requested, as exact counts scan the table.
+pub async fn admin_count<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    params: Result<Query<CountParams>, QueryRejection>,
+) -> Result<Json<CountResponseBody>, ApiError> {
+    let Query(params) = unwrap_or_return!(
+        params,
+        Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "exact should be true or false"
+        ))
+    );
+
+    let filter = payments::ListFilter {
+        include_archived: true,
+        ..payments::ListFilter::default()
+    };
+    let exact = params.exact.unwrap_or(true);
+    match payments::count_with_kind(&bank_web.pool, &filter, exact).await {
+        Ok((count, count_kind)) => Ok(Json(CountResponseBody { count, count_kind })),
+        Err(e) => {
+            tracing::error!("failed to count payments: {e}");
+            Err(storage_unavailable().into())
+        }
+    }
+}
+
+/// Returns a payment of any merchant, with the latency of the account service
+/// calls made to settle it and the fees withheld from it.
+pub async fn admin_get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    ApiPath(payment_id): ApiPath<Uuid>,
+) -> Result<Json<AdminResponseBody>, ApiError> {
+    let repo_error = |e| match e {
+        PaymentRepoError::NotFound => {
+            ApiError::new(StatusCode::NOT_FOUND, "payment doesn't exist").with_code("not_found")
+        }
+        e => {
+            tracing::error!("failed to get payment {payment_id}: {e}");
+            storage_unavailable().into()
+        }
+    };
+
+    let payment = payments::get(&bank_web.pool, payment_id)
+        .await
+        .map_err(repo_error)?;
+    let latency = payments::get_latency(&bank_web.pool, payment_id)
+        .await
+        .map_err(repo_error)?;
+
+    // only approved payments are charged, the fees being recorded when
+    // their funds were withdrawn
+    let fees = if payment.status == Status::Approved {
+        payments::get_fees(&bank_web.pool, payment_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("failed to get fees of payment {payment_id}: {e}");
+                ApiError::from(storage_unavailable())
+            })?
+    } else {
+        None
+    };
+
+    Ok(Json(AdminResponseBody {
+        data: AdminResponseData {
+            payment: payment.into(),
+            hold_latency_ms: latency.hold_ms,
+            withdraw_latency_ms: latency.withdraw_ms,
+            fees,
+        },
+    }))
+}
+
+/// Deletes a payment of any merchant for good, to clean up test data.
+///
+/// Refunded payments are kept, as the money they moved must stay accounted
+/// for.
+pub async fn delete<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    ApiPath(payment_id): ApiPath<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    match payments::delete(&bank_web.pool, payment_id).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(DeleteError::NotFound) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "payment doesn't exist",
+        )
+        .with_code("not_found")),
+        Err(DeleteError::HasRefunds { count }) => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "refunded payments can't be deleted",
+        )
+        .with_code("payment_has_refunds")
+        .with_details(serde_json::json!({ "refund_count": count }))),
+        Err(DeleteError::Database(e)) => {
+            tracing::error!("failed to delete payment {payment_id}: {e}");
+            Err(storage_unavailable().into())
+        }
+    }
+}
+
+/// Declines or fails processing payments of any merchant, e.g. the payments
+/// left processing by a bank outage, without running SQL.
+///
+/// Payments no longer in the `from` status are skipped rather than failing
+/// the request. Webhooks are notified of every payment moved.
+#[tracing::instrument(skip_all, fields(payment.status = ?body.to))]
+pub async fn bulk_transition<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    ApiJson(body): ApiJson<BulkTransitionRequestBody>,
+) -> Result<Json<BulkTransitionResponseBody>, ApiError> {
+    if body.payment_ids.len() > MAX_BULK_TRANSITIONS {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "too many payments requested at once",
+        )
+        .with_code("too_many_payments")
+        .with_details(serde_json::json!({ "max": MAX_BULK_TRANSITIONS })));
+    }
+
+    let transitioned = match payments::bulk_transition(
+        &bank_web.pool,
+        &body.payment_ids,
+        body.from,
+        body.to,
+    )
+    .await
+    {
+        Ok(transitioned) => transitioned,
+        Err(TransitionError::Illegal { from, to }) => {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "illegal payment status transition",
+            )
+            .with_code("illegal_transition")
+            .with_details(serde_json::json!({ "from": from, "to": to })))
+        }
+        Err(TransitionError::Unrepairable { from, to }) => {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "only processing payments can be declined or failed in bulk",
+            )
+            .with_code("unsupported_transition")
+            .with_details(serde_json::json!({ "from": from, "to": to })))
+        }
+        Err(e) => {
+            tracing::error!("failed to transition payments: {e}");
+            return Err(storage_unavailable().into());
+        }
+    };
+
+    for &payment_id in &transitioned {
+        bank_web.webhooks.notify(payment_id, body.to);
+    }
+    let skipped = body
+        .payment_ids
+        .into_iter()
+        .filter(|payment_id| !transitioned.contains(payment_id))
+        .collect();
+
+    Ok(Json(BulkTransitionResponseBody {
+        data: BulkTransitionData {
+            transitioned,
+            skipped,
+        },
+    }))
+}
+
+/// Lowers the amount of an authorized payment, e.g. once an item went out of
+/// stock, replacing its hold by one of the new amount.
+///
+/// The hold is released before the new one is placed. If the new hold can't
+/// be placed, or the amended payment can't be stored, a hold of the original
+/// amount is placed again and recorded, and the payment is left unchanged.
+/// Like `archive`, the amendment is conditioned on the payment's version when
+/// `If-Match` is sent, which `/api/v1` requires.
+///
+/// The hold is claimed for the whole amendment, like settlements claim it to
+/// withdraw it, see `settlement::withdraw`, so a payment being settled can't
+/// be amended, and its settlement withdraws the amended hold.
+#[allow(clippy::type_complexity)]
+#[tracing::instrument(
+    skip_all,
+    fields(payment.id = %payment_id, payment.amount, payment.status, account_service.error)
+)]
+pub async fn amend<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    Extension(api_version): Extension<ApiVersion>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    headers: HeaderMap,
+    ApiJson(body): ApiJson<AmendRequestBody>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    let payment = find_payment(&bank_web.pool, merchant, payment_id).await?;
+    record_payment(&payment);
+    check_if_match(api_version, &headers, &payment)?;
+
+    if payment.status != Status::Authorized {
+        return Err(not_amendable(
+            "only authorized payments can be amended",
+            payment.status,
+        ));
+    }
+
+    let amount = i64::from(body.payment.amount);
+    if amount <= 0 || amount >= payment.amount {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "amount should be positive and lower than the authorized one",
+        )
+        .with_code("amount_not_reduced")
+        .with_details(serde_json::json!({ "authorized_amount": payment.amount })));
+    }
+
+    // settlements withdraw the hold under the same claim, so the hold
+    // replaced here is never the one being withdrawn
+    let kind = TaskKind::hold();
+    let claimant = Uuid::new_v4();
+    match tasks::claim(
+        &bank_web.pool,
+        &kind,
+        payment_id,
+        claimant,
+        settlement::HOLD_CLAIM_TTL,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => return Err(not_amendable("payment is being settled", payment.status)),
+        Err(e) => {
+            tracing::error!("failed to claim hold of payment {payment_id}: {e}");
+            return Err(storage_unavailable().into());
+        }
+    }
+
+    let amended = amend_held(&bank_web, &payment, amount).await;
+    if let Err(e) = tasks::release(&bank_web.pool, &kind, payment_id, claimant).await {
+        tracing::error!("failed to release hold claim of payment {payment_id}: {e}");
+    }
+    amended.map(|payment| Json(payment.into()))
+}
+
+/// Amends `payment` to `amount`, as described by `amend`, once its hold is
+/// claimed.
+async fn amend_held<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    payment: &Payment,
+    amount: i64,
+) -> Result<Payment, ApiError> {
+    let payment_id = payment.id;
+    let cant_amend = || ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't amend payment");
+    // the hold is read again under the claim, as it was maybe withdrawn
+    // since the payment was read
+    let held = match payments::get_held_funds(&bank_web.pool, payment_id).await {
+        Ok(held) => held,
+        Err(e) => {
+            tracing::error!("failed to read hold of payment {payment_id}: {e}");
+            return Err(storage_unavailable().into());
+        }
+    };
+    if held.status != Status::Authorized || held.settlement_outcome.is_some() {
+        return Err(not_amendable("payment is being settled", held.status));
+    }
+    if held.version != payment.version {
+        return Err(version_mismatch());
+    }
+    let Some(hold_ref) = held.hold_ref.map(HoldRef::from_id) else {
+        tracing::error!("authorized payment {payment_id} has no recorded hold");
+        return Err(cant_amend());
+    };
+    // the account number is left visible in masked card numbers
+    let Some(account_number) = payment_instruments::masked_account_number(&payment.card_number)
+    else {
+        tracing::error!("payment {payment_id} has an invalid card number");
+        return Err(cant_amend());
+    };
+    let account_service = &bank_web.account_service;
+    let declined = |error: String, message| {
+        tracing::Span::current().record("account_service.error", error.as_str());
+        let payment_error = PaymentError::from(&error);
+        ApiError::new(payment_error.get_http_status_code(), message)
+            .with_code("amendment_declined")
+            .with_details(serde_json::json!({ "decline_reason": payment_error.reason }))
+    };
+
+    // account service calls are journaled as made for the payment
+    let new_hold_ref = journal::for_payment(payment_id, async {
+        if let Err(error) = account_service.release_hold(hold_ref).await {
+            return Err(declined(error, "can't release the authorized hold"));
+        }
+
+        match account_service.place_hold(account_number, amount).await {
+            Ok(new_hold_ref) => Ok(new_hold_ref),
+            Err(error) => {
+                restore_hold(bank_web, payment_id, account_number, held.amount).await;
+                Err(declined(error, "can't hold the amended amount"))
+            }
+        }
+    })
+    .await?;
+
+    let error = match payments::amend(
+        &bank_web.pool,
+        payment_id,
+        payment.version,
+        amount,
+        new_hold_ref.id(),
+    )
+    .await
+    {
+        Ok(Some(payment)) => {
+            record_payment(&payment);
+            return Ok(payment);
+        }
+        // the payment was changed since it was read
+        Ok(None) => version_mismatch(),
+        Err(e) => {
+            tracing::error!("failed to amend payment {payment_id}: {e}");
+            cant_amend()
+        }
+    };
+
+    // the payment wasn't amended, so the new hold isn't kept and the
+    // original one, already released, is placed again
+    journal::for_payment(payment_id, async {
+        if let Err(e) = account_service.release_hold(new_hold_ref).await {
+            tracing::error!("failed to release amended hold of payment {payment_id}: {e}");
+        }
+        restore_hold(bank_web, payment_id, account_number, held.amount).await;
+    })
+    .await;
+    Err(error)
+}
+
+fn not_amendable(message: &'static str, status: Status) -> ApiError {
+    ApiError::new(StatusCode::CONFLICT, message)
+        .with_code("payment_not_amendable")
+        .with_details(serde_json::json!({ "status": status }))
+}
+
+/// Places the hold of an authorized payment again once it was released, and
+/// records it as the payment's hold.
+///
+/// Failures are logged with the restored hold, if placed, for an operator to
+/// record it.
+async fn restore_hold<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    payment_id: Uuid,
+    account_number: &str,
+    amount: i64,
+) {
+    let restored = match bank_web
+        .account_service
+        .place_hold(account_number, amount)
+        .await
+    {
+        Ok(restored) => restored,
+        Err(e) => {
+            tracing::error!("failed to restore hold of payment {payment_id}: {e}");
+            return;
+        }
+    };
+    if let Err(e) = payments::record_hold(&bank_web.pool, payment_id, restored.id()).await {
+        tracing::error!(
+            hold_ref = %restored.id(),
+            "failed to record restored hold of payment {payment_id}: {e}"
+        );
+    }
+}
+
+/// Processes a failed payment again, e.g. one failed while the account
+/// service was unavailable, with its stored amount and card.
+///
+/// The payment moves back to processing before any account service call, so
+/// a payment is only retried once at a time. Its outcome is answered like the
+/// outcome of `post`.
+#[allow(clippy::type_complexity)]
+#[tracing::instrument(
+    skip_all,
+    fields(payment.id = %payment_id, payment.amount, payment.status, account_service.error)
+)]
+pub async fn retry<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+) -> Result<Response, ApiError> {
+    let payment = find_payment(&bank_web.pool, merchant, payment_id).await?;
+    record_payment(&payment);
+
+    if payment.status != Status::Failed {
+        return Err(
+            ApiError::new(StatusCode::CONFLICT, "only failed payments can be retried")
+                .with_code("payment_not_retryable")
+                .with_details(serde_json::json!({ "status": payment.status })),
+        );
+    }
+
+    // the account number is left visible in masked card numbers
+    let Some(account_number) = payment_instruments::masked_account_number(&payment.card_number)
+    else {
+        tracing::error!("payment {payment_id} has an invalid card number");
+        return Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "can't retry payment",
+        ));
+    };
+
+    // only one of concurrent retries moves the payment back to processing
+    payments::transition(
+        &bank_web.pool,
+        payment_id,
+        Status::Failed,
+        Status::Processing,
+    )
+    .await
+    .map_err(|e| transition_error(payment_id, e))?;
+
+    let notify = |payment_id, status| bank_web.webhooks.notify(payment_id, status);
+    let settlement = settlement::settle(
+        &bank_web.pool,
+        &bank_web.account_service,
+        &notify,
+        &bank_web.fee_policy,
+        payment_id,
+        account_number,
+        payment.amount,
+    )
+    .await
+    .map_err(|e| transition_error(payment_id, e))?;
+
+    let status_code = match &settlement.error {
+        Some(error) => {
+            tracing::Span::current().record("account_service.error", error.as_str());
+            PaymentError::from(error).get_http_status_code()
+        }
+        None => StatusCode::OK,
+    };
+
+    let payment = reload_payment(&bank_web.pool, payment_id).await?;
+    record_payment(&payment);
+    let response = (status_code, Json(ResponseBody::from(payment))).into_response();
+
+    match &settlement.error {
+        Some(error) => Err(ApiError::payment_declined(error, response)),
+        None => Ok(response),
+    }
+}
+
+/// Lists the payments of the merchant, newest first unless sorted otherwise.
+///
+/// Cursors only continue the listing of the sort they were returned with.
+pub async fn list<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+    headers: HeaderMap,
+    Query(params): Query<ListParams>,
+    StatusFilter(statuses): StatusFilter,
+    // only checked when payments are listed, counts ignore the pagination
+    pagination: Result<PaginationParams, (StatusCode, Json<ErrorResponseBody>)>,
+    fieldset: Fieldset<ResponseData>,
+) -> Result<Response, ApiError> {
+    let sort = params.sort();
+
+    // metadata is matched on a single key-value pair
+    let metadata = match (params.metadata_key, params.metadata_value) {
+        (Some(key), Some(value)) => Some(serde_json::json!({ key: value })),
+        (None, None) => None,
+        _ => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "metadata_key and metadata_value must be given together",
+            ))
+        }
+    };
+    let source = params.source.map(parse_source).transpose()?;
+    let filter = payments::ListFilter {
+        merchant_id: Some(merchant_id),
+        metadata,
+        customer_reference: params
+            .customer_reference
+            .filter(|reference| !reference.is_empty()),
+        source,
+        include_archived: params.include_archived,
+        statuses,
+    };
+
+    if params.count_only {
+        if params.exact == Some(false) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "counts of a merchant's payments are always exact",
+            )
+            .with_code("estimate_unavailable"));
+        }
+        let (count, count_kind) = unwrap_or_return!(
+            payments::count_with_kind(&bank_web.pool, &filter, true).await,
+            Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "can't count payments"
+            ))
+        );
+        return Ok((
+            StatusCode::OK,
+            Json(CountResponseBody { count, count_kind }),
+        )
+            .into_response());
+    }
+
+    let pagination = pagination?;
+    let sort = sort.ok_or(
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "sort should be inserted_at, amount or status, and order asc or desc",
+        )
+        .with_code("invalid_sort"),
+    )?;
+    if let Some(cursor) = &pagination.after {
+        if cursor.sort.as_ref().map(|position| &position.sort) != sort.name().as_ref() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "cursor was returned with a different sort",
+            )
+            .with_code("cursor_sort_mismatch"));
+        }
+    }
+
+    if csv_requested(params.format.as_deref(), &headers)? {
+        return Ok(stream_csv::<_, _, CsvRow>(
+            "payments",
+            &CSV_HEADERS,
+            bank_web.pool,
+            filter,
+            payments::stream,
+        ));
+    }
+
+    let payments = match payments::list(&bank_web.pool, &filter, sort, &pagination.page()).await {
+        Ok(payments) => payments,
+        Err(e) => {
+            tracing::error!("failed to list payments: {e}");
+            return Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "can't list payments",
+            ));
+        }
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(Paginated::new(
+            payments,
+            &pagination,
+            |payment| sort.cursor(payment),
+            |payment| fieldset.project(&ResponseData::from(payment)),
+        )),
+    )
+        .into_response())
+}
+
+/// Searches the payments of the merchant by the last four digits of their
+/// card number, newest first.
+///
+/// Matches the last four digits left visible in masked card numbers.
+pub async fn search<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+    Query(params): Query<SearchParams>,
+) -> Result<(StatusCode, Json<SearchResponseBody>), ApiError> {
+    let suffix = match params.card_last4 {
+        Some(suffix) if suffix.len() == 4 && suffix.bytes().all(|b| b.is_ascii_digit()) => suffix,
+        _ => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "card_last4 must be exactly 4 digits",
+            )
+            .with_code("invalid_card_last4"))
+        }
+    };
+
+    let payments = unwrap_or_return!(
+        payments::find_by_card_suffix(&bank_web.pool, merchant_id, &suffix).await,
+        Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "can't search payments"
+        ))
+    );
+
+    let data = payments.into_iter().map(ResponseData::from).collect();
+
+    Ok((StatusCode::OK, Json(SearchResponseBody { data })))
+}
+
+/// Row of the CSV export of payments.
+///
+/// Text cells are escaped by `export::csv_escape`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CsvRow {
+    pub id: Uuid,
+    pub amount: i64,
+    /// Masked card number, see `Card::masked`.
+    pub card_number: String,
+    pub status: payments::Status,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+    /// Metadata serialized as a JSON object, empty without metadata.
+    pub metadata: Option<String>,
+}
+
+impl From<payments::Payment> for CsvRow {
+    fn from(payment: payments::Payment) -> Self {
+        CsvRow {
+            id: payment.id,
+            amount: payment.amount,
+            card_number: export::csv_escape(&payment.card_number),
+            status: payment.status,
+            inserted_at: payment.inserted_at.assume_utc(),
+            metadata: payment
+                .metadata
+                .map(|metadata| export::csv_escape(&metadata.to_string())),
+        }
+    }
+}
+
+const CSV_HEADERS: [&str; 6] = [
+    "id",
+    "amount",
+    "card_number",
+    "status",
+    "inserted_at",
+    "metadata",
+];
+
+pub fn to_utc(datetime: OffsetDateTime) -> PrimitiveDateTime {
+    let datetime = datetime.to_offset(UtcOffset::UTC);
+    PrimitiveDateTime::new(datetime.date(), datetime.time())
+}
+
+pub async fn stats<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<(StatusCode, Json<StatsResponseBody>), ApiError> {
+    // from and to must be RFC 3339 timestamps, group_by day, hour or source
+    let Query(params) = unwrap_or_return!(
+        params,
+        Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Invalid from, to or group_by"
+        ))
+    );
+
+    if params.from >= params.to {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "from should be before to",
+        ));
+    }
+
+    let (from, to) = (to_utc(params.from), to_utc(params.to));
+    let granularity = match params.group_by {
+        StatsGroupBy::Hour => Some(Granularity::Hour),
+        StatsGroupBy::Day => Some(Granularity::Day),
+        StatsGroupBy::Source => None,
+    };
+    let aggregates = match granularity {
+        Some(granularity) => {
+            payments::aggregate(&bank_web.pool, merchant_id, from, to, granularity)
+                .await
+                .map(|aggregates| {
+                    aggregates
+                        .into_iter()
+                        .map(|aggregate| {
+                            let stats = StatusStats {
+                                status: aggregate.status,
+                                count: aggregate.count,
+                                total_amount: aggregate.total_amount,
+                            };
+                            let bucket = Some(aggregate.bucket.assume_utc());
+                            ((bucket, None, aggregate.currency), stats)
+                        })
+                        .collect::<Vec<_>>()
+                })
+        }
+        None => payments::aggregate_by_source(&bank_web.pool, merchant_id, from, to)
+            .await
+            .map(|aggregates| {
+                aggregates
+                    .into_iter()
+                    .map(|aggregate| {
+                        let stats = StatusStats {
+                            status: aggregate.status,
+                            count: aggregate.count,
+                            total_amount: aggregate.total_amount,
+                        };
+                        ((None, Some(aggregate.source), aggregate.currency), stats)
+                    })
+                    .collect()
+            }),
+    };
+    let aggregates = unwrap_or_return!(
+        aggregates,
+        Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "can't aggregate payments"
+        ))
+    );
+
+    let percentiles = unwrap_or_return!(
+        payments::latency_percentiles(&bank_web.pool, merchant_id, from, to).await,
+        Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "can't aggregate payments"
+        ))
+    );
+    let latency = AccountLatencyStats {
+        hold_ms: LatencyStats {
+            p50: percentiles.hold_p50_ms,
+            p95: percentiles.hold_p95_ms,
+        },
+        withdraw_ms: LatencyStats {
+            p50: percentiles.withdraw_p50_ms,
+            p95: percentiles.withdraw_p95_ms,
+        },
+    };
+
+    // aggregates are ordered by bucket or source then currency, so each
+    // group is a contiguous run
+    let mut buckets: Vec<BucketStats> = Vec::new();
+    for ((bucket, source, currency), stats) in aggregates {
+        match buckets.last_mut() {
+            Some(last)
+                if last.bucket == bucket && last.source == source && last.currency == currency =>
+            {
+                last.statuses.push(stats)
+            }
+            _ => buckets.push(BucketStats {
+                bucket,
+                source,
+                currency,
+                statuses: vec![stats],
+            }),
+        }
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(StatsResponseBody {
+            data: buckets,
+            latency,
+        }),
+    ))
+}
+
+pub async fn events<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    pagination: PaginationParams,
+) -> Result<(StatusCode, Json<Paginated<EventData>>), ApiError> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+
+    let events = unwrap_or_return!(
+        payments::list_events(&bank_web.pool, payment_id, &pagination.page()).await,
+        Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "can't list payment events"
+        ))
+    );
+
+    Ok((
+        StatusCode::OK,
+        Json(Paginated::new(
+            events,
+            &pagination,
+            |event| Cursor {
+                inserted_at: event.inserted_at,
+                id: event.id,
+                sort: None,
+            },
+            |event| EventData {
+                from_status: event.from_status,
+                to_status: event.to_status,
+                inserted_at: event.inserted_at.assume_utc(),
+            },
+        )),
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use axum::http::header::{
+        ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_ENCODING,
+        CONTENT_LENGTH, CONTENT_TYPE, ETAG, LOCATION, RETRY_AFTER, WWW_AUTHENTICATE,
+    };
+
+    use super::*;
+    use crate::bank::{
+        accounts::{AccountMethod, AccountService, DummyService, HoldRef, ScriptedOutcome},
+        journal::{self, OperationKind, Outcome},
+    };
+    use crate::{
+        bank::{fees::FeePolicy, merchants, payment_instruments::Card, payments::Status},
+        bank_web::{
+            merchant::MERCHANT_ID_HEADER,
+            refunds::tests::RefundRequestBuilder,
+            tests::{
+                admin_delete, admin_get, admin_post, delete, delete_if_match,
+                deserialize_response_body, get, get_as, get_if_none_match, patch, post, post_as,
+                send_request, TestApp, TEST_MERCHANT_ID,
+            },
+            RateLimit, TokenBuckets,
+        },
+        errors::ProblemDetails,
+        telemetry::tests::RecordedFields,
+    };
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    /// Account service counting and recording the calls made through it.
+    #[derive(Clone, Default)]
+    pub struct MockService {
+        pub dummy: DummyService,
+        pub place_hold_count: Arc<AtomicUsize>,
+        pub release_hold_count: Arc<AtomicUsize>,
+        pub withdraw_funds_count: Arc<AtomicUsize>,
+        pub deposit_funds_count: Arc<AtomicUsize>,
+        /// Methods called, in order.
+        calls: Arc<Mutex<Vec<AccountMethod>>>,
+        /// Run before the next hold is placed, e.g. to change the payment
+        /// meanwhile.
+        before_next_hold: Arc<Mutex<Option<futures::future::BoxFuture<'static, ()>>>>,
+    }
+
+    impl MockService {
+        fn record_call(&self, method: AccountMethod) {
+            self.calls.lock().unwrap().push(method);
+        }
+
+        fn before_next_hold(&self, future: impl std::future::Future<Output = ()> + Send + 'static) {
+            *self.before_next_hold.lock().unwrap() = Some(Box::pin(future));
+        }
+
+        fn calls(&self) -> Vec<AccountMethod> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AccountService for MockService {
+        async fn place_hold(&self, account_number: &str, amount: i64) -> Result<HoldRef, String> {
+            self.place_hold_count.fetch_add(1, Ordering::SeqCst);
+            self.record_call(AccountMethod::Hold);
+            let before_hold = self.before_next_hold.lock().unwrap().take();
+            if let Some(before_hold) = before_hold {
+                before_hold.await;
+            }
+            self.dummy.place_hold(account_number, amount).await
+        }
+
+        async fn release_hold(&self, hold_ref: HoldRef) -> Result<(), String> {
+            self.release_hold_count.fetch_add(1, Ordering::SeqCst);
+            self.record_call(AccountMethod::Release);
+            self.dummy.release_hold(hold_ref).await
+        }
+
+        async fn withdraw_funds(&self, hold_ref: HoldRef) -> Result<(), String> {
+            self.withdraw_funds_count.fetch_add(1, Ordering::SeqCst);
+            self.record_call(AccountMethod::Withdraw);
+            self.dummy.withdraw_funds(hold_ref).await
+        }
+
+        async fn deposit_funds(&self, account_number: &str, amount: i64) -> Result<(), String> {
+            self.deposit_funds_count.fetch_add(1, Ordering::SeqCst);
+            self.record_call(AccountMethod::Deposit);
+            self.dummy.deposit_funds(account_number, amount).await
+        }
+
+        async fn validate_account(&self, account_number: &str) -> Result<(), String> {
+            self.dummy.validate_account(account_number).await
+        }
+    }
+
+    /// Builds payment request bodies, by default of
+    /// `PaymentRequestBuilder::DEFAULT_AMOUNT` paid with a new test card.
+    pub struct PaymentRequestBuilder {
+        data: RequestData,
+    }
+
+    impl PaymentRequestBuilder {
+        pub const DEFAULT_AMOUNT: i64 = 1205;
+
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn amount(mut self, amount: i64) -> Self {
+            self.data.amount = Amount(amount);
+            self
+        }
+
+        pub fn card(mut self, card: Card) -> Self {
+            self.data.card_number = card.into();
+            self
+        }
+
+        pub fn currency(mut self, currency: &str) -> Self {
+            self.data.currency = Some(currency.to_string());
+            self
+        }
+
+        pub fn metadata(mut self, metadata: serde_json::Value) -> Self {
+            self.data.metadata = Some(metadata);
+            self
+        }
+
+        pub fn customer_reference(mut self, customer_reference: &str) -> Self {
+            self.data.customer_reference = Some(customer_reference.to_string());
+            self
+        }
+
+        pub fn source(mut self, source: &str) -> Self {
+            self.data.source = Some(source.to_string());
+            self
+        }
+
+        pub fn build(self) -> RequestBody {
+            RequestBody { payment: self.data }
+        }
+    }
+
+    impl Default for PaymentRequestBuilder {
+        fn default() -> Self {
+            Self {
+                data: RequestData {
+                    amount: Amount(Self::DEFAULT_AMOUNT),
+                    card_number: Card::new_test().into(),
+                    currency: None,
+                    metadata: None,
+                    customer_reference: None,
+                    source: None,
+                },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_place_hold_for_payment_with_negative_amount() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool, mock_service.clone()).into_router();
+
+        let request_body = PaymentRequestBuilder::new().amount(-1).build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            mock_service.place_hold_count.load(Ordering::SeqCst),
+            0,
+            "should not try to place hold for amount -1"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_withdraw_funds_on_successful_payment() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool, mock_service.clone()).into_router();
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        assert_eq!(mock_service.withdraw_funds_count.load(Ordering::SeqCst), 1);
+    }
+
+    /// Makes a payment through `account_service`, returning its status and
+    /// the kind, outcome and error of the account operations journaled for it.
+    async fn make_journaled_payment(
+        account_service: DummyService,
+    ) -> (
+        Status,
+        Vec<(OperationKind, Option<Outcome>, Option<String>)>,
+    ) {
+        let pool = crate::pg_pool().await.unwrap();
+        let router = BankWeb::new(pool.clone(), account_service).into_router();
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        let payment = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        let operations = journal::list(&pool, payment.id).await.unwrap();
+        let hold_refs: HashSet<_> = operations.iter().map(|o| o.hold_ref).collect();
+        assert!(hold_refs.len() <= 1, "operations on several holds");
+        assert!(operations.iter().all(|o| o.finished_at.is_some()));
+        let violations = journal::violations(&pool, Some(payment.id)).await.unwrap();
+        assert_eq!(violations, []);
+
+        let operations = operations
+            .into_iter()
+            .map(|o| (o.kind, o.outcome, o.error))
+            .collect();
+        (payment.status, operations)
+    }
+
+    #[tokio::test]
+    async fn should_journal_approved_payment() {
+        let (status, operations) = make_journaled_payment(DummyService::default()).await;
+
+        assert_eq!(status, Status::Approved);
+        assert_eq!(
+            operations,
+            [
+                (OperationKind::Hold, Some(Outcome::Succeeded), None),
+                (OperationKind::Withdraw, Some(Outcome::Succeeded), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_journal_declined_payment() {
+        let account_service = DummyService::default().with_scripted_outcome(
+            ScriptedOutcome::new("insufficient_funds").with_method(AccountMethod::Hold),
+        );
+        let (status, operations) = make_journaled_payment(account_service).await;
+
+        assert_eq!(status, Status::Declined);
+        assert_eq!(
+            operations,
+            [(
+                OperationKind::Hold,
+                Some(Outcome::Failed),
+                Some("insufficient_funds".to_string())
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_release_hold_that_cant_be_withdrawn() {
+        let account_service = DummyService::default().with_scripted_outcome(
+            ScriptedOutcome::new("service_unavailable").with_method(AccountMethod::Withdraw),
+        );
+        let (status, operations) = make_journaled_payment(account_service).await;
+
+        assert_eq!(status, Status::Failed);
+        assert_eq!(
+            operations,
+            [
+                (OperationKind::Hold, Some(Outcome::Succeeded), None),
+                (
+                    OperationKind::Withdraw,
+                    Some(Outcome::Failed),
+                    Some("service_unavailable".to_string())
+                ),
+                (OperationKind::Release, Some(Outcome::Succeeded), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_settle_async_payment_in_the_background() {
+        let bank_web = BankWeb::new_test().await;
+        let worker = bank_web.settlement_worker();
+        let router = bank_web.into_router();
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+        let response = post(&router, "/api/payments?async=true", &request_body).await;
+        assert_eq!(response.status(), 202);
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, Status::Processing);
+
+        let response = get(&router, &location).await;
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, Status::Processing);
+
+        while worker.settle_batch().await.unwrap() > 0 {}
+
+        let response = get(&router, &location).await;
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, Status::Approved);
+    }
+
+    #[tokio::test]
+    async fn should_return_account_service_latency_to_admins() {
+        let router = BankWeb::new_test().await.into_router();
+        let latency = |router: axum::Router, payment_id: Uuid| async move {
+            let response = admin_get(&router, format!("/api/admin/payments/{payment_id}")).await;
+            assert_eq!(response.status(), 200);
+            let response_body = deserialize_response_body::<AdminResponseBody>(response).await;
+            assert_eq!(response_body.data.payment.id, payment_id);
+            (
+                response_body.data.hold_latency_ms,
+                response_body.data.withdraw_latency_ms,
+            )
+        };
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let approved = deserialize_response_body::<ResponseBody>(response).await;
+        let (hold, withdraw) = latency(router.clone(), approved.data.id).await;
+        assert!(hold.is_some_and(|ms| ms >= 0), "{hold:?}");
+        assert!(withdraw.is_some_and(|ms| ms >= 0), "{withdraw:?}");
+
+        // async payments aren't settled until the worker picks them up
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments?async=true", &request_body).await;
+        assert_eq!(response.status(), 202);
+        let pending = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(latency(router.clone(), pending.data.id).await, (None, None));
+
+        let declining_router = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&declining_router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 402);
+        let declined = deserialize_response_body::<ResponseBody>(response).await;
+        let (hold, withdraw) = latency(router.clone(), declined.data.id).await;
+        assert!(hold.is_some(), "the hold was attempted");
+        assert_eq!(withdraw, None);
+
+        let response = admin_get(&router, format!("/api/admin/payments/{}", Uuid::new_v4())).await;
+        assert_eq!(response.status(), 404);
+
+        // a merchant can't read payments through the admin endpoint
+        let response = get(&router, format!("/api/admin/payments/{}", approved.data.id)).await;
+        assert_eq!(response.status(), 401);
+    }
+
+    async fn make_payment(router: axum::Router, card: Card) -> hyper::StatusCode {
+        let request_body = PaymentRequestBuilder::new().amount(123).card(card).build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn should_not_place_holds_for_concurrent_payments() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool, mock_service.clone()).into_router();
+
+        let card = Card::new_test();
+
+        let fut_a = make_payment(router.clone(), card.clone());
+        let fut_b = make_payment(router, card.clone());
+        let (status_a, status_b) = tokio::join!(fut_a, fut_b);
+
+        assert_eq!(status_a.min(status_b), 201, "one payment should succeed");
+        assert_eq!(status_a.max(status_b), 422, "one payment should fail");
+
+        assert_eq!(
+            mock_service.place_hold_count.load(Ordering::SeqCst),
+            1,
+            "should not try to place hold for concurrent requests"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_approve_valid_payment() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, request_body.payment.amount.0);
+
+        let uri = format!("/api/payments/{}", response_body.data.id);
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, request_body.payment.amount.0);
+        assert_eq!(response_body.data.status, Status::Approved);
+    }
+
+    #[tokio::test]
+    async fn should_return_persisted_timestamps() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let created = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert!(created.inserted_at <= created.updated_at);
+
+        let response = get(&router, format!("/api/payments/{}", created.id)).await;
+        let fetched = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(fetched.inserted_at, created.inserted_at);
+        assert_eq!(fetched.updated_at, created.updated_at);
+    }
+
+    #[tokio::test]
+    async fn should_record_payment_fields_on_spans() {
+        let fields = RecordedFields::default();
+        let _guard = fields.install();
+        let router = BankWeb::new_test().await.into_router();
+
+        let card = Card::new_test();
+        let request_body = PaymentRequestBuilder::new().card(card.clone()).build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let payment_id = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        let response = get(&router, format!("/api/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 200);
+
+        for span in ["bank_web::payments::post", "bank_web::payments::get"] {
+            assert!(fields
+                .get(span, "payment.id")
+                .contains(&payment_id.to_string()));
+            assert!(fields
+                .get(span, "payment.amount")
+                .contains(&"1205".to_string()));
+            assert!(fields
+                .get(span, "payment.status")
+                .contains(&"Approved".to_string()));
+        }
+        assert!(fields
+            .get("bank::payments::insert", "payment.id")
+            .contains(&payment_id.to_string()));
+
+        // the card number is never recorded
+        assert!(fields
+            .values()
+            .iter()
+            .all(|value| !value.contains(card.card_number())));
+    }
+
+    #[tokio::test]
+    async fn should_record_account_service_error_on_span() {
+        let fields = RecordedFields::default();
+        let _guard = fields.install();
+        let router = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 402);
+
+        let span = "bank_web::payments::post";
+        assert_eq!(
+            fields.get(span, "account_service.error"),
+            vec!["insufficient_funds".to_string()]
+        );
+        assert_eq!(
+            fields.get(span, "payment.status"),
+            vec!["Declined".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_decline_payment_and_return_402_with_insufficient_funds() {
+        let router = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 402);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, request_body.payment.amount.0);
+        assert_eq!(response_body.data.status, Status::Declined);
+    }
+
+    #[tokio::test]
+    async fn should_decline_payment_with_problem_details_when_accepted() {
+        let router = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::POST)
+            .uri("/api/payments")
+            .header(ACCEPT, "application/problem+json")
+            .header(CONTENT_TYPE, "application/json")
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .body(serde_json::to_vec(&request_body).unwrap().into())
+            .unwrap();
+        let response = send_request(&router, request).await;
+        assert_eq!(response.status(), 402);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+        assert!(response.headers().contains_key(LOCATION));
+
+        let problem = deserialize_response_body::<ProblemDetails>(response).await;
+        assert_eq!(
+            problem,
+            ProblemDetails {
+                problem_type: "urn:bank-payment-service:problem:insufficient_funds".to_string(),
+                title: "payment declined".to_string(),
+                status: 402,
+                detail: Some("account service error: insufficient_funds".to_string()),
+                instance: Some("/api/payments".to_string()),
+                extensions: serde_json::Map::new(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn should_convert_error_envelopes_to_problem_details_when_accepted() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request = |uri: String| {
+            axum::http::Request::builder()
+                .uri(uri)
+                .header(ACCEPT, "application/problem+json")
+                .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+                .body(hyper::Body::empty())
+                .unwrap()
+        };
+
+        let uri = format!("/api/payments/{}", Uuid::new_v4());
+        let response = send_request(&router, request(uri.clone())).await;
+        assert_eq!(response.status(), 404);
+        let problem = deserialize_response_body::<ProblemDetails>(response).await;
+        assert_eq!(
+            problem.problem_type,
+            "urn:bank-payment-service:problem:not_found"
+        );
+        assert_eq!(problem.title, "payment doesn't exist");
+        assert_eq!(problem.instance, Some(uri));
+
+        // details are kept as extension members
+        let response = send_request(&router, request("/api/payments?fields=pin".into())).await;
+        assert_eq!(response.status(), 400);
+        let problem = deserialize_response_body::<ProblemDetails>(response).await;
+        assert_eq!(
+            problem.problem_type,
+            "urn:bank-payment-service:problem:invalid_fields"
+        );
+        assert_eq!(
+            problem.extensions["unknown_fields"],
+            serde_json::json!(["pin"])
+        );
+
+        // successful responses are left alone
+        let response = send_request(&router, request("/api/payments".into())).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    }
+
+    #[tokio::test]
+    async fn should_decline_payment_and_return_403_for_invalid_account_number() {
+        let router = BankWeb::new_test_with_response("invalid_account_number")
+            .await
+            .into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 403);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, request_body.payment.amount.0);
+        assert_eq!(response_body.data.status, Status::Declined);
+    }
+
+    #[tokio::test]
+    async fn should_return_decline_reason() {
+        for (error, status_code, status, reason) in [
+            (
+                "insufficient_funds",
+                402,
+                Status::Declined,
+                DeclineReason::InsufficientFunds,
+            ),
+            (
+                "invalid_account_number",
+                403,
+                Status::Declined,
+                DeclineReason::InvalidAccountNumber,
+            ),
+            (
+                "invalid_amount",
+                400,
+                Status::Failed,
+                DeclineReason::InvalidAmount,
+            ),
+            (
+                "service_unavailable",
+                503,
+                Status::Failed,
+                DeclineReason::ServiceFailure,
+            ),
+            (
+                "unknown_error",
+                500,
+                Status::Failed,
+                DeclineReason::ServiceFailure,
+            ),
+        ] {
+            let router = BankWeb::new_test_with_response(error).await.into_router();
+
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = post(&router, "/api/payments", &request_body).await;
+            assert_eq!(response.status(), status_code, "{error}");
+            let data = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data;
+            assert_eq!(data.status, status, "{error}");
+            assert_eq!(data.decline_reason, Some(reason), "{error}");
+
+            let response = get(&router, format!("/api/payments/{}", data.id)).await;
+            let body = deserialize_response_body::<serde_json::Value>(response).await;
+            assert_eq!(
+                body["data"]["decline_reason"],
+                serde_json::to_value(reason).unwrap(),
+                "{error}"
+            );
+        }
+
+        let payment = TestApp::new().await.create_approved_payment().await.data;
+        assert_eq!(payment.decline_reason, None);
+    }
+
+    #[tokio::test]
+    async fn should_return_204_for_zero_amount() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new().amount(0).build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 204);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "0");
+        assert!(response.headers().get(CONTENT_TYPE).is_none());
+
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_zero_amount_when_rejected() {
+        let router = BankWeb::new_test()
+            .await
+            .with_zero_amount_policy(ZeroAmountPolicy::Reject)
+            .into_router();
+
+        let request_body = PaymentRequestBuilder::new().amount(0).build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 400);
+
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("zero_amount"));
+    }
+
+    #[tokio::test]
+    async fn should_accept_maximum_amount() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new()
+            .amount(BankWeb::<DummyService>::DEFAULT_MAX_AMOUNT)
+            .build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_amount_over_maximum() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone())
+            .with_max_amount(1000)
+            .into_router();
+
+        for amount in [1001, i64::MAX] {
+            let card = Card::new_test();
+            let request_body = PaymentRequestBuilder::new()
+                .amount(amount)
+                .card(card.clone())
+                .build();
+
+            let response = post(&router, "/api/payments", &request_body).await;
+            assert_eq!(response.status(), 422, "{amount}");
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.error, "amount exceeds maximum");
+            assert_eq!(response_body.code.as_deref(), Some("amount_too_large"));
+
+            let inserted: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM payments WHERE card_fingerprint = $1")
+                    .bind(card.fingerprint())
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            assert_eq!(inserted, 0, "{amount}");
+        }
+        assert_eq!(mock_service.place_hold_count.load(Ordering::SeqCst), 0);
+
+        let request_body = PaymentRequestBuilder::new().amount(1000).build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    /// Inserts an authorized payment of `payments::tests::PAYMENT_AMOUNT`,
+    /// returning its id and hold.
+    async fn new_authorized_payment(pool: &PgPool) -> (Uuid, Uuid) {
+        let payment_id = payments::tests::new_processing_payment(pool).await;
+        let hold_ref = Uuid::new_v4();
+        payments::record_hold(pool, payment_id, hold_ref)
+            .await
+            .unwrap();
+        payments::transition(pool, payment_id, Status::Processing, Status::Authorized)
+            .await
+            .unwrap();
+        (payment_id, hold_ref)
+    }
+
+    fn amend_request(amount: i64) -> AmendRequestBody {
+        AmendRequestBody {
+            payment: AmendRequestData {
+                amount: Amount(amount),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn should_amend_authorized_payment_with_new_hold() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+        let (payment_id, hold_ref) = new_authorized_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        let amount = payments::tests::PAYMENT_AMOUNT - 23;
+        let response = patch(&router, &uri, &amend_request(amount)).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, amount);
+        assert_eq!(response_body.data.status, Status::Authorized);
+        assert_eq!(response_body.data.version, 3);
+
+        assert_eq!(
+            mock_service.calls(),
+            [AccountMethod::Release, AccountMethod::Hold]
+        );
+        let new_hold_ref = payments::get_hold_ref(&pool, payment_id).await.unwrap();
+        assert!(new_hold_ref.is_some_and(|new_hold_ref| new_hold_ref != hold_ref));
+        let operations = journal::list(&pool, payment_id).await.unwrap();
+        assert_eq!(
+            operations
+                .iter()
+                .map(|operation| (operation.kind, operation.hold_ref))
+                .collect::<Vec<_>>(),
+            [
+                (OperationKind::Release, Some(hold_ref)),
+                (OperationKind::Hold, new_hold_ref),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_restore_hold_when_amended_hold_is_declined() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        mock_service.dummy.set_scripted_outcome(Some(
+            ScriptedOutcome::new("insufficient_funds")
+                .with_method(AccountMethod::Hold)
+                .with_remaining(1.try_into().unwrap()),
+        ));
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+        let (payment_id, hold_ref) = new_authorized_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        let response = patch(&router, &uri, &amend_request(100)).await;
+        assert_eq!(response.status(), 402);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("amendment_declined"));
+
+        // the original amount is held again
+        assert_eq!(
+            mock_service.calls(),
+            [
+                AccountMethod::Release,
+                AccountMethod::Hold,
+                AccountMethod::Hold
+            ]
+        );
+        let payment = payments::get(&pool, payment_id).await.unwrap();
+        assert_eq!(payment.amount, payments::tests::PAYMENT_AMOUNT);
+        assert_eq!(payment.status, Status::Authorized);
+        assert_eq!(payment.version, 2);
+        let restored_hold_ref = payments::get_hold_ref(&pool, payment_id).await.unwrap();
+        assert!(restored_hold_ref.is_some_and(|restored| restored != hold_ref));
+    }
+
+    /// Asserts that the hold of an authorized payment was released, then
+    /// placed again once its amendment failed, leaving the payment unchanged.
+    async fn assert_hold_restored(
+        pool: &PgPool,
+        mock_service: &MockService,
+        payment_id: Uuid,
+        hold_ref: Uuid,
+    ) {
+        assert_eq!(
+            mock_service.calls(),
+            [
+                AccountMethod::Release,
+                AccountMethod::Hold,
+                AccountMethod::Release,
+                AccountMethod::Hold
+            ]
+        );
+        let payment = payments::get(pool, payment_id).await.unwrap();
+        assert_eq!(payment.amount, payments::tests::PAYMENT_AMOUNT);
+        assert_eq!(payment.status, Status::Authorized);
+
+        // the new hold is released and the restored one recorded
+        let operations = journal::list(pool, payment_id).await.unwrap();
+        let hold_refs: Vec<_> = operations
+            .iter()
+            .map(|operation| (operation.kind, operation.hold_ref))
+            .collect();
+        assert_eq!(
+            hold_refs,
+            [
+                (OperationKind::Release, Some(hold_ref)),
+                (OperationKind::Hold, hold_refs[1].1),
+                (OperationKind::Release, hold_refs[1].1),
+                (OperationKind::Hold, hold_refs[3].1),
+            ]
+        );
+        let restored_hold_ref = payments::get_hold_ref(pool, payment_id).await.unwrap();
+        assert!(restored_hold_ref.is_some());
+        assert_eq!(restored_hold_ref, hold_refs[3].1);
+        assert_ne!(restored_hold_ref, hold_refs[1].1);
+    }
+
+    #[tokio::test]
+    async fn should_restore_hold_when_amended_payment_changed_meanwhile() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+        let (payment_id, hold_ref) = new_authorized_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        // the payment is changed while its new hold is placed
+        let concurrent_pool = pool.clone();
+        mock_service.before_next_hold(async move {
+            sqlx::query!(
+                "UPDATE payments SET version = version + 1 WHERE id = $1",
+                payment_id
+            )
+            .execute(&concurrent_pool)
+            .await
+            .unwrap();
+        });
+
+        let response = patch(&router, &uri, &amend_request(100)).await;
+        assert_eq!(response.status(), 412);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("version_mismatch"));
+
+        assert_hold_restored(&pool, &mock_service, payment_id, hold_ref).await;
+    }
+
+    #[tokio::test]
+    async fn should_restore_hold_when_amended_payment_cant_be_stored() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+        let (payment_id, hold_ref) = new_authorized_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        // storing the amended payment fails, as its version can't be
+        // incremented
+        sqlx::query!(
+            "UPDATE payments SET version = 2147483647 WHERE id = $1",
+            payment_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let response = patch(&router, &uri, &amend_request(100)).await;
+        assert_eq!(response.status(), 500);
+
+        assert_hold_restored(&pool, &mock_service, payment_id, hold_ref).await;
+    }
+
+    #[tokio::test]
+    async fn should_withdraw_amended_hold_when_settled_during_amendment() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+        let (payment_id, hold_ref) = new_authorized_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        // the settlement goes on while the new hold is placed, waiting for
+        // the amendment to withdraw
+        let settlement = Arc::new(Mutex::new(None));
+        let (settling_pool, settling_service, spawned) =
+            (pool.clone(), mock_service.clone(), settlement.clone());
+        mock_service.before_next_hold(async move {
+            let handle = tokio::spawn(journal::for_payment(payment_id, async move {
+                let account_service =
+                    journal::JournaledService::new(settling_pool.clone(), settling_service);
+                settlement::withdraw(
+                    &settling_pool,
+                    &account_service,
+                    payment_id,
+                    HoldRef::from_id(hold_ref),
+                    &mut payments::Latency::default(),
+                )
+                .await
+            }));
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            assert!(!handle.is_finished());
+            *spawned.lock().unwrap() = Some(handle);
+        });
+
+        let amount = payments::tests::PAYMENT_AMOUNT - 23;
+        let response = patch(&router, &uri, &amend_request(amount)).await;
+        assert_eq!(response.status(), 200);
+
+        let handle = settlement.lock().unwrap().take().expect("not settled");
+        let withdrawal = handle
+            .await
+            .expect("settlement panicked")
+            .expect("failed to withdraw");
+        assert_eq!(withdrawal, settlement::Withdrawal::Withdrawn { amount });
+        assert_eq!(
+            mock_service.calls(),
+            [
+                AccountMethod::Release,
+                AccountMethod::Hold,
+                AccountMethod::Withdraw
+            ]
+        );
+        let new_hold_ref = payments::get_hold_ref(&pool, payment_id).await.unwrap();
+        let operations = journal::list(&pool, payment_id).await.unwrap();
+        assert_eq!(
+            operations
+                .iter()
+                .map(|operation| (operation.kind, operation.hold_ref))
+                .collect::<Vec<_>>(),
+            [
+                (OperationKind::Release, Some(hold_ref)),
+                (OperationKind::Hold, new_hold_ref),
+                (OperationKind::Withdraw, new_hold_ref),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_amend_payment_whose_hold_was_withdrawn() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+        let (payment_id, hold_ref) = new_authorized_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        // withdrawn, but not approved yet
+        let withdrawal = settlement::withdraw(
+            &pool,
+            &mock_service,
+            payment_id,
+            HoldRef::from_id(hold_ref),
+            &mut payments::Latency::default(),
+        )
+        .await
+        .expect("failed to withdraw");
+        assert_eq!(
+            withdrawal,
+            settlement::Withdrawal::Withdrawn {
+                amount: payments::tests::PAYMENT_AMOUNT
+            }
+        );
+
+        let response = patch(&router, &uri, &amend_request(100)).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("payment_not_amendable"));
+
+        assert_eq!(mock_service.calls(), [AccountMethod::Withdraw]);
+        assert_eq!(
+            payments::get_hold_ref(&pool, payment_id).await.unwrap(),
+            Some(hold_ref)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_only_amend_authorized_payments_downward() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+        let (payment_id, _) = new_authorized_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        for amount in [
+            payments::tests::PAYMENT_AMOUNT,
+            payments::tests::PAYMENT_AMOUNT + 1,
+            0,
+        ] {
+            let response = patch(&router, &uri, &amend_request(amount)).await;
+            assert_eq!(response.status(), 422, "{amount}");
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.code.as_deref(), Some("amount_not_reduced"));
+        }
+
+        let payment_id = payments::tests::new_processing_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+        let response = patch(&router, &uri, &amend_request(100)).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("payment_not_amendable"));
+
+        assert!(mock_service.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_payments_with_same_card() {
+        let mock_service = MockService::default();
+        let router = BankWeb::new(crate::pg_pool().await.unwrap(), mock_service.clone())
+            .with_rate_limiter(Arc::new(TokenBuckets::new(
+                RateLimit {
+                    capacity: 3,
+                    refill_interval: Duration::from_secs(60),
+                },
+                RateLimit::MERCHANT,
+            )))
+            .into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        for _ in 0..2 {
+            let response = post(&router, "/api/payments", &request_body).await;
+            assert_eq!(response.status(), 422);
+        }
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("rate_limited"));
+        assert_eq!(mock_service.place_hold_count.load(Ordering::SeqCst), 1);
+
+        // other cards aren't limited
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/v1/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_payments_of_same_merchant() {
+        let mock_service = MockService::default();
+        let router = BankWeb::new(crate::pg_pool().await.unwrap(), mock_service.clone())
+            .with_rate_limiter(Arc::new(TokenBuckets::new(
+                RateLimit::CARD,
+                RateLimit {
+                    capacity: 2,
+                    refill_interval: Duration::from_millis(2500),
+                },
+            )))
+            .into_router();
+        let merchant_id = Uuid::new_v4();
+
+        for _ in 0..2 {
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+            assert_eq!(response.status(), 201);
+        }
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers()[RETRY_AFTER], "3");
+        assert_eq!(mock_service.place_hold_count.load(Ordering::SeqCst), 2);
+
+        let count = payments::count(
+            &crate::pg_pool().await.unwrap(),
+            &payments::ListFilter {
+                merchant_id: Some(merchant_id),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(count, 2);
+
+        // other merchants aren't limited
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn should_validate_payment_without_placing_hold_on_dry_run() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let router = BankWeb::new(pool.clone(), mock_service.clone()).into_router();
+
+        let card = Card::new_test();
+        let request_body = PaymentRequestBuilder::new().card(card.clone()).build();
+        let response = post(&router, "/api/payments?dry_run=true", &request_body).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<DryRunResponseBody>(response).await;
+        assert_eq!(
+            response_body,
+            DryRunResponseBody {
+                data: DryRunData { valid: true }
+            }
+        );
+        assert_eq!(mock_service.place_hold_count.load(Ordering::SeqCst), 0);
+
+        let inserted: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM payments WHERE card_fingerprint = $1")
+                .bind(card.fingerprint())
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(inserted, 0);
+
+        // the dry run didn't use up the card
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        assert_eq!(mock_service.place_hold_count.load(Ordering::SeqCst), 1);
+
+        let response = post(&router, "/api/payments?dry_run=true", &request_body).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, "card_number already used");
+        assert_eq!(mock_service.place_hold_count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_return_same_errors_on_dry_run() {
+        let mock_service = MockService::default();
+        let router = BankWeb::new(crate::pg_pool().await.unwrap(), mock_service.clone())
+            .with_max_amount(1000)
+            .into_router();
+
+        for request_body in [
+            PaymentRequestBuilder::new().amount(-1).build(),
+            PaymentRequestBuilder::new().amount(1001).build(),
+            RequestBody {
+                payment: RequestData {
+                    card_number: "12345".to_string(),
+                    ..PaymentRequestBuilder::new().build().payment
+                },
+            },
+        ] {
+            let response = post(&router, "/api/payments?dry_run=true", &request_body).await;
+            let status = response.status();
+            let dry_run_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+
+            let response = post(&router, "/api/payments", &request_body).await;
+            assert_eq!(status, response.status());
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(dry_run_body.error, response_body.error);
+            assert_eq!(dry_run_body.code, response_body.code);
+        }
+
+        // a new test card, on the account the dummy service rejects
+        let card_number = String::from(Card::new_test());
+        let card = Card::try_from(format!(
+            "{}{}",
+            DummyService::INVALID_ACCOUNT_NUMBER,
+            &card_number[DummyService::INVALID_ACCOUNT_NUMBER.len()..]
+        ))
+        .unwrap();
+        let request_body = PaymentRequestBuilder::new().amount(1000).card(card).build();
+        let response = post(&router, "/api/payments?dry_run=true", &request_body).await;
+        assert_eq!(response.status(), 403);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("payment_declined"));
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "decline_reason": "invalid_account_number" }))
+        );
+        assert_eq!(mock_service.place_hold_count.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn should_return_503_when_storage_is_unavailable() {
+        let bank_web = BankWeb::new_test().await;
+        bank_web.pool.close().await;
+        let router = bank_web.into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("storage_unavailable"));
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_existing_card_number() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let original = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, "card_number already used");
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "payment_id": original.id, "status": "approved" }))
+        );
+
+        // payments of other merchants aren't named
+        let response = post_as(&router, "/api/payments", &request_body, Uuid::new_v4()).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.details, None);
+    }
+
+    #[tokio::test]
+    async fn should_default_currency_to_usd() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new().build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.currency, "USD");
+    }
+
+    #[tokio::test]
+    async fn should_accept_explicit_currency() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new().currency("EUR").build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.currency, "EUR");
+
+        let uri = format!("/api/payments/{}", response_body.data.id);
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.currency, "EUR");
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_unknown_currency() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request_body = PaymentRequestBuilder::new().currency("US").build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, "Unknown currency");
+        assert_eq!(response_body.code.as_deref(), Some("invalid_currency"));
+    }
+
+    #[tokio::test]
+    async fn should_record_declined_transition_once() {
+        let router = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+
+        let card = Card::new_test();
+        let request_body = PaymentRequestBuilder::new().card(card.clone()).build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 402);
+
+        let pool = crate::pg_pool().await.unwrap();
+        let payment_id: Uuid =
+            sqlx::query_scalar("SELECT id FROM payments WHERE card_fingerprint = $1")
+                .bind(card.fingerprint())
+                .fetch_one(&pool)
+                .await
+                .expect("failed to find declined payment");
+
+        let uri = format!("/api/payments/{payment_id}/events");
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<Paginated<EventData>>(response).await;
+        assert_eq!(response_body.data.len(), 1);
+        assert_eq!(response_body.data[0].from_status, Status::Processing);
+        assert_eq!(response_body.data[0].to_status, Status::Declined);
+    }
+
+    #[tokio::test]
+    async fn should_return_refundable_amount() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{payment_id}");
+
+        // fresh, partially refunded then fully refunded
+        for (refund, refunded_amount, refundable_amount) in
+            [(None, 0, 1205), (Some(500), 500, 705), (Some(705), 1205, 0)]
+        {
+            if let Some(amount) = refund {
+                let request_body = RefundRequestBuilder::new().amount(amount).build();
+                let response = app.create_refund(payment_id, &request_body).await;
+                assert_eq!(response.status(), 201);
+            }
+
+            let response = get(&app.router, &uri).await;
+            assert_eq!(response.status(), 200);
+            let data = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data;
+            assert_eq!(data.refunded_amount, Some(refunded_amount));
+            assert_eq!(data.refundable_amount, Some(refundable_amount));
+        }
+
+        let request_body = RefundRequestBuilder::new().amount(1).build();
+        let response = app.create_refund(payment_id, &request_body).await;
+        assert_eq!(response.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn should_return_fees_of_approved_payment_to_admins() {
+        let pool = crate::pg_pool().await.unwrap();
+        let router = BankWeb::new_test()
+            .await
+            .with_fee_policy(FeePolicy::new(100, 10).unwrap())
+            .into_router();
+        let (merchant_id, default_merchant_id) = (Uuid::new_v4(), Uuid::new_v4());
+        merchants::set_fee_policy(&pool, merchant_id, FeePolicy::new(140, 25).unwrap())
+            .await
+            .expect("failed to set fee policy");
+
+        let pay = |merchant_id| {
+            let router = router.clone();
+            async move {
+                let request_body = PaymentRequestBuilder::new().amount(10_000).build();
+                let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+                assert_eq!(response.status(), 201);
+                deserialize_response_body::<ResponseBody>(response)
+                    .await
+                    .data
+                    .id
+            }
+        };
+        let fees = |payment_id: Uuid| {
+            let router = router.clone();
+            async move {
+                let response =
+                    admin_get(&router, format!("/api/admin/payments/{payment_id}")).await;
+                assert_eq!(response.status(), 200);
+                deserialize_response_body::<AdminResponseBody>(response)
+                    .await
+                    .data
+                    .fees
+                    .expect("missing fees")
+                    .total_fee
+            }
+        };
+
+        let payment_id = pay(merchant_id).await;
+        let default_payment_id = pay(default_merchant_id).await;
+        assert_eq!(fees(payment_id).await, 165);
+        assert_eq!(fees(default_payment_id).await, 110);
+
+        // merchants aren't shown the breakdown
+        let response = get_as(&router, format!("/api/payments/{payment_id}"), merchant_id).await;
+        let response_body = deserialize_response_body::<serde_json::Value>(response).await;
+        assert!(response_body["data"].get("fees").is_none());
+
+        // a new policy only applies to the payments settled from then on
+        merchants::set_fee_policy(&pool, merchant_id, FeePolicy::new(300, 0).unwrap())
+            .await
+            .expect("failed to set fee policy");
+        assert_eq!(fees(payment_id).await, 165);
+        assert_eq!(fees(pay(merchant_id).await).await, 300);
+    }
+
+    #[tokio::test]
+    async fn should_accept_card_number_reused_outside_window() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let card = Card::new_test();
+        let request_body = PaymentRequestBuilder::new()
+            .amount(123)
+            .card(card.clone())
+            .build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let pool = crate::pg_pool().await.unwrap();
+        sqlx::query(
+            "UPDATE payments SET inserted_at = inserted_at - interval '25 hours' WHERE card_fingerprint = $1",
+        )
+        .bind(card.fingerprint())
+        .execute(&pool)
+        .await
+        .expect("failed to backdate payment");
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 422);
+    }
+
+    async fn post_with_metadata(
+        router: &axum::Router,
+        metadata: serde_json::Value,
+    ) -> hyper::Response<http_body::combinators::UnsyncBoxBody<axum::body::Bytes, axum::Error>>
+    {
+        let request_body = PaymentRequestBuilder::new()
+            .amount(123)
+            .metadata(metadata)
+            .build();
+
+        post(router, "/api/payments", &request_body).await
+    }
+
+    #[tokio::test]
+    async fn should_round_trip_metadata() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let metadata = serde_json::json!({
+            "order_id": Uuid::new_v4().to_string(),
+            "commande": { "numéro": 42, "étiquettes": ["été", "日本"] },
+            "ключ": null,
+        });
+
+        let response = post_with_metadata(&router, metadata.clone()).await;
+        assert_eq!(response.status(), 201);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.metadata.as_ref(), Some(&metadata));
+
+        let uri = format!("/api/payments/{}", response_body.data.id);
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.metadata, Some(metadata));
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_invalid_metadata() {
+        let router = BankWeb::new_test().await.into_router();
+
+        for metadata in [
+            serde_json::json!("order"),
+            serde_json::json!(["order"]),
+            serde_json::json!(42),
+        ] {
+            let response = post_with_metadata(&router, metadata).await;
+            assert_eq!(response.status(), 422);
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.error, "metadata must be a JSON object");
+        }
+
+        let metadata = serde_json::json!({ "note": "a".repeat(MAX_METADATA_SIZE) });
+        let response = post_with_metadata(&router, metadata).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, "metadata is too large");
+    }
+
+    #[tokio::test]
+    async fn should_list_payments_by_metadata() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let order_id = Uuid::new_v4().to_string();
+        let response =
+            post_with_metadata(&router, serde_json::json!({ "order_id": order_id })).await;
+        assert_eq!(response.status(), 201);
+        let payment = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        let uri = format!("/api/payments?metadata_key=order_id&metadata_value={order_id}");
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(response_body.data, vec![payment]);
+
+        let response = get(&router, "/api/payments?metadata_key=order_id").await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_store_customer_reference() {
+        let app = TestApp::new().await;
+
+        let request_body = PaymentRequestBuilder::new()
+            .customer_reference("customer-42")
+            .build();
+        let payment = app.create_payment(&request_body).await;
+        assert_eq!(payment.status(), 201);
+        let payment = deserialize_response_body::<ResponseBody>(payment)
+            .await
+            .data;
+        assert_eq!(payment.customer_reference.as_deref(), Some("customer-42"));
+
+        let response = get(&app.router, format!("/api/payments/{}", payment.id)).await;
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(
+            response_body.data.customer_reference.as_deref(),
+            Some("customer-42")
+        );
+
+        // empty references are stored as missing
+        let request_body = PaymentRequestBuilder::new().customer_reference("").build();
+        let payment = app.create_payment(&request_body).await;
+        assert_eq!(payment.status(), 201);
+        let payment = deserialize_response_body::<serde_json::Value>(payment).await;
+        assert!(payment["data"].get("customer_reference").is_none());
+    }
+
+    #[tokio::test]
+    async fn should_list_payments_by_customer_reference() {
+        let app = TestApp::new().await;
+        let customer_reference = Uuid::new_v4().to_string();
+
+        let mut payments = Vec::new();
+        for _ in 0..2 {
+            let request_body = PaymentRequestBuilder::new()
+                .customer_reference(&customer_reference)
+                .build();
+            let response = app.create_payment(&request_body).await;
+            assert_eq!(response.status(), 201);
+            payments.push(
+                deserialize_response_body::<ResponseBody>(response)
+                    .await
+                    .data,
+            );
+        }
+        let other = PaymentRequestBuilder::new()
+            .customer_reference(&Uuid::new_v4().to_string())
+            .build();
+        assert_eq!(app.create_payment(&other).await.status(), 201);
+
+        let uri = format!("/api/payments?customer_reference={customer_reference}");
+        let response = get(&app.router, uri).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        payments.reverse();
+        assert_eq!(response_body.data, payments);
+    }
+
+    /// Creates two approved payments and a declined one of a new customer,
+    /// returning the customer reference.
+    async fn seed_customer_payments(app: &TestApp) -> String {
+        let customer_reference = Uuid::new_v4().to_string();
+
+        for _ in 0..2 {
+            let request_body = PaymentRequestBuilder::new()
+                .customer_reference(&customer_reference)
+                .build();
+            assert_eq!(app.create_payment(&request_body).await.status(), 201);
+        }
+
+        let declining = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+        let request_body = PaymentRequestBuilder::new()
+            .customer_reference(&customer_reference)
+            .build();
+        let response = post(&declining, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 402);
+
+        customer_reference
+    }
+
+    #[tokio::test]
+    async fn should_list_payments_in_any_of_several_statuses() {
+        let app = TestApp::new().await;
+        let customer_reference = seed_customer_payments(&app).await;
+
+        for (statuses, expected) in [
+            ("", 3),
+            ("&status=declined", 1),
+            ("&status=approved&status=declined", 3),
+            ("&status=failed", 0),
+        ] {
+            let uri = format!("/api/payments?customer_reference={customer_reference}{statuses}");
+            let response = get(&app.router, uri).await;
+            assert_eq!(response.status(), 200, "{statuses}");
+
+            let response_body =
+                deserialize_response_body::<Paginated<ResponseData>>(response).await;
+            assert_eq!(response_body.data.len(), expected, "{statuses}");
+        }
+
+        let response = get(&app.router, "/api/payments?status=refunded").await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_status"));
+    }
+
+    #[tokio::test]
+    async fn should_only_count_payments_when_asked() {
+        let app = TestApp::new().await;
+        let customer_reference = seed_customer_payments(&app).await;
+
+        for (params, expected) in [
+            ("", 3),
+            ("&status=approved", 2),
+            ("&status=approved&status=declined", 3),
+            // the pagination is ignored, even when invalid
+            ("&status=approved&limit=1", 2),
+            ("&limit=0&cursor=abc", 3),
+            ("&exact=true", 3),
+        ] {
+            let uri = format!(
+                "/api/payments?customer_reference={customer_reference}&count_only=true{params}"
+            );
+            let response = get(&app.router, uri).await;
+            assert_eq!(response.status(), 200, "{params}");
+
+            let response_body = deserialize_response_body::<CountResponseBody>(response).await;
+            assert_eq!(response_body.count, expected, "{params}");
+            assert_eq!(response_body.count_kind, CountKind::Exact, "{params}");
+        }
+
+        // counts of a merchant's payments can't be estimated
+        let uri = format!(
+            "/api/payments?customer_reference={customer_reference}&count_only=true&exact=false"
+        );
+        let response = get(&app.router, uri).await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("estimate_unavailable"));
+    }
+
+    #[tokio::test]
+    async fn should_count_seeded_payments_exactly() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let merchant_id = seed_merchant_payments(&pool, 1_000).await;
+
+        let response = get_as(&router, "/api/payments?count_only=true", merchant_id).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<CountResponseBody>(response).await;
+        assert_eq!(
+            response_body,
+            CountResponseBody {
+                count: 1_000,
+                count_kind: CountKind::Exact,
+            }
+        );
+
+        let response = admin_get(&router, "/api/admin/payments/count?exact=true").await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<CountResponseBody>(response).await;
+        assert_eq!(response_body.count_kind, CountKind::Exact);
+        assert!(response_body.count >= 1_000, "{}", response_body.count);
+    }
+
+    #[tokio::test]
+    async fn should_estimate_count_of_every_payment_when_asked() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        seed_merchant_payments(&pool, 1_000).await;
+        sqlx::query("ANALYZE payments")
+            .execute(&pool)
+            .await
+            .expect("failed to analyze payments");
+
+        let started = std::time::Instant::now();
+        let response = admin_get(&router, "/api/admin/payments/count?exact=false").await;
+        assert!(
+            started.elapsed() < Duration::from_secs(1),
+            "estimate too slow"
+        );
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<CountResponseBody>(response).await;
+        assert_eq!(response_body.count_kind, CountKind::Estimated);
+        assert!(response_body.count > 0);
+
+        let response = admin_get(&router, "/api/admin/payments/count?exact=maybe").await;
+        assert_eq!(response.status(), 400);
+
+        // platform-wide volumes are for operators only
+        let response = get(&router, "/api/admin/payments/count?exact=false").await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_too_long_customer_reference() {
+        let app = TestApp::new().await;
+
+        let longest = "é".repeat(MAX_CUSTOMER_REFERENCE_LENGTH);
+        let request_body = PaymentRequestBuilder::new()
+            .customer_reference(&longest)
+            .build();
+        assert_eq!(app.create_payment(&request_body).await.status(), 201);
+
+        let request_body = PaymentRequestBuilder::new()
+            .customer_reference(&format!("{longest}e"))
+            .build();
+        let response = app.create_payment(&request_body).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_body"));
+        assert_eq!(
+            response_body.details.unwrap()["errors"][0]["field"],
+            "payment.customer_reference"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_store_payment_source() {
+        let app = TestApp::new().await;
+
+        let request_body = PaymentRequestBuilder::new().source("mobile").build();
+        let response = app.create_payment(&request_body).await;
+        assert_eq!(response.status(), 201);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.source, Source::Mobile);
+
+        let response = get(
+            &app.router,
+            format!("/api/payments/{}", response_body.data.id),
+        )
+        .await;
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.source, Source::Mobile);
+
+        // payments not telling their source are made through the API
+        let response_body = app.create_approved_payment().await;
+        assert_eq!(response_body.data.source, Source::Api);
+    }
+
+    #[tokio::test]
+    async fn should_list_payments_by_source() {
+        let app = TestApp::new().await;
+        let customer_reference = Uuid::new_v4().to_string();
+        let mut backoffice = HashSet::new();
+        for source in ["backoffice", "web", "backoffice"] {
+            let request_body = PaymentRequestBuilder::new()
+                .customer_reference(&customer_reference)
+                .source(source)
+                .build();
+            let response = app.create_payment(&request_body).await;
+            let payment = deserialize_response_body::<ResponseBody>(response).await;
+            if source == "backoffice" {
+                backoffice.insert(payment.data.id);
+            }
+        }
+
+        let uri =
+            format!("/api/payments?customer_reference={customer_reference}&source=backoffice");
+        let response = get(&app.router, &uri).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        let ids = response_body
+            .data
+            .iter()
+            .map(|payment| payment.id)
+            .collect::<HashSet<_>>();
+        assert_eq!(ids, backoffice);
+
+        let uri = format!("{uri}&count_only=true");
+        let response = get(&app.router, &uri).await;
+        let response_body = deserialize_response_body::<CountResponseBody>(response).await;
+        assert_eq!(response_body.count, 2);
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_unknown_source() {
+        let app = TestApp::new().await;
+
+        let request_body = PaymentRequestBuilder::new().source("fax").build();
+        let response = app.create_payment(&request_body).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_source"));
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "allowed": ["web", "mobile", "backoffice", "api"] }))
+        );
+
+        let response = get(&app.router, "/api/payments?source=fax").await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_source"));
+    }
+
+    #[tokio::test]
+    async fn should_sort_payments_by_amount() {
+        let app = TestApp::new().await;
+        let batch = Uuid::new_v4().to_string();
+        let create = |amount| {
+            let request_body = PaymentRequestBuilder::new()
+                .amount(amount)
+                .metadata(serde_json::json!({ "batch": batch }))
+                .build();
+            let app = &app;
+            async move {
+                let response = app.create_payment(&request_body).await;
+                assert_eq!(response.status(), 201);
+                deserialize_response_body::<ResponseBody>(response)
+                    .await
+                    .data
+            }
+        };
+        let list = |query: String| {
+            let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}&{query}");
+            let app = &app;
+            async move { get(&app.router, uri).await }
+        };
+
+        let mut payments = Vec::new();
+        for amount in [300, 100, 200, 100] {
+            payments.push(create(amount).await);
+        }
+
+        let response = list("sort=amount&order=asc&limit=2".to_string()).await;
+        assert_eq!(response.status(), 200);
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        // ties are ordered by insertion time
+        assert_eq!(
+            page.data.iter().map(|p| p.id).collect::<Vec<_>>(),
+            [payments[1].id, payments[3].id]
+        );
+        let cursor = page.page_info.next_cursor.expect("missing next cursor");
+
+        // payments sorted before the cursor don't shift the next page
+        create(50).await;
+
+        let response = list(format!("sort=amount&order=asc&limit=2&cursor={cursor}")).await;
+        assert_eq!(response.status(), 200);
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(
+            page.data.iter().map(|p| p.id).collect::<Vec<_>>(),
+            [payments[2].id, payments[0].id]
+        );
+        assert!(!page.page_info.has_more);
+
+        let response = list("sort=amount&order=desc".to_string()).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(
+            page.data.iter().map(|p| p.amount).collect::<Vec<_>>(),
+            [300, 200, 100, 100, 50]
+        );
+
+        // every payment of the batch is approved, so only ties are left
+        let response = list("sort=status&order=asc&limit=3".to_string()).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        let cursor = page.page_info.next_cursor.expect("missing next cursor");
+        let response = list(format!("sort=status&order=asc&cursor={cursor}")).await;
+        let next_page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        let ids = page
+            .data
+            .iter()
+            .chain(&next_page.data)
+            .map(|p| p.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 5);
+        assert_eq!(&ids[..4], payments.iter().map(|p| p.id).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn should_reject_cursor_of_other_sort() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = seed_batch(&router, 2).await;
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}&limit=1");
+
+        let response = get(&router, format!("{uri}&sort=amount")).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        let amount_cursor = page.page_info.next_cursor.expect("missing next cursor");
+
+        let response = get(&router, &uri).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        let default_cursor = page.page_info.next_cursor.expect("missing next cursor");
+
+        for query in [
+            format!("cursor={amount_cursor}"),
+            format!("sort=amount&order=asc&cursor={amount_cursor}"),
+            format!("sort=status&cursor={amount_cursor}"),
+            format!("sort=amount&cursor={default_cursor}"),
+        ] {
+            let response = get(&router, format!("{uri}&{query}")).await;
+            assert_eq!(response.status(), 400, "{query}");
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                body.code.as_deref(),
+                Some("cursor_sort_mismatch"),
+                "{query}"
+            );
+        }
+
+        let response = get(&router, format!("{uri}&sort=amount&cursor={amount_cursor}")).await;
+        assert_eq!(response.status(), 200);
+
+        for query in [
+            "sort=card_number",
+            "sort=amount%3BDROP%20TABLE%20payments",
+            "order=up",
+        ] {
+            let response = get(&router, format!("{uri}&{query}")).await;
+            assert_eq!(response.status(), 400, "{query}");
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(body.code.as_deref(), Some("invalid_sort"), "{query}");
+        }
+    }
+
+    /// Account service taking a while to place holds, recording how many
+    /// holds were being placed at once.
+    #[derive(Clone, Default)]
+    struct SlowService {
+        dummy: DummyService,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl AccountService for SlowService {
+        async fn place_hold(&self, account_number: &str, amount: i64) -> Result<HoldRef, String> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.dummy.place_hold(account_number, amount).await
+        }
+
+        async fn release_hold(&self, hold_ref: HoldRef) -> Result<(), String> {
+            self.dummy.release_hold(hold_ref).await
+        }
+
+        async fn withdraw_funds(&self, hold_ref: HoldRef) -> Result<(), String> {
+            self.dummy.withdraw_funds(hold_ref).await
+        }
+
+        async fn deposit_funds(&self, account_number: &str, amount: i64) -> Result<(), String> {
+            self.dummy.deposit_funds(account_number, amount).await
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_hold_connections_during_account_calls() {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be in environment"))
+            .await
+            .unwrap();
+        let slow_service = SlowService::default();
+        let router = BankWeb::new(pool, slow_service.clone()).into_router();
+
+        let payments = (0..6).map(|_| make_payment(router.clone(), Card::new_test()));
+        let statuses = futures::future::join_all(payments).await;
+
+        assert!(statuses.iter().all(|status| *status == 201), "{statuses:?}");
+        assert!(
+            slow_service.max_in_flight.load(Ordering::SeqCst) > 2,
+            "account calls should not be limited by the pool size"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_location_of_declined_payment() {
+        let router = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+
+        let card = Card::new_test();
+        let request_body = PaymentRequestBuilder::new().card(card.clone()).build();
+
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 402);
+
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(location, format!("/api/payments/{}", response_body.data.id));
+
+        let response = get(&router, location).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, Status::Declined);
+        assert_eq!(response_body.data.card_number, card.masked());
+    }
+
+    #[tokio::test]
+    async fn should_return_stats_per_day_and_status() {
+        let pool = crate::pg_pool().await.unwrap();
+        let router = BankWeb::new_test().await.into_router();
+
+        // a merchant of its own, so payments of other tests are left out
+        let (merchant_id, other_merchant_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let day = time::Date::from_calendar_date(2023, time::Month::January, 1)
+            .unwrap()
+            .midnight()
+            .assume_utc();
+
+        for (merchant_id, hours, amount, currency, status) in [
+            (merchant_id, 1, 100, "USD", Status::Approved),
+            (merchant_id, 5, 200, "USD", Status::Approved),
+            (merchant_id, 6, 50, "USD", Status::Failed),
+            (merchant_id, 25, 300, "USD", Status::Approved),
+            (merchant_id, 26, 20, "USD", Status::Declined),
+            (merchant_id, 27, 30, "USD", Status::Declined),
+            (merchant_id, 28, 40, "EUR", Status::Declined),
+            (other_merchant_id, 2, 1000, "USD", Status::Approved),
+        ] {
+            let request_body = PaymentRequestBuilder::new()
+                .amount(amount)
+                .currency(currency)
+                .build();
+            let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+            let id = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data
+                .id;
+
+            // latencies are the amounts, only approved payments withdrawing
+            let withdraw_latency = (status == Status::Approved).then_some(amount);
+            sqlx::query(
+                r#"
+                    UPDATE payments SET inserted_at = $2, status = $3, hold_latency_ms = $4,
+                      withdraw_latency_ms = $5
+                    WHERE id = $1
+                "#,
+            )
+            .bind(id)
+            .bind(to_utc(day + time::Duration::hours(hours)))
+            .bind(status)
+            .bind(amount)
+            .bind(withdraw_latency)
+            .execute(&pool)
+            .await
+            .expect("failed to backdate payment");
+        }
+
+        let format = |datetime: OffsetDateTime| {
+            datetime
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap()
+        };
+        let uri = format!(
+            "/api/payments/stats?from={}&to={}&group_by=day",
+            format(day),
+            format(day + time::Duration::days(2))
+        );
+        let response = get_as(&router, uri, merchant_id).await;
+        assert_eq!(response.status(), 200);
+
+        let stats = |status, count, total_amount| StatusStats {
+            status,
+            count,
+            total_amount,
+        };
+        let response_body = deserialize_response_body::<StatsResponseBody>(response).await;
+        assert_eq!(
+            response_body.data,
+            vec![
+                BucketStats {
+                    bucket: Some(day),
+                    source: None,
+                    currency: "USD".to_string(),
+                    statuses: vec![
+                        stats(Status::Approved, 2, 300),
+                        stats(Status::Failed, 1, 50),
+                    ],
+                },
+                BucketStats {
+                    bucket: Some(day + time::Duration::days(1)),
+                    source: None,
+                    currency: "EUR".to_string(),
+                    statuses: vec![stats(Status::Declined, 1, 40)],
+                },
+                BucketStats {
+                    bucket: Some(day + time::Duration::days(1)),
+                    source: None,
+                    currency: "USD".to_string(),
+                    statuses: vec![
+                        stats(Status::Approved, 1, 300),
+                        stats(Status::Declined, 2, 50),
+                    ],
+                },
+            ]
+        );
+        assert_eq!(
+            response_body.latency,
+            AccountLatencyStats {
+                hold_ms: LatencyStats {
+                    p50: Some(50),
+                    p95: Some(270),
+                },
+                withdraw_ms: LatencyStats {
+                    p50: Some(200),
+                    p95: Some(290),
+                },
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_stats_per_source_and_status() {
+        let pool = crate::pg_pool().await.unwrap();
+        let router = BankWeb::new_test().await.into_router();
+
+        // a merchant of its own, so payments of other tests are left out
+        let merchant_id = Uuid::new_v4();
+        let day = time::Date::from_calendar_date(2023, time::Month::January, 1)
+            .unwrap()
+            .midnight()
+            .assume_utc();
+
+        for (source, amount, status) in [
+            (Some("web"), 100, Status::Approved),
+            (Some("web"), 200, Status::Approved),
+            (Some("mobile"), 50, Status::Declined),
+            (None, 300, Status::Approved),
+        ] {
+            let mut request_body = PaymentRequestBuilder::new().amount(amount);
+            if let Some(source) = source {
+                request_body = request_body.source(source);
+            }
+            let response =
+                post_as(&router, "/api/payments", &request_body.build(), merchant_id).await;
+            let id = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data
+                .id;
+
+            sqlx::query("UPDATE payments SET inserted_at = $2, status = $3 WHERE id = $1")
+                .bind(id)
+                .bind(to_utc(day + time::Duration::hours(1)))
+                .bind(status)
+                .execute(&pool)
+                .await
+                .expect("failed to backdate payment");
+        }
+
+        let format = |datetime: OffsetDateTime| {
+            datetime
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap()
+        };
+        let uri = format!(
+            "/api/payments/stats?from={}&to={}&group_by=source",
+            format(day),
+            format(day + time::Duration::days(1))
+        );
+        let response = get_as(&router, uri, merchant_id).await;
+        assert_eq!(response.status(), 200);
+
+        let stats = |status, count, total_amount| StatusStats {
+            status,
+            count,
+            total_amount,
+        };
+        let by_source = |source, statuses| BucketStats {
+            bucket: None,
+            source: Some(source),
+            currency: "USD".to_string(),
+            statuses,
+        };
+        let response_body = deserialize_response_body::<StatsResponseBody>(response).await;
+        assert_eq!(
+            response_body.data,
+            vec![
+                by_source(Source::Web, vec![stats(Status::Approved, 2, 300)]),
+                by_source(Source::Mobile, vec![stats(Status::Declined, 1, 50)]),
+                by_source(Source::Api, vec![stats(Status::Approved, 1, 300)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_invalid_stats_group_by() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let uri =
+            "/api/payments/stats?from=2023-01-01T00:00:00Z&to=2023-01-02T00:00:00Z&group_by=week";
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    async fn seed_batch(router: &axum::Router, size: usize) -> String {
+        let batch = Uuid::new_v4().to_string();
+
+        for _ in 0..size {
+            let response = post_with_metadata(router, serde_json::json!({ "batch": batch })).await;
+            assert_eq!(response.status(), 201);
+        }
+
+        batch
+    }
+
+    async fn read_csv(
+        response: hyper::Response<
+            http_body::combinators::UnsyncBoxBody<axum::body::Bytes, axum::Error>,
+        >,
+    ) -> Vec<CsvRow> {
+        let bytes = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("failed to read response body into bytes");
+
+        read_csv_bytes(&bytes)
+    }
+
+    fn read_csv_bytes(bytes: &[u8]) -> Vec<CsvRow> {
+        let mut reader = csv::Reader::from_reader(bytes);
+        assert_eq!(
+            reader.headers().expect("missing CSV headers"),
+            CSV_HEADERS.as_slice()
+        );
+
+        reader
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .expect("failed to parse CSV")
+    }
+
+    #[tokio::test]
+    async fn should_export_payments_as_csv() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = seed_batch(&router, 3).await;
+
+        let request = axum::http::Request::builder()
+            .uri(format!(
+                "/api/payments?metadata_key=batch&metadata_value={batch}"
+            ))
+            .header(ACCEPT, "text/csv")
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = crate::bank_web::tests::send_request(&router, request).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv; charset=utf-8");
+
+        let disposition = response.headers()[CONTENT_DISPOSITION].to_str().unwrap();
+        assert!(
+            disposition.starts_with("attachment; filename=\"payments-"),
+            "{disposition}"
+        );
+
+        let rows = read_csv(response).await;
+        assert_eq!(rows.len(), 3);
+        assert!(rows
+            .iter()
+            .all(|row| row.card_number[2..11] == *"*********" && row.amount == 123));
+    }
+
+    /// Inserts `count` approved payments of a new merchant straight into the
+    /// database, returning the merchant's id.
+    async fn seed_merchant_payments(pool: &PgPool, count: i32) -> Uuid {
+        let merchant_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+                INSERT INTO payments ( amount, card_fingerprint, card_number, status, merchant_id )
+                SELECT 123, md5($1 || i) || md5(i::text), '42*********4242', 'Approved', $2
+                FROM generate_series(1, $3) AS i
+            "#,
+            merchant_id.to_string(),
+            merchant_id,
+            count
+        )
+        .execute(pool)
+        .await
+        .expect("failed to seed payments");
+        merchant_id
+    }
+
+    #[tokio::test]
+    async fn should_stream_large_csv_exports() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let merchant_id = seed_merchant_payments(&pool, 10_000).await;
+
+        let response = get_as(&router, "/api/payments?format=csv", merchant_id).await;
+        assert_eq!(response.status(), 200);
+        let rows = read_csv(response).await;
+        assert_eq!(rows.len(), 10_000);
+        let ids: HashSet<_> = rows.iter().map(|row| row.id).collect();
+        assert_eq!(ids.len(), 10_000);
+    }
+
+    #[tokio::test]
+    async fn should_release_connection_when_csv_export_is_dropped() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let merchant_id = seed_merchant_payments(&pool, 10_000).await;
+
+        let response = get_as(&router, "/api/payments?format=csv", merchant_id).await;
+        assert_eq!(response.status(), 200);
+        let mut body = response.into_body();
+        hyper::body::HttpBody::data(&mut body)
+            .await
+            .expect("missing first chunk")
+            .expect("failed to read first chunk");
+        drop(body);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.num_idle() < pool.size() as usize {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("export kept its connection after the client went away");
+    }
+
+    #[tokio::test]
+    async fn should_neutralize_hostile_metadata_in_csv_export() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = Uuid::new_v4().to_string();
+        let metadata = serde_json::json!({
+            "batch": batch,
+            "=cmd": "=HYPERLINK(\"http://evil.example\", \"click\")",
+            "note": "@SUM(A1:A2),\"quoted\"\r\n-1+1",
+        });
+        let response = post_with_metadata(&router, metadata.clone()).await;
+        assert_eq!(response.status(), 201);
+
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}&format=csv");
+        let response = get(&router, uri).await;
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        let mut reader = csv::Reader::from_reader(bytes.as_ref());
+        let records: Vec<_> = reader
+            .records()
+            .collect::<Result<_, _>>()
+            .expect("failed to parse CSV");
+        assert_eq!(records.len(), 1);
+        for cell in &records[0] {
+            assert!(!cell.starts_with(['=', '+', '-', '@']), "{cell}");
+        }
+
+        let rows = read_csv_bytes(&bytes);
+        let exported: serde_json::Value =
+            serde_json::from_str(rows[0].metadata.as_deref().expect("missing metadata"))
+                .expect("metadata isn't JSON");
+        assert_eq!(exported, metadata);
+    }
+
+    #[tokio::test]
+    async fn should_export_payments_as_csv_with_format_parameter() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = seed_batch(&router, 2).await;
+
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}&format=csv");
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(read_csv(response).await.len(), 2);
+
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}");
+        let response = get(&router, uri).await;
+        let response_body = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(response_body.data.len(), 2, "JSON remains the default");
+
+        let response = get(&router, "/api/payments?format=xml").await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_page_through_payments() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = seed_batch(&router, 3).await;
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}&limit=2");
+
+        let response = get(&router, &uri).await;
+        assert_eq!(response.status(), 200);
+        let first_page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(first_page.data.len(), 2);
+        assert!(first_page.page_info.has_more);
+
+        let cursor = first_page.page_info.next_cursor.expect("missing cursor");
+        let response = get(&router, format!("{uri}&cursor={cursor}")).await;
+        assert_eq!(response.status(), 200);
+        let last_page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(last_page.data.len(), 1);
+        assert!(!last_page.page_info.has_more);
+        assert_eq!(last_page.page_info.next_cursor, None);
+
+        let ids: std::collections::HashSet<_> = first_page
+            .data
+            .iter()
+            .chain(&last_page.data)
+            .map(|payment| payment.id)
+            .collect();
+        assert_eq!(ids.len(), 3, "pages should not overlap");
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_invalid_pagination() {
+        let router = BankWeb::new_test().await.into_router();
+
+        for (uri, code) in [
+            ("/api/payments?cursor=abc", "invalid_cursor"),
+            ("/api/payments?limit=0", "invalid_limit"),
+            ("/api/payments?limit=101", "invalid_limit"),
+        ] {
+            let response = get(&router, uri).await;
+            assert_eq!(response.status(), 400, "{uri}");
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.code.as_deref(), Some(code), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_payment_with_etag() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let payment_id = payments::tests::new_processing_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        let response = get(&router, &uri).await;
+        assert_eq!(response.status(), 200);
+        let etag = response.headers()[ETAG].clone();
+
+        let response = get_if_none_match(&router, &uri, &etag).await;
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.headers()[ETAG], etag);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        payments::transition(&pool, payment_id, Status::Processing, Status::Authorized)
+            .await
+            .expect("failed to transition payment");
+
+        let response = get_if_none_match(&router, &uri, &etag).await;
+        assert_eq!(response.status(), 200);
+        assert_ne!(response.headers()[ETAG], etag);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, Status::Authorized);
+    }
+
+    #[tokio::test]
+    async fn should_answer_head_with_headers_only() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let payment_id = payments::tests::new_processing_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        let etag = get(&router, &uri).await.headers()[ETAG].clone();
+
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::HEAD)
+            .uri(&uri)
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = send_request(&router, request).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[ETAG], etag);
+        assert_ne!(response.headers()[CONTENT_LENGTH], "0");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
     }
 
     #[tokio::test]
-    async fn should_approve_valid_payment() {
+    async fn should_return_413_for_oversized_body() {
+        let router = BankWeb::new_test().await.into_router();
+        let mut request_body = PaymentRequestBuilder::new().build();
+        request_body.payment.card_number =
+            "4".repeat(BankWeb::<DummyService>::DEFAULT_MAX_BODY_SIZE);
+        let bytes = serde_json::to_vec(&request_body).unwrap();
+
+        // without a Content-Length, the limit is only hit while reading the body
+        for content_length in [None, Some(bytes.len())] {
+            let mut request = axum::http::Request::builder()
+                .method(axum::http::Method::POST)
+                .uri("/api/payments")
+                .header(CONTENT_TYPE, "application/json")
+                .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string());
+            if let Some(content_length) = content_length {
+                request = request.header(CONTENT_LENGTH, content_length);
+            }
+            let request = request.body(bytes.clone().into()).unwrap();
+
+            let response = send_request(&router, request).await;
+            assert_eq!(response.status(), 413, "{content_length:?}");
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                response_body.code.as_deref(),
+                Some("payload_too_large"),
+                "{content_length:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_compress_responses_when_accepted() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = seed_batch(&router, 3).await;
+
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::GET)
+            .uri(format!(
+                "/api/payments?metadata_key=batch&metadata_value={batch}"
+            ))
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = send_request(&router, request).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let decoder = flate2::read::GzDecoder::new(&bytes[..]);
+        let response_body = serde_json::from_reader::<_, Paginated<ResponseData>>(decoder).unwrap();
+        assert_eq!(response_body.data.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_report_unknown_payment_fields_on_v1_only() {
         let router = BankWeb::new_test().await.into_router();
 
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: 1205,
-                card_number: Card::new_test().into(),
+        let request_body = serde_json::json!({
+            "payment": {
+                "amount": 1205,
+                "card_number": String::from(Card::new_test()),
+                "curency": "EUR",
             },
-        };
+        });
+
+        let response = post(&router, "/api/v1/payments", &request_body).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("unknown_field"));
+        assert_eq!(
+            response_body.details.unwrap()["errors"][0]["field"],
+            "payment.curency"
+        );
+        assert!(response_body
+            .error
+            .starts_with("payment.curency: unknown field `curency`"));
 
         let response = post(&router, "/api/payments", &request_body).await;
         assert_eq!(response.status(), 201);
-
         let response_body = deserialize_response_body::<ResponseBody>(response).await;
-        assert_eq!(response_body.data.amount, request_body.payment.amount);
+        assert_eq!(response_body.data.currency, "USD");
+    }
 
-        let uri = format!("/api/payments/{}", response_body.data.id);
-        let response = get(&router, uri).await;
+    #[tokio::test]
+    async fn should_name_the_offending_field_of_invalid_bodies() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let cases = [
+            (
+                serde_json::json!({ "payment": { "card_number": String::from(Card::new_test()) } }),
+                "payment: missing field `amount`",
+            ),
+            (
+                serde_json::json!({
+                    "payment": { "amount": "12.345", "card_number": String::from(Card::new_test()) },
+                }),
+                "payment.amount: amount should have at most 2 fraction digits",
+            ),
+            (
+                serde_json::json!({
+                    "payment": { "amount": 12.05, "card_number": String::from(Card::new_test()) },
+                }),
+                "payment.amount: invalid type: floating point `12.05`, expected an integer of minor units or a decimal string",
+            ),
+        ];
+        for (request_body, error) in cases {
+            let response = post(&router, "/api/payments", &request_body).await;
+            assert_eq!(response.status(), 422);
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.error, error);
+            assert_eq!(response_body.code.as_deref(), Some("invalid_body"));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_accept_decimal_string_amounts() {
+        let app = TestApp::new().await;
+
+        let request_body = serde_json::json!({
+            "payment": { "amount": "12.05", "card_number": String::from(Card::new_test()) },
+        });
+        let response = post(&app.router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let payment = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(payment.amount, 1205);
+
+        for amount in ["12.345", "1e5", "-0.00"] {
+            let request_body = serde_json::json!({
+                "payment": { "amount": amount, "card_number": String::from(Card::new_test()) },
+            });
+            let response = post(&app.router, "/api/payments", &request_body).await;
+            assert_eq!(response.status(), 422, "{amount}");
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                response_body.details.unwrap()["errors"][0]["field"],
+                "payment.amount",
+                "{amount}"
+            );
+        }
+
+        let refunds_uri = format!("/api/payments/{}/refunds", payment.id);
+        let request_body = serde_json::json!({ "refund": { "amount": "0.05" } });
+        let response = post(&app.router, &refunds_uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let refund = deserialize_response_body::<serde_json::Value>(response).await;
+        assert_eq!(refund["data"]["amount"], 5);
+
+        let request_body = serde_json::json!({ "refund": { "amount": "0.055" } });
+        let response = post(&app.router, &refunds_uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(
+            response_body.details.unwrap()["errors"][0]["field"],
+            "refund.amount"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_report_invalid_json_syntax() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::POST)
+            .uri("/api/payments")
+            .header(CONTENT_TYPE, "application/json")
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .body(hyper::Body::from(r#"{"payment": {"amount": 1205,}}"#))
+            .unwrap();
+        let response = send_request(&router, request).await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_json"));
+        assert!(response_body
+            .error
+            .starts_with("Request body isn't valid JSON: trailing comma"));
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "line": 1, "column": 29 }))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_search_payments_by_card_suffix() {
+        let router = BankWeb::new_test().await.into_router();
+        let merchant_id = Uuid::new_v4();
+        let card = Card::new_test();
+        let suffix = &card.card_number()[card.card_number().len() - 4..];
+
+        let request_body = PaymentRequestBuilder::new().card(card.clone()).build();
+        let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+        assert_eq!(response.status(), 201);
+        let payment_id = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        let uri = format!("/api/payments/search?card_last4={suffix}");
+        let response = get_as(&router, &uri, merchant_id).await;
         assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<SearchResponseBody>(response).await;
+        assert_eq!(response_body.data.len(), 1);
+        assert_eq!(response_body.data[0].id, payment_id);
+        assert_eq!(response_body.data[0].card_number, card.masked());
 
-        let response_body = deserialize_response_body::<ResponseBody>(response).await;
-        assert_eq!(response_body.data.amount, request_body.payment.amount);
-        assert_eq!(response_body.data.status, Status::Approved);
+        let response = get(&router, &uri).await;
+        let response_body = deserialize_response_body::<SearchResponseBody>(response).await;
+        assert!(response_body.data.iter().all(|p| p.id != payment_id));
     }
 
     #[tokio::test]
-    async fn should_decline_payment_and_return_402_with_insufficient_funds() {
+    async fn should_require_four_digits_to_search_payments() {
+        let router = BankWeb::new_test().await.into_router();
+
+        for uri in [
+            "/api/payments/search",
+            "/api/payments/search?card_last4=123",
+            "/api/payments/search?card_last4=12345",
+            "/api/payments/search?card_last4=12a4",
+        ] {
+            let response = get(&router, uri).await;
+            assert_eq!(response.status(), 400, "{uri}");
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.code.as_deref(), Some("invalid_card_last4"));
+        }
+    }
+
+    /// Keys of the JSON object `value`.
+    fn keys(value: &serde_json::Value) -> Vec<&str> {
+        let object = value.as_object().expect("not an object");
+        object.keys().map(String::as_str).collect()
+    }
+
+    #[tokio::test]
+    async fn should_return_requested_payment_fields_only() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = seed_batch(&router, 2).await;
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}");
+
+        let response = get(&router, format!("{uri}&fields=status,amount")).await;
+        assert_eq!(response.status(), 200);
+        let page = deserialize_response_body::<serde_json::Value>(response).await;
+        let payments = page["data"].as_array().expect("missing payments");
+        assert_eq!(payments.len(), 2);
+        for payment in payments {
+            assert_eq!(keys(payment), ["amount", "id", "status"]);
+        }
+
+        let payment_id = payments[0]["id"].as_str().unwrap();
+        let response = get(
+            &router,
+            format!("/api/payments/{payment_id}?fields=refunded_amount"),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<serde_json::Value>(response).await;
+        assert_eq!(keys(&response_body["data"]), ["id", "refunded_amount"]);
+
+        let response = get(&router, format!("/api/payments/{payment_id}?fields=id")).await;
+        let response_body = deserialize_response_body::<serde_json::Value>(response).await;
+        assert_eq!(keys(&response_body["data"]), ["id"]);
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_unknown_payment_fields() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let response = get(&router, "/api/payments?fields=status,secret").await;
+        assert_eq!(response.status(), 400);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_fields"));
+        let details = response_body.details.expect("missing details");
+        assert_eq!(details["unknown_fields"], serde_json::json!(["secret"]));
+        assert_eq!(
+            details["valid_fields"],
+            serde_json::json!(<ResponseData as Sparse>::FIELDS)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_tag_each_fieldset_of_a_payment() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let payment_id = payments::tests::new_processing_payment(&pool).await;
+        let uri = format!("/api/payments/{payment_id}");
+
+        let response = get(&router, &uri).await;
+        let etag = response.headers()[ETAG].clone();
+
+        let response = get_if_none_match(&router, format!("{uri}?fields=status"), &etag).await;
+        assert_eq!(response.status(), 200);
+        assert_ne!(response.headers()[ETAG], etag);
+    }
+
+    #[tokio::test]
+    async fn should_retry_failed_payment() {
+        let bank_web = BankWeb::new_test().await;
+        let account_service = bank_web.account_service.0.inner().clone();
+        let router = bank_web.into_router();
+
+        // the account service is unavailable for the first hold only
+        account_service.set_scripted_outcome(Some(
+            ScriptedOutcome::new("service_unavailable")
+                .with_method(AccountMethod::Hold)
+                .with_remaining(1.try_into().unwrap()),
+        ));
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 503);
+        let payment = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(payment.status, Status::Failed);
+        assert_eq!(payment.decline_reason, Some(DeclineReason::ServiceFailure));
+
+        let uri = format!("/api/payments/{}/retry", payment.id);
+        let response = post(&router, &uri, &serde_json::json!({})).await;
+        assert_eq!(response.status(), 200);
+        let retried = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(retried.id, payment.id);
+        assert_eq!(retried.status, Status::Approved);
+        assert_eq!(retried.amount, payment.amount);
+        assert_eq!(retried.card_number, payment.card_number);
+        assert_eq!(retried.decline_reason, None);
+
+        let response = get(&router, format!("/api/payments/{}/events", payment.id)).await;
+        let events = deserialize_response_body::<Paginated<EventData>>(response)
+            .await
+            .data
+            .into_iter()
+            .map(|event| (event.from_status, event.to_status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                (Status::Processing, Status::Failed),
+                (Status::Failed, Status::Processing),
+                (Status::Processing, Status::Authorized),
+                (Status::Authorized, Status::Approved),
+            ]
+        );
+
+        // approved payments can't be retried
+        let response = post(&router, &uri, &serde_json::json!({})).await;
+        assert_eq!(response.status(), 409);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("payment_not_retryable"));
+    }
+
+    #[tokio::test]
+    async fn should_return_409_when_retrying_declined_payment() {
         let router = BankWeb::new_test_with_response("insufficient_funds")
             .await
             .into_router();
 
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: 1205,
-                card_number: Card::new_test().into(),
-            },
-        };
-
+        let request_body = PaymentRequestBuilder::new().build();
         let response = post(&router, "/api/payments", &request_body).await;
         assert_eq!(response.status(), 402);
+        let payment_id = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+            .id;
 
-        let response_body = deserialize_response_body::<ResponseBody>(response).await;
-        assert_eq!(response_body.data.amount, request_body.payment.amount);
-        assert_eq!(response_body.data.status, Status::Declined);
+        let uri = format!("/api/payments/{payment_id}/retry");
+        let response = post(&router, uri, &serde_json::json!({})).await;
+        assert_eq!(response.status(), 409);
     }
 
     #[tokio::test]
-    async fn should_decline_payment_and_return_403_for_invalid_account_number() {
-        let router = BankWeb::new_test_with_response("invalid_account_number")
-            .await
-            .into_router();
+    async fn should_archive_payment() {
+        let router = BankWeb::new_test().await.into_router();
+        let batch = seed_batch(&router, 2).await;
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}");
 
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: 1205,
-                card_number: Card::new_test().into(),
-            },
-        };
+        let response = get(&router, &uri).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        let payment_id = page.data[0].id;
+        assert_eq!(page.data[0].archived_at, None);
 
-        let response = post(&router, "/api/payments", &request_body).await;
-        assert_eq!(response.status(), 403);
+        let response = delete(&router, format!("/api/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        let archived_at = response_body.data.archived_at.expect("missing archived_at");
 
+        let response = delete(&router, format!("/api/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 200);
         let response_body = deserialize_response_body::<ResponseBody>(response).await;
-        assert_eq!(response_body.data.amount, request_body.payment.amount);
-        assert_eq!(response_body.data.status, Status::Declined);
+        assert_eq!(response_body.data.archived_at, Some(archived_at));
+
+        let response = get(&router, format!("/api/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.archived_at, Some(archived_at));
+
+        let response = get(&router, &uri).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        let ids: Vec<_> = page.data.iter().map(|payment| payment.id).collect();
+        assert_eq!(ids.len(), 1);
+        assert!(!ids.contains(&payment_id), "archived payments are hidden");
+
+        let response = get(&router, format!("{uri}&include_archived=true")).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(page.data.len(), 2);
+        assert!(page.data.iter().any(|payment| payment.id == payment_id));
     }
 
     #[tokio::test]
-    async fn should_return_204_for_zero_amount() {
-        let router = BankWeb::new_test().await.into_router();
+    async fn should_delete_payments_without_refunds_only() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let app = TestApp::from(bank_web);
 
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: 0,
-                card_number: Card::new_test().into(),
-            },
-        };
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        sqlx::query!(
+            "UPDATE payments SET status = 'Declined' WHERE id = $1",
+            payment.id
+        )
+        .execute(&pool)
+        .await
+        .expect("failed to decline payment");
 
-        let response = post(&router, "/api/payments", &request_body).await;
+        let uri = format!("/api/admin/payments/{}", payment.id);
+        let response = admin_delete(&app.router, &uri).await;
         assert_eq!(response.status(), 204);
+        let response = get_as(
+            &app.router,
+            format!("/api/payments/{}", payment.id),
+            payment.merchant_id,
+        )
+        .await;
+        assert_eq!(response.status(), 404);
+        assert_eq!(admin_delete(&app.router, &uri).await.status(), 404);
+
+        let payment_id = app.create_approved_payment().await.data.id;
+        let request_body = RefundRequestBuilder::new().build();
+        let response = post(
+            &app.router,
+            format!("/api/payments/{payment_id}/refunds"),
+            &request_body,
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+
+        let response = admin_delete(&app.router, format!("/api/admin/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("payment_has_refunds"));
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "refund_count": 1 }))
+        );
     }
 
     #[tokio::test]
-    async fn should_return_422_for_existing_card_number() {
-        let router = BankWeb::new_test().await.into_router();
+    async fn should_return_401_for_delete_without_admin_token() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let app = TestApp::from(bank_web);
 
-        let request_body = RequestBody {
-            payment: RequestData {
-                amount: 123,
-                card_number: Card::new_test().into(),
-            },
-        };
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        sqlx::query!(
+            "UPDATE payments SET status = 'Declined' WHERE id = $1",
+            payment.id
+        )
+        .execute(&pool)
+        .await
+        .expect("failed to decline payment");
+        let uri = format!("/api/admin/payments/{}", payment.id);
 
-        let response = post(&router, "/api/payments", &request_body).await;
-        assert_eq!(response.status(), 201);
+        // the merchant credentials of the public API aren't admin credentials
+        let response = delete(&app.router, &uri).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("unauthorized"));
 
-        let response = post(&router, "/api/payments", &request_body).await;
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::DELETE)
+            .uri(&uri)
+            .header(AUTHORIZATION, "Bearer not-the-admin-token")
+            .body(hyper::Body::empty())
+            .unwrap();
+        assert_eq!(send_request(&app.router, request).await.status(), 401);
+
+        // admin endpoints are closed unless a token is configured
+        let mut bank_web = BankWeb::new_test().await;
+        bank_web.admin_token = None;
+        let router = bank_web.into_router();
+        assert_eq!(admin_delete(&router, &uri).await.status(), 401);
+
+        payments::get(&pool, payment.id)
+            .await
+            .expect("payment should still exist");
+    }
+
+    #[tokio::test]
+    async fn should_bulk_transition_payments_in_expected_status_only() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let app = TestApp::from(bank_web);
+
+        let stuck = payments::tests::new_processing_payment(&pool).await;
+        let approved = app.create_approved_payment().await.data.id;
+        let unknown = Uuid::new_v4();
+
+        let request_body = serde_json::json!({
+            "payment_ids": [stuck, approved, unknown],
+            "from": "processing",
+            "to": "failed",
+        });
+        let response = admin_post(
+            &app.router,
+            "/api/admin/payments/bulk_transition",
+            &request_body,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<BulkTransitionResponseBody>(response).await;
+        assert_eq!(response_body.data.transitioned, vec![stuck]);
+        assert_eq!(response_body.data.skipped, vec![approved, unknown]);
+
+        let payment = payments::get(&pool, stuck)
+            .await
+            .expect("failed to get payment");
+        assert_eq!(payment.status, Status::Failed);
+        let payment = payments::get(&pool, approved)
+            .await
+            .expect("failed to get payment");
+        assert_eq!(payment.status, Status::Approved);
+
+        let request_body = serde_json::json!({
+            "payment_ids": [approved],
+            "from": "approved",
+            "to": "processing",
+        });
+        let response = admin_post(
+            &app.router,
+            "/api/admin/payments/bulk_transition",
+            &request_body,
+        )
+        .await;
         assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("illegal_transition"));
+
+        // captures and voids must go through the account service
+        for to in ["approved", "voided"] {
+            let request_body = serde_json::json!({
+                "payment_ids": [approved],
+                "from": "authorized",
+                "to": to,
+            });
+            let response = admin_post(
+                &app.router,
+                "/api/admin/payments/bulk_transition",
+                &request_body,
+            )
+            .await;
+            assert_eq!(response.status(), 422, "{to}");
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                response_body.code.as_deref(),
+                Some("unsupported_transition")
+            );
+        }
+
+        let request_body = serde_json::json!({
+            "payment_ids": [stuck],
+            "from": "processing",
+            "to": "failed",
+        });
+        let response = post(
+            &app.router,
+            "/api/admin/payments/bulk_transition",
+            &request_body,
+        )
+        .await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn should_archive_payment_at_current_version_only() {
+        let app = TestApp::new().await;
+        let payment = app.create_approved_payment().await.data;
+        let uri = format!("/api/v1/payments/{}", payment.id);
+
+        let response = get(&app.router, &uri).await;
+        let version = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+            .version;
+        assert_eq!(version, payment.version);
+
+        let if_match = format!("\"{version}\"");
+        let response = delete_if_match(&app.router, &uri, &if_match).await;
+        assert_eq!(response.status(), 200);
+        let archived = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(archived.version, version + 1);
+
+        // the second archive acts on the payment as it was before the first
+        let response = delete_if_match(&app.router, &uri, &if_match).await;
+        assert_eq!(response.status(), 412);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("version_mismatch"));
+
+        let if_match = format!("\"{}\"", archived.version);
+        let response = delete_if_match(&app.router, &uri, &if_match).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_require_if_match_to_archive_on_v1_only() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let response = delete(&app.router, format!("/api/v1/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 428);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("if_match_required"));
+
+        let response = delete(&app.router, format!("/api/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_return_409_when_archiving_pending_payment() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let payment_id = payments::tests::new_processing_payment(&pool).await;
 
+        let response = delete(&router, format!("/api/payments/{payment_id}")).await;
+        assert_eq!(response.status(), 409);
         let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
-        assert_eq!(response_body.error, "card_number already used");
+        assert_eq!(response_body.code.as_deref(), Some("payment_pending"));
+
+        let payment = payments::get(&pool, payment_id).await.unwrap();
+        assert_eq!(payment.archived_at, None);
+    }
+
+    #[tokio::test]
+    async fn should_scope_payments_to_their_merchant() {
+        let router = BankWeb::new_test().await.into_router();
+        let merchant_id = Uuid::new_v4();
+        let batch = Uuid::new_v4().to_string();
+
+        let request_body = PaymentRequestBuilder::new()
+            .metadata(serde_json::json!({ "batch": batch }))
+            .build();
+        let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+        assert_eq!(response.status(), 201);
+        let payment = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(payment.merchant_id, merchant_id);
+
+        let uri = format!("/api/payments/{}", payment.id);
+        assert_eq!(get_as(&router, &uri, merchant_id).await.status(), 200);
+        assert_eq!(get(&router, &uri).await.status(), 404);
+        assert_eq!(get(&router, format!("{uri}/events")).await.status(), 404);
+
+        let uri = format!("/api/payments?metadata_key=batch&metadata_value={batch}");
+        let response = get_as(&router, &uri, merchant_id).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert_eq!(page.data.len(), 1);
+        let response = get(&router, &uri).await;
+        let page = deserialize_response_body::<Paginated<ResponseData>>(response).await;
+        assert!(page.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_require_merchant_id() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let request = axum::http::Request::builder()
+            .uri("/api/payments")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = send_request(&router, request).await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("missing_merchant_id"));
+
+        let request = axum::http::Request::builder()
+            .uri("/api/payments")
+            .header(MERCHANT_ID_HEADER, "acme")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = send_request(&router, request).await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_merchant_id"));
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_malformed_payment_id() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let response = patch(&router, "/api/payments/not-a-uuid", &amend_request(10)).await;
+        assert_eq!(response.status(), 400);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("invalid_path_parameter"));
+
+        for uri in [
+            "/api/payments/not-a-uuid/events",
+            "/api/payments/not-a-uuid/notes",
+            "/api/payments/not-a-uuid/receipt",
+            "/api/payments/not-a-uuid/refunds",
+        ] {
+            let response = get(&router, uri).await;
+            assert_eq!(response.status(), 400, "{uri}");
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                body.code.as_deref(),
+                Some("invalid_path_parameter"),
+                "{uri}"
+            );
+        }
     }
 }
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, State},
+    http::{header::RETRY_AFTER, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body::{LengthLimitError, Limited};
+use uuid::Uuid;
+
+use super::{
+    body_too_large, json::ApiVersion, merchant::MerchantId, payments::RequestBody,
+    ErrorResponseBody,
+};
+use crate::bank::payment_instruments::Card;
+
+/// What payment requests are counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    /// The fingerprint of the card paid with.
+    Card(String),
+    /// The merchant paid.
+    Merchant(Uuid),
+}
+
+/// Limits how often payments can be requested for a key.
+///
+/// Implemented in-process by `TokenBuckets`, shared stores can implement it
+/// to limit requests across instances.
+#[async_trait::async_trait]
+pub trait RateLimiter: Send + Sync + 'static {
+    /// Counts a request against `key`, returning how long until the next
+    /// request is allowed if the limit is exceeded.
+    async fn acquire(&self, key: &RateLimitKey) -> Result<(), Duration>;
+}
+
+/// Size and refill rate of the token bucket of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Number of requests allowed in a burst.
+    pub capacity: u32,
+    /// Delay after which a request used up is allowed again.
+    pub refill_interval: Duration,
+}
+
+impl RateLimit {
+    /// Default limit of a card, far above legitimate retries, which are
+    /// rejected within the card reuse window anyway.
+    pub const CARD: RateLimit = RateLimit {
+        capacity: 5,
+        refill_interval: Duration::from_secs(12),
+    };
+    /// Default limit of a merchant.
+    pub const MERCHANT: RateLimit = RateLimit {
+        capacity: 100,
+        refill_interval: Duration::from_millis(10),
+    };
+
+    fn of(key: &RateLimitKey, card: RateLimit, merchant: RateLimit) -> RateLimit {
+        match key {
+            RateLimitKey::Card(_) => card,
+            RateLimitKey::Merchant(_) => merchant,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// In-process token buckets, one per key.
+pub struct TokenBuckets {
+    card: RateLimit,
+    merchant: RateLimit,
+    buckets: Mutex<HashMap<RateLimitKey, Bucket>>,
+}
+
+impl TokenBuckets {
+    /// Number of buckets above which the full ones are dropped, as they are
+    /// no different from missing ones.
+    const PRUNE_THRESHOLD: usize = 10_000;
+
+    pub fn new(card: RateLimit, merchant: RateLimit) -> Self {
+        Self {
+            card,
+            merchant,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn refill(bucket: &mut Bucket, limit: RateLimit, now: Instant) {
+        let elapsed = now.duration_since(bucket.refilled_at);
+        let refilled = elapsed.as_secs_f64() / limit.refill_interval.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(f64::from(limit.capacity));
+        bucket.refilled_at = now;
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimiter for TokenBuckets {
+    async fn acquire(&self, key: &RateLimitKey) -> Result<(), Duration> {
+        let limit = RateLimit::of(key, self.card, self.merchant);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if buckets.len() > Self::PRUNE_THRESHOLD {
+            let (card, merchant) = (self.card, self.merchant);
+            buckets.retain(|key, bucket| {
+                let limit = RateLimit::of(key, card, merchant);
+                Self::refill(bucket, limit, now);
+                bucket.tokens < f64::from(limit.capacity)
+            });
+        }
+
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: f64::from(limit.capacity),
+            refilled_at: now,
+        });
+        Self::refill(bucket, limit, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(limit.refill_interval.mul_f64(1.0 - bucket.tokens))
+        }
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    // clients are told to wait at least a second, rather than retry at once
+    let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after)],
+        Json(
+            ErrorResponseBody::new("too many payment requests")
+                .with_code("rate_limited")
+                .with_details(serde_json::json!({ "retry_after": retry_after })),
+        ),
+    )
+        .into_response()
+}
+
+fn is_payment_creation<B>(request: &Request<B>) -> bool {
+    request.method() == Method::POST
+        && [ApiVersion::Legacy, ApiVersion::V1]
+            .iter()
+            .any(|version| request.uri().path() == format!("{}/payments", version.prefix()))
+}
+
+/// Answers payment requests with a 429 once the card or the merchant paid
+/// exceeds its rate limit, before anything is recorded or held.
+///
+/// Requests whose card or merchant can't be read aren't limited on it, as
+/// they are rejected by `payments::post`.
+pub async fn limit_payments(
+    State(limiter): State<Option<Arc<dyn RateLimiter>>>,
+    request: Request<Limited<Body>>,
+    next: Next<Limited<Body>>,
+) -> Response {
+    let Some(limiter) = limiter.filter(|_| is_payment_creation(&request)) else {
+        return next.run(request).await;
+    };
+
+    // the card number is in the body, which is given back to the handler
+    let (mut parts, body) = request.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.downcast_ref::<LengthLimitError>().is_some() => {
+            return body_too_large().into_response()
+        }
+        Err(e) => {
+            tracing::warn!("failed to read payment request: {e}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponseBody::new("can't read request body")),
+            )
+                .into_response();
+        }
+    };
+
+    let card = serde_json::from_slice::<RequestBody>(&bytes)
+        .ok()
+        .and_then(|body| Card::try_from(body.payment.card_number).ok());
+    let merchant = MerchantId::from_request_parts(&mut parts, &()).await.ok();
+    let keys = card
+        .map(|card| RateLimitKey::Card(card.fingerprint()))
+        .into_iter()
+        .chain(merchant.map(|MerchantId(id)| RateLimitKey::Merchant(id)));
+
+    for key in keys {
+        if let Err(retry_after) = limiter.acquire(&key).await {
+            // card fingerprints are derived from card numbers, so aren't logged
+            tracing::warn!(merchant.id = ?merchant.map(|MerchantId(id)| id), "rate limited payment request");
+            return too_many_requests(retry_after);
+        }
+    }
+
+    let length = bytes.len();
+    next.run(Request::from_parts(
+        parts,
+        Limited::new(Body::from(bytes), length),
+    ))
+    .await
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_refill_buckets_over_time() {
+        let limit = RateLimit {
+            capacity: 2,
+            refill_interval: Duration::from_millis(50),
+        };
+        let limiter = TokenBuckets::new(limit, limit);
+        let card = RateLimitKey::Card("fingerprint".into());
+        let merchant = RateLimitKey::Merchant(Uuid::new_v4());
+
+        assert_eq!(limiter.acquire(&card).await, Ok(()));
+        assert_eq!(limiter.acquire(&card).await, Ok(()));
+        let retry_after = limiter.acquire(&card).await.unwrap_err();
+        assert!(retry_after <= limit.refill_interval);
+
+        // buckets are per key
+        assert_eq!(limiter.acquire(&merchant).await, Ok(()));
+
+        tokio::time::sleep(retry_after).await;
+        assert_eq!(limiter.acquire(&card).await, Ok(()));
+        assert!(limiter.acquire(&card).await.is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use std::fmt::Write;
+
+use axum::{
+    extract::State,
+    http::{header::ACCEPT, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use uuid::Uuid;
+
+use super::{merchant::MerchantId, path::ApiPath, payments::found, BankWeb, ErrorResponseBody};
+use crate::bank::{
+    accounts::AccountService,
+    payments::{self, PaymentWithRefunds, Status},
+    refunds::{self, Reason},
+};
+
+const TEXT_CONTENT_TYPE: &str = "text/plain";
+
+/// Receipt of an approved payment, the document merchants keep for their
+/// records.
+///
+/// Amounts are in minor units of `currency`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReceiptData {
+    /// Derived from the payment id, see `receipt_number`.
+    pub receipt_number: String,
+    pub payment_id: Uuid,
+    /// Masked card number, see `Card::masked`.
+    pub card_number: String,
+    pub amount: i64,
+    pub currency: String,
+    pub status: Status,
+    #[serde(with = "time::serde::rfc3339")]
+    pub paid_at: OffsetDateTime,
+    /// Refunds of the payment, oldest first.
+    pub refunds: Vec<ReceiptRefund>,
+    /// Total amount of the pending and settled refunds, the failed and
+    /// canceled ones returning no money.
+    pub refunded_amount: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReceiptRefund {
+    pub id: Uuid,
+    pub amount: i64,
+    pub status: refunds::Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Reason>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub refunded_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReceiptBody {
+    pub data: ReceiptData,
+}
+
+impl From<PaymentWithRefunds> for ReceiptData {
+    fn from(PaymentWithRefunds { payment, refunds }: PaymentWithRefunds) -> Self {
+        Self {
+            receipt_number: receipt_number(payment.id),
+            payment_id: payment.id,
+            card_number: payment.card_number,
+            amount: payment.amount,
+            currency: payment.currency,
+            status: payment.status,
+            paid_at: payment.inserted_at.assume_utc(),
+            refunded_amount: refunds
+                .iter()
+                .filter(|r| r.status.is_refunded())
+                .map(|r| r.amount)
+                .sum(),
+            refunds: refunds
+                .into_iter()
+                .map(|refund| ReceiptRefund {
+                    id: refund.id,
+                    amount: refund.amount,
+                    status: refund.status,
+                    reason: refund.reason,
+                    refunded_at: refund.inserted_at.assume_utc(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Returns the number of the receipt of a payment, e.g.
+/// `RCPT-0A1B-2C3D-4E5F-6789`.
+///
+/// The number is the first half of the payment id, so it stays the same
+/// however often the receipt is rendered.
+pub fn receipt_number(payment_id: Uuid) -> String {
+    let hex = payment_id.simple().to_string().to_uppercase();
+    format!(
+        "RCPT-{}-{}-{}-{}",
+        &hex[0..4],
+        &hex[4..8],
+        &hex[8..12],
+        &hex[12..16]
+    )
+}
+
+fn rfc3339(datetime: OffsetDateTime) -> String {
+    datetime
+        .format(&Rfc3339)
+        .expect("failed to format timestamp")
+}
+
+/// Renders a receipt as plain text, one labelled line per field.
+pub fn render_text(receipt: &ReceiptData) -> String {
+    let currency = &receipt.currency;
+    let mut text = String::new();
+
+    // writing to a String can't fail
+    let _ = writeln!(text, "Receipt {}", receipt.receipt_number);
+    let _ = writeln!(text, "Payment:  {}", receipt.payment_id);
+    let _ = writeln!(text, "Paid at:  {}", rfc3339(receipt.paid_at));
+    let _ = writeln!(text, "Card:     {}", receipt.card_number);
+    let _ = writeln!(text, "Amount:   {} {currency}", receipt.amount);
+    let _ = writeln!(text, "Status:   {:?}", receipt.status);
+
+    if !receipt.refunds.is_empty() {
+        let _ = writeln!(text, "Refunds:");
+        for refund in &receipt.refunds {
+            let _ = write!(
+                text,
+                "  {}  {} {currency}  {:?}",
+                rfc3339(refund.refunded_at),
+                refund.amount,
+                refund.status
+            );
+            if let Some(reason) = refund.reason {
+                let _ = write!(text, "  {reason:?}");
+            }
+            let _ = writeln!(text);
+        }
+    }
+    let _ = writeln!(text, "Refunded: {} {currency}", receipt.refunded_amount);
+
+    text
+}
+
+/// Returns the receipt of an approved payment, rendered as plain text when
+/// the `Accept` header asks for it.
+///
+/// Payments that aren't approved didn't move money, so they have no receipt.
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponseBody>)> {
+    let payment = found(
+        merchant,
+        payment_id,
+        payments::get_with_refunds(&bank_web.pool, payment_id).await,
+    )?;
+
+    let status = payment.payment.status;
+    if status != Status::Approved {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(
+                ErrorResponseBody::new("only approved payments have a receipt")
+                    .with_code("receipt_unavailable")
+                    .with_details(serde_json::json!({ "status": status })),
+            ),
+        ));
+    }
+
+    let receipt = ReceiptData::from(payment);
+    let accepts_text = headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(TEXT_CONTENT_TYPE));
+
+    if accepts_text {
+        return Ok(render_text(&receipt).into_response());
+    }
+    Ok(Json(ReceiptBody { data: receipt }).into_response())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use axum::http::{header::CONTENT_TYPE, Method, Request};
+
+    use super::*;
+    use crate::bank_web::{
+        json::ApiVersion,
+        merchant::MERCHANT_ID_HEADER,
+        payments::tests::PaymentRequestBuilder,
+        refunds::tests::RefundRequestBuilder,
+        tests::{
+            deserialize_response_body, get, get_as, post, send_request, TestApp, TEST_MERCHANT_ID,
+        },
+        BankWeb,
+    };
+
+    #[test]
+    fn test_receipt_number() {
+        let payment_id = Uuid::parse_str("0a1b2c3d-4e5f-6789-abcd-ef0123456789").unwrap();
+        assert_eq!(receipt_number(payment_id), "RCPT-0A1B-2C3D-4E5F-6789");
+    }
+
+    /// Refunds the default payment twice, returning the refunds as created.
+    async fn refund_twice(app: &TestApp, payment_id: Uuid) -> Vec<serde_json::Value> {
+        let mut refunds = Vec::new();
+        for request_body in [
+            RefundRequestBuilder::new()
+                .amount(5)
+                .reason("duplicate")
+                .build(),
+            RefundRequestBuilder::new().amount(200).build(),
+        ] {
+            let response = app.create_refund(payment_id, &request_body).await;
+            assert_eq!(response.status(), 201);
+            let body = deserialize_response_body::<serde_json::Value>(response).await;
+            refunds.push(body["data"].clone());
+        }
+        refunds
+    }
+
+    #[tokio::test]
+    async fn should_return_receipt_of_approved_payment() {
+        let app = TestApp::new().await;
+        let payment = app.create_approved_payment().await.data;
+        let refunds = refund_twice(&app, payment.id).await;
+
+        for version in [ApiVersion::Legacy, ApiVersion::V1] {
+            let uri = format!("{}/payments/{}/receipt", version.prefix(), payment.id);
+            let response = get(&app.router, uri).await;
+            assert_eq!(response.status(), 200);
+
+            let body = deserialize_response_body::<serde_json::Value>(response).await;
+            assert_eq!(
+                body,
+                serde_json::json!({
+                    "data": {
+                        "receipt_number": receipt_number(payment.id),
+                        "payment_id": payment.id,
+                        "card_number": payment.card_number,
+                        "amount": 1205,
+                        "currency": "USD",
+                        "status": "approved",
+                        "paid_at": rfc3339(payment.inserted_at),
+                        "refunds": [
+                            {
+                                "id": refunds[0]["id"],
+                                "amount": 5,
+                                "status": "settled",
+                                "reason": "duplicate",
+                                "refunded_at": refunds[0]["inserted_at"],
+                            },
+                            {
+                                "id": refunds[1]["id"],
+                                "amount": 200,
+                                "status": "settled",
+                                "refunded_at": refunds[1]["inserted_at"],
+                            },
+                        ],
+                        "refunded_amount": 205,
+                    }
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_render_receipt_as_text() {
+        let app = TestApp::new().await;
+        let payment = app.create_approved_payment().await.data;
+        let refunds = refund_twice(&app, payment.id).await;
+
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("/api/payments/{}/receipt", payment.id))
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .header(ACCEPT, "text/plain")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = send_request(&app.router, request).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let refunded_at = |i: usize| refunds[i]["inserted_at"].as_str().unwrap().to_string();
+        assert_eq!(
+            String::from_utf8(bytes.to_vec()).unwrap(),
+            format!(
+                "Receipt {receipt_number}\n\
+                 Payment:  {payment_id}\n\
+                 Paid at:  {paid_at}\n\
+                 Card:     {card_number}\n\
+                 Amount:   1205 USD\n\
+                 Status:   Approved\n\
+                 Refunds:\n  \
+                 {first}  5 USD  Settled  Duplicate\n  \
+                 {second}  200 USD  Settled\n\
+                 Refunded: 205 USD\n",
+                receipt_number = receipt_number(payment.id),
+                payment_id = payment.id,
+                paid_at = rfc3339(payment.inserted_at),
+                card_number = payment.card_number,
+                first = refunded_at(0),
+                second = refunded_at(1),
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn should_only_count_refunds_returning_money() {
+        let pool = crate::pg_pool().await.unwrap();
+        let app = TestApp::new().await;
+        let payment = app.create_approved_payment().await.data;
+
+        let mut amount = 1;
+        for status in refunds::Status::ALL {
+            let request_body = RefundRequestBuilder::new().amount(amount).build();
+            let response = app.create_refund(payment.id, &request_body).await;
+            assert_eq!(response.status(), 201);
+            let body = deserialize_response_body::<serde_json::Value>(response).await;
+            let refund_id = Uuid::parse_str(body["data"]["id"].as_str().unwrap()).unwrap();
+            if status != refunds::Status::Settled {
+                sqlx::query!(
+                    "UPDATE refunds SET status = $2 WHERE id = $1",
+                    refund_id,
+                    status as refunds::Status
+                )
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+            amount *= 2;
+        }
+
+        let response = get(&app.router, format!("/api/payments/{}/receipt", payment.id)).await;
+        assert_eq!(response.status(), 200);
+        let receipt = deserialize_response_body::<ReceiptBody>(response)
+            .await
+            .data;
+        assert_eq!(
+            receipt
+                .refunds
+                .iter()
+                .map(|refund| (refund.amount, refund.status))
+                .collect::<Vec<_>>(),
+            [
+                (1, refunds::Status::Pending),
+                (2, refunds::Status::Settled),
+                (4, refunds::Status::Failed),
+                (8, refunds::Status::Canceled),
+                (16, refunds::Status::Abandoned),
+            ]
+        );
+        // the pending and settled ones
+        assert_eq!(receipt.refunded_amount, 3);
+    }
+
+    #[tokio::test]
+    async fn should_return_409_for_receipt_of_declined_payment() {
+        let router = BankWeb::new_test_with_response("insufficient_funds")
+            .await
+            .into_router();
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 402);
+        let payment = deserialize_response_body::<serde_json::Value>(response).await;
+
+        let uri = format!(
+            "/api/payments/{}/receipt",
+            payment["data"]["id"].as_str().unwrap()
+        );
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 409);
+
+        let body = deserialize_response_body::<serde_json::Value>(response).await;
+        assert_eq!(body["code"], "receipt_unavailable");
+        assert_eq!(body["details"], serde_json::json!({ "status": "declined" }));
+    }
+
+    #[tokio::test]
+    async fn should_not_return_receipt_of_other_merchant() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let uri = format!("/api/payments/{payment_id}/receipt");
+        let response = get_as(&app.router, uri, Uuid::new_v4()).await;
+        assert_eq!(response.status(), 404);
+    }
+}
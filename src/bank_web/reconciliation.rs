@@ -0,0 +1,241 @@
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{payments::to_utc, BankWeb, ErrorResponseBody};
+use crate::bank::{
+    accounts::AccountService,
+    expiry,
+    payments::{self, Discrepancy, Mismatch, SettlementOutcome, Status},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReconciliationParams {
+    #[serde(with = "time::serde::rfc3339")]
+    pub from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub to: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MismatchData {
+    pub payment_id: Uuid,
+    pub status: Status,
+    pub hold_ref: Option<Uuid>,
+    /// Whether the hold was withdrawn or released, if it was concluded.
+    pub settlement_outcome: Option<SettlementOutcome>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+}
+
+impl From<Mismatch> for MismatchData {
+    fn from(mismatch: Mismatch) -> Self {
+        MismatchData {
+            payment_id: mismatch.payment_id,
+            status: mismatch.status,
+            hold_ref: mismatch.hold_ref,
+            settlement_outcome: mismatch.settlement_outcome,
+            inserted_at: mismatch.inserted_at.assume_utc(),
+        }
+    }
+}
+
+/// Mismatched payments, grouped by `Discrepancy`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReportData {
+    pub approved_without_withdraw: Vec<MismatchData>,
+    pub declined_with_hold_not_released: Vec<MismatchData>,
+    pub processing_stale: Vec<MismatchData>,
+    pub repair_failed: Vec<MismatchData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: ReportData,
+}
+
+/// Reports the payments inserted in `[from, to)` whose status doesn't match
+/// the account service calls made for them, e.g. leaked holds.
+///
+/// Payments are stale once processing for longer than the default max age of
+/// the expiry reaper.
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    params: Result<Query<ReconciliationParams>, QueryRejection>,
+) -> Result<Json<ResponseBody>, (StatusCode, Json<ErrorResponseBody>)> {
+    // from and to must be RFC 3339 timestamps
+    let Query(params) = params.map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponseBody::new("Invalid from or to")),
+        )
+    })?;
+
+    if params.from >= params.to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponseBody::new("from should be before to")),
+        ));
+    }
+
+    let mismatches = payments::reconcile(
+        &bank_web.pool,
+        to_utc(params.from),
+        to_utc(params.to),
+        expiry::Reaper::DEFAULT_MAX_AGE,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to reconcile payments: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponseBody::new("can't reconcile payments")),
+        )
+    })?;
+
+    let mut report = ReportData::default();
+    for mismatch in mismatches {
+        let group = match mismatch.discrepancy {
+            Discrepancy::ApprovedWithoutWithdraw => &mut report.approved_without_withdraw,
+            Discrepancy::DeclinedWithHoldNotReleased => &mut report.declined_with_hold_not_released,
+            Discrepancy::ProcessingStale => &mut report.processing_stale,
+            Discrepancy::RepairFailed => &mut report.repair_failed,
+        };
+        group.push(mismatch.into());
+    }
+
+    Ok(Json(ResponseBody { data: report }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use time::format_description::well_known::Rfc3339;
+
+    use super::*;
+    use crate::{
+        bank::accounts::{AccountMethod, ScriptedOutcome},
+        bank_web::{
+            payments::{tests::PaymentRequestBuilder, ResponseBody as PaymentResponseBody},
+            tests::{admin_get, deserialize_response_body, get, post, TestApp},
+        },
+    };
+
+    #[tokio::test]
+    async fn should_report_payments_not_matching_account_service_calls() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let account_service = bank_web.account_service.0.inner().clone();
+        let app = TestApp::from(bank_web);
+
+        let approved = app.create_approved_payment().await.data.id;
+        let not_withdrawn = app.create_approved_payment().await.data.id;
+
+        // declined payments whose hold is released once the withdrawal fails
+        account_service.set_scripted_outcome(Some(
+            ScriptedOutcome::new("insufficient_funds").with_method(AccountMethod::Withdraw),
+        ));
+        let mut declined = Vec::new();
+        for _ in 0..2 {
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = app.create_payment(&request_body).await;
+            assert_eq!(response.status(), 402);
+            declined.push(
+                deserialize_response_body::<PaymentResponseBody>(response)
+                    .await
+                    .data
+                    .id,
+            );
+        }
+        account_service.set_scripted_outcome(None);
+        let (released, not_released) = (declined[0], declined[1]);
+
+        // async payments stay processing, as no settlement worker runs
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post(&app.router, "/api/payments?async=true", &request_body).await;
+        assert_eq!(response.status(), 202);
+        let stale = deserialize_response_body::<PaymentResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        sqlx::query("UPDATE payments SET settlement_outcome = NULL WHERE id = ANY($1)")
+            .bind(vec![not_withdrawn, not_released])
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "UPDATE payments SET inserted_at = inserted_at - interval '1 hour' WHERE id = $1",
+        )
+        .bind(stale)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let now = OffsetDateTime::now_utc();
+        let uri = format!(
+            "/api/admin/reconciliation?from={}&to={}",
+            (now - time::Duration::days(1)).format(&Rfc3339).unwrap(),
+            (now + time::Duration::days(1)).format(&Rfc3339).unwrap()
+        );
+        let response = admin_get(&app.router, &uri).await;
+        assert_eq!(response.status(), 200);
+        let report = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        let ids = |group: &[MismatchData]| {
+            group
+                .iter()
+                .map(|mismatch| mismatch.payment_id)
+                .collect::<Vec<_>>()
+        };
+        let groups = [
+            ids(&report.approved_without_withdraw),
+            ids(&report.declined_with_hold_not_released),
+            ids(&report.processing_stale),
+        ];
+        for id in [approved, released] {
+            assert!(groups.iter().all(|group| !group.contains(&id)));
+        }
+        assert!(groups[0].contains(&not_withdrawn));
+        assert!(groups[1].contains(&not_released));
+        assert!(groups[2].contains(&stale));
+
+        let mismatch = report
+            .declined_with_hold_not_released
+            .iter()
+            .find(|mismatch| mismatch.payment_id == not_released)
+            .unwrap();
+        assert_eq!(mismatch.status, Status::Declined);
+        assert!(mismatch.hold_ref.is_some());
+        assert_eq!(mismatch.settlement_outcome, None);
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_invalid_window() {
+        let app = TestApp::new().await;
+
+        for uri in [
+            "/api/admin/reconciliation",
+            "/api/admin/reconciliation?from=yesterday&to=today",
+            "/api/admin/reconciliation?from=2023-04-02T00:00:00Z&to=2023-04-01T00:00:00Z",
+        ] {
+            let response = admin_get(&app.router, uri).await;
+            assert_eq!(response.status(), 400, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn should_return_401_without_admin_token() {
+        let app = TestApp::new().await;
+
+        let uri = "/api/admin/reconciliation?from=2023-04-01T00:00:00Z&to=2023-04-02T00:00:00Z";
+        let response = get(&app.router, uri).await;
+        assert_eq!(response.status(), 401);
+    }
+}
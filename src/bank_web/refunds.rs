@@ -1,29 +1,225 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{rejection::QueryRejection, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use std::collections::BTreeMap;
+
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use uuid::Uuid;
 
-use super::{BankWeb, ErrorResponseBody};
-use crate::bank::{accounts::AccountService, payments::Status, refunds};
+use super::{
+    admin::Principal,
+    etag,
+    export::{csv_requested, stream_csv},
+    fieldset::{Fieldset, Sparse},
+    json::{invalid_body, ApiJson, FieldError},
+    location,
+    merchant::MerchantId,
+    pagination::{decode_cursor, Paginated, PaginationParams, DEFAULT_LIMIT},
+    path::ApiPath,
+    payments::{find_payment, to_utc, MAX_METADATA_SIZE},
+    storage_unavailable, BankWeb, Location,
+};
+use crate::bank::{
+    accounts::AccountService,
+    journal, merchants,
+    pagination::Cursor,
+    payment_instruments,
+    payments::{Amount, Granularity, Payment, Status},
+    refunds::{
+        self,
+        retries::{self, RestartOutcome},
+        FullRefundOutcome, Reason, Refund, RefundOutcome, RefundWindowExpired, RefundWithPayment,
+        Status as RefundStatus,
+    },
+    transactions,
+};
+use crate::errors::ApiError;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct RequestData {
-    amount: i32,
+    /// Minor units, or a decimal string of major units, see `Amount`.
+    amount: Amount,
+    /// ISO 4217 code, which must be exactly the payment's currency when
+    /// given, the payment's currency when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<String>,
+    /// One of the `Reason` values, in snake case.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// Explanation of the `other` reason, which requires it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason_detail: Option<String>,
+    /// Merchant supplied JSON object, e.g. their own accounting entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<serde_json::Value>,
+    /// Merchant's id of the refund, unique among their refunds, by which
+    /// refunds can be listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    external_reference: Option<String>,
+    /// Refunds the payment even though its refund window expired, which
+    /// only admins may request.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    override_refund_window: bool,
 }
 
+/// Maximum length of the detail of a refund reason, in characters.
+pub const MAX_REASON_DETAIL_LENGTH: usize = 500;
+
+/// Maximum length of an external reference, in characters.
+pub const MAX_EXTERNAL_REFERENCE_LENGTH: usize = 128;
+
+/// Header identifying a refund request, so it can be retried without
+/// refunding twice.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Maximum length of an idempotency key, in characters.
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct RequestBody {
     refund: RequestData,
 }
 
+impl RequestData {
+    /// Checks the requested refund of `payment`, returning its reason.
+    ///
+    /// Every problem is reported at once, so clients can fix them in one go.
+    /// The sign of the amount is checked beforehand by `post`, and the
+    /// remaining refundable amount only when inserting.
+    pub fn validate(&self, payment: &Payment) -> Result<Option<Reason>, Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        if i64::from(self.amount) > payment.amount {
+            errors.push(FieldError::new(
+                "refund.amount",
+                "excessive refund amount requested",
+            ));
+        }
+
+        if let Some(currency) = &self.currency {
+            if *currency != payment.currency {
+                errors.push(FieldError::new(
+                    "refund.currency",
+                    format!(
+                        "refund currency {currency} doesn't match payment currency {}",
+                        payment.currency
+                    ),
+                ));
+            }
+        }
+
+        let reason = match &self.reason {
+            Some(reason) => match serde_json::from_value(reason.as_str().into()) {
+                Ok(reason) => Some(reason),
+                Err(_) => {
+                    errors.push(FieldError::new(
+                        "refund.reason",
+                        "reason must be one of requested_by_customer, duplicate, fraudulent, other",
+                    ));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        match (&self.reason_detail, reason) {
+            (Some(detail), _) if detail.chars().count() > MAX_REASON_DETAIL_LENGTH => {
+                errors.push(FieldError::new(
+                    "refund.reason_detail",
+                    format!(
+                        "reason_detail should be at most {MAX_REASON_DETAIL_LENGTH} characters"
+                    ),
+                ));
+            }
+            (Some(_), Some(Reason::Other)) => {}
+            (Some(_), _) => errors.push(FieldError::new(
+                "refund.reason_detail",
+                "reason_detail is only allowed when reason is other",
+            )),
+            (None, Some(Reason::Other)) => errors.push(FieldError::new(
+                "refund.reason_detail",
+                "reason_detail is required when reason is other",
+            )),
+            (None, _) => {}
+        }
+
+        if let Some(metadata) = &self.metadata {
+            if !metadata.is_object() {
+                errors.push(FieldError::new(
+                    "refund.metadata",
+                    "metadata must be a JSON object",
+                ));
+            } else if metadata.to_string().len() > MAX_METADATA_SIZE {
+                errors.push(FieldError::new("refund.metadata", "metadata is too large"));
+            }
+        }
+
+        if let Some(reference) = &self.external_reference {
+            if reference.chars().count() > MAX_EXTERNAL_REFERENCE_LENGTH {
+                errors.push(FieldError::new(
+                    "refund.external_reference",
+                    format!(
+                        "external_reference should be at most {MAX_EXTERNAL_REFERENCE_LENGTH} characters"
+                    ),
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(reason)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ResponseData {
     id: Uuid,
-    amount: i32,
+    amount: i64,
+    /// Currency of the payment, which refunds are always made in.
+    currency: String,
     payment_id: Uuid,
+    status: RefundStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<Reason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason_detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    external_reference: Option<String>,
+    /// Principal which requested the refund, `admin` or `anonymous`.
+    initiated_by: String,
+    #[serde(with = "time::serde::rfc3339")]
+    inserted_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    updated_at: OffsetDateTime,
+}
+
+impl Sparse for ResponseData {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "amount",
+        "currency",
+        "payment_id",
+        "status",
+        "reason",
+        "reason_detail",
+        "metadata",
+        "external_reference",
+        "initiated_by",
+        "inserted_at",
+        "updated_at",
+    ];
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -31,198 +227,2839 @@ pub struct ResponseBody {
     data: ResponseData,
 }
 
-impl ResponseBody {
-    pub fn new(id: Uuid, amount: i32, payment_id: Uuid) -> Self {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ListResponseBody {
+    data: Vec<ResponseData>,
+}
+
+/// A refund listed by `list_all`, with the payment it refunds.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ListedData {
+    #[serde(flatten)]
+    refund: ResponseData,
+    /// Masked card number of the payment.
+    card_number: String,
+    payment_status: Status,
+}
+
+impl From<RefundWithPayment> for ListedData {
+    fn from(listed: RefundWithPayment) -> Self {
         Self {
-            data: ResponseData {
-                id,
-                amount,
-                payment_id,
-            },
+            refund: listed.refund.into(),
+            card_number: listed.card_number,
+            payment_status: listed.payment_status,
         }
     }
 }
 
-macro_rules! unwrap_or_return {
-    ( $e:expr, $err:expr ) => {
-        match $e {
-            Ok(x) => x,
-            Err(_) => return $err,
+/// A refund checked by `preview`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PreviewData {
+    pub allowed: bool,
+    /// What would be left to refund of the payment after the refund.
+    pub remaining_after: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PreviewResponseBody {
+    pub data: PreviewData,
+}
+
+/// Row of the CSV export of refunds.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CsvRow {
+    pub id: Uuid,
+    pub payment_id: Uuid,
+    pub amount: i64,
+    pub status: RefundStatus,
+    pub reason: Option<Reason>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub inserted_at: OffsetDateTime,
+}
+
+impl From<Refund> for CsvRow {
+    fn from(refund: Refund) -> Self {
+        Self {
+            id: refund.id,
+            payment_id: refund.payment_id,
+            amount: refund.amount,
+            status: refund.status,
+            reason: refund.reason,
+            inserted_at: refund.inserted_at.assume_utc(),
         }
-    };
+    }
+}
+
+const CSV_HEADERS: [&str; 6] = [
+    "id",
+    "payment_id",
+    "amount",
+    "status",
+    "reason",
+    "inserted_at",
+];
+
+/// Largest number of refunds requested at once by `bulk`.
+pub const MAX_BULK_REFUNDS: usize = 500;
+
+/// Number of refunds of a `bulk` request processed at the same time.
+const BULK_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkRequestItem {
+    payment_id: Uuid,
+    /// Minor units, or a decimal string of major units, see `Amount`.
+    amount: Amount,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkRequestBody {
+    refunds: Vec<BulkRequestItem>,
+}
+
+/// Why a refund of a `bulk` request wasn't made, as it would be answered by
+/// `post`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BulkItemError {
+    /// HTTP status `post` would answer.
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    message: String,
+}
+
+/// Outcome of a refund of a `bulk` request, the refund made or the error.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BulkItemOutcome {
+    payment_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refund_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<BulkItemError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BulkResponseBody {
+    /// Outcomes in the order of the requested refunds.
+    data: Vec<BulkItemOutcome>,
+    /// Number of refunds per outcome, `created` or the error code.
+    summary: BTreeMap<String, usize>,
+}
+
+/// Largest page of `list_all`, larger ones being clamped to it.
+pub const MAX_LIST_ALL_LIMIT: i64 = 200;
+
+/// Query parameters of `list_all`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ListAllParams {
+    /// Only lists refunds inserted at or after this time.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    /// Only lists refunds inserted before this time.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+    pub min_amount: Option<i64>,
+    pub status: Option<String>,
+    pub external_reference: Option<String>,
+    /// Only lists the refunds requested by this principal, see `Principal`.
+    pub initiated_by: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    /// `json` or `csv`, overriding the `Accept` header.
+    pub format: Option<String>,
+}
+
+/// Query parameters of `stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StatsParams {
+    #[serde(with = "time::serde::rfc3339")]
+    pub from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub to: OffsetDateTime,
+    #[serde(default)]
+    pub group_by: Granularity,
+}
+
+/// Refunds of a time bucket and currency, see `refunds::Aggregate`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BucketStats {
+    #[serde(with = "time::serde::rfc3339")]
+    pub bucket: OffsetDateTime,
+    pub currency: String,
+    pub count: i64,
+    pub refunded_amount: i64,
+    /// Amount of the payments approved in the bucket.
+    pub captured_amount: i64,
+    /// Share of the captured amount refunded, `null` without captured
+    /// payments.
+    pub refund_rate: Option<f64>,
+}
+
+impl From<refunds::Aggregate> for BucketStats {
+    fn from(aggregate: refunds::Aggregate) -> Self {
+        let refund_rate = aggregate.refund_rate();
+        Self {
+            bucket: aggregate.bucket.assume_utc(),
+            currency: aggregate.currency,
+            count: aggregate.count,
+            refunded_amount: aggregate.refunded_amount,
+            captured_amount: aggregate.captured_amount,
+            refund_rate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StatsResponseBody {
+    pub data: Vec<BucketStats>,
+}
+
+/// Query parameters of `list`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ListParams {
+    /// Only lists the refunds of this status.
+    pub status: Option<String>,
+}
+
+impl From<Refund> for ResponseData {
+    fn from(refund: Refund) -> Self {
+        Self {
+            id: refund.id,
+            amount: refund.amount,
+            currency: refund.currency,
+            payment_id: refund.payment_id,
+            status: refund.status,
+            reason: refund.reason,
+            reason_detail: refund.reason_detail,
+            metadata: refund.metadata,
+            external_reference: refund.external_reference,
+            initiated_by: refund.initiated_by,
+            inserted_at: refund.inserted_at.assume_utc(),
+            updated_at: refund.updated_at.assume_utc(),
+        }
+    }
 }
 
+#[allow(clippy::type_complexity)]
 pub async fn post<T: AccountService>(
     State(bank_web): State<BankWeb<T>>,
-    Path(payment_id): Path<Uuid>,
-    Json(body): Json<RequestBody>,
-) -> Result<(StatusCode, Json<ResponseBody>), (StatusCode, Json<ErrorResponseBody>)> {
-    // body.refund.amount
+    merchant: MerchantId,
+    principal: Principal,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    headers: HeaderMap,
+    ApiJson(body): ApiJson<RequestBody>,
+) -> Result<(StatusCode, Location, Json<ResponseBody>), ApiError> {
+    let idempotency_key = idempotency_key(&headers)?;
+    create(
+        &bank_web,
+        merchant,
+        principal,
+        payment_id,
+        body.refund,
+        idempotency_key,
+    )
+    .await
+}
 
-    // Gettting the payment details from payment table
-    let payment_result = crate::bank::payments::get(&bank_web.pool, payment_id)
-        .await
-        .ok();
+/// Refunds a payment as requested, through `post` or as an entry of `bulk`.
+#[allow(clippy::type_complexity)]
+#[tracing::instrument(
+    skip_all,
+    fields(payment.id = %payment_id, payment.amount, payment.status, refund.id, refund.amount)
+)]
+async fn create<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    merchant: MerchantId,
+    principal: Principal,
+    payment_id: Uuid,
+    request: RequestData,
+    idempotency_key: Option<&str>,
+) -> Result<(StatusCode, Location, Json<ResponseBody>), ApiError> {
+    let span = tracing::Span::current();
+    span.record("refund.amount", request.amount.0);
 
-    if let Some(p) = payment_result {
-        if p.status != Status::Approved {
-            return Err((
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponseBody::new("has a status other than approved")),
-            ));
+    let payment = refunded_payment(bank_web, merchant, payment_id, &request).await?;
+    span.record("payment.amount", payment.amount);
+    span.record("payment.status", tracing::field::debug(payment.status));
+
+    // retried requests are answered before validation, which may have been
+    // passed by the original request only, e.g. within the refund window
+    if let Some(key) = idempotency_key {
+        if let Some(refund) = find_replayed(bank_web, payment_id, key).await? {
+            return replay(refund, request.amount.into());
         }
-    } else {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponseBody::new("payment doesn't exist")),
-        ));
+    }
+
+    let reason = validate(bank_web, principal, &payment, &request).await?;
+
+    // empty external references are stored as missing
+    let external_reference = request
+        .external_reference
+        .as_deref()
+        .filter(|reference| !reference.is_empty());
+
+    // refunds are always recorded in the currency of their payment
+    let outcome = refunds::checked_insert(
+        &bank_web.pool,
+        payment_id,
+        request.amount.into(),
+        payment.currency.clone(),
+        reason,
+        request.reason_detail.as_deref(),
+        idempotency_key,
+        request.metadata.clone(),
+        external_reference,
+        principal.as_str(),
+        bank_web.max_refunds_per_payment,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to insert refund of payment {payment_id}: {e}");
+        ApiError::from(storage_unavailable())
+    })?;
+
+    let refund_id = match outcome {
+        RefundOutcome::Created(refund_id) => refund_id,
+        RefundOutcome::DuplicateIdempotencyKey => {
+            // a concurrent request with the same key recorded the refund first
+            let replayed = match idempotency_key {
+                Some(key) => find_replayed(bank_web, payment_id, key).await?,
+                None => None,
+            };
+            return match replayed {
+                Some(refund) => replay(refund, request.amount.into()),
+                None => Err(storage_unavailable().into()),
+            };
+        }
+        rejected => return Err(rejected_refund(rejected)),
     };
 
-    let refund_id = unwrap_or_return!(
-        refunds::checked_insert(&bank_web.pool, payment_id, body.refund.amount).await,
-        Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponseBody::new(
-                "can't add refund since the db problem"
-            )),
-        ))
-    );
+    span.record("refund.id", tracing::field::display(refund_id));
 
-    if refund_id.is_none() {
-        Err((
-            StatusCode::UNPROCESSABLE_ENTITY,
-            Json(ErrorResponseBody::new("excessive refund amount requested")),
-        ))
+    deposit(bank_web, &payment, refund_id, request.amount.into()).await
+}
+
+/// Finds the payment refunded by `request`, which must be approved.
+async fn refunded_payment<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    merchant: MerchantId,
+    payment_id: Uuid,
+    request: &RequestData,
+) -> Result<Payment, ApiError> {
+    // refunds of no or negative amounts would add to the refundable amount
+    if request.amount.0 <= 0 {
+        return Err(
+            ApiError::new(StatusCode::BAD_REQUEST, "refund.amount must be positive")
+                .with_code("invalid_amount"),
+        );
+    }
+
+    let payment = find_payment(&bank_web.pool, merchant, payment_id).await?;
+    check_approved(&payment)?;
+
+    Ok(payment)
+}
+
+/// Validates `request` against the refunded payment, as far as it can be
+/// without locking the payment, returning the reason of the refund.
+async fn validate<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    principal: Principal,
+    payment: &Payment,
+    request: &RequestData,
+) -> Result<Option<Reason>, ApiError> {
+    if request.override_refund_window && principal != Principal::Admin {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "only admins can override the refund window",
+        )
+        .with_code("refund_window_override_forbidden"));
+    }
+
+    let reason = request
+        .validate(payment)
+        .map_err(|errors| invalid_body("Invalid refund", "invalid_refund", errors))?;
+
+    if request.override_refund_window {
+        tracing::warn!(
+            principal = principal.as_str(),
+            payment.id = %payment.id,
+            "refund window overridden"
+        );
     } else {
-        Ok((
-            StatusCode::CREATED,
-            Json(ResponseBody::new(
-                refund_id.unwrap(),
-                body.refund.amount,
-                payment_id,
-            )),
-        ))
+        check_refund_window(bank_web, payment).await?;
     }
+
+    Ok(reason)
 }
 
-pub async fn get<T: AccountService>(
+/// Reports a refund rejected once checked against its locked payment.
+fn rejected_refund(outcome: RefundOutcome) -> ApiError {
+    match outcome {
+        RefundOutcome::ExceedsRemaining { remaining } => ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "excessive refund amount requested",
+        )
+        .with_code("exceeds_remaining_amount")
+        .with_details(serde_json::json!({ "remaining": remaining })),
+        RefundOutcome::CountLimitReached { limit } => refund_count_limit_reached(limit),
+        RefundOutcome::DuplicateExternalReference { refund_id } => ApiError::new(
+            StatusCode::CONFLICT,
+            "external_reference already used for another refund",
+        )
+        .with_code("external_reference_reused")
+        .with_details(serde_json::json!({ "refund_id": refund_id })),
+        RefundOutcome::NotRefundable { status } => not_approved(status),
+        RefundOutcome::Disputed => disputed(),
+        RefundOutcome::PaymentNotFound => {
+            ApiError::new(StatusCode::NOT_FOUND, "payment doesn't exist").with_code("not_found")
+        }
+        RefundOutcome::Created(_) | RefundOutcome::DuplicateIdempotencyKey => {
+            unreachable!("refund {outcome:?} wasn't rejected")
+        }
+    }
+}
+
+/// Checks a refund exactly as `post` would, without making it, so merchants
+/// can show how much is left to refund before committing to a refund.
+///
+/// Rejected refunds are answered with the errors `post` would answer.
+pub async fn preview<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    principal: Principal,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    ApiJson(body): ApiJson<RequestBody>,
+) -> Result<Json<PreviewResponseBody>, ApiError> {
+    let request = body.refund;
+    let payment = refunded_payment(&bank_web, merchant, payment_id, &request).await?;
+    validate(&bank_web, principal, &payment, &request).await?;
+
+    let validated = refunds::preview(
+        &bank_web.pool,
+        payment_id,
+        request.amount.into(),
+        &payment.currency,
+        bank_web.max_refunds_per_payment,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to preview refund of payment {payment_id}: {e}");
+        ApiError::from(storage_unavailable())
+    })?
+    .map_err(rejected_refund)?;
+
+    Ok(Json(PreviewResponseBody {
+        data: PreviewData {
+            allowed: true,
+            remaining_after: validated.remaining_after,
+        },
+    }))
+}
+
+/// Refunds several payments of the merchant at once, e.g. after a product
+/// recall.
+///
+/// Each refund is made as by `post`, a few at a time, and refunds which
+/// can't be made don't prevent the others.
+pub async fn bulk<T: AccountService>(
     State(bank_web): State<BankWeb<T>>,
-    Path((payment_id, refund_id)): Path<(Uuid, Uuid)>,
-) -> Result<(StatusCode, Json<ResponseBody>), (StatusCode, Json<ErrorResponseBody>)> {
-    let data = refunds::get(&bank_web.pool, refund_id).await.unwrap();
+    merchant: MerchantId,
+    principal: Principal,
+    ApiJson(body): ApiJson<BulkRequestBody>,
+) -> Result<Json<BulkResponseBody>, ApiError> {
+    if body.refunds.len() > MAX_BULK_REFUNDS {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "too many refunds requested at once",
+        )
+        .with_code("too_many_refunds")
+        .with_details(serde_json::json!({ "max": MAX_BULK_REFUNDS })));
+    }
+
+    let bank_web = &bank_web;
+    let data: Vec<_> = futures::stream::iter(body.refunds)
+        .map(|item| async move {
+            let request = RequestData {
+                amount: item.amount,
+                currency: None,
+                reason: None,
+                reason_detail: None,
+                metadata: None,
+                external_reference: None,
+                override_refund_window: false,
+            };
+            // refunds are tracked on their own, as another one's transaction
+            // may be open while one deposits
+            let created = transactions::track(create(
+                bank_web,
+                merchant,
+                principal,
+                item.payment_id,
+                request,
+                None,
+            ))
+            .await;
+            let (refund_id, error) = match created {
+                Ok((_, _, Json(response_body))) => (Some(response_body.data.id), None),
+                Err(e) => {
+                    let error = BulkItemError {
+                        status: e.status().as_u16(),
+                        code: e.body().code.clone(),
+                        message: e.body().error.clone(),
+                    };
+                    (None, Some(error))
+                }
+            };
+            BulkItemOutcome {
+                payment_id: item.payment_id,
+                refund_id,
+                error,
+            }
+        })
+        // outcomes are collected in the order of the requested refunds
+        .buffered(BULK_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut summary = BTreeMap::new();
+    for outcome in &data {
+        let key = match &outcome.error {
+            None => "created",
+            Some(error) => error.code.as_deref().unwrap_or("error"),
+        };
+        *summary.entry(key.to_string()).or_insert(0) += 1;
+    }
+
+    Ok(Json(BulkResponseBody { data, summary }))
+}
+
+/// Refunds whatever is left of an approved payment, in a single request.
+///
+/// The body is empty or an empty object, the amount refunded is answered.
+#[allow(clippy::type_complexity)]
+#[tracing::instrument(
+    skip_all,
+    fields(payment.id = %payment_id, payment.amount, payment.status, refund.id, refund.amount)
+)]
+pub async fn post_full<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    principal: Principal,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    body: Bytes,
+) -> Result<(StatusCode, Location, Json<ResponseBody>), ApiError> {
+    let span = tracing::Span::current();
+
+    let is_empty = body.iter().all(u8::is_ascii_whitespace)
+        || serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&body)
+            .is_ok_and(|object| object.is_empty());
+    if !is_empty {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "full refunds take no parameters",
+        )
+        .with_code("invalid_body"));
+    }
+
+    let payment = find_payment(&bank_web.pool, merchant, payment_id).await?;
+    span.record("payment.amount", payment.amount);
+    span.record("payment.status", tracing::field::debug(payment.status));
+    check_approved(&payment)?;
+
+    check_refund_window(&bank_web, &payment).await?;
+
+    let outcome = refunds::insert_full(
+        &bank_web.pool,
+        payment_id,
+        principal.as_str(),
+        bank_web.max_refunds_per_payment,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to insert full refund of payment {payment_id}: {e}");
+        ApiError::from(storage_unavailable())
+    })?;
+    let (refund_id, amount) = match outcome {
+        FullRefundOutcome::Created { id, amount } => (id, amount),
+        FullRefundOutcome::NotRefundable { status } => return Err(not_approved(status)),
+        FullRefundOutcome::Disputed => return Err(disputed()),
+        FullRefundOutcome::NothingToRefund => {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "payment is already fully refunded",
+            )
+            .with_code("nothing_to_refund"));
+        }
+        FullRefundOutcome::CountLimitReached { limit } => {
+            return Err(refund_count_limit_reached(limit));
+        }
+    };
+
+    span.record("refund.id", tracing::field::display(refund_id));
+    span.record("refund.amount", amount);
+
+    deposit(&bank_web, &payment, refund_id, amount).await
+}
+
+/// Answers a 409 for payments which exist but can't be refunded, as only
+/// approved payments can.
+///
+/// The status is checked again when the refund is inserted, as it may change
+/// meanwhile.
+fn check_approved(payment: &Payment) -> Result<(), ApiError> {
+    if payment.status == Status::Approved {
+        return Ok(());
+    }
+
+    Err(not_approved(payment.status))
+}
+
+fn not_approved(status: Status) -> ApiError {
+    ApiError::new(
+        StatusCode::CONFLICT,
+        "only approved payments can be refunded",
+    )
+    .with_code("payment_not_approved")
+    .with_details(serde_json::json!({ "status": status }))
+}
+
+fn disputed() -> ApiError {
+    ApiError::new(StatusCode::CONFLICT, "payment is under dispute").with_code("payment_disputed")
+}
+
+fn refund_count_limit_reached(limit: u32) -> ApiError {
+    ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "refund count limit reached",
+    )
+    .with_code("refund_count_limit_reached")
+    .with_details(serde_json::json!({ "limit": limit }))
+}
+
+/// Deposits a recorded refund on the client account, answering the refund.
+#[allow(clippy::type_complexity)]
+async fn deposit<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    payment: &Payment,
+    refund_id: Uuid,
+    amount: i64,
+) -> Result<(StatusCode, Location, Json<ResponseBody>), ApiError> {
+    let payment_id = payment.id;
+
+    // the account number is left visible in masked card numbers
+    let Some(account_number) = payment_instruments::masked_account_number(&payment.card_number)
+    else {
+        tracing::error!("payment {payment_id} has an invalid card number");
+        record_status(bank_web, refund_id, RefundStatus::Failed).await;
+        return Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "can't refund payment",
+        ));
+    };
+
+    let deposit = journal::for_payment(
+        payment_id,
+        bank_web
+            .account_service
+            .deposit_funds(account_number, amount),
+    )
+    .await;
+    if let Err(error) = deposit {
+        tracing::warn!("failed to deposit refund {refund_id}: {error}");
+        record_status(bank_web, refund_id, RefundStatus::Failed).await;
+        return Err(
+            ApiError::new(StatusCode::BAD_GATEWAY, "refund deposit failed")
+                .with_code("refund_deposit_failed")
+                .with_details(serde_json::json!({ "refund_id": refund_id })),
+        );
+    }
+    record_status(bank_web, refund_id, RefundStatus::Settled).await;
+
+    let refund = refunds::get(&bank_web.pool, refund_id).await.map_err(|e| {
+        tracing::error!("failed to read back refund {refund_id}: {e}");
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't get refund")
+    })?;
 
     Ok((
-        StatusCode::OK,
-        Json(ResponseBody::new(data.id, data.amount, payment_id)),
+        StatusCode::CREATED,
+        refund_location(payment_id, refund_id),
+        Json(ResponseBody {
+            data: refund.into(),
+        }),
     ))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        bank::{payment_instruments::Card, payments::Status},
-        bank_web::{
-            payments,
-            tests::{deserialize_response_body, get, post},
-        },
+fn refund_location(payment_id: Uuid, refund_id: Uuid) -> Location {
+    location(format!("/api/payments/{payment_id}/refunds/{refund_id}"))
+}
+
+/// Returns the idempotency key of a refund request, if any.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<&str>, ApiError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
     };
 
-    async fn setup() -> (axum::Router, payments::ResponseBody) {
-        let router = BankWeb::new_test().await.into_router();
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.chars().count() <= MAX_IDEMPOTENCY_KEY_LENGTH => {
+            Ok(Some(key))
+        }
+        _ => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Idempotency-Key should be 1 to 255 visible ASCII characters",
+        )
+        .with_code("invalid_idempotency_key")),
+    }
+}
 
-        let request_body = payments::RequestBody {
-            payment: payments::RequestData {
-                amount: 1205,
-                card_number: Card::new_test().into(),
-            },
-        };
+/// Returns the refund of `payment_id` recorded under `idempotency_key`, if
+/// any.
+async fn find_replayed<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    payment_id: Uuid,
+    idempotency_key: &str,
+) -> Result<Option<Refund>, ApiError> {
+    refunds::find_by_idempotency_key(&bank_web.pool, payment_id, idempotency_key)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                "failed to find refund of payment {payment_id} by idempotency key: {e}"
+            );
+            storage_unavailable().into()
+        })
+}
 
-        let response = post(&router, "/api/payments", &request_body).await;
-        assert_eq!(response.status(), 201);
+/// Answers a retried refund request like the request that recorded
+/// `refund`, unless it asks for another amount under the same key.
+fn replay(
+    refund: Refund,
+    amount: i64,
+) -> Result<(StatusCode, Location, Json<ResponseBody>), ApiError> {
+    if refund.amount != amount {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "Idempotency-Key already used for another refund",
+        )
+        .with_code("idempotency_key_reused")
+        .with_details(serde_json::json!({ "refund_id": refund.id })));
+    }
 
-        let response_body = deserialize_response_body::<payments::ResponseBody>(response).await;
-        assert_eq!(response_body.data.status, Status::Approved);
+    tracing::Span::current().record("refund.id", tracing::field::display(refund.id));
+    Ok((
+        StatusCode::CREATED,
+        refund_location(refund.payment_id, refund.id),
+        Json(ResponseBody {
+            data: refund.into(),
+        }),
+    ))
+}
 
-        (router, response_body)
+/// Records whether the money of a refund was deposited, which is only logged
+/// on failure since the deposit was made or failed either way.
+async fn record_status<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    refund_id: Uuid,
+    status: RefundStatus,
+) {
+    if let Err(e) = refunds::set_status(&bank_web.pool, refund_id, status).await {
+        tracing::error!("failed to record status {status:?} of refund {refund_id}: {e}");
     }
+}
 
-    async fn request_refund(router: axum::Router, payment_id: Uuid) -> StatusCode {
-        let request_body = RequestBody {
-            refund: RequestData { amount: 1205 },
-        };
+/// Rejects refunds of payments older than the refund window of their
+/// merchant.
+async fn check_refund_window<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    payment: &Payment,
+) -> Result<(), ApiError> {
+    let policy = merchants::get_refund_policy(&bank_web.pool, payment.merchant_id)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                "failed to get refund policy of {}: {e}",
+                payment.merchant_id
+            );
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't get refund policy")
+        })?;
 
-        let uri = format!("/api/payments/{payment_id}/refunds",);
-        let response = post(&router, uri, &request_body).await;
-        response.status()
-    }
+    let window = policy.refund_window(bank_web.refund_window);
+    refunds::check_refund_window(payment, window, bank_web.clock.now()).map_err(
+        |RefundWindowExpired { cutoff }| {
+            ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "refund window expired")
+                .with_code("refund_window_expired")
+                .with_details(serde_json::json!({
+                    "refund_window_days": window.as_secs() / (24 * 60 * 60),
+                    "cutoff": cutoff.format(&Rfc3339).ok(),
+                }))
+                .with_detail(format!("refunds were allowed until {}", cutoff.date()))
+        },
+    )
+}
 
-    #[tokio::test]
-    async fn should_handle_concurrent_refunds() {
-        let router = BankWeb::new_test().await.into_router();
+/// Lists the refunds of a payment, oldest first, those inserted at the same
+/// time ordered by id so the order never changes between requests.
+///
+/// Payments have few refunds, which are all listed at once.
+pub async fn list<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath(payment_id): ApiPath<Uuid>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<ListResponseBody>, ApiError> {
+    let status = params.status.map(parse_status).transpose()?;
 
-        let request_body = payments::RequestBody {
-            payment: payments::RequestData {
-                amount: 1205,
-                card_number: Card::new_test().into(),
-            },
-        };
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
 
-        let response = post(&router, "/api/payments", &request_body).await;
-        assert_eq!(response.status(), 201);
+    let refunds = refunds::list_with_status(&bank_web.pool, payment_id, status)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to list refunds of payment {payment_id}: {e}");
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't list refunds")
+        })?;
 
-        let response_body = deserialize_response_body::<payments::ResponseBody>(response).await;
-        let payment_id = response_body.data.id;
+    Ok(Json(ListResponseBody {
+        data: refunds.into_iter().map(ResponseData::from).collect(),
+    }))
+}
 
-        let fut_a = request_refund(router.clone(), payment_id);
-        let fut_b = request_refund(router, payment_id);
-        let (status_a, status_b) = tokio::join!(fut_a, fut_b);
+/// Lists the refunds of every payment of the merchant, newest first, for
+/// finance reporting.
+///
+/// Exported as CSV, the refunds matching the filter are streamed at once,
+/// without pagination.
+pub async fn list_all<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    headers: HeaderMap,
+    params: Result<Query<ListAllParams>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    // from and to must be RFC 3339 timestamps
+    let Query(params) = params.map_err(|_| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Invalid from, to, min_amount or limit",
+        )
+    })?;
 
-        assert_eq!(status_a.min(status_b), 201, "one refund should succeed");
-        assert_eq!(status_a.max(status_b), 422, "one refund should fail");
+    if let (Some(from), Some(to)) = (params.from, params.to) {
+        if from >= to {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "from should be before to",
+            ));
+        }
     }
 
-    #[tokio::test]
-    async fn should_refund_valid_amount() {
-        let (router, payment_response_body) = setup().await;
-        let payment_id = payment_response_body.data.id;
+    let status = params.status.map(parse_status).transpose()?;
+    let after = match params.cursor {
+        Some(cursor) => Some(decode_cursor(&cursor).ok_or_else(|| {
+            ApiError::new(StatusCode::BAD_REQUEST, "Invalid cursor").with_code("invalid_cursor")
+        })?),
+        None => None,
+    };
+    let pagination = PaginationParams {
+        after,
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_LIMIT)
+            .clamp(1, MAX_LIST_ALL_LIMIT),
+    };
 
-        let request_body = RequestBody {
-            refund: RequestData { amount: 42 },
-        };
+    let MerchantId(merchant_id) = merchant;
+    let filter = refunds::ListFilter {
+        merchant_id: Some(merchant_id),
+        from: params.from.map(to_utc),
+        to: params.to.map(to_utc),
+        min_amount: params.min_amount,
+        status,
+        external_reference: params.external_reference,
+        initiated_by: params.initiated_by,
+    };
+    if csv_requested(params.format.as_deref(), &headers)? {
+        return Ok(stream_csv::<_, _, CsvRow>(
+            "refunds",
+            &CSV_HEADERS,
+            bank_web.pool,
+            filter,
+            refunds::stream,
+        ));
+    }
 
-        let uri = format!("/api/payments/{payment_id}/refunds",);
-        let response = post(&router, uri.to_string(), &request_body).await;
-        assert_eq!(response.status(), 201);
+    let refunds = refunds::list_all(&bank_web.pool, &filter, &pagination.page())
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to list refunds: {e}");
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't list refunds")
+        })?;
 
-        let response_body = deserialize_response_body::<ResponseBody>(response).await;
-        assert_eq!(response_body.data.amount, request_body.refund.amount);
-        let refund_id = response_body.data.id;
+    Ok(Json(Paginated::new(
+        refunds,
+        &pagination,
+        |listed| Cursor {
+            inserted_at: listed.refund.inserted_at,
+            id: listed.refund.id,
+            sort: None,
+        },
+        ListedData::from,
+    ))
+    .into_response())
+}
 
-        let uri = format!("/api/payments/{payment_id}/refunds/{refund_id}");
-        let response = get(&router, uri).await;
-        assert_eq!(response.status(), 200);
+/// Aggregates the refunds of the merchant per time bucket and currency, next
+/// to the payments they refund, for finance reporting.
+pub async fn stats<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Json<StatsResponseBody>, ApiError> {
+    // from and to must be RFC 3339 timestamps, group_by day or hour
+    let Query(params) = params
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "Invalid from, to or group_by"))?;
 
-        let response_body = deserialize_response_body::<ResponseBody>(response).await;
-        assert_eq!(response_body.data.amount, request_body.refund.amount);
+    if params.from >= params.to {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "from should be before to",
+        ));
     }
 
-    #[tokio::test]
-    async fn should_reject_refund_of_invalid_amount() {
-        let (router, payment_response_body) = setup().await;
-        let payment_id = payment_response_body.data.id;
+    let aggregates = refunds::aggregate(
+        &bank_web.pool,
+        merchant_id,
+        to_utc(params.from),
+        to_utc(params.to),
+        params.group_by,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("failed to aggregate refunds: {e}");
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't aggregate refunds")
+    })?;
 
-        let request_body = RequestBody {
-            refund: RequestData {
-                amount: payment_response_body.data.amount + 1,
-            },
-        };
+    Ok(Json(StatsResponseBody {
+        data: aggregates.into_iter().map(BucketStats::from).collect(),
+    }))
+}
 
-        let uri = format!("/api/payments/{payment_id}/refunds",);
-        let response = post(&router, uri, &request_body).await;
-        assert_eq!(response.status(), 422);
+/// Parses the status of a refund, unknown statuses being answered with a 422
+/// listing the known ones.
+fn parse_status(status: String) -> Result<RefundStatus, ApiError> {
+    serde_json::from_value(serde_json::Value::String(status)).map_err(|_| {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "Unknown refund status")
+            .with_code("invalid_status")
+            .with_details(serde_json::json!({ "allowed": RefundStatus::ALL }))
+    })
+}
 
-        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
-        assert_eq!(response_body.error, "excessive refund amount requested");
+/// Cancels a refund which didn't settle yet, answering it canceled.
+pub async fn cancel<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath((payment_id, refund_id)): ApiPath<(Uuid, Uuid)>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+    let refund = find_refund(&bank_web, payment_id, refund_id).await?;
+
+    let canceled = refunds::cancel(&bank_web.pool, refund_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to cancel refund {refund_id}: {e}");
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "can't cancel refund")
+        })?;
+
+    match canceled {
+        Some(refund) => Ok(Json(ResponseBody {
+            data: refund.into(),
+        })),
+        // the refund may have settled since it was read
+        None => Err(
+            ApiError::new(StatusCode::CONFLICT, "only pending refunds can be canceled")
+                .with_code("refund_not_pending")
+                .with_details(serde_json::json!({ "status": refund.status })),
+        ),
+    }
+}
+
+/// Retries the deposit of a failed refund, answering it settled.
+///
+/// The refund is checked against its payment again, as failed refunds don't
+/// count against it, and is left failed if the deposit fails again.
+pub async fn retry<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath((payment_id, refund_id)): ApiPath<(Uuid, Uuid)>,
+) -> Result<Json<ResponseBody>, ApiError> {
+    let payment = find_payment(&bank_web.pool, merchant, payment_id).await?;
+    find_refund(&bank_web, payment_id, refund_id).await?;
+
+    let outcome = retries::retry(&bank_web.pool, refund_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to retry refund {refund_id}: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+
+    match outcome {
+        RestartOutcome::Restarted(refund) => {
+            let (_, _, body) = deposit(&bank_web, &payment, refund_id, refund.amount).await?;
+            Ok(body)
+        }
+        RestartOutcome::NotFound => {
+            Err(ApiError::new(StatusCode::NOT_FOUND, "refund doesn't exist").with_code("not_found"))
+        }
+        RestartOutcome::NotFailed { status } => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "only failed refunds can be retried",
+        )
+        .with_code("refund_not_failed")
+        .with_details(serde_json::json!({ "status": status }))),
+        RestartOutcome::Disputed => Err(disputed()),
+        RestartOutcome::ExceedsRemaining { remaining } => {
+            Err(rejected_refund(RefundOutcome::ExceedsRemaining {
+                remaining,
+            }))
+        }
+    }
+}
+
+/// Returns a refund of `payment_id`, answering a 404 for refunds of other
+/// payments.
+async fn find_refund<T: AccountService>(
+    bank_web: &BankWeb<T>,
+    payment_id: Uuid,
+    refund_id: Uuid,
+) -> Result<Refund, ApiError> {
+    match refunds::get(&bank_web.pool, refund_id).await {
+        Ok(refund) if refund.payment_id == payment_id => Ok(refund),
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            Err(ApiError::new(StatusCode::NOT_FOUND, "refund doesn't exist").with_code("not_found"))
+        }
+        Err(e) => {
+            tracing::error!("failed to get refund {refund_id}: {e}");
+            Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "can't get refund",
+            ))
+        }
+    }
+}
+
+/// Returns a refund, or a 304 if the `If-None-Match` header holds its
+/// current ETag.
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    merchant: MerchantId,
+    ApiPath((payment_id, refund_id)): ApiPath<(Uuid, Uuid)>,
+    headers: HeaderMap,
+    fieldset: Fieldset<ResponseData>,
+) -> Result<Response, ApiError> {
+    find_payment(&bank_web.pool, merchant, payment_id).await?;
+    let data = find_refund(&bank_web, payment_id, refund_id).await?;
+
+    let etag = etag::entity_tag(&format!(
+        "{}:{}:{fieldset}",
+        data.id,
+        data.updated_at.assume_utc().unix_timestamp_nanos()
+    ));
+
+    let data = ResponseData::from(data);
+    Ok(etag::conditional(
+        &headers,
+        etag,
+        Json(serde_json::json!({ "data": fieldset.project(&data) })),
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        num::NonZeroU32,
+        sync::{atomic::Ordering, Arc},
+    };
+
+    use axum::http::{
+        header::{AUTHORIZATION, CONTENT_TYPE, ETAG, LOCATION},
+        Method, Request,
+    };
+    use time::Duration;
+
+    use super::*;
+    use crate::bank::{
+        accounts::{AccountMethod, DummyService, ScriptedOutcome},
+        clock::tests::ManualClock,
+        merchants::RefundPolicy,
+    };
+    use crate::bank_web::{
+        merchant::MERCHANT_ID_HEADER,
+        payments::{
+            self,
+            tests::{MockService, PaymentRequestBuilder},
+        },
+        tests::{
+            admin_request, delete, deserialize_response_body, get, get_as, get_if_none_match, post,
+            post_as, send_request, TestApp, TEST_MERCHANT_ID,
+        },
+        ErrorResponseBody,
+    };
+
+    /// Builds refund request bodies, by default of
+    /// `RefundRequestBuilder::DEFAULT_AMOUNT` in the currency of the payment.
+    pub struct RefundRequestBuilder {
+        data: RequestData,
+    }
+
+    impl RefundRequestBuilder {
+        pub const DEFAULT_AMOUNT: i64 = 42;
+
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn amount(mut self, amount: i64) -> Self {
+            self.data.amount = Amount(amount);
+            self
+        }
+
+        pub fn currency(mut self, currency: &str) -> Self {
+            self.data.currency = Some(currency.to_string());
+            self
+        }
+
+        pub fn reason(mut self, reason: &str) -> Self {
+            self.data.reason = Some(reason.to_string());
+            self
+        }
+
+        pub fn reason_detail(mut self, detail: &str) -> Self {
+            self.data.reason_detail = Some(detail.to_string());
+            self
+        }
+
+        pub fn metadata(mut self, metadata: serde_json::Value) -> Self {
+            self.data.metadata = Some(metadata);
+            self
+        }
+
+        pub fn external_reference(mut self, reference: &str) -> Self {
+            self.data.external_reference = Some(reference.to_string());
+            self
+        }
+
+        pub fn override_refund_window(mut self) -> Self {
+            self.data.override_refund_window = true;
+            self
+        }
+
+        pub fn build(self) -> RequestBody {
+            RequestBody { refund: self.data }
+        }
+    }
+
+    impl Default for RefundRequestBuilder {
+        fn default() -> Self {
+            Self {
+                data: RequestData {
+                    amount: Amount(Self::DEFAULT_AMOUNT),
+                    currency: None,
+                    reason: None,
+                    reason_detail: None,
+                    metadata: None,
+                    external_reference: None,
+                    override_refund_window: false,
+                },
+            }
+        }
+    }
+
+    async fn setup() -> (axum::Router, payments::ResponseBody) {
+        let app = TestApp::new().await;
+        let response_body = app.create_approved_payment().await;
+
+        (app.router, response_body)
+    }
+
+    async fn request_refund(router: axum::Router, payment_id: Uuid) -> StatusCode {
+        let request_body = RefundRequestBuilder::new().amount(1205).build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds",);
+        let response = post(&router, uri, &request_body).await;
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn should_deposit_each_refund_once() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let app = TestApp::from(BankWeb::new(pool, mock_service.clone()));
+        let payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds");
+
+        let request_body = RefundRequestBuilder::new().build();
+        let response = post(&app.router, &uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, RefundStatus::Settled);
+        assert_eq!(mock_service.deposit_funds_count.load(Ordering::SeqCst), 1);
+
+        // refunds declined by checked_insert move no money
+        let request_body = RefundRequestBuilder::new().amount(1205).build();
+        let response = post(&app.router, &uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+        assert_eq!(mock_service.deposit_funds_count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_mark_refund_failed_when_deposit_fails() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        mock_service.dummy.set_scripted_outcome(Some(
+            ScriptedOutcome::new("service_unavailable").with_method(AccountMethod::Deposit),
+        ));
+        let app = TestApp::from(BankWeb::new(pool, mock_service.clone()));
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&app.router, uri, &request_body).await;
+        assert_eq!(response.status(), 502);
+        assert_eq!(mock_service.deposit_funds_count.load(Ordering::SeqCst), 1);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("refund_deposit_failed"));
+        let refund_id = response_body.details.unwrap()["refund_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let uri = format!("/api/payments/{payment_id}/refunds/{refund_id}");
+        let response_body =
+            deserialize_response_body::<ResponseBody>(get(&app.router, uri).await).await;
+        assert_eq!(response_body.data.status, RefundStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn should_retry_failed_refund_deposits() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        // the first deposit and the first retry fail
+        mock_service.dummy.set_scripted_outcome(Some(
+            ScriptedOutcome::new("service_unavailable")
+                .with_method(AccountMethod::Deposit)
+                .with_remaining(NonZeroU32::new(2).unwrap()),
+        ));
+        let app = TestApp::from(BankWeb::new(pool.clone(), mock_service.clone()));
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&app.router, uri, &request_body).await;
+        assert_eq!(response.status(), 502);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        let refund_id: Uuid = response_body.details.unwrap()["refund_id"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let uri = format!("/api/payments/{payment_id}/refunds/{refund_id}/retry");
+        let response = post(&app.router, &uri, &()).await;
+        assert_eq!(response.status(), 502);
+        let response = post(&app.router, &uri, &()).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, RefundStatus::Settled);
+        assert_eq!(mock_service.deposit_funds_count.load(Ordering::SeqCst), 3);
+        assert_eq!(retries::tests::deposit_attempts(&pool, refund_id).await, 3);
+
+        // settled refunds aren't deposited again
+        let response = post(&app.router, &uri, &()).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("refund_not_failed"));
+        assert_eq!(mock_service.deposit_funds_count.load(Ordering::SeqCst), 3);
+    }
+
+    async fn post_with_idempotency_key(
+        router: &axum::Router,
+        payment_id: Uuid,
+        request_body: &RequestBody,
+        key: &str,
+    ) -> axum::response::Response<axum::body::BoxBody> {
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::POST)
+            .uri(format!("/api/payments/{payment_id}/refunds"))
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .header(IDEMPOTENCY_KEY_HEADER, key)
+            .body(serde_json::to_vec(request_body).unwrap().into())
+            .unwrap();
+        send_request(router, request).await
+    }
+
+    #[tokio::test]
+    async fn should_replay_refund_with_same_idempotency_key() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let app = TestApp::from(BankWeb::new(pool.clone(), mock_service.clone()));
+        let payment_id = app.create_approved_payment().await.data.id;
+        let request_body = RefundRequestBuilder::new().build();
+        let key = Uuid::new_v4().to_string();
+
+        let first = post_with_idempotency_key(&app.router, payment_id, &request_body, &key).await;
+        assert_eq!(first.status(), 201);
+        let replayed =
+            post_with_idempotency_key(&app.router, payment_id, &request_body, &key).await;
+        assert_eq!(replayed.status(), 201);
+        assert_eq!(replayed.headers()[LOCATION], first.headers()[LOCATION]);
+
+        let first = deserialize_response_body::<ResponseBody>(first).await;
+        let replayed = deserialize_response_body::<ResponseBody>(replayed).await;
+        assert_eq!(replayed.data.id, first.data.id);
+        assert_eq!(mock_service.deposit_funds_count.load(Ordering::SeqCst), 1);
+        assert_eq!(refunds::list(&pool, payment_id).await.unwrap().len(), 1);
+
+        // other keys record other refunds
+        let other_key = Uuid::new_v4().to_string();
+        let response =
+            post_with_idempotency_key(&app.router, payment_id, &request_body, &other_key).await;
+        assert_eq!(response.status(), 201);
+        assert_eq!(refunds::list(&pool, payment_id).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_reject_idempotency_key_reused_for_other_amount() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let key = Uuid::new_v4().to_string();
+
+        let request_body = RefundRequestBuilder::new().amount(10).build();
+        let response = post_with_idempotency_key(&router, payment_id, &request_body, &key).await;
+        assert_eq!(response.status(), 201);
+        let refund_id = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        let request_body = RefundRequestBuilder::new().amount(20).build();
+        let response = post_with_idempotency_key(&router, payment_id, &request_body, &key).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(
+            response_body.code.as_deref(),
+            Some("idempotency_key_reused")
+        );
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "refund_id": refund_id }))
+        );
+
+        let response = post_with_idempotency_key(&router, payment_id, &request_body, "").await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_record_concurrent_duplicates_once() {
+        let pool = crate::pg_pool().await.unwrap();
+        let mock_service = MockService::default();
+        let app = TestApp::from(BankWeb::new(pool.clone(), mock_service.clone()));
+        let payment_id = app.create_approved_payment().await.data.id;
+        let request_body = RefundRequestBuilder::new().build();
+        let key = Uuid::new_v4().to_string();
+
+        let (a, b) = tokio::join!(
+            post_with_idempotency_key(&app.router, payment_id, &request_body, &key),
+            post_with_idempotency_key(&app.router, payment_id, &request_body, &key),
+        );
+        assert_eq!(
+            (a.status(), b.status()),
+            (StatusCode::CREATED, StatusCode::CREATED)
+        );
+
+        let a = deserialize_response_body::<ResponseBody>(a).await;
+        let b = deserialize_response_body::<ResponseBody>(b).await;
+        assert_eq!(a.data.id, b.data.id);
+        assert_eq!(refunds::list(&pool, payment_id).await.unwrap().len(), 1);
+        assert_eq!(mock_service.deposit_funds_count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_handle_concurrent_refunds() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let fut_a = request_refund(router.clone(), payment_id);
+        let fut_b = request_refund(router, payment_id);
+        let (status_a, status_b) = tokio::join!(fut_a, fut_b);
+
+        assert_eq!(status_a.min(status_b), 201, "one refund should succeed");
+        assert_eq!(status_a.max(status_b), 422, "one refund should fail");
+    }
+
+    #[tokio::test]
+    async fn should_reject_refunds_over_the_limit() {
+        // small refunds are limited in number rather than amount
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let app = TestApp::from(bank_web);
+        let payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let request_body = RefundRequestBuilder::new().amount(1).build();
+        let statuses = futures::future::join_all(
+            (0..12).map(|_| async { post(&app.router, &uri, &request_body).await.status() }),
+        )
+        .await;
+
+        let limit = BankWeb::<DummyService>::DEFAULT_MAX_REFUNDS_PER_PAYMENT as usize;
+        let created = statuses.iter().filter(|status| **status == 201).count();
+        assert_eq!(created, limit, "{statuses:?}");
+        assert!(statuses
+            .iter()
+            .all(|status| [201, 422].contains(&status.as_u16())));
+        let refunds = refunds::list(&pool, payment_id)
+            .await
+            .expect("failed to list refunds");
+        assert_eq!(refunds.len(), limit);
+
+        let response = post(&app.router, &uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(
+            response_body.code.as_deref(),
+            Some("refund_count_limit_reached")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_refund_payment_in_several_parts() {
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let request_body = RefundRequestBuilder::new()
+            .amount(500)
+            .reason("requested_by_customer")
+            .build();
+        for _ in 0..2 {
+            let response = app.create_refund(payment_id, &request_body).await;
+            assert_eq!(response.status(), 201);
+        }
+
+        // only 205 of the 1205 paid are left to refund
+        let response = app.create_refund(payment_id, &request_body).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(
+            response_body.code.as_deref(),
+            Some("exceeds_remaining_amount")
+        );
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "remaining": 205 }))
+        );
+
+        let request_body = RefundRequestBuilder::new().amount(205).build();
+        let response = app.create_refund(payment_id, &request_body).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    async fn request_full_refund(
+        router: &axum::Router,
+        payment_id: Uuid,
+    ) -> axum::response::Response<axum::body::BoxBody> {
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::POST)
+            .uri(format!("/api/payments/{payment_id}/refunds/full"))
+            .header(MERCHANT_ID_HEADER, TEST_MERCHANT_ID.to_string())
+            .body(axum::body::Body::empty())
+            .unwrap();
+        send_request(router, request).await
+    }
+
+    #[tokio::test]
+    async fn should_refund_remaining_amount_in_full() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().amount(200).build();
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        assert_eq!(post(&router, uri, &request_body).await.status(), 201);
+
+        let response = request_full_refund(&router, payment_id).await;
+        assert_eq!(response.status(), 201);
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, 1005);
+        assert_eq!(response_body.data.status, RefundStatus::Settled);
+        assert_eq!(
+            location,
+            format!(
+                "/api/payments/{payment_id}/refunds/{}",
+                response_body.data.id
+            )
+        );
+
+        let response = request_full_refund(&router, payment_id).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("nothing_to_refund"));
+    }
+
+    #[tokio::test]
+    async fn should_only_accept_empty_full_refund_requests() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds/full");
+
+        let request_body = RefundRequestBuilder::new().build();
+        let response = post(&router, &uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+
+        let response = post(&router, &uri, &serde_json::json!({})).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn should_not_refund_more_than_paid_concurrently() {
+        let pool = crate::pg_pool().await.unwrap();
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let request_body = RefundRequestBuilder::new().amount(600).build();
+        let (full, partial) = tokio::join!(
+            request_full_refund(&app.router, payment_id),
+            app.create_refund(payment_id, &request_body),
+        );
+        assert_eq!(full.status(), 201);
+        assert!(
+            [201, 422].contains(&partial.status().as_u16()),
+            "unexpected status {}",
+            partial.status()
+        );
+
+        let refunded: i64 = refunds::list(&pool, payment_id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|refund| refund.amount)
+            .sum();
+        assert_eq!(refunded, 1205);
+    }
+
+    #[tokio::test]
+    async fn should_refund_valid_amount() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds",);
+        let response = post(&router, uri.to_string(), &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, request_body.refund.amount.0);
+        let refund_id = response_body.data.id;
+
+        let uri = format!("/api/payments/{payment_id}/refunds/{refund_id}");
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, request_body.refund.amount.0);
+    }
+
+    #[tokio::test]
+    async fn should_reject_refund_of_invalid_amount() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new()
+            .amount(payment_response_body.data.amount + 1)
+            .build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds",);
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, "excessive refund amount requested");
+    }
+
+    #[tokio::test]
+    async fn should_reject_refund_of_unapproved_payment() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+
+        for status in [Status::Declined, Status::Processing] {
+            let payment = Payment::new_test(&pool)
+                .await
+                .expect("failed to create payment");
+            sqlx::query!(
+                "UPDATE payments SET status = $2 WHERE id = $1",
+                payment.id,
+                status as Status
+            )
+            .execute(&pool)
+            .await
+            .expect("failed to update payment");
+
+            let uri = format!("/api/payments/{}/refunds", payment.id);
+            let request_body = RefundRequestBuilder::new().build();
+            let response = post_as(&router, &uri, &request_body, payment.merchant_id).await;
+            assert_eq!(response.status(), 409, "{status:?}");
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(body.code.as_deref(), Some("payment_not_approved"));
+            assert_eq!(body.details, Some(serde_json::json!({ "status": status })));
+
+            let uri = format!("{uri}/full");
+            let response =
+                post_as(&router, &uri, &serde_json::json!({}), payment.merchant_id).await;
+            assert_eq!(response.status(), 409, "{status:?}");
+
+            let refunds = refunds::list(&pool, payment.id)
+                .await
+                .expect("failed to list refunds");
+            assert!(refunds.is_empty());
+        }
+
+        // missing payments are still not found
+        let uri = format!("/api/payments/{}/refunds", Uuid::new_v4());
+        let response = post(&router, &uri, &RefundRequestBuilder::new().build()).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn should_reject_refund_of_non_positive_amount() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds");
+
+        for amount in [0, -1] {
+            let request_body = RefundRequestBuilder::new().amount(amount).build();
+            let response = post(&router, &uri, &request_body).await;
+            assert_eq!(response.status(), 400, "{amount}");
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.error, "refund.amount must be positive");
+        }
+
+        // rejected refunds leave the whole payment to refund, and no more
+        let request_body = RefundRequestBuilder::new()
+            .amount(payment_response_body.data.amount)
+            .build();
+        assert_eq!(post(&router, &uri, &request_body).await.status(), 201);
+        let request_body = RefundRequestBuilder::new().amount(1).build();
+        assert_eq!(post(&router, &uri, &request_body).await.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn should_accept_decimal_string_amounts() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds");
+
+        let request_body = serde_json::json!({ "refund": { "amount": "0.05" } });
+        let response = post(&router, &uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, 5);
+
+        let request_body = serde_json::json!({ "refund": { "amount": "0.00" } });
+        let response = post(&router, &uri, &request_body).await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, "refund.amount must be positive");
+
+        let cases = [
+            (
+                serde_json::json!("12.345"),
+                "refund.amount: amount should have at most 2 fraction digits",
+            ),
+            (
+                serde_json::json!(12.05),
+                "refund.amount: invalid type: floating point `12.05`, expected an integer of minor units or a decimal string",
+            ),
+            (
+                serde_json::json!("1e5"),
+                "refund.amount: amount should be a decimal number",
+            ),
+            (
+                serde_json::json!("-0.00"),
+                "refund.amount: amount should be a decimal number",
+            ),
+            (
+                serde_json::json!("92233720368547758.08"),
+                "refund.amount: amount is too large",
+            ),
+        ];
+        for (amount, error) in cases {
+            let request_body = serde_json::json!({ "refund": { "amount": amount } });
+            let response = post(&router, &uri, &request_body).await;
+            assert_eq!(response.status(), 422, "{amount}");
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(response_body.error, error);
+            assert_eq!(response_body.code.as_deref(), Some("invalid_body"));
+            assert_eq!(
+                response_body.details.unwrap()["errors"][0]["field"],
+                "refund.amount"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_list_refunds_of_status() {
+        let pool = crate::pg_pool().await.unwrap();
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let mut ids = Vec::new();
+        for amount in [10, 20, 30] {
+            let request_body = RefundRequestBuilder::new().amount(amount).build();
+            let response = app.create_refund(payment_id, &request_body).await;
+            let response_body = deserialize_response_body::<ResponseBody>(response).await;
+            ids.push(response_body.data.id);
+        }
+        refunds::set_status(&pool, ids[0], RefundStatus::Pending)
+            .await
+            .unwrap();
+        refunds::set_status(&pool, ids[2], RefundStatus::Failed)
+            .await
+            .unwrap();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = get(&app.router, &uri).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<ListResponseBody>(response).await;
+        let listed: Vec<_> = response_body.data.iter().map(|refund| refund.id).collect();
+        assert_eq!(listed, ids);
+
+        for (status, id) in [("pending", ids[0]), ("settled", ids[1]), ("failed", ids[2])] {
+            let response = get(&app.router, format!("{uri}?status={status}")).await;
+            assert_eq!(response.status(), 200);
+            let response_body = deserialize_response_body::<ListResponseBody>(response).await;
+            let listed: Vec<_> = response_body.data.iter().map(|refund| refund.id).collect();
+            assert_eq!(listed, [id], "{status}");
+        }
+
+        let response = get(&app.router, format!("{uri}?status=completed")).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_status"));
+    }
+
+    #[tokio::test]
+    async fn should_cancel_pending_refund() {
+        let pool = crate::pg_pool().await.unwrap();
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().amount(1000).build();
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let refund_id = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+            .id;
+        // refunds are settled within the request unless their deposit fails
+        refunds::set_status(&pool, refund_id, RefundStatus::Pending)
+            .await
+            .unwrap();
+
+        let response = delete(&router, &location).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.id, refund_id);
+        assert_eq!(response_body.data.status, RefundStatus::Canceled);
+
+        // canceled refunds leave the whole payment to refund
+        let response = request_full_refund(&router, payment_id).await;
+        assert_eq!(response.status(), 201);
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.amount, 1205);
+
+        let response = delete(&router, &location).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "status": "canceled" }))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_cancel_settled_refund() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, &uri, &request_body).await;
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+
+        let response = delete(&router, &location).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("refund_not_pending"));
+        assert_eq!(
+            response_body.details,
+            Some(serde_json::json!({ "status": "settled" }))
+        );
+
+        let response = get(&router, &location).await;
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.status, RefundStatus::Settled);
+
+        let response = delete(&router, format!("{uri}/{}", Uuid::new_v4())).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn should_list_refunds_of_every_payment() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+        let merchant_id = Uuid::new_v4();
+
+        let mut payments = Vec::new();
+        for _ in 0..2 {
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+            let payment = deserialize_response_body::<payments::ResponseBody>(response)
+                .await
+                .data;
+            payments.push(payment);
+        }
+
+        let mut refunds = Vec::new();
+        for (payment, amount) in [
+            (&payments[0], 100),
+            (&payments[1], 300),
+            (&payments[0], 500),
+        ] {
+            let uri = format!("/api/payments/{}/refunds", payment.id);
+            let request_body = RefundRequestBuilder::new().amount(amount).build();
+            let response = post_as(&router, uri, &request_body, merchant_id).await;
+            assert_eq!(response.status(), 201);
+            let refund = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data;
+            refunds.push(refund);
+        }
+        refunds::set_status(&pool, refunds[1].id, RefundStatus::Failed)
+            .await
+            .unwrap();
+
+        let list = |query: String| {
+            let router = router.clone();
+            async move {
+                let response = get_as(&router, format!("/api/refunds?{query}"), merchant_id).await;
+                assert_eq!(response.status(), 200, "{query}");
+                deserialize_response_body::<Paginated<ListedData>>(response).await
+            }
+        };
+        let ids = |page: &Paginated<ListedData>| -> Vec<Uuid> {
+            page.data.iter().map(|listed| listed.refund.id).collect()
+        };
+
+        let page = list(String::new()).await;
+        assert_eq!(ids(&page), [refunds[2].id, refunds[1].id, refunds[0].id]);
+        assert_eq!(page.data[0].card_number, payments[0].card_number);
+        assert_eq!(page.data[1].card_number, payments[1].card_number);
+        assert_eq!(page.data[0].payment_status, Status::Approved);
+        assert_eq!(page.data[1].refund.status, RefundStatus::Failed);
+
+        let page = list("min_amount=300".to_string()).await;
+        assert_eq!(ids(&page), [refunds[2].id, refunds[1].id]);
+
+        let page = list("status=failed".to_string()).await;
+        assert_eq!(ids(&page), [refunds[1].id]);
+
+        let rfc3339 = |at: OffsetDateTime| at.format(&Rfc3339).unwrap();
+        let (from, to) = (
+            rfc3339(refunds[1].inserted_at),
+            rfc3339(refunds[2].inserted_at),
+        );
+        let page = list(format!("from={from}&to={to}")).await;
+        assert_eq!(ids(&page), [refunds[1].id]);
+
+        let page = list("limit=2".to_string()).await;
+        assert_eq!(ids(&page), [refunds[2].id, refunds[1].id]);
+        let cursor = page.page_info.next_cursor.expect("missing next cursor");
+        let page = list(format!("limit=2&cursor={cursor}")).await;
+        assert_eq!(ids(&page), [refunds[0].id]);
+        assert!(!page.page_info.has_more);
+
+        // larger pages are clamped rather than rejected
+        let page = list("limit=1000".to_string()).await;
+        assert_eq!(page.data.len(), 3);
+
+        // other merchants don't see the refunds
+        let response = get_as(&router, "/api/refunds", Uuid::new_v4()).await;
+        let page = deserialize_response_body::<Paginated<ListedData>>(response).await;
+        assert!(page.data.is_empty());
+
+        let response = get_as(&router, "/api/refunds?status=done", merchant_id).await;
+        assert_eq!(response.status(), 422);
+        let response = get_as(&router, "/api/refunds?from=yesterday", merchant_id).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_list_refunds_by_external_reference() {
+        let router = BankWeb::new_test().await.into_router();
+        let merchant_id = Uuid::new_v4();
+
+        let mut refunds = Vec::new();
+        for reference in ["erp-1", "erp-2"] {
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+            let payment = deserialize_response_body::<payments::ResponseBody>(response)
+                .await
+                .data;
+
+            let uri = format!("/api/payments/{}/refunds", payment.id);
+            let request_body = RefundRequestBuilder::new()
+                .metadata(serde_json::json!({ "entry": reference }))
+                .external_reference(reference)
+                .build();
+            let response = post_as(&router, uri, &request_body, merchant_id).await;
+            assert_eq!(response.status(), 201);
+            let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+
+            let response = get_as(&router, location, merchant_id).await;
+            let refund = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data;
+            assert_eq!(
+                refund.metadata,
+                Some(serde_json::json!({ "entry": reference }))
+            );
+            assert_eq!(refund.external_reference.as_deref(), Some(reference));
+            refunds.push(refund);
+        }
+
+        let response = get_as(
+            &router,
+            "/api/refunds?external_reference=erp-2",
+            merchant_id,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let page = deserialize_response_body::<Paginated<ListedData>>(response).await;
+        let ids: Vec<_> = page.data.iter().map(|listed| listed.refund.id).collect();
+        assert_eq!(ids, [refunds[1].id]);
+        assert_eq!(
+            page.data[0].refund.external_reference.as_deref(),
+            Some("erp-2")
+        );
+
+        let response = get_as(
+            &router,
+            "/api/refunds?external_reference=erp-3",
+            merchant_id,
+        )
+        .await;
+        let page = deserialize_response_body::<Paginated<ListedData>>(response).await;
+        assert!(page.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_record_who_initiated_refunds() {
+        let router = BankWeb::new_test().await.into_router();
+        let merchant_id = Uuid::new_v4();
+
+        let mut refunds = Vec::new();
+        for authorization in [Some("Bearer test-admin-token"), Some("Bearer guess"), None] {
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+            let payment = deserialize_response_body::<payments::ResponseBody>(response)
+                .await
+                .data;
+
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(format!("/api/payments/{}/refunds", payment.id))
+                .header(CONTENT_TYPE, "application/json")
+                .header(MERCHANT_ID_HEADER, merchant_id.to_string());
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            let request_body = RefundRequestBuilder::new().build();
+            let request = request
+                .body(serde_json::to_vec(&request_body).unwrap().into())
+                .unwrap();
+            let response = send_request(&router, request).await;
+            assert_eq!(response.status(), 201);
+            let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+
+            let response = get_as(&router, location, merchant_id).await;
+            refunds.push(
+                deserialize_response_body::<ResponseBody>(response)
+                    .await
+                    .data,
+            );
+        }
+
+        let initiators: Vec<_> = refunds
+            .iter()
+            .map(|refund| refund.initiated_by.as_str())
+            .collect();
+        assert_eq!(initiators, ["admin", "anonymous", "anonymous"]);
+
+        let response = get_as(&router, "/api/refunds?initiated_by=admin", merchant_id).await;
+        assert_eq!(response.status(), 200);
+        let page = deserialize_response_body::<Paginated<ListedData>>(response).await;
+        let ids: Vec<_> = page.data.iter().map(|listed| listed.refund.id).collect();
+        assert_eq!(ids, [refunds[0].id]);
+        assert_eq!(page.data[0].refund.initiated_by, "admin");
+
+        let response = get_as(&router, "/api/refunds?initiated_by=anonymous", merchant_id).await;
+        let page = deserialize_response_body::<Paginated<ListedData>>(response).await;
+        let ids: Vec<_> = page.data.iter().map(|listed| listed.refund.id).collect();
+        assert_eq!(ids, [refunds[2].id, refunds[1].id]);
+    }
+
+    #[tokio::test]
+    async fn should_export_refunds_as_csv() {
+        let router = BankWeb::new_test().await.into_router();
+        let merchant_id = Uuid::new_v4();
+
+        let mut payment_ids = Vec::new();
+        for reason in [None, Some("duplicate"), Some("fraudulent")] {
+            let request_body = PaymentRequestBuilder::new().build();
+            let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+            let payment = deserialize_response_body::<payments::ResponseBody>(response)
+                .await
+                .data;
+
+            let uri = format!("/api/payments/{}/refunds", payment.id);
+            let mut request_body = RefundRequestBuilder::new();
+            if let Some(reason) = reason {
+                request_body = request_body.reason(reason);
+            }
+            let response = post_as(&router, uri, &request_body.build(), merchant_id).await;
+            assert_eq!(response.status(), 201);
+            payment_ids.push(payment.id);
+        }
+
+        let response = get_as(&router, "/api/refunds?format=csv", merchant_id).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv; charset=utf-8");
+
+        let bytes = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("failed to read response body into bytes");
+        let mut reader = csv::Reader::from_reader(bytes.as_ref());
+        assert_eq!(
+            reader.headers().expect("missing CSV headers"),
+            CSV_HEADERS.as_slice()
+        );
+        let rows: Vec<CsvRow> = reader
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .expect("failed to parse CSV");
+
+        // newest first
+        payment_ids.reverse();
+        assert_eq!(
+            rows.iter().map(|row| row.payment_id).collect::<Vec<_>>(),
+            payment_ids
+        );
+        assert_eq!(
+            rows.iter().map(|row| row.reason).collect::<Vec<_>>(),
+            [Some(Reason::Fraudulent), Some(Reason::Duplicate), None]
+        );
+        assert!(rows
+            .iter()
+            .all(|row| row.amount == RefundRequestBuilder::DEFAULT_AMOUNT));
+
+        let response = get_as(&router, "/api/refunds?format=xml", merchant_id).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_preview_refund_without_making_it() {
+        let app = TestApp::new().await;
+        let payment = app.create_approved_payment().await.data;
+        let uri = format!("/api/payments/{}/refunds/preview", payment.id);
+
+        let request_body = RefundRequestBuilder::new().build();
+        let response = post(&app.router, &uri, &request_body).await;
+        assert_eq!(response.status(), 200);
+        let body = deserialize_response_body::<PreviewResponseBody>(response).await;
+        assert_eq!(
+            body.data,
+            PreviewData {
+                allowed: true,
+                remaining_after: payment.amount - RefundRequestBuilder::DEFAULT_AMOUNT,
+            }
+        );
+
+        let response = get(&app.router, format!("/api/payments/{}/refunds", payment.id)).await;
+        let body = deserialize_response_body::<ListResponseBody>(response).await;
+        assert!(body.data.is_empty());
+
+        // rejected with the errors of a refund
+        let request_body = RefundRequestBuilder::new().amount(0).build();
+        let response = post(&app.router, &uri, &request_body).await;
+        assert_eq!(response.status(), 400);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("invalid_amount"));
+
+        let refunds_uri = format!("/api/payments/{}/refunds", payment.id);
+        let request_body = RefundRequestBuilder::new().build();
+        let response = post(&app.router, &refunds_uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let request_body = RefundRequestBuilder::new().amount(payment.amount).build();
+        let response = post(&app.router, &uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("exceeds_remaining_amount"));
+    }
+
+    #[tokio::test]
+    async fn should_reject_reused_external_reference() {
+        let app = TestApp::new().await;
+        let reference = Uuid::new_v4().to_string();
+
+        let payment_id = app.create_approved_payment().await.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let request_body = RefundRequestBuilder::new()
+            .amount(10)
+            .external_reference(&reference)
+            .build();
+        let response = post(&app.router, &uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let refund = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        // references are unique across the payments of the merchant
+        let other_payment_id = app.create_approved_payment().await.data.id;
+        for uri in [uri, format!("/api/payments/{other_payment_id}/refunds")] {
+            let response = post(&app.router, &uri, &request_body).await;
+            assert_eq!(response.status(), 409, "{uri}");
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(body.code.as_deref(), Some("external_reference_reused"));
+            assert_eq!(
+                body.details,
+                Some(serde_json::json!({ "refund_id": refund.id }))
+            );
+        }
+
+        // but not across merchants
+        let request_body = PaymentRequestBuilder::new().build();
+        let merchant_id = Uuid::new_v4();
+        let response = post_as(&app.router, "/api/payments", &request_body, merchant_id).await;
+        let payment = deserialize_response_body::<payments::ResponseBody>(response)
+            .await
+            .data;
+        let uri = format!("/api/payments/{}/refunds", payment.id);
+        let request_body = RefundRequestBuilder::new()
+            .external_reference(&reference)
+            .build();
+        let response = post_as(&app.router, uri, &request_body, merchant_id).await;
+        assert_eq!(response.status(), 201);
+
+        let request_body = RefundRequestBuilder::new()
+            .external_reference(&"x".repeat(MAX_EXTERNAL_REFERENCE_LENGTH + 1))
+            .metadata(serde_json::json!(["not", "an", "object"]))
+            .build();
+        let response = post(
+            &app.router,
+            format!("/api/payments/{other_payment_id}/refunds"),
+            &request_body,
+        )
+        .await;
+        assert_eq!(response.status(), 422);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        let fields: Vec<_> = body.details.unwrap()["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(fields, ["refund.metadata", "refund.external_reference"]);
+    }
+
+    #[tokio::test]
+    async fn should_return_refund_stats_per_day() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let app = TestApp::from(bank_web);
+
+        // a merchant of its own, so payments of other tests are left out
+        let merchant_id = Uuid::new_v4();
+        let day = time::Date::from_calendar_date(2023, time::Month::January, 1)
+            .unwrap()
+            .midnight()
+            .assume_utc();
+
+        // a payment of the first day refunded a fifth, one of the second
+        // day not refunded, backdated once refunded within the refund window
+        for (hours, refund_amount) in [(1, Some(241)), (25, None)] {
+            let payment_id = app.create_approved_payment().await.data.id;
+            let inserted_at = to_utc(day + Duration::hours(hours));
+
+            if let Some(amount) = refund_amount {
+                let request_body = RefundRequestBuilder::new().amount(amount).build();
+                let response = app.create_refund(payment_id, &request_body).await;
+                let refund = deserialize_response_body::<ResponseBody>(response)
+                    .await
+                    .data;
+                sqlx::query!(
+                    "UPDATE refunds SET merchant_id = $2, inserted_at = $3 WHERE id = $1",
+                    refund.id,
+                    merchant_id,
+                    inserted_at
+                )
+                .execute(&pool)
+                .await
+                .expect("failed to backdate refund");
+            }
+
+            sqlx::query!(
+                "UPDATE payments SET merchant_id = $2, inserted_at = $3 WHERE id = $1",
+                payment_id,
+                merchant_id,
+                inserted_at
+            )
+            .execute(&pool)
+            .await
+            .expect("failed to backdate payment");
+        }
+
+        let uri = format!(
+            "/api/refunds/stats?from={}&to={}&group_by=day",
+            day.format(&Rfc3339).unwrap(),
+            (day + Duration::days(2)).format(&Rfc3339).unwrap()
+        );
+        let response = get_as(&app.router, uri, merchant_id).await;
+        assert_eq!(response.status(), 200);
+        let body = deserialize_response_body::<StatsResponseBody>(response).await;
+
+        let amount = PaymentRequestBuilder::DEFAULT_AMOUNT;
+        assert_eq!(
+            body.data,
+            vec![
+                BucketStats {
+                    bucket: day,
+                    currency: "USD".to_string(),
+                    count: 1,
+                    refunded_amount: 241,
+                    captured_amount: amount,
+                    refund_rate: Some(0.2),
+                },
+                BucketStats {
+                    bucket: day + Duration::days(1),
+                    currency: "USD".to_string(),
+                    count: 0,
+                    refunded_amount: 0,
+                    captured_amount: amount,
+                    refund_rate: Some(0.0),
+                },
+            ]
+        );
+
+        let response = get(&app.router, "/api/refunds/stats?from=yesterday").await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_refund_payments_in_bulk() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let app = TestApp::from(bank_web);
+        let payment_ids = [
+            app.create_approved_payment().await.data.id,
+            app.create_approved_payment().await.data.id,
+        ];
+        let missing_payment_id = Uuid::new_v4();
+
+        let request_body = serde_json::json!({
+            "refunds": [
+                { "payment_id": payment_ids[0], "amount": 100 },
+                { "payment_id": payment_ids[1], "amount": 5000 },
+                { "payment_id": missing_payment_id, "amount": 100 },
+                { "payment_id": payment_ids[1], "amount": 50 },
+            ],
+        });
+        let response = post(&app.router, "/api/refunds/bulk", &request_body).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<BulkResponseBody>(response).await;
+
+        let outcomes = &response_body.data;
+        let payment_ids_of: Vec<_> = outcomes.iter().map(|outcome| outcome.payment_id).collect();
+        assert_eq!(
+            payment_ids_of,
+            [
+                payment_ids[0],
+                payment_ids[1],
+                missing_payment_id,
+                payment_ids[1]
+            ]
+        );
+        for (outcome, payment_id) in [
+            (&outcomes[0], payment_ids[0]),
+            (&outcomes[3], payment_ids[1]),
+        ] {
+            let refund_id = outcome.refund_id.expect("missing refund");
+            let refund = refunds::get(&pool, refund_id)
+                .await
+                .expect("failed to get refund");
+            assert_eq!(refund.payment_id, payment_id);
+            assert_eq!(refund.status, RefundStatus::Settled);
+        }
+
+        let error = outcomes[1].error.as_ref().expect("missing error");
+        assert_eq!(error.status, 422);
+        assert_eq!(error.code.as_deref(), Some("invalid_refund"));
+        let error = outcomes[2].error.as_ref().expect("missing error");
+        assert_eq!(error.status, 404);
+        assert_eq!(error.code.as_deref(), Some("not_found"));
+        assert_eq!(error.message, "payment doesn't exist");
+
+        let summary: Vec<_> = response_body
+            .summary
+            .iter()
+            .map(|(outcome, count)| (outcome.as_str(), *count))
+            .collect();
+        assert_eq!(
+            summary,
+            [("created", 2), ("invalid_refund", 1), ("not_found", 1)]
+        );
+
+        let request_body = serde_json::json!({
+            "refunds": vec![
+                serde_json::json!({ "payment_id": payment_ids[0], "amount": 1 });
+                MAX_BULK_REFUNDS + 1
+            ],
+        });
+        let response = post(&app.router, "/api/refunds/bulk", &request_body).await;
+        assert_eq!(response.status(), 422);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("too_many_refunds"));
+    }
+
+    #[tokio::test]
+    async fn should_return_location_of_refund() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        let refund_id = response_body.data.id;
+        assert_eq!(
+            location,
+            format!("/api/payments/{payment_id}/refunds/{refund_id}")
+        );
+
+        let response = get(&router, location).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<ResponseBody>(response).await;
+        assert_eq!(response_body.data.id, refund_id);
+    }
+
+    #[tokio::test]
+    async fn should_return_persisted_refund_timestamps() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let created = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        let uri = format!("/api/payments/{payment_id}/refunds/{}", created.id);
+        let response = get(&router, uri).await;
+        let fetched = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!(fetched.inserted_at, created.inserted_at);
+        assert_eq!(fetched.updated_at, created.updated_at);
+    }
+
+    #[tokio::test]
+    async fn should_list_refunds_of_same_time_by_id() {
+        let pool = crate::pg_pool().await.unwrap();
+        let app = TestApp::new().await;
+        let payment_id = app.create_approved_payment().await.data.id;
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let request_body = RefundRequestBuilder::new().amount(10).build();
+            let response = app.create_refund(payment_id, &request_body).await;
+            let response_body = deserialize_response_body::<ResponseBody>(response).await;
+            ids.push(response_body.data.id);
+        }
+        sqlx::query!(
+            "UPDATE refunds SET inserted_at = LOCALTIMESTAMP WHERE payment_id = $1",
+            payment_id
+        )
+        .execute(&pool)
+        .await
+        .expect("failed to update refunds");
+        ids.sort();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = get(&app.router, &uri).await;
+        let response_body = deserialize_response_body::<serde_json::Value>(response).await;
+        let refunds = response_body["data"].as_array().expect("missing refunds");
+        let listed: Vec<Uuid> = refunds
+            .iter()
+            .map(|refund| refund["id"].as_str().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(listed, ids);
+
+        // timestamps are RFC 3339, whatever the client parses them with
+        let inserted_at: Vec<_> = refunds
+            .iter()
+            .map(|refund| {
+                OffsetDateTime::parse(refund["inserted_at"].as_str().unwrap(), &Rfc3339)
+                    .expect("invalid inserted_at")
+            })
+            .collect();
+        assert!(inserted_at.iter().all(|at| *at == inserted_at[0]));
+        for refund in refunds {
+            OffsetDateTime::parse(refund["updated_at"].as_str().unwrap(), &Rfc3339)
+                .expect("invalid updated_at");
+        }
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_refund_with_etag() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+
+        let response = get(&router, &location).await;
+        assert_eq!(response.status(), 200);
+        let etag = response.headers()[ETAG].clone();
+
+        let response = get_if_none_match(&router, &location, &etag).await;
+        assert_eq!(response.status(), 304);
+    }
+
+    #[tokio::test]
+    async fn should_report_unknown_refund_fields_on_v1_only() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = serde_json::json!({ "refund": { "ammount": 42, "amount": 42 } });
+
+        let uri = format!("/api/v1/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("unknown_field"));
+        assert_eq!(
+            response_body.details.unwrap()["errors"][0]["field"],
+            "refund.ammount"
+        );
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn should_report_every_invalid_refund_field() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new()
+            .amount(payment_response_body.data.amount + 1)
+            .currency("EUR")
+            .reason("changed_my_mind")
+            .build();
+
+        let uri = format!("/api/v1/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 422);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_refund"));
+
+        let details = response_body.details.unwrap();
+        let fields: Vec<_> = details["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            ["refund.amount", "refund.currency", "refund.reason"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_refund_in_currency_of_payment() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds");
+
+        // omitted currencies are inherited, given ones must match exactly
+        for currency in [None, Some("USD")] {
+            let mut request_body = RefundRequestBuilder::new().amount(10);
+            if let Some(currency) = currency {
+                request_body = request_body.currency(currency);
+            }
+            let response = post(&router, &uri, &request_body.build()).await;
+            assert_eq!(response.status(), 201, "{currency:?}");
+            let response_body = deserialize_response_body::<ResponseBody>(response).await;
+            assert_eq!(response_body.data.currency, "USD");
+        }
+
+        for currency in ["EUR", "usd"] {
+            let request_body = RefundRequestBuilder::new().currency(currency).build();
+            let response = post(&router, &uri, &request_body).await;
+            assert_eq!(response.status(), 422, "{currency}");
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            let error = &response_body.details.unwrap()["errors"][0];
+            assert_eq!(error["field"], "refund.currency");
+            assert_eq!(
+                error["message"],
+                format!("refund currency {currency} doesn't match payment currency USD")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_record_refund_reason() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new()
+            .currency("USD")
+            .reason("requested_by_customer")
+            .build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let response_body =
+            deserialize_response_body::<ResponseBody>(get(&router, location).await).await;
+        assert_eq!(response_body.data.reason, Some(Reason::RequestedByCustomer));
+    }
+
+    #[tokio::test]
+    async fn should_record_detail_of_other_refund_reason() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new()
+            .reason("other")
+            .reason_detail("goodwill gesture after a late delivery")
+            .build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let response_body =
+            deserialize_response_body::<ResponseBody>(get(&router, location).await).await;
+        assert_eq!(response_body.data.reason, Some(Reason::Other));
+        assert_eq!(
+            response_body.data.reason_detail.as_deref(),
+            Some("goodwill gesture after a late delivery")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_refund_reason_detail() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let uri = format!("/api/payments/{payment_id}/refunds");
+
+        let too_long = "a".repeat(MAX_REASON_DETAIL_LENGTH + 1);
+        for request_body in [
+            RefundRequestBuilder::new().reason("other").build(),
+            RefundRequestBuilder::new()
+                .reason("duplicate")
+                .reason_detail("charged twice")
+                .build(),
+            RefundRequestBuilder::new()
+                .reason("other")
+                .reason_detail(&too_long)
+                .build(),
+        ] {
+            let response = post(&router, &uri, &request_body).await;
+            assert_eq!(response.status(), 422);
+
+            let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                response_body.details.unwrap()["errors"][0]["field"],
+                "refund.reason_detail"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_enforce_refund_window_of_merchant() {
+        let clock = Arc::new(ManualClock::new(OffsetDateTime::now_utc()));
+        let bank_web = BankWeb::new_test().await.with_clock(clock.clone());
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+
+        let merchant_id = Uuid::new_v4();
+        let policy = RefundPolicy {
+            refund_window_days: Some(180),
+        };
+        merchants::set_refund_policy(&pool, merchant_id, policy)
+            .await
+            .expect("failed to set refund policy");
+
+        let request_body = PaymentRequestBuilder::new().build();
+        let response = post_as(&router, "/api/payments", &request_body, merchant_id).await;
+        assert_eq!(response.status(), 201);
+        let payment = deserialize_response_body::<payments::ResponseBody>(response)
+            .await
+            .data;
+
+        let uri = format!("/api/payments/{}/refunds", payment.id);
+        let request_body = RefundRequestBuilder::new().amount(10).build();
+        for (age, status) in [
+            (Duration::days(179), 201),
+            (Duration::days(180), 201),
+            (Duration::days(180) + Duration::seconds(1), 422),
+        ] {
+            clock.set(payment.inserted_at + age);
+            let response = post_as(&router, &uri, &request_body, merchant_id).await;
+            assert_eq!(response.status(), status, "{age:?}");
+
+            if status == 422 {
+                let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+                assert_eq!(body.code.as_deref(), Some("refund_window_expired"));
+                let cutoff = payment.inserted_at + Duration::days(180);
+                assert_eq!(
+                    body.details,
+                    Some(serde_json::json!({
+                        "refund_window_days": 180,
+                        "cutoff": cutoff.format(&Rfc3339).unwrap(),
+                    }))
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_enforce_default_refund_window() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+
+        let request_body = RefundRequestBuilder::new().amount(10).build();
+        for (age, status) in [
+            (Duration::days(90) - Duration::minutes(1), 201),
+            (Duration::days(90) + Duration::minutes(1), 422),
+        ] {
+            let payment = Payment::new_test(&pool)
+                .await
+                .expect("failed to create payment");
+            sqlx::query!(
+                "UPDATE payments SET inserted_at = $2 WHERE id = $1",
+                payment.id,
+                payment.inserted_at - age,
+            )
+            .execute(&pool)
+            .await
+            .expect("failed to backdate payment");
+
+            let uri = format!("/api/payments/{}/refunds", payment.id);
+            let response = post_as(&router, &uri, &request_body, payment.merchant_id).await;
+            assert_eq!(response.status(), status, "{age:?}");
+
+            if status == 422 {
+                let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+                assert_eq!(body.code.as_deref(), Some("refund_window_expired"));
+                let details = body.details.expect("missing details");
+                assert_eq!(details["refund_window_days"], 90);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_let_admins_override_refund_window() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web.into_router();
+
+        let payment = Payment::new_test(&pool)
+            .await
+            .expect("failed to create payment");
+        sqlx::query!(
+            "UPDATE payments SET inserted_at = $2 WHERE id = $1",
+            payment.id,
+            payment.inserted_at - Duration::days(91),
+        )
+        .execute(&pool)
+        .await
+        .expect("failed to backdate payment");
+        let uri = format!("/api/payments/{}/refunds", payment.id);
+        let admin_post = |request_body: RequestBody| {
+            let request = admin_request(Method::POST, &uri)
+                .header(CONTENT_TYPE, "application/json")
+                .header(MERCHANT_ID_HEADER, payment.merchant_id.to_string())
+                .body(serde_json::to_vec(&request_body).unwrap().into())
+                .unwrap();
+            send_request(&router, request)
+        };
+
+        let request_body = RefundRequestBuilder::new()
+            .amount(10)
+            .override_refund_window()
+            .build();
+        let response = post_as(&router, &uri, &request_body, payment.merchant_id).await;
+        assert_eq!(response.status(), 403);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(
+            body.code.as_deref(),
+            Some("refund_window_override_forbidden")
+        );
+
+        // admins are held to the window unless overriding it
+        let response = admin_post(RefundRequestBuilder::new().amount(10).build()).await;
+        assert_eq!(response.status(), 422);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("refund_window_expired"));
+
+        let response = admin_post(request_body).await;
+        assert_eq!(response.status(), 201);
+        let refund = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+        assert_eq!((refund.amount, refund.initiated_by.as_str()), (10, "admin"));
+    }
+
+    #[tokio::test]
+    async fn should_return_requested_refund_fields_only() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let request_body = RefundRequestBuilder::new().build();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+
+        let response = get(&router, format!("{location}?fields=amount")).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<serde_json::Value>(response).await;
+        let data = response_body["data"].as_object().expect("missing refund");
+        let keys: Vec<_> = data.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["amount", "id"]);
+
+        let response = get(&router, format!("{location}?fields=amount,card_number")).await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code.as_deref(), Some("invalid_fields"));
+    }
+
+    #[tokio::test]
+    async fn should_hide_refunds_of_other_merchants() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let other_merchant_id = Uuid::new_v4();
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let request_body = RefundRequestBuilder::new().build();
+        let response = post_as(&router, &uri, &request_body, other_merchant_id).await;
+        assert_eq!(response.status(), 404);
+
+        let response = post(&router, &uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+
+        assert_eq!(get(&router, &location).await.status(), 200);
+        let response = get_as(&router, &location, other_merchant_id).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn should_not_return_refund_under_another_payment() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+        let (_, other_payment_response_body) = setup().await;
+        let other_payment_id = other_payment_response_body.data.id;
+
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let request_body = RefundRequestBuilder::new().build();
+        let response = post(&router, uri, &request_body).await;
+        let refund_id = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        let uri = format!("/api/payments/{other_payment_id}/refunds/{refund_id}");
+        assert_eq!(get(&router, uri).await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn should_return_404_for_unknown_refund() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let uri = format!("/api/payments/{payment_id}/refunds/{}", Uuid::new_v4());
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 404);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("not_found"));
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_malformed_refund_id() {
+        let (router, payment_response_body) = setup().await;
+        let payment_id = payment_response_body.data.id;
+
+        let uri = format!("/api/payments/{payment_id}/refunds/not-a-uuid");
+        let response = get(&router, uri).await;
+        assert_eq!(response.status(), 400);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.code.as_deref(), Some("invalid_path_parameter"));
+        assert!(body.error.contains("UUID"), "{}", body.error);
+    }
+
+    #[tokio::test]
+    async fn should_return_400_for_malformed_payment_id() {
+        let router = BankWeb::new_test().await.into_router();
+        let request_body = RefundRequestBuilder::new().build();
+
+        for uri in [
+            "/api/payments/not-a-uuid/refunds",
+            "/api/payments/not-a-uuid/refunds/preview",
+        ] {
+            let response = post(&router, uri, &request_body).await;
+            assert_eq!(response.status(), 400, "{uri}");
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(
+                body.code.as_deref(),
+                Some("invalid_path_parameter"),
+                "{uri}"
+            );
+        }
     }
 }
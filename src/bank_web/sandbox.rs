@@ -0,0 +1,110 @@
+use std::sync::PoisonError;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+use super::BankWeb;
+use crate::bank::accounts::{AccountService, ScriptedOutcome};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RequestBody {
+    /// Outcome of the next account service calls, `None` to stop scripting.
+    pub outcome: Option<ScriptedOutcome>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: Option<ScriptedOutcome>,
+}
+
+/// Scripts the outcome of the account service calls.
+///
+/// Only routed in sandbox mode.
+pub async fn put_account_outcome<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    Json(body): Json<RequestBody>,
+) -> Result<Json<ResponseBody>, StatusCode> {
+    let scenario = bank_web.sandbox.ok_or(StatusCode::NOT_FOUND)?;
+
+    tracing::warn!(outcome = ?body.outcome, "scripting account service outcome");
+    *scenario.write().unwrap_or_else(PoisonError::into_inner) = body.outcome.clone();
+
+    Ok(Json(ResponseBody { data: body.outcome }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use axum::{
+        http::{header::CONTENT_TYPE, Method},
+        Router,
+    };
+
+    use super::*;
+    use crate::{
+        bank::accounts::DummyService,
+        bank_web::{
+            payments::tests::PaymentRequestBuilder,
+            tests::{admin_request, post, send_request},
+        },
+    };
+
+    async fn sandbox_router() -> Router {
+        let bank_web = BankWeb::<DummyService>::new_test().await;
+        let scenario = bank_web.account_service.0.inner().scenario();
+        bank_web.with_sandbox(scenario).into_router()
+    }
+
+    async fn put(router: &Router, body: &str) -> StatusCode {
+        let request = admin_request(Method::PUT, "/api/admin/sandbox/account_outcome")
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string().into())
+            .expect("failed to build PUT request");
+        send_request(router, request).await.status()
+    }
+
+    async fn pay(router: &Router) -> StatusCode {
+        let request_body = PaymentRequestBuilder::new().build();
+        post(router, "/api/payments", &request_body).await.status()
+    }
+
+    #[tokio::test]
+    async fn should_fail_next_holds_then_recover() {
+        let router = sandbox_router().await;
+
+        let body = r#"{"outcome":{"error":"insufficient_funds","method":"hold","remaining":2}}"#;
+        assert_eq!(put(&router, body).await, 200);
+
+        assert_eq!(pay(&router).await, 402);
+        assert_eq!(pay(&router).await, 402);
+        assert_eq!(pay(&router).await, 201);
+    }
+
+    #[tokio::test]
+    async fn should_only_fail_targeted_method() {
+        let router = sandbox_router().await;
+
+        let body = r#"{"outcome":{"error":"insufficient_funds","method":"withdraw"}}"#;
+        assert_eq!(put(&router, body).await, 200);
+        assert_eq!(pay(&router).await, 402);
+
+        assert_eq!(put(&router, r#"{"outcome":null}"#).await, 200);
+        assert_eq!(pay(&router).await, 201);
+    }
+
+    #[tokio::test]
+    async fn should_reject_zero_remaining_failures() {
+        let router = sandbox_router().await;
+
+        let body = r#"{"outcome":{"error":"insufficient_funds","remaining":0}}"#;
+        assert_eq!(put(&router, body).await, 422);
+    }
+
+    #[tokio::test]
+    async fn should_not_route_sandbox_in_live_mode() {
+        let router = BankWeb::new_test().await.into_router();
+
+        let body = r#"{"outcome":{"error":"insufficient_funds"}}"#;
+        assert_eq!(put(&router, body).await, 404);
+        assert_eq!(pay(&router).await, 201);
+    }
+}
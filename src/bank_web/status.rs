@@ -0,0 +1,336 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::{
+        header::{HeaderName, CACHE_CONTROL},
+        Request,
+    },
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+use super::{storage_unavailable, BankWeb};
+use crate::bank::{
+    accounts::AccountService,
+    availability::{self, BurnRateEvaluator, Endpoint, Minute},
+};
+use crate::errors::ApiError;
+
+/// How long the status is served from the cache, also told to clients.
+pub const CACHE_TTL: Duration = Duration::from_secs(30);
+/// Width of the points of the series.
+const STEP: Duration = Duration::from_secs(5 * 60);
+/// Period covered by the series.
+const HISTORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PointData {
+    /// Start of the 5 minutes of the point.
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub requests: i64,
+    /// Requests answered with a server error.
+    pub errors: i64,
+    /// Highest per-minute p95 of the 5 minutes.
+    pub p95_latency_ms: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SeriesData {
+    pub endpoint: Endpoint,
+    pub degraded: bool,
+    /// Points with requests only, oldest first.
+    pub points: Vec<PointData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StatusData {
+    /// Whether an endpoint is degraded, see `BurnRateEvaluator`.
+    pub degraded: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+    pub series: Vec<SeriesData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: StatusData,
+}
+
+/// Records the outcome and latency of the requests to the payment and refund
+/// endpoints in the SLO aggregator, server errors counting as failures.
+pub async fn track<T, B>(
+    State(bank_web): State<BankWeb<T>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(endpoint) = endpoint_of(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    bank_web.slo.record(
+        endpoint,
+        !response.status().is_server_error(),
+        started.elapsed(),
+        bank_web.clock.now(),
+    );
+    response
+}
+
+/// Returns the tracked endpoint serving `path`, refunds of payments being
+/// refund endpoints.
+fn endpoint_of(path: &str) -> Option<Endpoint> {
+    let path = path.strip_prefix("/api/")?;
+    let path = path.strip_prefix("v1/").unwrap_or(path);
+    let mut segments = path.split('/');
+    match segments.next()? {
+        "payments" if segments.any(|segment| segment == "refunds") => Some(Endpoint::Refunds),
+        "payments" => Some(Endpoint::Payments),
+        "refunds" => Some(Endpoint::Refunds),
+        _ => None,
+    }
+}
+
+/// Reports the availability of the payment and refund endpoints over the
+/// last 24 hours, to anyone.
+///
+/// The series is rolled up every `RollUp` interval, so the current minutes
+/// aren't in it yet.
+pub async fn get<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+) -> Result<([(HeaderName, String); 1], Json<ResponseBody>), ApiError> {
+    let now = bank_web.clock.now();
+    let cache_control = [(
+        CACHE_CONTROL,
+        format!("public, max-age={}", CACHE_TTL.as_secs()),
+    )];
+
+    let cached = bank_web.status_cache.lock().unwrap().clone();
+    if let Some(status) =
+        cached.filter(|status| status.updated_at <= now && now - status.updated_at < CACHE_TTL)
+    {
+        return Ok((cache_control, Json(ResponseBody { data: status })));
+    }
+
+    let now_utc = now.to_offset(UtcOffset::UTC);
+    let current_minute = PrimitiveDateTime::new(
+        now_utc.date(),
+        now_utc
+            .time()
+            .replace_second(0)
+            .expect("invalid second")
+            .replace_nanosecond(0)
+            .expect("invalid nanosecond"),
+    );
+    let minutes = availability::list(&bank_web.pool, current_minute - HISTORY, current_minute)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to list availability minutes: {e}");
+            ApiError::from(storage_unavailable())
+        })?;
+
+    let evaluator = BurnRateEvaluator::default();
+    let series: Vec<SeriesData> = Endpoint::ALL
+        .into_iter()
+        .map(|endpoint| {
+            let minutes: Vec<Minute> = minutes
+                .iter()
+                .filter(|minute| minute.endpoint == endpoint)
+                .cloned()
+                .collect();
+            SeriesData {
+                endpoint,
+                degraded: evaluator.is_degraded(&minutes, current_minute),
+                points: points(&minutes),
+            }
+        })
+        .collect();
+
+    let status = StatusData {
+        degraded: series.iter().any(|series| series.degraded),
+        updated_at: now,
+        series,
+    };
+    *bank_web.status_cache.lock().unwrap() = Some(status.clone());
+
+    Ok((cache_control, Json(ResponseBody { data: status })))
+}
+
+/// Sums `minutes`, ordered by minute, into points of `STEP`.
+fn points(minutes: &[Minute]) -> Vec<PointData> {
+    let step = STEP.as_secs() as i64;
+    let mut points: Vec<PointData> = Vec::new();
+    for minute in minutes {
+        let start = minute.minute.assume_utc().unix_timestamp().div_euclid(step) * step;
+        let at = OffsetDateTime::from_unix_timestamp(start).expect("minute out of range");
+        match points.last_mut() {
+            Some(point) if point.at == at => {
+                point.requests += minute.successes + minute.errors;
+                point.errors += minute.errors;
+                point.p95_latency_ms = point.p95_latency_ms.max(minute.p95_latency_ms);
+            }
+            _ => points.push(PointData {
+                at,
+                requests: minute.successes + minute.errors,
+                errors: minute.errors,
+                p95_latency_ms: minute.p95_latency_ms,
+            }),
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use rand::Rng;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        bank::clock::tests::ManualClock,
+        bank_web::tests::{deserialize_response_body, get, get_as},
+    };
+
+    async fn get_status(router: &axum::Router) -> StatusData {
+        let response = get(router, "/api/status").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CACHE_CONTROL], "public, max-age=30");
+        deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data
+    }
+
+    #[tokio::test]
+    async fn should_report_availability_degraded_during_bad_window() {
+        // 5 minutes of the 23rd century no other test stores minutes in
+        let t0 = OffsetDateTime::from_unix_timestamp(
+            7_258_118_400 + rand::thread_rng().gen_range(0..1_000_000) * 300,
+        )
+        .unwrap();
+        let clock = Arc::new(ManualClock::new(t0));
+        let bank_web = BankWeb::new_test().await.with_clock(clock.clone());
+        let aggregator = bank_web.slo.clone();
+        let roll_up = bank_web.availability_roll_up();
+        let router = bank_web.into_router();
+
+        // two hours of traffic, half the payment requests failing from 70 to
+        // 60 minutes before t0
+        for minutes_before in 1..=120 {
+            let at = t0 - Duration::from_secs(minutes_before * 60);
+            let errors = if (61..=70).contains(&minutes_before) {
+                5
+            } else {
+                0
+            };
+            for i in 0..10 {
+                aggregator.record(
+                    Endpoint::Payments,
+                    i >= errors,
+                    Duration::from_millis(20 + i),
+                    at,
+                );
+            }
+            aggregator.record(Endpoint::Refunds, true, Duration::from_millis(30), at);
+        }
+        assert_eq!(roll_up.run_once().await.expect("failed to roll up"), 240);
+
+        // during the bad window
+        clock.set(t0 - Duration::from_secs(60 * 60));
+        let status = get_status(&router).await;
+        assert!(status.degraded);
+        let (payments, refunds) = (&status.series[0], &status.series[1]);
+        assert_eq!(
+            (payments.endpoint, payments.degraded),
+            (Endpoint::Payments, true)
+        );
+        assert_eq!(
+            (refunds.endpoint, refunds.degraded),
+            (Endpoint::Refunds, false)
+        );
+        assert_eq!(payments.points.len(), 12);
+        assert_eq!(
+            payments
+                .points
+                .iter()
+                .map(|point| point.errors)
+                .collect::<Vec<_>>(),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 25, 25]
+        );
+        assert_eq!(payments.points[0].at, t0 - Duration::from_secs(120 * 60));
+        assert_eq!(payments.points[0].requests, 50);
+        assert_eq!(payments.points[0].p95_latency_ms, 29);
+
+        // an hour later, the errors stopped
+        clock.set(t0);
+        let status = get_status(&router).await;
+        assert!(!status.degraded);
+        let (payments, refunds) = (&status.series[0], &status.series[1]);
+        assert!(!payments.degraded);
+        assert_eq!(payments.points.len(), 24);
+        assert_eq!(
+            payments
+                .points
+                .iter()
+                .map(|point| (point.requests, point.errors))
+                .fold((0, 0), |(r, e), (pr, pe)| (r + pr, e + pe)),
+            (1200, 50)
+        );
+        assert_eq!(
+            refunds
+                .points
+                .iter()
+                .map(|point| point.requests)
+                .sum::<i64>(),
+            120
+        );
+
+        // served from the cache for a while
+        clock.set(t0 + Duration::from_secs(10));
+        assert_eq!(get_status(&router).await.updated_at, t0);
+
+        // requests to the payment endpoints are tracked, whatever the answer
+        let response = get_as(
+            &router,
+            format!("/api/v1/payments/{}", Uuid::new_v4()),
+            Uuid::new_v4(),
+        )
+        .await;
+        assert_eq!(response.status(), 404);
+        let minutes = aggregator.drain_completed(t0 + Duration::from_secs(60));
+        assert_eq!(
+            minutes
+                .iter()
+                .map(|minute| (minute.endpoint, minute.successes, minute.errors))
+                .collect::<Vec<_>>(),
+            [(Endpoint::Payments, 1, 0)]
+        );
+    }
+
+    #[test]
+    fn should_track_payment_and_refund_endpoints_only() {
+        assert_eq!(endpoint_of("/api/payments"), Some(Endpoint::Payments));
+        assert_eq!(
+            endpoint_of("/api/v1/payments/1/notes"),
+            Some(Endpoint::Payments)
+        );
+        assert_eq!(
+            endpoint_of("/api/payments/1/refunds/2"),
+            Some(Endpoint::Refunds)
+        );
+        assert_eq!(
+            endpoint_of("/api/v1/refunds/stats"),
+            Some(Endpoint::Refunds)
+        );
+        assert_eq!(endpoint_of("/api/status"), None);
+        assert_eq!(endpoint_of("/api/admin/payments/1"), None);
+        assert_eq!(endpoint_of("/api/payments_export"), None);
+    }
+}
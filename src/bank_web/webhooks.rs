@@ -0,0 +1,876 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, Method, Request, StatusCode, Uri},
+    Json,
+};
+use hmac::{Hmac, Mac};
+use hyper::{client::HttpConnector, Body, Client};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{merchant::MerchantId, BankWeb, ErrorResponseBody};
+use crate::bank::{
+    accounts::AccountService,
+    events::{self, Event, PayloadVersion},
+    outbox::{self, EventType, RefundPayload},
+    payments::{self, Status},
+    refunds,
+    webhooks::{self, Webhook},
+};
+
+/// Header holding the hex encoded HMAC-SHA256 of the request body, keyed with
+/// the webhook secret and prefixed with `sha256=`.
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Time after which a delivery attempt is considered failed.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RequestData {
+    pub url: String,
+    pub secret: String,
+    /// Shape of the events sent to `url`, `PayloadVersion::LATEST` if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_version: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RequestBody {
+    pub webhook: RequestData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseData {
+    pub id: Uuid,
+    pub url: String,
+    pub payload_version: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResponseBody {
+    pub data: ResponseData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ListResponseBody {
+    pub data: Vec<ResponseData>,
+}
+
+impl From<Webhook> for ResponseData {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            payload_version: webhook.payload_version.into(),
+        }
+    }
+}
+
+/// Event about a refund, as sent to webhooks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct RefundEvent<'a> {
+    /// Id of the delivery.
+    id: Uuid,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    refund_id: Uuid,
+    payment_id: Uuid,
+    amount: i64,
+    remaining_amount: i64,
+}
+
+/// Returns the event type notified for a payment entering `status`, if any.
+fn event_type(status: Status) -> Option<&'static str> {
+    match status {
+        Status::Processing | Status::Authorized => None,
+        Status::Approved => Some("payment.approved"),
+        Status::Declined => Some("payment.declined"),
+        Status::Failed => Some("payment.failed"),
+        Status::Voided => Some("payment.voided"),
+    }
+}
+
+/// Signs `body` with `secret`, as sent in the `SIGNATURE_HEADER` header.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// How failed deliveries are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every attempt.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait after the failed `attempt` (starting at 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/// Delivers payment and refund events to the webhooks of the merchant of the
+/// payment.
+///
+/// Deliveries run in the background so they never delay the API response,
+/// and each attempt is recorded in `webhook_deliveries`.
+#[derive(Clone)]
+pub struct Dispatcher {
+    pool: PgPool,
+    client: Client<HttpConnector>,
+    retry_policy: RetryPolicy,
+}
+
+impl Dispatcher {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            client: Client::new(),
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Notifies the webhooks of the merchant that the payment entered
+    /// `status`.
+    pub fn notify(&self, payment_id: Uuid, status: Status) {
+        let Some(event_type) = event_type(status) else {
+            return;
+        };
+
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            let webhooks = match webhooks::list_for_payment(&dispatcher.pool, payment_id).await {
+                Ok(webhooks) => webhooks,
+                Err(e) => {
+                    tracing::error!("failed to list webhooks: {e}");
+                    return;
+                }
+            };
+            if webhooks.is_empty() {
+                return;
+            }
+
+            // the same snapshot is sent to every webhook, whatever its version
+            let payment = match payments::get(&dispatcher.pool, payment_id).await {
+                Ok(payment) => payment,
+                Err(e) => {
+                    tracing::error!("failed to get notified payment: {e}");
+                    return;
+                }
+            };
+            let refunds = match refunds::list(&dispatcher.pool, payment_id).await {
+                Ok(refunds) => refunds,
+                Err(e) => {
+                    tracing::error!("failed to list notified payment refunds: {e}");
+                    return;
+                }
+            };
+
+            for webhook in webhooks {
+                let dispatcher = dispatcher.clone();
+                let event = Event {
+                    id: Uuid::nil(),
+                    event_type: event_type.to_string(),
+                    status,
+                    payment: payment.clone(),
+                    refunds: refunds.clone(),
+                };
+                tokio::spawn(async move {
+                    if let Err(e) = dispatcher.deliver(webhook, event).await {
+                        tracing::error!("failed to record webhook delivery: {e}");
+                    }
+                });
+            }
+        });
+    }
+
+    /// Returns an outbox sink delivering the refund events to the webhooks of
+    /// the merchant of the refunded payment.
+    ///
+    /// Events are delivered one at a time, in the order they are published,
+    /// so a refund is never notified settled before it is notified created.
+    pub fn outbox_sink(&self) -> outbox::Sink {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<RefundPayload>();
+
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            while let Some(payload) = receiver.recv().await {
+                dispatcher.notify_refund(payload).await;
+            }
+        });
+
+        Arc::new(move |event| {
+            outbox::log_event(event);
+
+            let is_refund_event = EventType::REFUNDS
+                .iter()
+                .any(|event_type| event_type.as_str() == event.event_type);
+            if !is_refund_event {
+                return;
+            }
+            match serde_json::from_value::<RefundPayload>(event.payload.clone()) {
+                Ok(payload) => {
+                    let _ = sender.send(payload);
+                }
+                Err(e) => tracing::error!("invalid refund event {}: {e}", event.id),
+            }
+        })
+    }
+
+    /// Delivers a refund event to the webhooks of the merchant, returning once
+    /// every delivery succeeded or ran out of attempts.
+    async fn notify_refund(&self, payload: RefundPayload) {
+        let webhooks = match webhooks::list_for_payment(&self.pool, payload.payment_id).await {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                tracing::error!("failed to list webhooks: {e}");
+                return;
+            }
+        };
+
+        let deliveries = webhooks.into_iter().map(|webhook| {
+            let payload = payload.clone();
+            async move {
+                let event_type = payload.event_type.as_str();
+                let body = |id| {
+                    serde_json::to_vec(&RefundEvent {
+                        id,
+                        event_type,
+                        refund_id: payload.refund_id,
+                        payment_id: payload.payment_id,
+                        amount: payload.amount,
+                        remaining_amount: payload.remaining_amount,
+                    })
+                    .expect("failed to serialize refund event")
+                };
+                if let Err(e) = self
+                    .deliver_body(&webhook, payload.payment_id, event_type, body)
+                    .await
+                {
+                    tracing::error!("failed to record webhook delivery: {e}");
+                }
+            }
+        });
+        futures::future::join_all(deliveries).await;
+    }
+
+    /// Delivers `event`, whose id is set to the id of the recorded delivery.
+    async fn deliver(&self, webhook: Webhook, mut event: Event) -> Result<(), sqlx::Error> {
+        let payment_id = event.payment.id;
+        let event_type = event.event_type.clone();
+        let payload_version = webhook.payload_version;
+
+        self.deliver_body(&webhook, payment_id, &event_type, |id| {
+            event.id = id;
+            events::serialize(&event, payload_version)
+        })
+        .await
+    }
+
+    /// Delivers the body built from the id of the recorded delivery,
+    /// retrying failed attempts.
+    async fn deliver_body(
+        &self,
+        webhook: &Webhook,
+        payment_id: Uuid,
+        event_type: &str,
+        body: impl FnOnce(Uuid) -> Vec<u8>,
+    ) -> Result<(), sqlx::Error> {
+        let id = webhooks::insert_delivery(&self.pool, webhook.id, payment_id, event_type).await?;
+
+        let body = body(id);
+        let signature = sign(&webhook.secret, &body);
+
+        for attempt in 1..=self.retry_policy.max_attempts {
+            let status_code = self.send(&webhook.url, &signature, body.clone()).await;
+            let delivered = status_code.is_some_and(|code| code.is_success());
+
+            webhooks::record_attempt(
+                &self.pool,
+                id,
+                status_code.map(|code| i32::from(code.as_u16())),
+                delivered,
+            )
+            .await?;
+
+            if delivered {
+                return Ok(());
+            }
+
+            if attempt < self.retry_policy.max_attempts {
+                tokio::time::sleep(self.retry_policy.delay(attempt)).await;
+            }
+        }
+
+        tracing::warn!(webhook.id = %webhook.id, "abandoned webhook delivery {id}");
+        webhooks::abandon(&self.pool, id).await
+    }
+
+    /// Posts a signed event, returning the response status if one was received.
+    async fn send(&self, url: &str, signature: &str, body: Vec<u8>) -> Option<StatusCode> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .body(Body::from(body))
+            .ok()?;
+
+        match tokio::time::timeout(ATTEMPT_TIMEOUT, self.client.request(request)).await {
+            Ok(Ok(response)) => Some(response.status()),
+            Ok(Err(e)) => {
+                tracing::warn!("failed to deliver webhook to {url}: {e}");
+                None
+            }
+            Err(_) => {
+                tracing::warn!("timed out delivering webhook to {url}");
+                None
+            }
+        }
+    }
+}
+
+pub async fn post<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+    Json(body): Json<RequestBody>,
+) -> Result<(StatusCode, Json<ResponseBody>), (StatusCode, Json<ErrorResponseBody>)> {
+    // only absolute http urls can be delivered to, the client doesn't use TLS yet
+    let valid_url = match body.webhook.url.parse::<Uri>() {
+        Ok(uri) => uri.host().is_some() && uri.scheme_str() == Some("http"),
+        Err(_) => false,
+    };
+    if !valid_url {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponseBody::new("Invalid webhook url")),
+        ));
+    }
+
+    if body.webhook.secret.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponseBody::new("Webhook secret shouldn't be empty")),
+        ));
+    }
+
+    let payload_version = match body.webhook.payload_version {
+        None => PayloadVersion::LATEST,
+        Some(version) => PayloadVersion::try_from(version).map_err(|_| {
+            let supported_versions: Vec<i32> =
+                PayloadVersion::ALL.into_iter().map(i32::from).collect();
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(
+                    ErrorResponseBody::new("Unsupported webhook payload version")
+                        .with_code("unsupported_payload_version")
+                        .with_details(serde_json::json!({
+                            "supported_versions": supported_versions,
+                        })),
+                ),
+            )
+        })?,
+    };
+
+    let id = match webhooks::insert(
+        &bank_web.pool,
+        merchant_id,
+        body.webhook.url.clone(),
+        body.webhook.secret,
+        payload_version,
+    )
+    .await
+    {
+        Ok(id) => id,
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponseBody::new("can't register webhook")),
+            ))
+        }
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ResponseBody {
+            data: ResponseData {
+                id,
+                url: body.webhook.url,
+                payload_version: payload_version.into(),
+            },
+        }),
+    ))
+}
+
+/// Lists the webhooks registered by the merchant, oldest first.
+pub async fn list<T: AccountService>(
+    State(bank_web): State<BankWeb<T>>,
+    MerchantId(merchant_id): MerchantId,
+) -> Result<Json<ListResponseBody>, (StatusCode, Json<ErrorResponseBody>)> {
+    let webhooks = webhooks::list(&bank_web.pool, merchant_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to list webhooks: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponseBody::new("can't list webhooks")),
+            )
+        })?;
+
+    Ok(Json(ListResponseBody {
+        data: webhooks.into_iter().map(ResponseData::from).collect(),
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{body::Bytes, http::HeaderMap, routing};
+
+    use super::*;
+    use crate::{
+        bank::{outbox::tests::list_for_payment, webhooks::tests::list_deliveries},
+        bank_web::{
+            payments::{self, tests::PaymentRequestBuilder},
+            refunds::tests::RefundRequestBuilder,
+            tests::{deserialize_response_body, get_as, post, post_as},
+        },
+    };
+
+    /// Number of failures answered to the first attempts of every event.
+    const FAILURES: usize = 2;
+
+    /// Requests received by the test server, and the number of attempts per
+    /// payment.
+    #[derive(Clone, Default)]
+    struct Receiver {
+        requests: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+        attempts: Arc<Mutex<HashMap<Uuid, usize>>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        let payment_id = payment_id(&body);
+        receiver.requests.lock().unwrap().push((headers, body));
+
+        let mut attempts = receiver.attempts.lock().unwrap();
+        let attempts = attempts.entry(payment_id).or_default();
+        *attempts += 1;
+
+        if *attempts > FAILURES {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn payment_id(body: &[u8]) -> Uuid {
+        let event: serde_json::Value = serde_json::from_slice(body).expect("invalid event");
+        serde_json::from_value(event["payment_id"].clone()).expect("invalid event payment id")
+    }
+
+    impl Receiver {
+        /// Waits for the first event received about `payment_id`.
+        async fn event(&self, payment_id: Uuid) -> serde_json::Value {
+            for _ in 0..100 {
+                let requests = self.requests.lock().unwrap().clone();
+                if let Some((_, body)) = requests
+                    .iter()
+                    .find(|(_, body)| self::payment_id(body) == payment_id)
+                {
+                    return serde_json::from_slice(body).unwrap();
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            panic!("no event received for payment {payment_id}");
+        }
+    }
+
+    fn serve(receiver: Receiver) -> SocketAddr {
+        let router = axum::Router::new()
+            .route("/hook", routing::post(receive))
+            .with_state(receiver);
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(router.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[test]
+    fn should_double_retry_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_invalid_webhooks() {
+        let router = BankWeb::new_test().await.into_router();
+
+        for (url, secret) in [("localhost/hook", "secret"), ("http://localhost/hook", "")] {
+            let request_body = RequestBody {
+                webhook: RequestData {
+                    url: url.to_string(),
+                    secret: secret.to_string(),
+                    payload_version: None,
+                },
+            };
+
+            let response = post(&router, "/api/webhooks", &request_body).await;
+            assert_eq!(response.status(), 422, "{url} {secret}");
+        }
+    }
+
+    #[tokio::test]
+    async fn should_deliver_signed_events_with_retries() {
+        let receiver = Receiver::default();
+        let addr = serve(receiver.clone());
+
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web
+            .with_webhook_retry_policy(RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_millis(10),
+            })
+            .into_router();
+
+        let secret = Uuid::new_v4().to_string();
+        let request_body = RequestBody {
+            webhook: RequestData {
+                url: format!("http://{addr}/hook"),
+                secret: secret.clone(),
+                payload_version: None,
+            },
+        };
+        let response = post(&router, "/api/webhooks", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let webhook = deserialize_response_body::<ResponseBody>(response)
+            .await
+            .data;
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let payment_id = deserialize_response_body::<payments::ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        let mut deliveries = Vec::new();
+        for _ in 0..100 {
+            deliveries = list_deliveries(&pool, webhook.id, payment_id)
+                .await
+                .expect("failed to list deliveries");
+            if deliveries
+                .iter()
+                .all(|delivery| delivery.delivered_at.is_some())
+                && !deliveries.is_empty()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event, "payment.approved");
+        assert_eq!(deliveries[0].attempts, FAILURES as i32 + 1);
+        assert_eq!(deliveries[0].last_status_code, Some(204));
+
+        let requests = receiver.requests.lock().unwrap().clone();
+        let (headers, body) = requests
+            .iter()
+            .find(|(_, body)| self::payment_id(body) == payment_id)
+            .expect("missing event");
+        assert_eq!(
+            headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap(),
+            sign(&secret, body)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_deliver_refund_events_in_order() {
+        let receiver = Receiver::default();
+        let addr = serve(receiver.clone());
+
+        let bank_web = BankWeb::new_test()
+            .await
+            .with_webhook_retry_policy(RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_millis(10),
+            });
+        let pool = bank_web.pool.clone();
+        let sink = bank_web.webhooks.outbox_sink();
+        let router = bank_web.into_router();
+
+        let secret = Uuid::new_v4().to_string();
+        let request_body = RequestBody {
+            webhook: RequestData {
+                url: format!("http://{addr}/hook"),
+                secret: secret.clone(),
+                payload_version: None,
+            },
+        };
+        let response = post(&router, "/api/webhooks", &request_body).await;
+        assert_eq!(response.status(), 201);
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        let payment_id = deserialize_response_body::<payments::ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        let request_body = RefundRequestBuilder::new().amount(23).build();
+        let uri = format!("/api/payments/{payment_id}/refunds");
+        let response = post(&router, uri, &request_body).await;
+        assert_eq!(response.status(), 201);
+        let refund = deserialize_response_body::<serde_json::Value>(response).await;
+        let refund_id = &refund["data"]["id"];
+
+        // the publisher would pass every event of the outbox to the sink
+        for event in list_for_payment(&pool, payment_id)
+            .await
+            .expect("failed to list outbox events")
+        {
+            sink(&event);
+        }
+
+        // retried deliveries are sent again under the same id
+        let mut events = Vec::<serde_json::Value>::new();
+        for _ in 0..100 {
+            events.clear();
+            for (headers, body) in receiver.requests.lock().unwrap().iter() {
+                let event: serde_json::Value = serde_json::from_slice(body).unwrap();
+                let is_refund_event = event["type"].as_str().unwrap().starts_with("refund.");
+                if event["payment_id"] != payment_id.to_string() || !is_refund_event {
+                    continue;
+                }
+                assert_eq!(
+                    headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap(),
+                    sign(&secret, body)
+                );
+                if !events.iter().any(|seen| seen["id"] == event["id"]) {
+                    events.push(event);
+                }
+            }
+            if events.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let expected: Vec<_> = ["refund.created", "refund.settled"]
+            .into_iter()
+            .zip(&events)
+            .map(|(event_type, event)| {
+                serde_json::json!({
+                    "id": event["id"],
+                    "type": event_type,
+                    "refund_id": refund_id,
+                    "payment_id": payment_id,
+                    "amount": 23,
+                    "remaining_amount": 100,
+                })
+            })
+            .collect();
+        assert_eq!(events, expected);
+    }
+
+    #[tokio::test]
+    async fn should_deliver_events_to_the_webhooks_of_the_merchant_only() {
+        let bank_web = BankWeb::new_test().await;
+        let pool = bank_web.pool.clone();
+        let router = bank_web
+            .with_webhook_retry_policy(RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_millis(10),
+            })
+            .into_router();
+
+        let mut merchants = Vec::new();
+        for _ in 0..2 {
+            let merchant_id = Uuid::new_v4();
+            let receiver = Receiver::default();
+            let addr = serve(receiver.clone());
+
+            let request_body = RequestBody {
+                webhook: RequestData {
+                    url: format!("http://{addr}/hook"),
+                    secret: "secret".to_string(),
+                    payload_version: None,
+                },
+            };
+            let response = post_as(&router, "/api/webhooks", &request_body, merchant_id).await;
+            assert_eq!(response.status(), 201);
+            let webhook = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data;
+
+            merchants.push((merchant_id, webhook, receiver));
+        }
+        let (merchant_a, webhook_a, receiver_a) = &merchants[0];
+        let (merchant_b, webhook_b, receiver_b) = &merchants[1];
+
+        let response = get_as(&router, "/api/webhooks", *merchant_b).await;
+        assert_eq!(response.status(), 200);
+        let body = deserialize_response_body::<ListResponseBody>(response).await;
+        assert_eq!(body.data, vec![webhook_b.clone()]);
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+        let response = post_as(&router, "/api/payments", &request_body, *merchant_a).await;
+        assert_eq!(response.status(), 201);
+        let payment_id = deserialize_response_body::<payments::ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        // once merchant A was notified, merchant B would have been too
+        let event = receiver_a.event(payment_id).await;
+        assert_eq!(event["type"], "payment.approved");
+
+        assert!(receiver_b.requests.lock().unwrap().is_empty());
+        let deliveries = list_deliveries(&pool, webhook_b.id, payment_id)
+            .await
+            .expect("failed to list deliveries");
+        assert!(deliveries.is_empty());
+        let deliveries = list_deliveries(&pool, webhook_a.id, payment_id)
+            .await
+            .expect("failed to list deliveries");
+        assert_eq!(deliveries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_return_422_for_unsupported_payload_version() {
+        let router = BankWeb::new_test().await.into_router();
+
+        for payload_version in [0, 3] {
+            let request_body = RequestBody {
+                webhook: RequestData {
+                    url: "http://localhost/hook".to_string(),
+                    secret: "secret".to_string(),
+                    payload_version: Some(payload_version),
+                },
+            };
+
+            let response = post(&router, "/api/webhooks", &request_body).await;
+            assert_eq!(response.status(), 422, "{payload_version}");
+            let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+            assert_eq!(body.code.as_deref(), Some("unsupported_payload_version"));
+            assert_eq!(
+                body.details,
+                Some(serde_json::json!({ "supported_versions": [1, 2] }))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_deliver_events_in_the_payload_version_of_each_webhook() {
+        let bank_web = BankWeb::new_test().await;
+        let router = bank_web
+            .with_webhook_retry_policy(RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_millis(10),
+            })
+            .into_router();
+
+        let mut receivers = Vec::new();
+        for payload_version in [Some(1), Some(2), None] {
+            let receiver = Receiver::default();
+            let addr = serve(receiver.clone());
+
+            let request_body = RequestBody {
+                webhook: RequestData {
+                    url: format!("http://{addr}/hook"),
+                    secret: "secret".to_string(),
+                    payload_version,
+                },
+            };
+            let response = post(&router, "/api/webhooks", &request_body).await;
+            assert_eq!(response.status(), 201);
+            let webhook = deserialize_response_body::<ResponseBody>(response)
+                .await
+                .data;
+            assert_eq!(webhook.payload_version, payload_version.unwrap_or(2));
+
+            receivers.push(receiver);
+        }
+
+        let request_body = PaymentRequestBuilder::new().amount(123).build();
+        let response = post(&router, "/api/payments", &request_body).await;
+        assert_eq!(response.status(), 201);
+        let payment_id = deserialize_response_body::<payments::ResponseBody>(response)
+            .await
+            .data
+            .id;
+
+        let v1 = receivers[0].event(payment_id).await;
+        assert_eq!(
+            v1,
+            serde_json::json!({
+                "payload_version": 1,
+                "id": v1["id"],
+                "type": "payment.approved",
+                "payment_id": payment_id,
+                "status": "approved",
+            })
+        );
+
+        for receiver in &receivers[1..] {
+            let v2 = receiver.event(payment_id).await;
+            assert_eq!(
+                v2,
+                serde_json::json!({
+                    "payload_version": 2,
+                    "id": v2["id"],
+                    "type": "payment.approved",
+                    "payment_id": payment_id,
+                    "status": "approved",
+                    "amounts": { "amount": 123, "refunded_amount": 0, "currency": "USD" },
+                    "refunds": [],
+                })
+            );
+            assert_ne!(v2["id"], v1["id"]);
+        }
+    }
+}
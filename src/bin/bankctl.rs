@@ -0,0 +1,88 @@
+//! Operator commands run against the database of the bank:
+//!
+//! ```text
+//! bankctl backfill list
+//! bankctl backfill run <name>
+//! ```
+//!
+//! `BACKFILL_BATCH_SIZE` and `BACKFILL_THROTTLE_MS` tune the batches, and a
+//! killed `backfill run` resumes where it stopped when run again.
+
+use std::{process::ExitCode, time::Duration};
+
+use dotenvy::dotenv;
+use hiring_challenge_rust::{bank::backfill, env_var, pg_pool};
+
+const USAGE: &str = "usage: bankctl backfill list | bankctl backfill run <name>";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv().expect("failed to load .env");
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["backfill", "list"] => list().await,
+        ["backfill", "run", name] => run(name).await,
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn list() -> ExitCode {
+    let pool = pg_pool().await.expect("failed to connect to postgres");
+    let recorded = backfill::list_progress(&pool)
+        .await
+        .expect("failed to list backfill progress");
+
+    for job in backfill::registry() {
+        match recorded.iter().find(|progress| progress.name == job.name()) {
+            Some(progress) => println!(
+                "{}: {} rows processed, {} updated, {}",
+                progress.name,
+                progress.processed_rows,
+                progress.updated_rows,
+                if progress.completed_at.is_some() {
+                    "completed"
+                } else {
+                    "in progress"
+                }
+            ),
+            None => println!("{}: not started", job.name()),
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+async fn run(name: &str) -> ExitCode {
+    let Some(job) = backfill::find(name) else {
+        eprintln!("unknown backfill {name}, see `bankctl backfill list`");
+        return ExitCode::FAILURE;
+    };
+
+    let pool = pg_pool().await.expect("failed to connect to postgres");
+    let mut backfiller = backfill::Backfiller::new(pool);
+    if let Some(batch_size) = env_var("BACKFILL_BATCH_SIZE") {
+        backfiller = backfiller.with_batch_size(batch_size);
+    }
+    if let Some(millis) = env_var("BACKFILL_THROTTLE_MS") {
+        backfiller = backfiller.with_throttle(Duration::from_millis(millis));
+    }
+
+    match backfiller.run(job.as_ref()).await {
+        Ok(progress) => {
+            println!(
+                "{name}: completed, {} rows processed, {} updated",
+                progress.processed_rows, progress.updated_rows
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{name}: failed, run it again to resume: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,12 +1,26 @@
-use axum::http::StatusCode;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
-use crate::bank::payments::Status;
+use crate::{
+    bank::payments::{DeclineReason, Status},
+    bank_web::ErrorResponseBody,
+};
+
+/// Media type of problem details, which clients opt into with `Accept`.
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Prefix of the `type` of problem details, followed by the error code.
+const PROBLEM_TYPE_PREFIX: &str = "urn:bank-payment-service:problem:";
 
 #[derive(Debug)]
 pub struct PaymentError {
     pub code: i32,
-    pub message: String,
+    pub reason: DeclineReason,
 }
 
 impl Display for PaymentError {
@@ -19,34 +33,179 @@ impl Default for PaymentError {
     fn default() -> Self {
         PaymentError {
             code: 403,
-            message: "Forbidden".to_string(),
+            reason: DeclineReason::InvalidAccountNumber,
         }
     }
 }
 
 impl PaymentError {
     pub fn from(messages: &str) -> PaymentError {
-        let (code, message) = match messages {
-            "invalid_account_number" => (403, "Forbidden"),
-            "invalid_amount" => (400, "Bad Request"),
-            "insufficient_funds" => (402, "Payment Required"),
-            "service_unavailable" => (503, "Service unavailable"),
-            _ => (500, "Internal Error"),
+        let (code, reason) = match messages {
+            "invalid_account_number" => (403, DeclineReason::InvalidAccountNumber),
+            "invalid_amount" => (400, DeclineReason::InvalidAmount),
+            "insufficient_funds" => (402, DeclineReason::InsufficientFunds),
+            "service_unavailable" => (503, DeclineReason::ServiceFailure),
+            _ => (500, DeclineReason::ServiceFailure),
         };
-        PaymentError {
-            code,
-            message: message.to_string(),
+        PaymentError { code, reason }
+    }
+
+    pub fn get_http_status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.code as u16).unwrap_or(StatusCode::NOT_FOUND)
+    }
+
+    /// Returns the code of the error, which is also its problem type.
+    fn error_code(&self) -> &'static str {
+        match self.reason {
+            DeclineReason::InsufficientFunds => "insufficient_funds",
+            DeclineReason::InvalidAccountNumber => "invalid_account_number",
+            DeclineReason::InvalidAmount => "invalid_amount",
+            DeclineReason::ServiceFailure => "service_failure",
+            DeclineReason::OperatorRepair => "operator_repair",
+        }
+    }
+}
+
+/// Problem details (RFC 7807) of an error, answered instead of the
+/// `ErrorResponseBody` envelope to clients accepting them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    /// Stable URI of the kind of error, derived from its code, or
+    /// `about:blank` for errors without one.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    /// Explanation specific to this occurrence of the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Path of the request that failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Members of the envelope's details, e.g. the accepted values.
+    #[serde(flatten)]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, code: Option<&str>, title: impl Into<String>) -> Self {
+        Self {
+            problem_type: code.map_or_else(
+                || "about:blank".to_string(),
+                |code| format!("{PROBLEM_TYPE_PREFIX}{code}"),
+            ),
+            title: title.into(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            extensions: serde_json::Map::new(),
         }
     }
 
-    pub fn get_payment_status(&self) -> Status {
-        match self.code {
-            402 | 403 => Status::Declined,
-            _ => Status::Failed,
+    /// Adds the members of `details` as extension members, details other
+    /// than objects being kept under `details`.
+    pub fn with_extensions(mut self, details: Option<&serde_json::Value>) -> Self {
+        match details {
+            Some(serde_json::Value::Object(members)) => self.extensions.extend(members.clone()),
+            Some(details) => {
+                self.extensions.insert("details".into(), details.clone());
+            }
+            None => {}
         }
+        self
     }
+}
 
-    pub fn get_http_status_code(&self) -> StatusCode {
-        StatusCode::from_u16(self.code as u16).unwrap_or(StatusCode::NOT_FOUND)
+/// Error answered by the payments and refunds handlers.
+///
+/// It is rendered as an `ErrorResponseBody` envelope, or a given response,
+/// carrying its problem details as an extension, which the `problem_details`
+/// middleware answers instead to clients accepting them.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    body: ErrorResponseBody,
+    detail: Option<String>,
+    /// Response given instead of the envelope, e.g. a declined payment.
+    response: Option<Box<Response>>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: &'static str) -> Self {
+        Self::from((status, Json(ErrorResponseBody::new(message))))
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.body = self.body.with_code(code);
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.body = self.body.with_details(details);
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Returns the error of a payment the account service declined or
+    /// failed with `error`, answered with `response` unless problem details
+    /// are accepted.
+    pub fn payment_declined(error: &str, response: Response) -> Self {
+        let payment_error = PaymentError::from(error);
+        let message = if payment_error.reason.status() == Status::Declined {
+            "payment declined"
+        } else {
+            "payment failed"
+        };
+
+        Self {
+            response: Some(Box::new(response)),
+            ..Self::new(payment_error.get_http_status_code(), message)
+                .with_code(payment_error.error_code())
+                .with_detail(format!("account service error: {error}"))
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the envelope of the error, also when another response is
+    /// given instead.
+    pub fn body(&self) -> &ErrorResponseBody {
+        &self.body
+    }
+
+    pub fn problem(&self) -> ProblemDetails {
+        ProblemDetails {
+            detail: self.detail.clone(),
+            ..self.body.problem(self.status)
+        }
+    }
+}
+
+impl From<(StatusCode, Json<ErrorResponseBody>)> for ApiError {
+    fn from((status, Json(body)): (StatusCode, Json<ErrorResponseBody>)) -> Self {
+        Self {
+            status,
+            body,
+            detail: None,
+            response: None,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let problem = self.problem();
+        let mut response = match self.response {
+            Some(response) => *response,
+            None => (self.status, Json(self.body)).into_response(),
+        };
+        response.extensions_mut().insert(problem);
+        response
     }
 }
@@ -0,0 +1,32 @@
+use std::{fmt::Debug, str::FromStr, time::Duration};
+
+use dotenvy::dotenv;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+
+pub mod bank;
+pub mod bank_web;
+pub mod errors;
+pub mod telemetry;
+
+/// Parses the environment variable `name`, if set.
+pub fn env_var<T>(name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    std::env::var(name).ok().map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|e| panic!("invalid {name} in environment: {e:?}"))
+    })
+}
+
+pub async fn pg_pool() -> Result<PgPool, sqlx::Error> {
+    dotenv().expect("failed to load .env");
+
+    PgPoolOptions::new()
+        .max_connections(5)
+        .acquire_timeout(Duration::from_secs(1))
+        .connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be in environment"))
+        .await
+}
@@ -1,29 +1,19 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use dotenvy::dotenv;
-use sqlx::{postgres::PgPoolOptions, PgPool};
-
-use crate::bank_web::BankWeb;
-
-mod bank;
-mod bank_web;
-mod errors;
-
-pub async fn pg_pool() -> Result<PgPool, sqlx::Error> {
-    dotenv().expect("failed to load .env");
-
-    PgPoolOptions::new()
-        .max_connections(5)
-        .acquire_timeout(Duration::from_secs(1))
-        .connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be in environment"))
-        .await
-}
+use hiring_challenge_rust::{
+    bank,
+    bank_web::{AdminToken, BankWeb, RateLimit, RetryPolicy, TokenBuckets, ZeroAmountPolicy},
+    env_var, pg_pool, telemetry,
+};
 
 #[tokio::main]
 async fn main() {
     dotenv().expect("failed to load .env");
 
-    init_tracing();
+    let _tracing_guard = telemetry::init_tracing(
+        telemetry::TracingConfig::from_env().expect("invalid tracing configuration"),
+    );
 
     let pool = pg_pool().await.expect("failed to connect to postgres");
 
@@ -33,7 +23,139 @@ async fn main() {
         .expect("failed to run sqlx migrations");
 
     let account_service = bank::accounts::DummyService::default();
-    let router = BankWeb::new(pool, account_service).into_router();
+    let scenario = account_service.scenario();
+    let fee_policy = bank::fees::FeePolicy::from_env().expect("invalid fee policy");
+    let mut bank_web = BankWeb::new(pool.clone(), account_service).with_fee_policy(fee_policy);
+
+    match env_var::<String>("ADMIN_TOKEN") {
+        Some(token) if !token.is_empty() => {
+            bank_web = bank_web.with_admin_token(AdminToken::new(token));
+        }
+        _ => tracing::warn!("no ADMIN_TOKEN: admin endpoints answer every request with a 401"),
+    }
+
+    if let Some(dir) = env_var::<String>("EXPORT_DIR") {
+        bank_web = bank_web.with_export_sink(Arc::new(bank::exports::LocalDirSink::new(dir)));
+    }
+
+    if env_var("SANDBOX_MODE").unwrap_or(false) {
+        tracing::warn!("sandbox mode: account service outcomes can be scripted");
+        bank_web = bank_web.with_sandbox(scenario);
+    }
+
+    if let Some(secs) = env_var("CARD_REUSE_WINDOW_SECS") {
+        bank_web = bank_web.with_card_reuse_window(Duration::from_secs(secs));
+    }
+
+    if let Some(days) = env_var::<u64>("REFUND_WINDOW_DAYS") {
+        bank_web = bank_web.with_refund_window(Duration::from_secs(days * 24 * 60 * 60));
+    }
+
+    if let Some(max_refunds) = env_var("MAX_REFUNDS_PER_PAYMENT") {
+        bank_web = bank_web.with_max_refunds_per_payment(max_refunds);
+    }
+
+    if let Some(max_amount) = env_var("MAX_PAYMENT_AMOUNT") {
+        bank_web = bank_web.with_max_amount(max_amount);
+    }
+
+    if let Some(max_body_size) = env_var("MAX_BODY_SIZE") {
+        bank_web = bank_web.with_max_body_size(max_body_size);
+    }
+
+    if env_var("REJECT_ZERO_AMOUNT").unwrap_or(false) {
+        bank_web = bank_web.with_zero_amount_policy(ZeroAmountPolicy::Reject);
+    }
+
+    let rate_limit = |prefix: &str, default: RateLimit| RateLimit {
+        capacity: env_var(&format!("{prefix}_RATE_LIMIT_CAPACITY")).unwrap_or(default.capacity),
+        refill_interval: env_var(&format!("{prefix}_RATE_LIMIT_REFILL_MS"))
+            .map(Duration::from_millis)
+            .unwrap_or(default.refill_interval),
+    };
+    bank_web = bank_web.with_rate_limiter(Arc::new(TokenBuckets::new(
+        rate_limit("CARD", RateLimit::CARD),
+        rate_limit("MERCHANT", RateLimit::MERCHANT),
+    )));
+
+    let default_retry_policy = RetryPolicy::default();
+    bank_web = bank_web.with_webhook_retry_policy(RetryPolicy {
+        max_attempts: env_var("WEBHOOK_MAX_ATTEMPTS").unwrap_or(default_retry_policy.max_attempts),
+        base_delay: env_var("WEBHOOK_RETRY_BASE_DELAY_MS")
+            .map(Duration::from_millis)
+            .unwrap_or(default_retry_policy.base_delay),
+    });
+
+    let mut settlement_worker = bank_web.settlement_worker();
+    if let Some(millis) = env_var("SETTLEMENT_POLL_INTERVAL_MS") {
+        settlement_worker = settlement_worker.with_poll_interval(Duration::from_millis(millis));
+    }
+    tokio::spawn(settlement_worker.run());
+
+    let mut expiry_reaper = bank_web.expiry_reaper();
+    if let Some(secs) = env_var("EXPIRY_INTERVAL_SECS") {
+        expiry_reaper = expiry_reaper.with_interval(Duration::from_secs(secs));
+    }
+    if let Some(secs) = env_var("PROCESSING_TIMEOUT_SECS") {
+        expiry_reaper = expiry_reaper.with_max_age(Duration::from_secs(secs));
+    }
+    tokio::spawn(expiry_reaper.run());
+
+    let mut refund_retrier = bank_web.refund_retrier();
+    if let Some(secs) = env_var("REFUND_RETRY_INTERVAL_SECS") {
+        refund_retrier = refund_retrier.with_interval(Duration::from_secs(secs));
+    }
+    if let Some(max_attempts) = env_var("REFUND_MAX_DEPOSIT_ATTEMPTS") {
+        refund_retrier = refund_retrier.with_max_attempts(max_attempts);
+    }
+    tokio::spawn(refund_retrier.run());
+
+    let mut outbox_publisher = bank_web.outbox_publisher();
+    if let Some(millis) = env_var("OUTBOX_POLL_INTERVAL_MS") {
+        outbox_publisher = outbox_publisher.with_poll_interval(Duration::from_millis(millis));
+    }
+    if let Some(batch_size) = env_var("OUTBOX_BATCH_SIZE") {
+        outbox_publisher = outbox_publisher.with_batch_size(batch_size);
+    }
+    tokio::spawn(outbox_publisher.run());
+
+    let mut digester = bank_web.digester();
+    if let Some(endpoint) = env_var("DIGEST_EMAIL_ENDPOINT") {
+        digester = digester.with_notifier(Arc::new(bank::digests::EmailNotifier::new(
+            pool.clone(),
+            endpoint,
+            env_var("DIGEST_EMAIL_API_KEY").unwrap_or_default(),
+            env_var("DIGEST_EMAIL_FROM").expect("DIGEST_EMAIL_FROM must be in environment"),
+        )));
+    }
+    if let Some(secs) = env_var("DIGEST_INTERVAL_SECS") {
+        digester = digester.with_interval(Duration::from_secs(secs));
+    }
+    if let Some(secs) = env_var("DIGEST_STUCK_AFTER_SECS") {
+        digester = digester.with_stuck_after(Duration::from_secs(secs));
+    }
+    tokio::spawn(digester.run());
+
+    let mut availability_roll_up = bank_web.availability_roll_up();
+    if let Some(secs) = env_var("AVAILABILITY_ROLL_UP_INTERVAL_SECS") {
+        availability_roll_up = availability_roll_up.with_interval(Duration::from_secs(secs));
+    }
+    if let Some(days) = env_var::<u64>("AVAILABILITY_RETENTION_DAYS") {
+        availability_roll_up =
+            availability_roll_up.with_retention(Duration::from_secs(days * 24 * 60 * 60));
+    }
+    tokio::spawn(availability_roll_up.run());
+
+    let mut exporter = bank_web.exporter();
+    if let Some(millis) = env_var("EXPORT_POLL_INTERVAL_MS") {
+        exporter = exporter.with_poll_interval(Duration::from_millis(millis));
+    }
+    if let Some(batch_size) = env_var("EXPORT_BATCH_SIZE") {
+        exporter = exporter.with_batch_size(batch_size);
+    }
+    tokio::spawn(exporter.run());
+
+    let router = bank_web.into_router();
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 4000));
     tracing::info!("listening on http://{}", addr);
@@ -43,42 +165,3 @@ async fn main() {
         .await
         .expect("failed to serve");
 }
-
-pub fn init_tracing() {
-    use opentelemetry_otlp::WithExportConfig;
-    use tracing_subscriber::prelude::*;
-
-    let filter_layer = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
-
-    let fmt_layer =
-        tracing_subscriber::fmt::layer().event_format(tracing_subscriber::fmt::format().pretty());
-
-    let otel_exporter = opentelemetry_otlp::new_exporter()
-        .tonic()
-        .with_endpoint("http://localhost:4317");
-
-    let otel_resource = opentelemetry::sdk::Resource::new([opentelemetry::KeyValue::new(
-        "service.name",
-        "hiring_challenge_rust",
-    )]);
-
-    let otel_tracer = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(otel_exporter)
-        .with_trace_config(
-            opentelemetry::sdk::trace::config()
-                .with_resource(otel_resource)
-                .with_sampler(opentelemetry::sdk::trace::Sampler::AlwaysOn),
-        )
-        .install_simple()
-        .unwrap();
-
-    let otel_trace_layer = tracing_opentelemetry::layer().with_tracer(otel_tracer);
-
-    tracing_subscriber::Registry::default()
-        .with(filter_layer)
-        .with(fmt_layer)
-        .with(otel_trace_layer)
-        .init();
-}
@@ -0,0 +1,794 @@
+use std::{borrow::Cow, num::ParseFloatError};
+
+use axum::{http::Request, middleware::Next, response::Response};
+use opentelemetry::{
+    metrics::MetricsError,
+    sdk::{
+        export::metrics::aggregation::cumulative_temporality_selector,
+        metrics::{controllers::BasicController, selectors},
+        trace::{ShouldSample, TracerProvider},
+        InstrumentationLibrary, Resource,
+    },
+    trace::{
+        Link, OrderMap, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceError,
+        TraceId, TraceState, TracerProvider as _,
+    },
+    Context, Key, KeyValue, Value,
+};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use rand::Rng;
+use tracing::{subscriber::DefaultGuard, Instrument, Subscriber};
+use tracing_subscriber::{prelude::*, util::SubscriberInitExt, EnvFilter};
+
+/// Environment variable holding the ratio used when no route rule matches.
+pub const SAMPLER_RATIO_ENV: &str = "OTEL_SAMPLER_RATIO";
+/// Environment variable holding the per-route rules, e.g.
+/// `GET /health=0.001,POST /api/payments=1.0`.
+pub const SAMPLER_RULES_ENV: &str = "OTEL_SAMPLER_RULES";
+
+/// Environment variable switching the export of spans over OTLP, on by
+/// default when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub const OTEL_ENABLED_ENV: &str = "OTEL_ENABLED";
+/// Environment variable holding the endpoint of the collector spans are
+/// exported to.
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+/// Collector endpoint used when export is enabled without an endpoint.
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4317";
+
+/// Name of the meter the service's metrics are recorded with, through
+/// `opentelemetry::global::meter`.
+pub const METER_NAME: &str = "hiring_challenge_rust";
+
+/// Attribute set on spans whose request ended with a 4xx/5xx status.
+///
+/// Tail-based samplers (e.g. the collector's `tail_sampling` processor) can
+/// use it to keep the whole trace.
+pub const SAMPLING_PRIORITY_FIELD: &str = "sampling.priority";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SamplerConfigError {
+    InvalidRatio(String),
+    InvalidRule(String),
+    ParseError(ParseFloatError),
+}
+
+impl std::fmt::Display for SamplerConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TracingConfigError {
+    InvalidEnabled(String),
+    Sampler(SamplerConfigError),
+}
+
+impl std::fmt::Display for TracingConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<SamplerConfigError> for TracingConfigError {
+    fn from(error: SamplerConfigError) -> Self {
+        Self::Sampler(error)
+    }
+}
+
+/// Sampling ratio override for the requests matching a route.
+///
+/// A rule without a method applies to every method; `path_prefix` is matched
+/// against the request target with its query string stripped, by whole path
+/// segments, so `/api/payments` matches `/api/payments/123` but not
+/// `/api/payments_export`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteRule {
+    pub method: Option<String>,
+    pub path_prefix: String,
+    pub ratio: f64,
+}
+
+impl RouteRule {
+    fn matches(&self, method: Option<&str>, path: &str) -> bool {
+        let method_matches = match (&self.method, method) {
+            (None, _) => true,
+            (Some(expected), Some(method)) => expected.eq_ignore_ascii_case(method),
+            (Some(_), None) => false,
+        };
+
+        let prefix = self.path_prefix.trim_end_matches('/');
+        let path_matches = path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+
+        method_matches && path_matches
+    }
+}
+
+impl TryFrom<&str> for RouteRule {
+    type Error = SamplerConfigError;
+
+    /// Parses a rule written as `[METHOD ]PATH=RATIO`.
+    fn try_from(rule: &str) -> Result<Self, Self::Error> {
+        let (route, ratio) = rule
+            .rsplit_once('=')
+            .ok_or_else(|| SamplerConfigError::InvalidRule(rule.to_string()))?;
+
+        let (method, path_prefix) = match route.trim().split_once(' ') {
+            Some((method, path)) => (Some(method.trim().to_uppercase()), path.trim()),
+            None => (None, route.trim()),
+        };
+
+        if !path_prefix.starts_with('/') {
+            return Err(SamplerConfigError::InvalidRule(rule.to_string()));
+        }
+
+        Ok(Self {
+            method,
+            path_prefix: path_prefix.to_string(),
+            ratio: parse_ratio(ratio)?,
+        })
+    }
+}
+
+fn parse_ratio(ratio: &str) -> Result<f64, SamplerConfigError> {
+    let ratio = ratio
+        .trim()
+        .parse::<f64>()
+        .map_err(SamplerConfigError::ParseError)?;
+
+    if (0.0..=1.0).contains(&ratio) {
+        Ok(ratio)
+    } else {
+        Err(SamplerConfigError::InvalidRatio(ratio.to_string()))
+    }
+}
+
+/// Typed configuration of the head sampler built by `init_tracing`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplerConfig {
+    pub default_ratio: f64,
+    pub rules: Vec<RouteRule>,
+}
+
+impl Default for SamplerConfig {
+    /// Samples everything, which matches the previous `AlwaysOn` behavior.
+    fn default() -> Self {
+        Self {
+            default_ratio: 1.0,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl SamplerConfig {
+    /// Reads `OTEL_SAMPLER_RATIO` and `OTEL_SAMPLER_RULES`, falling back to
+    /// the defaults for any variable that is not set.
+    pub fn from_env() -> Result<Self, SamplerConfigError> {
+        let default_ratio = match std::env::var(SAMPLER_RATIO_ENV) {
+            Ok(ratio) => parse_ratio(&ratio)?,
+            Err(_) => Self::default().default_ratio,
+        };
+
+        let rules = match std::env::var(SAMPLER_RULES_ENV) {
+            Ok(rules) => Self::parse_rules(&rules)?,
+            Err(_) => Vec::new(),
+        };
+
+        Ok(Self {
+            default_ratio,
+            rules,
+        })
+    }
+
+    /// Parses a comma separated list of `[METHOD ]PATH=RATIO` rules.
+    pub fn parse_rules(rules: &str) -> Result<Vec<RouteRule>, SamplerConfigError> {
+        rules
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(RouteRule::try_from)
+            .collect()
+    }
+}
+
+/// Typed configuration of the subscriber installed by `init_tracing`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracingConfig {
+    /// Endpoint of the collector spans are exported to, `None` leaving out
+    /// the OpenTelemetry layer so that spans are only logged.
+    pub otlp_endpoint: Option<String>,
+    pub sampler: SamplerConfig,
+    /// Filter used when `RUST_LOG` isn't set.
+    pub default_filter: String,
+}
+
+impl Default for TracingConfig {
+    /// Logs at the info level, without exporting spans.
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            sampler: SamplerConfig::default(),
+            default_filter: "info".to_string(),
+        }
+    }
+}
+
+impl TracingConfig {
+    /// Reads `OTEL_ENABLED`, `OTEL_EXPORTER_OTLP_ENDPOINT` and the sampler
+    /// configuration, so that local runs without a collector don't try to
+    /// export spans.
+    pub fn from_env() -> Result<Self, TracingConfigError> {
+        let endpoint = std::env::var(OTLP_ENDPOINT_ENV).ok();
+        let enabled = match std::env::var(OTEL_ENABLED_ENV) {
+            Ok(enabled) => enabled
+                .parse()
+                .map_err(|_| TracingConfigError::InvalidEnabled(enabled))?,
+            Err(_) => endpoint.is_some(),
+        };
+
+        Ok(Self {
+            otlp_endpoint: enabled
+                .then(|| endpoint.unwrap_or_else(|| DEFAULT_OTLP_ENDPOINT.to_string())),
+            sampler: SamplerConfig::from_env()?,
+            ..Self::default()
+        })
+    }
+}
+
+/// Keeps tracing set up until dropped.
+///
+/// Dropping it exports the spans and metrics still pending and, for a
+/// subscriber installed by `init_scoped_tracing`, restores the previous
+/// subscriber.
+#[must_use]
+pub struct TracingGuard {
+    provider: Option<TracerProvider>,
+    metrics: Option<BasicController>,
+    _default: Option<DefaultGuard>,
+}
+
+impl TracingGuard {
+    /// Returns whether spans are exported over OTLP.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn exports_spans(&self) -> bool {
+        self.provider.is_some()
+    }
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.metrics {
+            if let Err(e) = metrics.stop(&Context::current()) {
+                tracing::error!("failed to export pending metrics: {e}");
+            }
+        }
+
+        let Some(provider) = &self.provider else {
+            return;
+        };
+
+        for result in provider.force_flush() {
+            if let Err(e) = result {
+                tracing::error!("failed to export pending spans: {e}");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber.
+///
+/// Installing it again, e.g. from several tests, only logs a warning: tests
+/// should use `init_scoped_tracing` instead.
+pub fn init_tracing(config: TracingConfig) -> TracingGuard {
+    let (provider, export_error) = otlp_provider(&config);
+    let (metrics, metrics_error) = otlp_metrics(&config);
+
+    if subscriber(&config, provider.as_ref(), false)
+        .try_init()
+        .is_err()
+    {
+        tracing::warn!("a global tracing subscriber is already installed");
+    }
+    if let Some(e) = export_error {
+        tracing::error!("failed to set up the export of spans, only logging them: {e}");
+    }
+    if let Some(e) = metrics_error {
+        tracing::error!("failed to set up the export of metrics: {e}");
+    }
+
+    TracingGuard {
+        provider,
+        metrics,
+        _default: None,
+    }
+}
+
+/// Installs the subscriber for the current thread until the guard is dropped,
+/// logging through the test harness so output is captured per test.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn init_scoped_tracing(config: TracingConfig) -> TracingGuard {
+    let (provider, export_error) = otlp_provider(&config);
+
+    let default = tracing::subscriber::set_default(subscriber(&config, provider.as_ref(), true));
+    if let Some(e) = export_error {
+        tracing::error!("failed to set up the export of spans, only logging them: {e}");
+    }
+
+    TracingGuard {
+        provider,
+        metrics: None,
+        _default: Some(default),
+    }
+}
+
+/// Builds the subscriber described by `config`, exporting spans through
+/// `provider` if given.
+fn subscriber(
+    config: &TracingConfig,
+    provider: Option<&TracerProvider>,
+    test_writer: bool,
+) -> impl Subscriber + Send + Sync {
+    let filter_layer = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(&config.default_filter));
+
+    let fmt_layer =
+        tracing_subscriber::fmt::layer().event_format(tracing_subscriber::fmt::format().pretty());
+    let fmt_layer = if test_writer {
+        fmt_layer.with_test_writer().boxed()
+    } else {
+        fmt_layer.boxed()
+    };
+
+    let otel_layer = provider.map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("hiring_challenge_rust"))
+    });
+
+    tracing_subscriber::Registry::default()
+        .with(filter_layer)
+        .with(fmt_layer)
+        .with(otel_layer)
+}
+
+/// Returns the provider exporting spans to the OTLP endpoint of `config`,
+/// if any.
+///
+/// A failure to set up the export is returned alongside no provider, so that
+/// spans are still logged rather than preventing the service from starting.
+fn otlp_provider(config: &TracingConfig) -> (Option<TracerProvider>, Option<TraceError>) {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return (None, None);
+    };
+
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(endpoint),
+    )
+    .build_span_exporter();
+    let exporter = match exporter {
+        Ok(exporter) => exporter,
+        Err(e) => return (None, Some(e)),
+    };
+
+    let resource = Resource::new([KeyValue::new("service.name", "hiring_challenge_rust")]);
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .with_config(
+            opentelemetry::sdk::trace::config()
+                .with_resource(resource)
+                .with_sampler(RouteSampler::new(config.sampler.clone())),
+        )
+        .build();
+
+    (Some(provider), None)
+}
+
+/// Exports the metrics of the global meter provider, which it installs, to
+/// the collector spans are exported to.
+///
+/// Must be called from a Tokio runtime, which pushes the metrics
+/// periodically.
+fn otlp_metrics(config: &TracingConfig) -> (Option<BasicController>, Option<MetricsError>) {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return (None, None);
+    };
+
+    let controller = opentelemetry_otlp::new_pipeline()
+        .metrics(
+            selectors::simple::inexpensive(),
+            cumulative_temporality_selector(),
+            opentelemetry::runtime::Tokio,
+        )
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            "hiring_challenge_rust",
+        )]))
+        .build();
+
+    match controller {
+        Ok(controller) => (Some(controller), None),
+        Err(e) => (None, Some(e)),
+    }
+}
+
+/// Head sampler applying a per-route ratio to root spans.
+///
+/// Child spans (and spans continuing a remote trace) follow the decision of
+/// their parent so traces are never sampled partially.
+#[derive(Debug, Clone)]
+pub struct RouteSampler {
+    config: SamplerConfig,
+}
+
+impl RouteSampler {
+    pub fn new(config: SamplerConfig) -> Self {
+        Self { config }
+    }
+
+    /// Returns the ratio of the first rule matching the request, in
+    /// declaration order, or the default ratio.
+    pub fn ratio_for(&self, method: Option<&str>, target: &str) -> f64 {
+        let path = target.split('?').next().unwrap_or(target);
+
+        self.config
+            .rules
+            .iter()
+            .find(|rule| rule.matches(method, path))
+            .map_or(self.config.default_ratio, |rule| rule.ratio)
+    }
+
+    /// Decides whether a root span for the request should be sampled.
+    pub fn decide(&self, method: Option<&str>, target: &str, rng: &mut impl Rng) -> bool {
+        let ratio = self.ratio_for(method, target);
+
+        if ratio >= 1.0 {
+            true
+        } else if ratio <= 0.0 {
+            false
+        } else {
+            rng.gen::<f64>() < ratio
+        }
+    }
+}
+
+/// Extracts the request method and target from the span attributes set by the
+/// HTTP tracing layer, falling back to the `METHOD /route` span name.
+fn request_of<'a>(
+    name: &'a str,
+    attributes: &'a OrderMap<Key, Value>,
+) -> (Option<Cow<'a, str>>, Cow<'a, str>) {
+    let attribute = |key: &'static str| {
+        attributes
+            .get(&Key::from_static_str(key))
+            .map(Value::as_str)
+    };
+
+    match (attribute("http.method"), attribute("http.target")) {
+        (method, Some(target)) => (method, target),
+        (method, None) => match name.split_once(' ') {
+            Some((name_method, route)) => (method.or(Some(name_method.into())), route.into()),
+            None => (method, name.into()),
+        },
+    }
+}
+
+impl ShouldSample for RouteSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        name: &str,
+        _span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        _links: &[Link],
+        _instrumentation_library: &InstrumentationLibrary,
+    ) -> SamplingResult {
+        let parent = parent_context.filter(|cx| cx.has_active_span());
+
+        let sampled = match parent {
+            Some(cx) => cx.span().span_context().is_sampled(),
+            None => {
+                let (method, target) = request_of(name, attributes);
+                self.decide(method.as_deref(), &target, &mut rand::thread_rng())
+            }
+        };
+
+        SamplingResult {
+            decision: if sampled {
+                SamplingDecision::RecordAndSample
+            } else {
+                SamplingDecision::Drop
+            },
+            attributes: Vec::new(),
+            trace_state: match parent {
+                Some(cx) => cx.span().span_context().trace_state().clone(),
+                None => TraceState::default(),
+            },
+        }
+    }
+}
+
+/// Marks requests ending with a status >= 400 for tail-based keeping.
+///
+/// The head sampler already decided whether the trace is recorded by the time
+/// the response status is known, so this cannot rescue a dropped trace: it
+/// only flags error outcomes of sampled traces by recording
+/// `sampling.priority = 1` on a span wrapping the handler, which tail-based
+/// samplers evaluating the whole trace can key on.
+pub async fn mark_error_outcome<B>(request: Request<B>, next: Next<B>) -> Response {
+    let span = tracing::info_span!("response", sampling.priority = tracing::field::Empty);
+
+    let response = next.run(request).instrument(span.clone()).await;
+
+    if response.status().as_u16() >= 400 {
+        span.record(SAMPLING_PRIORITY_FIELD, 1);
+    }
+
+    response
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+    };
+    use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+    use super::*;
+
+    /// Layer keeping the fields recorded on spans, for tests asserting what
+    /// ends up in span attributes.
+    #[derive(Debug, Clone, Default)]
+    pub struct RecordedFields(Arc<Mutex<Vec<(String, String, String)>>>);
+
+    impl RecordedFields {
+        /// Records the spans of the current thread until the guard is
+        /// dropped.
+        pub fn install(&self) -> DefaultGuard {
+            tracing::subscriber::set_default(
+                tracing_subscriber::Registry::default().with(self.clone()),
+            )
+        }
+
+        /// Returns the values of `field` recorded on the spans named `span`,
+        /// e.g. `bank_web::payments::post`.
+        pub fn get(&self, span: &str, field: &str) -> Vec<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, recorded, _)| name.ends_with(span) && recorded == field)
+                .map(|(_, _, value)| value.clone())
+                .collect()
+        }
+
+        /// Returns every recorded value.
+        pub fn values(&self) -> Vec<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, _, value)| value.clone())
+                .collect()
+        }
+    }
+
+    struct Visitor<'a> {
+        span: String,
+        fields: &'a mut Vec<(String, String, String)>,
+    }
+
+    impl Visit for Visitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields.push((
+                self.span.clone(),
+                field.name().to_string(),
+                value.to_string(),
+            ));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.fields.push((
+                self.span.clone(),
+                field.name().to_string(),
+                format!("{value:?}"),
+            ));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for RecordedFields {
+        fn on_new_span(&self, attributes: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            let metadata = attributes.metadata();
+            attributes.record(&mut Visitor {
+                span: format!("{}::{}", metadata.target(), metadata.name()),
+                fields: &mut self.0.lock().unwrap(),
+            });
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            let Some(span) = ctx.span(id) else {
+                return;
+            };
+            values.record(&mut Visitor {
+                span: format!("{}::{}", span.metadata().target(), span.name()),
+                fields: &mut self.0.lock().unwrap(),
+            });
+        }
+    }
+
+    fn sampler(default_ratio: f64, rules: &str) -> RouteSampler {
+        RouteSampler::new(SamplerConfig {
+            default_ratio,
+            rules: SamplerConfig::parse_rules(rules).expect("failed to parse rules"),
+        })
+    }
+
+    fn sampled_count(sampler: &RouteSampler, method: &str, target: &str, seed: u64) -> usize {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        (0..10_000)
+            .filter(|_| sampler.decide(Some(method), target, &mut rng))
+            .count()
+    }
+
+    #[test]
+    fn should_parse_rules() {
+        let rules = SamplerConfig::parse_rules("GET /health=0.001, post /api/payments=1.0,/api=0")
+            .expect("failed to parse rules");
+
+        assert_eq!(
+            rules,
+            vec![
+                RouteRule {
+                    method: Some("GET".to_string()),
+                    path_prefix: "/health".to_string(),
+                    ratio: 0.001,
+                },
+                RouteRule {
+                    method: Some("POST".to_string()),
+                    path_prefix: "/api/payments".to_string(),
+                    ratio: 1.0,
+                },
+                RouteRule {
+                    method: None,
+                    path_prefix: "/api".to_string(),
+                    ratio: 0.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_rules() {
+        assert!(SamplerConfig::parse_rules("/health").is_err());
+        assert!(SamplerConfig::parse_rules("/health=abc").is_err());
+        assert!(SamplerConfig::parse_rules("/health=1.5").is_err());
+        assert!(SamplerConfig::parse_rules("health=0.5").is_err());
+    }
+
+    #[test]
+    fn should_pick_first_matching_rule() {
+        let sampler = sampler(0.5, "GET /health=0.001,POST /api/payments=1.0,/api=0.1");
+
+        assert_eq!(sampler.ratio_for(Some("GET"), "/health"), 0.001);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/health?verbose=1"), 0.001);
+        assert_eq!(sampler.ratio_for(Some("POST"), "/health"), 0.5);
+        assert_eq!(sampler.ratio_for(Some("POST"), "/api/payments"), 1.0);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/api/payments/123"), 0.1);
+        assert_eq!(sampler.ratio_for(None, "/api/payments"), 0.1);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/other"), 0.5);
+    }
+
+    #[test]
+    fn should_match_whole_path_segments() {
+        let sampler = sampler(0.5, "/api/payments=1.0,/health/=0.001,/=0.1");
+
+        assert_eq!(sampler.ratio_for(Some("GET"), "/api/payments"), 1.0);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/api/payments/123"), 1.0);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/api/payments?limit=1"), 1.0);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/api/payments_export"), 0.1);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/health"), 0.001);
+        assert_eq!(sampler.ratio_for(Some("GET"), "/healthz"), 0.1);
+    }
+
+    #[test]
+    fn should_sample_according_to_route_ratio() {
+        let sampler = sampler(0.25, "GET /health=0.001,POST /api/payments=1.0,GET /api=0");
+
+        assert_eq!(sampled_count(&sampler, "POST", "/api/payments", 1), 10_000);
+        assert_eq!(sampled_count(&sampler, "GET", "/api/payments/123", 1), 0);
+
+        let health = sampled_count(&sampler, "GET", "/health", 1);
+        assert!(health < 50, "sampled {health} health checks");
+
+        let other = sampled_count(&sampler, "GET", "/other", 1);
+        assert!((2_000..3_000).contains(&other), "sampled {other} requests");
+    }
+
+    #[test]
+    fn should_be_deterministic_for_a_seed() {
+        let sampler = sampler(0.5, "");
+
+        assert_eq!(
+            sampled_count(&sampler, "GET", "/api/payments", 42),
+            sampled_count(&sampler, "GET", "/api/payments", 42)
+        );
+    }
+
+    #[test]
+    fn should_read_request_from_span_name_without_attributes() {
+        let attributes = OrderMap::default();
+        let (method, target) = request_of("POST /api/payments", &attributes);
+
+        assert_eq!(method.as_deref(), Some("POST"));
+        assert_eq!(target, "/api/payments");
+    }
+
+    #[test]
+    fn should_only_log_without_otlp_endpoint() {
+        let guard = init_scoped_tracing(TracingConfig::default());
+
+        assert!(!guard.exports_spans());
+        tracing::info!("logged without a collector");
+    }
+
+    #[tokio::test]
+    async fn should_export_spans_to_otlp_endpoint() {
+        let guard = init_scoped_tracing(TracingConfig {
+            otlp_endpoint: Some(DEFAULT_OTLP_ENDPOINT.to_string()),
+            ..TracingConfig::default()
+        });
+
+        assert!(guard.exports_spans());
+    }
+
+    #[tokio::test]
+    async fn should_only_log_when_export_cant_be_set_up() {
+        let guard = init_scoped_tracing(TracingConfig {
+            otlp_endpoint: Some("not an endpoint".to_string()),
+            ..TracingConfig::default()
+        });
+
+        assert!(!guard.exports_spans());
+    }
+
+    #[test]
+    fn should_not_panic_when_installed_twice() {
+        let quiet = || TracingConfig {
+            default_filter: "off".to_string(),
+            ..TracingConfig::default()
+        };
+
+        let _first = init_tracing(quiet());
+        let _second = init_tracing(quiet());
+    }
+
+    #[tokio::test]
+    async fn should_trace_requests_without_opentelemetry_layer() {
+        use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+        use tower::ServiceExt;
+
+        let _guard = init_scoped_tracing(TracingConfig::default());
+        let router = Router::new()
+            .route("/", get(|| async { StatusCode::IM_A_TEAPOT }))
+            .layer(middleware::from_fn(mark_error_outcome))
+            .layer(axum_tracing_opentelemetry::opentelemetry_tracing_layer());
+
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    }
+}